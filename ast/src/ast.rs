@@ -1,12 +1,35 @@
 use std::collections::HashMap;
-use crate::internal_types::{fetch_array, fetch_hash_map, fetch_integer, fetch_string};
+use crate::environment::{Captured, Environment};
+use crate::error::{RuntimeError, RuntimeErrorKind};
+use crate::internal_types::{fetch_array, fetch_boolean, fetch_hash_map, fetch_integer, fetch_string, type_of};
 use crate::node::{Node, OperatorKind};
-use crate::value::Value;
+use crate::value::{Arithmetic, Bitwise, Value};
+
+/// The default limit on nested function calls before evaluation fails with a stack overflow error.
+pub const DEFAULT_MAX_CALL_DEPTH: usize = 256;
+/// The highest `max_call_depth` evaluation accepts, which needs a stack of about 1GB.
+pub const MAX_CALL_DEPTH_LIMIT: usize = 16 * 1024;
+
+// generous upper bounds on the Rust stack used by the evaluator itself and by each nested call
+const BASE_STACK_SIZE: usize = 1024 * 1024;
+const STACK_PER_CALL: usize = 64 * 1024;
 
 #[derive(Debug)]
 pub struct Ast {
     pub nodes: Vec<Node>,
+    // the global frame; blocks, loops and calls push local frames onto `scopes`
     pub declarations: HashMap<String, Node>,
+    pub max_call_depth: usize,
+    scopes: Environment,
+    call_depth: usize,
+}
+
+// how a statement or block finished executing; control flow is kept apart from `RuntimeError`
+enum Flow {
+    Next,
+    Break,
+    Continue,
+    Return(Node),
 }
 
 impl Default for Ast {
@@ -38,6 +61,16 @@ impl Ast {
         Self {
             nodes: Vec::new(),
             declarations: HashMap::new(),
+            max_call_depth: DEFAULT_MAX_CALL_DEPTH,
+            scopes: Environment::new(),
+            call_depth: 0,
+        }
+    }
+
+    pub fn from_nodes(nodes: Vec<Node>) -> Self {
+        Self {
+            nodes,
+            ..Self::new()
         }
     }
 
@@ -47,1099 +80,819 @@ impl Ast {
         }
     }
 
-    pub fn upsert_declaration(&mut self, node: Node) -> Result<(), String> {
+    pub fn upsert_declaration(&mut self, node: Node) -> Result<(), RuntimeError> {
         match node {
-            Node::AssignStmt { left, right, .. } => {
+            Node::AssignStmt { left, right, kind, .. } => {
                 match *left {
-                    Node::Identifier { value: _name } => {
-                        self.declarations.insert(_name, *right);
-                    }
-                    Node::Ident { name, .. } => {
-                        self.declarations.insert(name, *right);
+                    Node::Identifier { value: name } | Node::Ident { name, .. } => {
+                        if kind == "let" {
+                            self.declare(name, *right);
+                        } else {
+                            self.assign(name, *right);
+                        }
                     }
-                    Node::IndexExpression { left: left2, index } => {
+                    Node::IndexExpression { left: left2, index, .. } => {
                         // fetch name from left
 
                         let name = fetch_string(*left2.clone())?;
 
-                        let array = self.declarations.get(&name).expect("var not found").clone();
+                        let array = self.lookup(&name)?;
 
                         let mut elements = fetch_array(array)?;
 
                         let i = extract_index(*index.clone())?;
-                        elements[i] = *right;
+                        let len = elements.len();
+                        let slot = elements
+                            .get_mut(i)
+                            .ok_or(RuntimeErrorKind::IndexOutOfBounds { index: i, len })?;
+                        *slot = *right;
 
-                        self.declarations.insert(name, Node::Array { elements });
+                        self.assign(name, Node::Array { elements });
                     }
                     _ => {
-                        return Err(format!("invalid type {:?} for left branch", left));
+                        return Err(RuntimeError::type_mismatch("assignable name", type_of(&left)));
                     }
                 }
 
             }
-            Node::FunctionDecl { name, arguments, returns, body } => {
+            Node::FunctionDecl { name, arguments, returns, body, span } => {
                 let n = fetch_string(*name.clone())?;
-                self.declarations.insert(n, Node::FunctionDecl {
+                let function = Node::FunctionDecl {
                     name,
                     arguments,
                     returns,
                     body,
-                });
+                    span,
+                };
+                if self.scopes.depth() == 0 {
+                    // globals are looked up when they are used, there is nothing to capture
+                    self.declare(n, function);
+                } else {
+                    // the closure finds itself through its own name on each call rather than through
+                    // what it captured, so it doesn't keep itself alive
+                    let closure = Node::Closure { function: Box::from(function), captured: self.scopes.capture_recursive(&n) };
+                    self.declare(n, closure);
+                }
             }
             Node::Atomic { .. } => {}
             Node::EmptyNode => {}
             Node::HMap { values } => {
-                self.declarations.insert(String::from(""), Node::HMap { values });
+                self.declare(String::from(""), Node::HMap { values });
             }
             _ => {
-                return Err(format!("Invalid node type: {:?}", node));
+                return Err(RuntimeError::type_mismatch("declaration", type_of(&node)));
             }
         }
         Ok(())
     }
 
-    pub fn remove_declaration(&mut self, name: &str) -> Result<(), String> {
+    pub fn remove_declaration(&mut self, name: &str) -> Result<(), RuntimeError> {
         self.declarations.remove(name);
         Ok(())
     }
 
+    /// Resolves a name against the innermost local frame first, falling back to the globals.
+    pub fn lookup(&self, name: &str) -> Result<Node, RuntimeError> {
+        self.scopes
+            .get(name)
+            .or_else(|| self.declarations.get(name).cloned())
+            .ok_or_else(|| RuntimeErrorKind::UndefinedVariable(name.to_string()).into())
+    }
+
+    // `let` always binds in the current frame, shadowing anything outside it
+    fn declare(&mut self, name: String, value: Node) {
+        if self.scopes.depth() == 0 {
+            self.declarations.insert(name, value);
+        } else {
+            self.scopes.declare(name, value);
+        }
+    }
+
+    // plain assignment updates the nearest existing binding, and only binds a
+    // new name in the current frame when nothing is in scope yet
+    fn assign(&mut self, name: String, value: Node) {
+        let Err(value) = self.scopes.set(&name, value) else {
+            return;
+        };
+        if self.declarations.contains_key(&name) {
+            self.declarations.insert(name, value);
+        } else {
+            self.declare(name, value);
+        }
+    }
+
+    // evaluates a list of statements inside a fresh frame, popping it again even on error
+    fn eval_scoped(&mut self, nodes: Vec<Node>) -> Result<Flow, RuntimeError> {
+        self.scopes.push();
+        let res = self.eval_block(nodes);
+        self.scopes.pop();
+        res
+    }
+
+    // runs statements in the current frame until one of them breaks or returns
+    fn eval_block(&mut self, nodes: Vec<Node>) -> Result<Flow, RuntimeError> {
+        for node in nodes {
+            match self.exec(node)? {
+                Flow::Next => (),
+                flow => return Ok(flow),
+            }
+        }
+        Ok(Flow::Next)
+    }
+
     pub fn add_node(&mut self, node: Node) {
         self.nodes.push(node);
     }
 
-    pub fn eval(&mut self) -> Result<(), String> {
+    /// Evaluates every top level node. The tree walker recurses on the Rust stack, so evaluation
+    /// runs on a thread whose stack is sized for `max_call_depth` nested calls; running out of
+    /// depth is reported as `StackOverflow` instead of aborting the process. A depth above
+    /// `MAX_CALL_DEPTH_LIMIT`, or a stack the system can't give, fails with `StackUnavailable`.
+    pub fn eval(&mut self) -> Result<(), RuntimeError> {
+        let depth = self.max_call_depth;
+        if depth > MAX_CALL_DEPTH_LIMIT {
+            return Err(RuntimeErrorKind::StackUnavailable { depth }.into());
+        }
+        let stack_size = BASE_STACK_SIZE + depth * STACK_PER_CALL;
+
+        std::thread::scope(|scope| {
+            std::thread::Builder::new()
+                .name("fox-eval".to_string())
+                .stack_size(stack_size)
+                .spawn_scoped(scope, || self.eval_nodes())
+                .map_err(|_| RuntimeError::from(RuntimeErrorKind::StackUnavailable { depth }))?
+                .join()
+                .unwrap_or_else(|panic| std::panic::resume_unwind(panic))
+        })
+    }
+
+    fn eval_nodes(&mut self) -> Result<(), RuntimeError> {
         for node in self.nodes.clone() {
-            self.eval_node(node)?;
+            match self.exec(node)? {
+                Flow::Next => (),
+                // a top level `return` simply stops the script
+                Flow::Return(_) => break,
+                Flow::Break => return Err(RuntimeErrorKind::BreakOutsideLoop.into()),
+                Flow::Continue => return Err(RuntimeErrorKind::ContinueOutsideLoop.into()),
+            }
         }
         Ok(())
     }
 
-    fn eval_node(&mut self, ast: Node) -> Result<Node, String> {
-        // traverse and evaluate the AST
-        match ast {
-            Node::BinaryExpression {
-                left,
-                operator,
-                right,
-            } => {
-                let res = self.eval_binary_expression(*left.clone(), operator, *right)?;
+    // errors that don't know their location yet are pinned to the statement being executed
+    fn exec(&mut self, node: Node) -> Result<Flow, RuntimeError> {
+        let span = node.span();
+        self.exec_inner(node).map_err(|e| match span {
+            Some(span) => e.with_span(span),
+            None => e,
+        })
+    }
 
-                return Ok(res);
+    // statements that can change control flow are handled here, everything else goes through eval_node
+    fn exec_inner(&mut self, node: Node) -> Result<Flow, RuntimeError> {
+        match node {
+            Node::Break { .. } => Ok(Flow::Break),
+            Node::Continue { .. } => Ok(Flow::Continue),
+            Node::Return { value, .. } => {
+                let res = self.eval_expression(*value)?;
+                Ok(Flow::Return(res))
             }
-            Node::UnaryExpression {
-                operator,
-                right,
+            Node::Conditional {
+                condition,
+                consequence,
+                alternative,
+                ..
+            } => {
+                self.eval_conditional(condition, consequence, alternative)
+            }
+            Node::ForLoop {
+                variable,
+                range,
+                body,
+                ..
             } => {
-                let res = self.eval_unary_expression(*right, operator)?;
-                return Ok(res);
+                self.parse_for(variable, range, body)
+            }
+            Node::WhileLoop { condition, body, .. } => {
+                self.eval_while(*condition, body)
             }
-            Node::Identifier { value: _value, .. } => {
-                "Identifiers";
+            Node::Loop { body, .. } => {
+                self.eval_loop(body)
             }
+            _ => {
+                self.eval_node(node)?;
+                Ok(Flow::Next)
+            }
+        }
+    }
+
+    fn eval_node(&mut self, ast: Node) -> Result<Node, RuntimeError> {
+        // traverse and evaluate the AST
+        match ast {
             Node::AssignStmt {
                 left,
                 right,
                 kind: _kind,
+                span,
             } => {
-                if let Node::IndexExpression { left, index } = *left.clone() {
-
-                    let replaced = self.replace_var(*index.clone())?;
-                    let res = self.eval_node(replaced)?;
+                if let Node::IndexExpression { left, index, span: index_span } = *left.clone() {
+                    let res = self.eval_expression(*index)?;
+                    let value = self.eval_expression(*right)?;
 
                     self.upsert_declaration(Node::AssignStmt {
                         left: Box::from(Node::IndexExpression {
                             left,
                             index: Box::from(res.clone()),
+                            span: index_span,
                         }),
-                        right: right.clone(),
+                        right: Box::from(value),
                         kind: _kind,
+                        span,
                     })?;
                     return Ok(res);
                 }
 
-                let mut res = Node::EmptyNode;
-                if let Node::Call { name, arguments, .. } = *right.clone() {
-                    res = self.eval_call(name, arguments)?;
-                } else {
-                    let replaced = self.replace_var(*right.clone())?;
-                    res = self.eval_node(replaced)?;
-                }
+                let res = self.eval_expression(*right)?;
 
                 self.upsert_declaration(Node::AssignStmt {
                     left,
                     right: Box::from(res.clone()),
                     kind: _kind,
+                    span,
                 })?;
-                return Ok(res);
-            }
-            Node::Call {
-                name,
-                arguments,
-                returns: _returns,
-            } => {
-                self.eval_call(name, arguments)?;
-            }
-            Node::Atomic { value } => {
-                return Ok(Node::Atomic { value });
-            }
-            Node::MMExpression { .. } => {
-                // todo!("will be migrated")
-                // let mut axiom = Axiom::new("ax-1".to_string(), expression);
-                // axiom.solve().expect("unexpected failure");
-                // println!("{:?}", axiom.steps);
+                Ok(res)
             }
             Node::Type { name: _name } => {
-                return Ok(Node::EmptyNode);
-            }
-            Node::Conditional {
-                condition,
-                consequence,
-                alternative,
-            } => {
-                self.eval_conditional(condition, consequence, alternative)?;
-            }
-            Node::ForLoop {
-                variable,
-                range,
-                body,
-            } => {
-                self.parse_for(variable, range, body)?;
+                Ok(Node::EmptyNode)
             }
-            Node::EmptyNode => {
-                return Ok(Node::EmptyNode);
+            Node::Conditional { .. }
+            | Node::ForLoop { .. }
+            | Node::WhileLoop { .. }
+            | Node::Loop { .. }
+            | Node::Return { .. }
+            | Node::Break { .. }
+            | Node::Continue { .. } => {
+                match self.exec(ast)? {
+                    Flow::Return(value) => Ok(value),
+                    Flow::Break => Err(RuntimeErrorKind::BreakOutsideLoop.into()),
+                    Flow::Continue => Err(RuntimeErrorKind::ContinueOutsideLoop.into()),
+                    Flow::Next => Ok(Node::EmptyNode),
+                }
             }
-            Node::Break { .. } => {
-                return Err("Break".to_string());
+            Node::FunctionDecl { .. } => {
+                self.upsert_declaration(ast)?;
+
+                Ok(Node::EmptyNode)
             }
-            Node::Array {elements: _elements } => {
-                let x = Node::Array {
-                    elements: _elements,
-                };
-                return Ok(x);
+            _ => {
+                self.eval_expression(ast)
             }
-            Node::IndexExpression { left, index } => {
-                let y = *left;
-
-                // ensure that "y" is an array
-                return match y.clone() {
-                    Node::AssignStmt { left, .. } => {
-                        let name = fetch_string(*left.clone())?;
-
-                        // fetch the name from the declarations
-                        let elements = self.declarations.get(&name).expect("unexpected failure");
+        }
+    }
 
-                        // check if the value is an array
-                        let result = fetch_array(elements.clone())?;
-                        let i = extract_index(*index.clone())?;
+    fn eval_expression(&mut self, node: Node) -> Result<Node, RuntimeError> {
+        let span = node.span();
+        self.eval_expression_inner(node).map_err(|e| match span {
+            Some(span) => e.with_span(span),
+            None => e,
+        })
+    }
 
-                        // Using only Atomic values for now
-                        Ok(Node::Atomic {
-                            value: result[i].val(),
-                        })
-                    }
-                    _ => {
-                        println!("{:?}", y);
-                        Err("Invalid type".to_string())
-                    }
-                }
+    // reduces an expression down to a value node (Atomic, Array or HMap)
+    fn eval_expression_inner(&mut self, node: Node) -> Result<Node, RuntimeError> {
+        match node {
+            Node::Atomic { value } => {
+                Ok(Node::Atomic { value })
             }
-            Node::FunctionDecl {
-                name,
-                arguments,
-                returns,
-                body,
-            } => {
-                let res = Node::FunctionDecl {
-                    name,
+            Node::Lambda { arguments, body, span } => {
+                let function = Node::FunctionDecl {
+                    name: Box::from(Node::Identifier { value: "lambda".to_string() }),
                     arguments,
-                    returns,
+                    returns: vec![],
                     body,
-
+                    span,
                 };
-
-                self.upsert_declaration(res)?;
-
-                return Ok(Node::EmptyNode);
+                Ok(Node::Closure { function: Box::from(function), captured: self.scopes.capture() })
             }
-            Node::Return { value } => {
-                // fetch the latest value of the variable
-                let res = self.replace_var(*value.clone())?;
-                return Ok(res);
+            closure @ Node::Closure { .. } => Ok(closure),
+            Node::Ident { name, .. } | Node::Identifier { value: name } | Node::Object { name, .. } => {
+                self.lookup(&name)
             }
-            Node::HMap {..} => {
-                // TODO: only supports empty initialization
-                return Ok(Node::HMap { values: Default::default() });
+            Node::AssignStmt { left, .. } => {
+                // the parser still wraps some variable reads in an AssignStmt
+                let name = fetch_string(*left)?;
+                self.lookup(&name)
             }
-            Node::MethodCall { name, target, arguments, .. } => {
-                let res = self.eval_method_call(name, target, arguments)?;
-                if let Node::EmptyNode{} = res {
-                    return Ok(Node::EmptyNode);
-                }
-                return Ok(res);
+            Node::BinaryExpression {
+                left,
+                operator,
+                right,
+                ..
+            } => {
+                self.eval_binary_expression(*left, operator, *right)
             }
-            _ => {
-                println!("{:?}", ast);
-                todo!("Unknown node");
+            Node::UnaryExpression {
+                operator,
+                right,
+                ..
+            } => {
+                self.eval_unary_expression(*right, operator)
             }
-        }
-
-        Ok(Node::EmptyNode)
-    }
-
-    fn replace_var(&mut self, node: Node) -> Result<Node, String> {
-        match node.clone() {
-            Node::AssignStmt {
-                right: _left_val,
-                left,
+            Node::Call {
+                name,
+                arguments,
                 ..
             } => {
-                let name = fetch_string(*left.clone())?;
-
-                let res = self
-                    .declarations
-                    .get(&name)
-                    .expect("unexpected failure")
-                    .clone();
-                return Ok(res);
+                self.eval_call(name, arguments)
             }
-            Node::Object {name, ..} => {
-                let res = self
-                    .declarations
-                    .get(&name)
-                    .expect("unexpected failure").clone();
-                return Ok(res);
+            Node::MethodCall { name, target, arguments, .. } => {
+                self.eval_method_call(name, target, arguments)
             }
-            Node::Atomic { value } => {
-                return Ok(Node::Atomic { value });
+            Node::IndexExpression { left, index, .. } => {
+                self.eval_index(*left, *index)
             }
-            Node::IndexExpression { left, index } => {
-                return self.replace_var_assign(*left, index);
+            Node::Array { elements } => {
+                let elements = elements
+                    .into_iter()
+                    .map(|element| self.eval_expression(element))
+                    .collect::<Result<Vec<_>, _>>()?;
+                Ok(Node::Array { elements })
+            }
+            Node::HMap {..} => {
+                // TODO: only supports empty initialization
+                Ok(Node::HMap { values: Default::default() })
+            }
+            Node::MMExpression { .. } => {
+                // todo!("will be migrated")
+                // let mut axiom = Axiom::new("ax-1".to_string(), expression);
+                // axiom.solve().expect("unexpected failure");
+                // println!("{:?}", axiom.steps);
+                Ok(Node::EmptyNode)
             }
-            Node::Ident { name, .. } => {
-                let res = self
-                    .declarations
-                    .get(&name)
-                    .expect("unexpected failure")
-                    .clone();
-                return Ok(res);
+            Node::EmptyNode => {
+                Ok(Node::EmptyNode)
             }
             _ => {
+                Err(RuntimeError::type_mismatch("expression", type_of(&node)))
             }
         }
-
-        Ok(node)
     }
 
-    fn replace_var_assign(&mut self, node: Node, index: Box<Node>) -> Result<Node, String> {
-        match node.clone() {
-            Node::AssignStmt { left, .. } => {
-                let name = fetch_string(*left.clone())?;
+    fn eval_index(&mut self, left: Node, index: Node) -> Result<Node, RuntimeError> {
+        let container = self.eval_expression(left)?;
+        let i = extract_index(self.eval_expression(index)?)?;
 
-                // fetch the name from the declarations
-                let elements = self.declarations.get(&name).expect("unexpected failure");
-
-                let result = fetch_array(elements.clone())?;
-                let i = extract_index(*index.clone())?;
-
-                // Using only Atomic values for now
-                Ok(Node::Atomic {
-                    value: result[i].val(),
-                })
+        match container {
+            Node::Array { elements } => {
+                let len = elements.len();
+                elements
+                    .into_iter()
+                    .nth(i)
+                    .ok_or_else(|| RuntimeErrorKind::IndexOutOfBounds { index: i, len }.into())
             }
-            Node::IndexExpression {index, .. } => {
-                let name = fetch_string(node)?;
-
-                let array = self.declarations.get(&name).expect("var not found").clone();
-
-                let outer_index = fetch_integer(*index.clone())?;
-
-                let elements = fetch_array(array)?;
-
-                let child_node = elements[outer_index as usize].clone();
-                println!("child node {:?}", child_node);
-                let child_array = fetch_array(child_node)?;
-                let res = child_array[0].clone();
-
-                Ok(res)
+            Node::Atomic { value: Value::Str(s) } => {
+                // strings are indexed by character
+                let len = s.chars().count();
+                let c = s.chars().nth(i).ok_or(RuntimeErrorKind::IndexOutOfBounds { index: i, len })?;
+                Ok(Node::Atomic { value: Value::Str(c.to_string()) })
             }
             _ => {
-                println!("{:?}", node);
-                Err("Invalid type".to_string())
+                Err(RuntimeError::type_mismatch("array or string", type_of(&container)))
             }
         }
     }
 
     fn eval_binary_expression(
         &mut self,
-        mut left: Node,
+        left: Node,
         operator: OperatorKind,
-        mut right: Node,
-    ) -> Result<Node, String> {
-        match operator {
-            OperatorKind::Add => {
-                // TODO: fix this. it is ugly
-                if let Node::AssignStmt {
-                    right: _left_val,
-                    left: _name,
-                    ..
-                } = left.clone()
-                {
-                    left = self.replace_var(left)?;
-                } else if let Node::Ident { .. } = left.clone() {
-                    left = self.replace_var(left)?;
-                }
-
-                if let Node::AssignStmt {
-                    right: _right_val,
-                    left: _name,
-                    ..
-                } = right.clone()
-                {
-                    right = self.replace_var(right)?;
-                } else if let Node::Ident { .. } = right.clone() {
-                    right = self.replace_var(right)?;
-                }
-
-                if let Node::Atomic { value: left_val } = left {
-                    if let Node::Atomic { value: right_val } = right {
-                        return match (left_val, right_val) {
-                            (Value::Int(left), Value::Int(right)) => Ok(Node::Atomic {
-                                value: Value::Int(left + right),
-                            }),
-                            _ => Err("Invalid types".to_string()),
-                        };
-                    }
-                }
-                return Err("Invalid type".to_string())
-            }
-            OperatorKind::Subtract => {
-                if let Node::AssignStmt {
-                    right: _left_val,
-                    left: _name,
-                    ..
-                } = left.clone()
-                {
-                    left = self.replace_var(left)?;
-                }
-                if let Node::AssignStmt {
-                    right: _right_val,
-                    left: _name,
-                    ..
-                } = right.clone()
-                {
-                    right = self.replace_var(right)?;
-                }
-
-                if let Node::Atomic { value: left_val } = left {
-                    if let Node::Atomic { value: right_val } = right {
-                        return match (left_val, right_val) {
-                            (Value::Int(left), Value::Int(right)) => Ok(Node::Atomic {
-                                value: Value::Int(left - right),
-                            }),
-                            _ => Err("Invalid types".to_string()),
-                        };
-                    }
-                }
-            }
-            OperatorKind::Multiply => {
-                if let Node::AssignStmt {
-                    right: _left_val,
-                    left: _name,
-                    ..
-                } = left.clone()
-                {
-                    left = self.replace_var(left)?;
-                }
-                if let Node::AssignStmt {
-                    right: _right_val,
-                    left: _name,
-                    ..
-                } = right.clone()
-                {
-                    right = self.replace_var(right)?;
-                }
-
-                if let Node::Atomic { value: left_val } = left {
-                    if let Node::Atomic { value: right_val } = right {
-                        return match (left_val, right_val) {
-                            (Value::Int(left), Value::Int(right)) => Ok(Node::Atomic {
-                                value: Value::Int(left * right),
-                            }),
-                            _ => Err("Invalid types".to_string()),
-                        };
-                    }
-                }
-            }
-            OperatorKind::Divide => {
-                if let Node::AssignStmt {
-                    right: _left_val,
-                    left: _name,
-                    ..
-                } = left.clone()
-                {
-                    left = self.replace_var(left)?;
-                }
-                if let Node::AssignStmt {
-                    right: _right_val,
-                    left: _name,
-                    ..
-                } = right.clone()
-                {
-                    right = self.replace_var(right)?;
-                }
-
-                if let Node::Atomic { value: left_val } = left {
-                    if let Node::Atomic { value: right_val } = right {
-                        return match (left_val, right_val) {
-                            (Value::Int(left), Value::Int(right)) => Ok(Node::Atomic {
-                                value: Value::Int(left / right),
-                            }),
-                            _ => Err("Invalid types".to_string()),
-                        };
-                    }
-                }
-            }
-            OperatorKind::Modulo => {
-                if let Node::AssignStmt {
-                    right: _left_val,
-                    left: _name,
-                    ..
-                } = left.clone()
-                {
-                    left = self.replace_var(left)?;
-                }
-                if let Node::AssignStmt {
-                    right: _right_val,
-                    left: _name,
-                    ..
-                } = right.clone()
-                {
-                    right = self.replace_var(right)?;
-                }
-
-                if let Node::Atomic { value: left_val } = left {
-                    if let Node::Atomic { value: right_val } = right {
-                        return match (left_val, right_val) {
-                            (Value::Int(left), Value::Int(right)) => Ok(Node::Atomic {
-                                value: Value::Int(left % right),
-                            }),
-                            _ => Err("Invalid types".to_string()),
-                        };
-                    }
-                }
-            }
-            OperatorKind::BitwiseAnd => {
-                if let Node::AssignStmt {
-                    right: _left_val,
-                    ..
-                } = left.clone()
-                {
-                    left = self.replace_var(left)?;
-                }
-                if let Node::AssignStmt {
-                    right: _right_val,
-                    ..
-                } = right.clone()
-                {
-                    right = self.replace_var(right)?;
-                }
-
-                if let Node::Atomic { value: left_val } = left {
-                    if let Node::Atomic { value: right_val } = right {
-                        return match (left_val.clone(), right_val.clone()) {
-                            (Value::Bin(left), Value::Bin(right)) => Ok(Node::Atomic {
-                                value: Value::Bin(left & right),
-                            }),
-                            (Value::Int(left), Value::Bin(right)) => Ok(Node::Atomic {
-                                value: Value::Bin(left as u32 & right),
-                            }),
-                            (Value::Int(left), Value::Int(right)) => Ok(Node::Atomic {
-                                value: Value::Bin(left as u32 & right as u32),
-                            }),
-                            _ => {
-                                println!("left_val: {:?}", left_val);
-                                println!("right_val: {:?}", right_val);
-                                Err("Invalid types".to_string())
-                            },
-                        };
-                    }
-                }
-            }
-            OperatorKind::BitwiseOr => {
-                if let Node::AssignStmt {
-                    right: _left_val,
-                    ..
-                } = left.clone()
-                {
-                    left = self.replace_var(left)?;
-                }
-                if let Node::AssignStmt {
-                    right: _right_val,
-                    ..
-                } = right.clone()
-                {
-                    right = self.replace_var(right)?;
-                }
+        right: Node,
+    ) -> Result<Node, RuntimeError> {
+        // && and || short circuit, so the right side is only evaluated when it is needed
+        if let OperatorKind::And | OperatorKind::Or = operator {
+            let left_truth = self.eval_condition(left)?;
+            let res = match operator {
+                OperatorKind::And if !left_truth => false,
+                OperatorKind::Or if left_truth => true,
+                _ => self.eval_condition(right)?,
+            };
+            return Ok(Node::Atomic { value: Value::Bool(res) });
+        }
 
-                if let Node::Atomic { value: left_val } = left {
-                    if let Node::Atomic { value: right_val } = right {
-                        return match (left_val, right_val) {
-                            (Value::Bin(left), Value::Bin(right)) => Ok(Node::Atomic {
-                                value: Value::Bin(left | right),
-                            }),
-                            (Value::Int(left), Value::Bin(right)) => Ok(Node::Atomic {
-                                value: Value::Bin(left as u32 | right),
-                            }),
-                            (Value::Int(left), Value::Int(right)) => Ok(Node::Atomic {
-                                value: Value::Bin(left as u32 | right as u32),
-                            }),
-                            _ => Err("Invalid types".to_string()),
-                        };
-                    }
+        let left = self.eval_expression(left)?;
+        let right = self.eval_expression(right)?;
+
+        let (left_val, right_val) = match (left, right) {
+            (Node::Atomic { value: left }, Node::Atomic { value: right }) => (left, right),
+            (Node::Atomic { .. }, other) | (other, _) => {
+                return Err(RuntimeError::type_mismatch("value", type_of(&other)));
+            }
+        };
+        // reported when the operand types don't fit the operator
+        let mismatch = |left: &Value, right: &Value| {
+            RuntimeError::type_mismatch(
+                format!("operands for {:?}", operator),
+                format!("{} and {}", left.type_name(), right.type_name()),
+            )
+        };
+
+        let value = match operator {
+            OperatorKind::Add
+            | OperatorKind::Subtract
+            | OperatorKind::Multiply
+            | OperatorKind::Divide
+            | OperatorKind::Modulo => {
+                let op = match operator {
+                    OperatorKind::Add => Arithmetic::Add,
+                    OperatorKind::Subtract => Arithmetic::Subtract,
+                    OperatorKind::Multiply => Arithmetic::Multiply,
+                    OperatorKind::Divide => Arithmetic::Divide,
+                    _ => Arithmetic::Modulo,
+                };
+                if Value::is_integer_division_by_zero(op, &left_val, &right_val) {
+                    return Err(RuntimeErrorKind::DivisionByZero.into());
+                }
+                match Value::arithmetic(op, &left_val, &right_val) {
+                    Some(value) => value,
+                    None => return Err(mismatch(&left_val, &right_val)),
+                }
+            }
+            OperatorKind::BitwiseAnd
+            | OperatorKind::BitwiseOr
+            | OperatorKind::BitwiseXor
+            | OperatorKind::ShiftLeft
+            | OperatorKind::ShiftRight => {
+                let op = match operator {
+                    OperatorKind::BitwiseAnd => Bitwise::And,
+                    OperatorKind::BitwiseOr => Bitwise::Or,
+                    OperatorKind::BitwiseXor => Bitwise::Xor,
+                    OperatorKind::ShiftLeft => Bitwise::ShiftLeft,
+                    _ => Bitwise::ShiftRight,
+                };
+                match Value::bitwise(op, &left_val, &right_val)? {
+                    Some(value) => value,
+                    None => return Err(mismatch(&left_val, &right_val)),
                 }
             }
-            OperatorKind::BitwiseXor => {
-                if let Node::AssignStmt {
-                    right: _left_val,
-                    ..
-                } = left.clone()
-                {
-                    left = self.replace_var(left)?;
-                }
-                if let Node::AssignStmt {
-                    right: _right_val,
-                    ..
-                } = right.clone()
-                {
-                    right = self.replace_var(right)?;
-                }
-
-                if let Node::Atomic { value: left_val } = left {
-                    if let Node::Atomic { value: right_val } = right {
-                        return match (left_val, right_val) {
-                            (Value::Bin(left), Value::Bin(right)) => Ok(Node::Atomic {
-                                value: Value::Bin(left ^ right),
-                            }),
-                            (Value::Int(left), Value::Bin(right)) => Ok(Node::Atomic {
-                                value: Value::Bin(left as u32 ^ right),
-                            }),
-                            (Value::Int(left), Value::Int(right)) => Ok(Node::Atomic {
-                                value: Value::Bin(left as u32 ^ right as u32),
-                            }),
-                            _ => Err("Invalid types".to_string()),
-                        };
-                    }
-                }
+            OperatorKind::IsEqual => {
+                Value::Bool(crate::value::compare_value(&left_val, &right_val))
             }
-            OperatorKind::ShiftLeft => {
-                if let Node::AssignStmt {
-                    right: _left_val,
-                    left: _name,
-                    ..
-                } = left.clone()
-                {
-                    left = self.replace_var(left)?;
+            OperatorKind::LessThan | OperatorKind::GreaterThan => {
+                // None when a NaN is involved, which is neither smaller nor greater than anything
+                let comparable = |value: &Value| value.as_f64().is_some() || matches!(value, Value::Bin(_));
+                if !comparable(&left_val) || !comparable(&right_val) {
+                    return Err(mismatch(&left_val, &right_val));
                 }
-                if let Node::AssignStmt {
-                    right: _right_val,
-                    left: _name,
-                    ..
-                } = right.clone()
-                {
-                    right = self.replace_var(right)?;
-                }
-
-                if let Node::Atomic { value: left_val } = left {
-                    if let Node::Atomic { value: right_val } = right {
-                        return match (left_val.clone(), right_val.clone()) {
-                            (Value::Bin(left), Value::Int(right)) => Ok(Node::Atomic {
-                                value: Value::Bin(left << right),
-                            }),
-                            (Value::Int(left), Value::Bin(right)) => Ok(Node::Atomic {
-                                value: Value::Bin(left as u32 & right),
-                            }),
-                            (Value::Int(left), Value::Int(right)) => Ok(Node::Atomic {
-                                value: Value::Bin((left << right) as u32),
-                            }),
-                            _ => {
-                                println!("left_val: {:?}", left_val);
-                                println!("right_val: {:?}", right_val);
-                                Err("Invalid types".to_string())
-                            },
-                        };
-                    }
-                }
-            }
-            OperatorKind::ShiftRight => {
-                if let Node::AssignStmt {
-                    right: _left_val,
-                    left: _name,
-                    ..
-                } = left.clone()
-                {
-                    left = self.replace_var(left)?;
-                }
-                if let Node::AssignStmt {
-                    right: _right_val,
-                    left: _name,
-                    ..
-                } = right.clone()
-                {
-                    right = self.replace_var(right)?;
-                }
-
-                if let Node::Atomic { value: left_val } = left {
-                    if let Node::Atomic { value: right_val } = right {
-                        return match (left_val, right_val) {
-                            (Value::Bin(left), Value::Int(right)) => Ok(Node::Atomic {
-                                value: Value::Bin(left >> right),
-                            }),
-                            _ => Err("Invalid types".to_string()),
-                        };
-                    }
+                let ordering = Value::compare_numbers(&left_val, &right_val);
+                match operator {
+                    OperatorKind::LessThan => Value::Bool(ordering.is_some_and(|o| o.is_lt())),
+                    _ => Value::Bool(ordering.is_some_and(|o| o.is_gt())),
                 }
             }
-            _ => return Err("Unknown operator".to_string()),
-        }
+            _ => return Err(RuntimeErrorKind::UnsupportedOperator(format!("{:?}", operator)).into()),
+        };
 
-        Ok(Node::EmptyNode)
+        Ok(Node::Atomic { value })
     }
 
     fn eval_unary_expression(
         &mut self,
-        mut right: Node,
+        right: Node,
         operator: OperatorKind,
-    ) -> Result<Node, String> {
-        match operator {
+    ) -> Result<Node, RuntimeError> {
+        let right = self.eval_expression(right)?;
+        let Node::Atomic { value: right_val } = right else {
+            return Err(RuntimeError::type_mismatch("value", type_of(&right)));
+        };
+        let mismatch = |right: &Value| {
+            RuntimeError::type_mismatch(format!("operand for {:?}", operator), right.type_name())
+        };
+
+        let value = match operator {
             OperatorKind::Negation => {
-                if let Node::AssignStmt {
-                    right: _right_val,
-                    left: _name,
-                    ..
-                } = right.clone()
-                {
-                    right = self.replace_var(right)?;
+                match right_val {
+                    Value::Int(right) => Value::Int(!right),
+                    Value::Bin(right) => Value::Bin(!right),
+                    Value::Bool(right) => Value::Bool(!right),
+                    right => return Err(mismatch(&right)),
                 }
-
-                if let Node::Atomic { value: right_val } = right {
-                    return match right_val {
-                        Value::Bin(right) => Ok(Node::Atomic {
-                            value: Value::Bin(!right),
-                        }),
-                        _ => Err("Invalid types".to_string()),
-                    };
+            }
+            OperatorKind::Subtract => {
+                match right_val.negate() {
+                    Some(value) => value,
+                    None => return Err(mismatch(&right_val)),
                 }
-                Ok(right)
             }
             _ => {
-                Err("Unknown operator".to_string())
+                return Err(RuntimeErrorKind::UnsupportedOperator(format!("{:?}", operator)).into());
             }
-        }
+        };
+
+        Ok(Node::Atomic { value })
     }
-    // pub fn parse(&mut self, input: &str) -> Result<(), String> {
-    //     let mut parser = crate::lang_parser::LangParser::new(input);
-    //     let ast = parser.parse().expect("unexpected failure");
-    //
-    //     for node in ast.nodes {
-    //         self.add_node(node);
-    //     }
-    //
-    //     Ok(())
-    // }
-
-    fn eval_call(&mut self, name: String, arguments: Vec<Node>) -> Result<Node, String> {
+
+    fn eval_condition(&mut self, condition: Node) -> Result<bool, RuntimeError> {
+        let condition = self.eval_expression(condition)?;
+        fetch_boolean(condition)
+    }
+
+    fn eval_call(&mut self, name: String, arguments: Vec<Node>) -> Result<Node, RuntimeError> {
         // TODO: Have the call names be enums
 
         match name.as_str() {
             "print" => {
-                // check if the first argument is an Object
-                let temp2 = *arguments[0].left()?;
-
-                match temp2 {
-                    Node::Call { name, arguments, .. } => {
-                        let x = self.eval_call(name, arguments)?;
-                        println!("{:?}", x.val());
-                        return Ok(Node::EmptyNode)
-                    }
-                    Node::IndexExpression { left, index } => {
-                        let x = self.eval_array(Node::IndexExpression { left, index })?;
-                        if let Node::Atomic { value } = x {
-                            if let Value::Str(_0) = value {
-                                println!("[{:?}]", _0);
-                            } else {
-                                println!("{:?}", value);
-                            }
-                        } else {
-                            println!("{:?}", x);
-                        }
-
-                        return Ok(Node::EmptyNode)
+                for argument in arguments {
+                    match self.eval_expression(argument)? {
+                        Node::Atomic { value } => println!("{}", value),
+                        other => println!("{:?}", other),
                     }
-                    _ => {}
-                };
-
-                println!("{:?}", self.replace_var(temp2).expect("unexpected failure"));
-                return Ok(Node::EmptyNode)
+                }
+                Ok(Node::EmptyNode)
             }
             "reduce" => {
                 println!("{:?}", arguments[0].left());
+                Ok(Node::EmptyNode)
             }
             "len" => {
-                let name = fetch_string(arguments[0].clone())?;
-                let n = self.declarations.get(&name).expect("missing declaration").clone();
-
-                return match n.node_type() {
-                    "Array" => {
-                        let elements = fetch_array(n.clone())?;
-                        Ok(Node::Atomic {
-                            value: Value::Int(elements.len() as i32),
-                        })
-                    }
-                    "Hashmap" => {
-                        let elements = fetch_hash_map(n.clone())?;
-                        Ok(Node::Atomic {
-                            value: Value::Int(elements.len() as i32),
-                        })
-                    }
-                    "Atomic" => {
-                        let s = fetch_string(n.clone())?;
-                        Ok(Node::Atomic {
-                            value: Value::Int(s.len() as i32),
-                        })
-                    }
+                let n = self.eval_single_argument(&name, arguments)?;
+
+                let len = match n {
+                    Node::Array { elements } => elements.len(),
+                    Node::HMap { values } => values.len(),
+                    Node::Atomic { value: Value::Str(s) } => s.chars().count(),
                     _ => {
-                        return Err(format!("Unknown type {}", n.node_type()));
+                        return Err(RuntimeError::type_mismatch("array, map or string", type_of(&n)));
+                    }
+                };
+                Ok(Node::Atomic {
+                    value: Value::Int(len as i32),
+                })
+            }
+            "bin" => {
+                let value = match self.eval_single_argument(&name, arguments)? {
+                    Node::Atomic { value } => value.to_bin()?,
+                    n => return Err(RuntimeError::type_mismatch("int", type_of(&n))),
+                };
+                Ok(Node::Atomic { value })
+            }
+            "float" | "sqrt" => {
+                let n = self.eval_single_argument(&name, arguments)?;
+                let number = match &n {
+                    Node::Atomic { value } => value.as_f64(),
+                    _ => None,
+                };
+                let Some(f) = number else {
+                    return Err(RuntimeError::type_mismatch("number", type_of(&n)));
+                };
+                let f = if name == "sqrt" { f.sqrt() } else { f };
+                Ok(Node::Atomic { value: Value::Float(f) })
+            }
+            "int" => {
+                let value = match self.eval_single_argument(&name, arguments)? {
+                    Node::Atomic { value } => value.to_int()?,
+                    n => return Err(RuntimeError::type_mismatch("number", type_of(&n))),
+                };
+                Ok(Node::Atomic { value })
+            }
+            "pow" => {
+                let [base, exponent] = self.eval_two_arguments(&name, arguments)?;
+                let value = match (&base, &exponent) {
+                    (Node::Atomic { value: base }, Node::Atomic { value: exponent }) => base.pow(exponent),
+                    _ => None,
+                };
+                match value {
+                    Some(value) => Ok(Node::Atomic { value }),
+                    None => Err(RuntimeError::type_mismatch(
+                        "numbers for pow",
+                        format!("{} and {}", type_of(&base), type_of(&exponent)),
+                    )),
+                }
+            }
+            "map" | "filter" => {
+                let [list, function] = self.eval_two_arguments(&name, arguments)?;
+                let mut results = Vec::new();
+                for element in fetch_array(list)? {
+                    let result = self.call_function(function.clone(), vec![element.clone()])?;
+                    if name == "map" {
+                        results.push(result);
+                    } else if fetch_boolean(result)? {
+                        results.push(element);
                     }
                 }
+                Ok(Node::Array { elements: results })
             }
             _ => {
-                println!("arguments: {:?}", arguments);
                 // function call is not a builtin function
-                let func = self.declarations.get(&name).expect("unexpected failure").clone();
-
-                let node = self.eval_function(func, arguments)?;
-
-                // upsert node
-                self.upsert_declaration(node.clone())?;
+                let func = self.lookup(&name)?;
 
-                return Ok(node);
+                self.eval_function(func, arguments)
             }
         }
-
-        Ok(Node::EmptyNode)
     }
 
-    fn eval_method_call(&mut self, name: String, target: String, arguments: Vec<Node>) -> Result<Node, String> {
+    fn eval_method_call(&mut self, name: String, target: String, arguments: Vec<Node>) -> Result<Node, RuntimeError> {
+        let arguments = arguments
+            .into_iter()
+            .map(|argument| self.eval_expression(argument))
+            .collect::<Result<Vec<_>, _>>()?;
+
         match name.as_str() {
             "push" => {
-                let array = self.declarations.get(&target).expect("missing declaration").clone();
-                let mut elements = fetch_hash_map(array.clone())?;
-                elements.insert(arguments[0].clone(), arguments[1].clone());
-
-                let ident = Node::AssignStmt {
-                    left: Box::new(Node::Ident { name: target, kind: "var".to_string() }),
-                    right: Box::from(Node::HMap { values: elements }),
-                    kind: "var".to_string(),
-                };
-                self.upsert_declaration(ident)?;
+                let array = self.lookup(&target)?;
+                let mut elements = fetch_hash_map(array)?;
+                let found = arguments.len();
+                let [key, value] = <[Node; 2]>::try_from(arguments).map_err(|_| RuntimeErrorKind::ArityMismatch {
+                    name: name.clone(),
+                    expected: 2,
+                    found,
+                })?;
+                elements.insert(key, value);
+
+                self.assign(target, Node::HMap { values: elements });
                 Ok(Node::EmptyNode)
             }
             "get" => {
-                let array = self.declarations.get(&target).expect("missing declaration").clone();
-                let elements = fetch_hash_map(array.clone())?;
-                let key = arguments[0].clone();
-                let value = elements.get(&key).expect("missing key").clone();
-                Ok(value)
+                let array = self.lookup(&target)?;
+                let elements = fetch_hash_map(array)?;
+                let found = arguments.len();
+                let [key] = <[Node; 1]>::try_from(arguments).map_err(|_| RuntimeErrorKind::ArityMismatch {
+                    name: name.clone(),
+                    expected: 1,
+                    found,
+                })?;
+                elements.get(&key).cloned().ok_or_else(|| {
+                    let key = match key {
+                        Node::Atomic { value } => value.to_string(),
+                        other => format!("{:?}", other),
+                    };
+                    RuntimeErrorKind::MissingKey(key).into()
+                })
             }
             _ => {
-                Err(format!("Unknown method call {}", name))
+                Err(RuntimeErrorKind::UnknownMethod(name).into())
             }
         }
     }
 
-    fn eval_function(&mut self, node: Node, arguments: Vec<Node>) -> Result<Node, String> {
-        // make sure that the node is a function
-        if let Node::FunctionDecl {
-            name: _name,
-            arguments: args,
-            returns: _returns,
-            body} = node
-        {
-            let mut ret = Node::EmptyNode;
-            for i in 0..args.len() {
-                let name = fetch_string(args[i].clone())?;
-                self.upsert_declaration(Node::AssignStmt {
-                    left: Box::new(Node::Ident {
-                        name: name.clone(),
-                        kind: "var".to_string(),
-                    }),
-                    right: Box::new(arguments[i].clone()),
-                    kind: "Nat".to_string(),
-                })?;
-            }
-
-            for expr in body {
-                // check if node is a return statement
-                if let Node::Return { value } = expr {
+    fn eval_function(&mut self, node: Node, arguments: Vec<Node>) -> Result<Node, RuntimeError> {
+        // arguments are evaluated in the caller's scope before the callee's frame exists
+        let arguments = arguments
+            .into_iter()
+            .map(|argument| self.eval_expression(argument))
+            .collect::<Result<Vec<_>, _>>()?;
 
-                    // TODO: this assumes a variable, eventually this should also evaluate a node
-                    ret = self.replace_var(*value)?;
-                    break;
-                }
+        self.call_function(node, arguments)
+    }
 
-                self.eval_node(expr)?;
-            }
+    // calls a function or closure with arguments that are already evaluated
+    fn call_function(&mut self, node: Node, arguments: Vec<Node>) -> Result<Node, RuntimeError> {
+        let (node, captured) = match node {
+            Node::Closure { function, captured } => (*function, captured),
+            node => (node, Captured::default()),
+        };
+        let this = captured.own_name().map(|name| {
+            (name.to_string(), Node::Closure { function: Box::from(node.clone()), captured: captured.clone() })
+        });
+        // make sure that the node is a function
+        let Node::FunctionDecl {
+            name,
+            arguments: args,
+            body,
+            ..
+        } = node
+        else {
+            return Err(RuntimeError::type_mismatch("function", type_of(&node)));
+        };
+
+        if args.len() != arguments.len() {
+            return Err(RuntimeErrorKind::ArityMismatch {
+                name: fetch_string(*name)?,
+                expected: args.len(),
+                found: arguments.len(),
+            }.into());
+        }
 
-            for arg in args {
-                self.remove_declaration(&fetch_string(arg)?)?;
-            }
+        if self.call_depth >= self.max_call_depth {
+            return Err(RuntimeErrorKind::StackOverflow { limit: self.max_call_depth }.into());
+        }
 
-            return Ok(ret);
+        // the callee sees what it captured and its own frame, but not the caller's locals
+        let caller = self.scopes.enter_call(&captured);
+        if let Some((name, closure)) = this {
+            self.declare(name, closure);
         }
+        self.call_depth += 1;
+        let res = self.eval_function_body(args, arguments, body);
+        self.call_depth -= 1;
+        self.scopes.leave_call(caller);
 
-        Ok(Node::EmptyNode)
+        res
     }
 
-    fn eval_conditional(&mut self, condition: Box<Node>, consequence: Vec<Node>, alternative: Vec<Node>) -> Result<(), String> {
-        // assert that node is of type Conditional
-        match *condition.clone() {
-            Node::BinaryExpression {
-                left,
-                operator,
-                right,
-            } => {
-                match operator {
-                    OperatorKind::IsEqual => {
-                        // TODO: This is using left.left and right.left which is an oopsie
-                        let pre_left = left.left()?;
-                        let replaced_left = self.replace_var(*pre_left.clone())?;
-
-                        let pre_right = right.left()?;
-                        let replaced_right = self.replace_var(*pre_right.clone())?;
-
-                        let best = crate::value::compare_value(&replaced_left.val(), &replaced_right.val());
-                        if best {
-                            for node in consequence.clone() {
-                                let res = self.eval_node(node)?;
-                                self.upsert_declaration(res)?
-                            }
-                        } else {
-                            for node in alternative.clone() {
-                                self.eval_node(node)?;
-                            }
-                        }
-                    }
-                    OperatorKind::Or => {
-                        // TODO: Sub expressions will need to be evaluated recursively
-                        let sub_left = left.left()?;
-                        let sub_right = left.right()?;
-
-                        let replaced_sub_left = self.replace_var(*sub_left.clone())?;
-                        let replaced_sub_right = self.replace_var(sub_right.clone())?;
-
-                        // check the truthiness of the left and right
-                        let left_truth = crate::value::compare_value(&replaced_sub_left.val(), &replaced_sub_right.val());
-
-                        let sub_right_left = right.left()?;
-                        let sub_right_right = right.right()?;
-
-                        let replaced_sub_right_left = self.replace_var(*sub_right_left.clone())?;
-                        let replaced_sub_right_right = self.replace_var(sub_right_right.clone())?;
-
-                        let right_truth = crate::value::compare_value(&replaced_sub_right_left.val(), &replaced_sub_right_right.val());
+    // evaluates the argument of a builtin that takes exactly one
+    fn eval_single_argument(&mut self, name: &str, arguments: Vec<Node>) -> Result<Node, RuntimeError> {
+        let found = arguments.len();
+        let [argument] = <[Node; 1]>::try_from(arguments).map_err(|_| RuntimeErrorKind::ArityMismatch {
+            name: name.to_string(),
+            expected: 1,
+            found,
+        })?;
+        self.eval_expression(argument)
+    }
 
-                        if left_truth || right_truth {
-                            for node in consequence.clone() {
-                                let res = self.eval_node(node)?;
-                                self.upsert_declaration(res)?
-                            }
-                        } else {
-                            for node in alternative.clone() {
-                                self.eval_node(node)?;
-                            }
-                        }
-                    }
-                    OperatorKind::LessThan | OperatorKind::GreaterThan => {
-                        let replaced_left = self.replace_var(*left.clone())?;
-
-                        let mut ordering = std::cmp::Ordering::Greater;
-                        match operator {
-                            OperatorKind::LessThan => {
-                                ordering = std::cmp::Ordering::Less;
-                            }
-                            OperatorKind::GreaterThan => {
-                                ordering = std::cmp::Ordering::Greater;
-                            }
-                            _ => {}
-                        }
+    // evaluates the arguments of a builtin that takes exactly two
+    fn eval_two_arguments(&mut self, name: &str, arguments: Vec<Node>) -> Result<[Node; 2], RuntimeError> {
+        let found = arguments.len();
+        let [first, second] = <[Node; 2]>::try_from(arguments).map_err(|_| RuntimeErrorKind::ArityMismatch {
+            name: name.to_string(),
+            expected: 2,
+            found,
+        })?;
+        Ok([self.eval_expression(first)?, self.eval_expression(second)?])
+    }
 
-                        match replaced_left.val() {
-                            Value::Int(i) => {
-                                match right.val() {
-                                    Value::Int(ii) => {
-                                        let _ordering = i.cmp(&ii);
-                                        for node in consequence.clone() {
-                                            let res = self.eval_node(node)?;
-                                            self.upsert_declaration(res)?
-                                        }
-                                    }
-                                    _ => {
-                                        println!("right: {:?}", right);
-                                        return Err("Invalid types".to_string());
-                                    }
-                                }
-                            }
-                            _ => {
-                                println!("left: {:?}", left);
-                                return Err("Invalid types".to_string());
-                            }
-                        }
+    fn eval_function_body(&mut self, args: Vec<Node>, arguments: Vec<Node>, body: Vec<Node>) -> Result<Node, RuntimeError> {
+        for (arg, value) in args.into_iter().zip(arguments) {
+            let name = fetch_string(arg)?;
+            self.declare(name, value);
+        }
 
-                    }
-                    _ => {
-                        println!("operator: {:?}", operator);
-                        // todo!("Unknown operator");
-                    }
-                }
-            }
-            Node::Atomic { value } => {
-                if value == Value::Bool(true) {
-                    for node in consequence.clone() {
-                        let res = self.eval_node(node)?;
-                        self.upsert_declaration(res)?
-                    }
-                }
-            }
-            _ => {
-                println!("condition: {:?}", condition);
-                // TODO: Check if boolean
-            }
+        match self.eval_block(body)? {
+            Flow::Return(value) => Ok(value),
+            Flow::Break => Err(RuntimeErrorKind::BreakOutsideLoop.into()),
+            Flow::Continue => Err(RuntimeErrorKind::ContinueOutsideLoop.into()),
+            Flow::Next => Ok(Node::EmptyNode),
         }
-        Ok(())
     }
 
-    fn eval_array(&mut self, node: Node) -> Result<Node, String> {
-        match node {
-            Node::IndexExpression { left, index: sub_index } => {
-                if let Node::IndexExpression {left, index} = *left {
-                    let res = self.eval_array(*left)?;
-
-                    let name = fetch_string(res.clone())?;
-                    let blah = self.declarations.get(&name).expect("missing declaration").clone();
-                    let array = fetch_array(blah)?;
-                    let index = fetch_integer(*index)?;
-                    let sub_index = fetch_integer(*sub_index)?;
-                    let outer = array[index as usize].clone();
-
-                    let inner = fetch_array(outer)?;
+    fn eval_conditional(&mut self, condition: Box<Node>, consequence: Vec<Node>, alternative: Vec<Node>) -> Result<Flow, RuntimeError> {
+        if self.eval_condition(*condition)? {
+            self.eval_scoped(consequence)
+        } else {
+            self.eval_scoped(alternative)
+        }
+    }
 
-                    let last = inner[sub_index as usize].clone();
+    fn parse_for(&mut self, variable: String, range: (Box<Node>, Box<Node>), body: Vec<Node>) -> Result<Flow, RuntimeError> {
+        let start = fetch_integer(self.eval_expression(*range.0)?)?;
+        let end = fetch_integer(self.eval_expression(*range.1)?)?;
 
-                    return Ok(last);
-                }
+        // the loop variable lives in its own frame around the body, and is gone once the loop ends
+        self.scopes.push();
+        self.declare(variable.clone(), Node::Atomic { value: Value::Int(start) });
 
-                if let Node::AssignStmt { left, .. } = *left {
-                    if let Node::Ident { name, .. } = *left {
-                        let string_node = self.declarations.get(&name).expect("missing x").clone();
-                        return match string_node.node_type() {
-                            "Array" => {
-                                let array = fetch_array(string_node)?;
-                                let index = fetch_integer(*sub_index)?;
-                                let v = array[index as usize].clone();
-                                Ok(v)
-                            }
-                            "Atomic" => {
-                                let replaced = self.replace_var(Node::Ident { name, kind: "".to_string() })?;
-                                let s = fetch_string(replaced)?;
-                                let replaced_index = self.replace_var(*sub_index)?;
-                                let index = fetch_integer(replaced_index)?;
-                                let v = s.as_bytes()[index as usize] as char;
-
-                                Ok(Node::Atomic { value: Value::Str(v.to_string()) })
-                            }
-                            _ => {
-                                println!("kind: {:?}", string_node.node_type());
-                                Err("Invalid type".to_string())
-                            }
-                        }
-                    }
-                }
+        let res = self.eval_for_body(&variable, start, end, body);
 
-                Ok(Node::EmptyNode)
-            }
-            Node::Ident {name, kind} => {
-                // fetch the ident by name from declarations and return
-                Ok(Node::Ident { name, kind })
-            }
-            _=> {
-                Err(format!("expected index expression but received {:?} while evaluating array", node))
-            }
-        }
+        self.scopes.pop();
+        res
     }
 
-    fn parse_for(&mut self, variable: String, range: (Box<Node>, Box<Node>), body: Vec<Node>) -> Result<(), String> {
-        let start_node = self.replace_var(*range.0)?;
-        let end_node = self.replace_var(*range.1)?;
-
-        let start = fetch_integer(start_node)?;
-        let replaced_end = self.replace_var(end_node)?;
-        println!("start node: {:?}", start);
-        println!("end node: {:?}", replaced_end);
-        let end = fetch_integer(replaced_end)?;
-
-
-        self.upsert_declaration(Node::AssignStmt {
-            left: Box::new(Node::Ident {
-                name: variable.clone(),
-                kind: "var".to_string(),
-            }),
-            right: Box::new(Node::Atomic {
-                value: Value::Int(start),
-            }),
-            kind: "Nat".to_string(),
-        })?;
-
-        // let start = range.0;
-        // let end = range.1;
+    fn eval_for_body(&mut self, variable: &str, start: i32, end: i32, body: Vec<Node>) -> Result<Flow, RuntimeError> {
         let mut i = start;
 
         while i < end {
-            for node in body.clone() {
-                let x = self.eval_node(node);
-                match x {
-                    Ok(_) => (),
-                    Err(e) => {
-                        if e == "Break" {
-                            i = end;
-                            break;
-                        }
-                    },
-                }
+            // every iteration gets a fresh frame, so a `let` in the body does not leak into the next one
+            match self.eval_scoped(body.clone())? {
+                Flow::Next | Flow::Continue => (),
+                Flow::Break => break,
+                Flow::Return(value) => return Ok(Flow::Return(value)),
             }
             i += 1;
 
-            self.upsert_declaration(Node::AssignStmt {
-                left: Box::new(Node::Ident {
-                    name: variable.clone(),
-                    kind: "var".to_string(),
-                }),
-                right: Box::new(Node::Atomic {
-                    value: Value::Int(i),
-                }),
-                kind: "Nat".to_string(),
-            })?;
+            self.assign(variable.to_string(), Node::Atomic { value: Value::Int(i) });
         }
 
-        self.remove_declaration(&variable)?;
-        Ok(())
+        Ok(Flow::Next)
+    }
+
+    fn eval_while(&mut self, condition: Node, body: Vec<Node>) -> Result<Flow, RuntimeError> {
+        while self.eval_condition(condition.clone())? {
+            match self.eval_scoped(body.clone())? {
+                Flow::Next | Flow::Continue => (),
+                Flow::Break => break,
+                Flow::Return(value) => return Ok(Flow::Return(value)),
+            }
+        }
+
+        Ok(Flow::Next)
+    }
+
+    fn eval_loop(&mut self, body: Vec<Node>) -> Result<Flow, RuntimeError> {
+        loop {
+            match self.eval_scoped(body.clone())? {
+                Flow::Next | Flow::Continue => (),
+                Flow::Break => return Ok(Flow::Next),
+                Flow::Return(value) => return Ok(Flow::Return(value)),
+            }
+        }
     }
 }
 
-fn extract_index(index: Node) -> Result<usize, String> {
+fn extract_index(index: Node) -> Result<usize, RuntimeError> {
     match index {
-        Node::Atomic { value: Value::Int(i) } => Ok(i as usize),
+        Node::Atomic { value: Value::Int(i) } if i >= 0 => Ok(i as usize),
         _ => {
-            Err(format!("expected atomic but received {:?} while extracting index", index))
+            Err(RuntimeError::type_mismatch("non-negative index", type_of(&index)))
         }
     }
 }
@@ -0,0 +1,380 @@
+use std::collections::HashMap;
+use std::mem::Discriminant;
+use crate::node::OperatorKind;
+use crate::span::Span;
+use crate::value::Value;
+
+/// Constants are addressed with a one byte operand, or a three byte one by the `...Long` instructions.
+pub const MAX_CONSTANTS: usize = 1 << 24;
+/// Jumps and loops have a three byte operand, so they can cross this many bytes of code.
+pub const MAX_JUMP: usize = (1 << 24) - 1;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[repr(u8)]
+pub enum OpCode {
+    /// Pushes a constant from the constant pool onto the stack.
+    /// Operand: 1 byte, the index of the constant.
+    Constant,
+
+    /// Pops two values, adds them, pushes the result.
+    Add,
+    /// Pops two values, subtracts them, pushes the result.
+    Subtract,
+    /// Pops two values, multiplies them, pushes the result.
+    Multiply,
+    /// Pops two values, divides them, pushes the result.
+    Divide,
+    /// A temporary instruction to end execution.
+    Return,
+
+    /// Pops a value, negates it, and pushes the result.
+    Negate,
+
+    /// Defines a new global variable.
+    /// Operand: 1 byte (index of the variable name in the constant pool).
+    DefineGlobal,
+
+    /// Pushes the value of a global variable onto the stack.
+    /// Operand: 1 byte (index of the variable name).
+    GetGlobal,
+
+    /// Pops a value and assigns it to an existing global variable.
+    /// Operand: 1 byte (index of the variable name).
+    SetGlobal,
+
+    /// Pushes a local variable, read from its slot in the current call frame.
+    /// Operand: 1 byte (the slot, counted from the frame's base).
+    GetLocal,
+
+    /// Stores the value on top of the stack in a local's slot, leaving it on the stack.
+    /// Operand: 1 byte (the slot, counted from the frame's base).
+    SetLocal,
+
+    OpTrue,
+    OpFalse,
+    OpNot,
+    OpEqual,
+    OpGreater,
+    OpLess,
+    OpModulo,
+    /// Pops an exponent and a base, pushes the base raised to the exponent.
+    Power,
+
+    /// Pops a value and jumps forwards if it is falsey.
+    /// Operand: 3 bytes, the distance from the end of the instruction.
+    JumpIfFalse,
+    /// Operand: 3 bytes, the distance from the end of the instruction.
+    Jump,
+    /// Jumps backwards, to the start of a loop.
+    /// Operand: 3 bytes, the distance back from the end of the instruction.
+    Loop,
+    Pop,
+    Nil,
+    /// Calls the function sitting below its arguments on the stack.
+    /// Operand: 1 byte, the number of arguments.
+    Call,
+    /// Pops a value and prints it.
+    Print,
+
+    /// Wraps a function constant in a closure and pushes it.
+    /// Operands: 1 byte, the index of the function; then two bytes per upvalue: 1 if it
+    /// captures a local of the enclosing function (0 if one of its upvalues), and that slot or index.
+    Closure,
+    /// Pushes the value of one of the current closure's upvalues.
+    /// Operand: 1 byte, the upvalue's index.
+    GetUpvalue,
+    /// Stores the value on top of the stack in an upvalue, leaving it on the stack.
+    /// Operand: 1 byte, the upvalue's index.
+    SetUpvalue,
+    /// Closes every open upvalue pointing at or above a slot, before those locals are dropped.
+    /// Operand: 1 byte (the slot, counted from the frame's base).
+    CloseUpvalues,
+
+    /// Pops the elements of an array literal and pushes the new array.
+    /// Operand: 1 byte, the number of elements.
+    Array,
+    /// Pushes a new, empty map.
+    Map,
+    /// Pops an index and an array or string, pushes the element at that index.
+    GetIndex,
+    /// Pops a value, an index and an array, stores the value at the index and pushes it back.
+    SetIndex,
+    /// Pops an array, map or string and pushes its length.
+    Len,
+    /// Replaces the value on top of the stack with a copy of it, so an array or map that is
+    /// stored somewhere new doesn't share its contents with the original.
+    Copy,
+    /// Calls a method on the value sitting below its arguments, like `m.push(k, v)`.
+    /// Operands: 1 byte, the index of the method name; 1 byte, the number of arguments.
+    Invoke,
+    /// Calls a native function of the VM with the arguments on top of the stack.
+    /// Operands: 1 byte, the `Builtin`; 1 byte, the number of arguments.
+    Builtin,
+
+    /// Pop two bins (or ints), push the result of the bitwise operation.
+    BitwiseAnd,
+    BitwiseOr,
+    BitwiseXor,
+    ShiftLeft,
+    ShiftRight,
+
+    /// The forms of the instructions taking a constant index, for chunks with more than 256
+    /// constants: the index is a 3 byte operand instead of 1 byte, the other operands are the same.
+    ConstantLong,
+    DefineGlobalLong,
+    GetGlobalLong,
+    SetGlobalLong,
+    ClosureLong,
+    InvokeLong,
+}
+
+impl OpCode {
+    /// Decodes an instruction byte, `None` if no instruction has it.
+    pub fn from_byte(byte: u8) -> Option<OpCode> {
+        if byte <= OpCode::InvokeLong as u8 {
+            // the variants are numbered from 0 without gaps, up to the last one
+            Some(unsafe { std::mem::transmute::<u8, OpCode>(byte) })
+        } else {
+            None
+        }
+    }
+
+    /// The form of an instruction taking a constant index that can address every constant.
+    pub fn long(self) -> Option<OpCode> {
+        match self {
+            OpCode::Constant => Some(OpCode::ConstantLong),
+            OpCode::DefineGlobal => Some(OpCode::DefineGlobalLong),
+            OpCode::GetGlobal => Some(OpCode::GetGlobalLong),
+            OpCode::SetGlobal => Some(OpCode::SetGlobalLong),
+            OpCode::Closure => Some(OpCode::ClosureLong),
+            OpCode::Invoke => Some(OpCode::InvokeLong),
+            _ => None,
+        }
+    }
+
+    /// The operator an arithmetic, comparison or bitwise instruction computes, so its errors can
+    /// name it like the interpreter does. `Negate` and `OpNot` are the unary `-` and `~`.
+    pub fn operator(self) -> Option<OperatorKind> {
+        let op = match self {
+            OpCode::Add => OperatorKind::Add,
+            OpCode::Subtract | OpCode::Negate => OperatorKind::Subtract,
+            OpCode::Multiply => OperatorKind::Multiply,
+            OpCode::Divide => OperatorKind::Divide,
+            OpCode::OpModulo => OperatorKind::Modulo,
+            OpCode::OpEqual => OperatorKind::IsEqual,
+            OpCode::OpLess => OperatorKind::LessThan,
+            OpCode::OpGreater => OperatorKind::GreaterThan,
+            OpCode::OpNot => OperatorKind::Negation,
+            OpCode::BitwiseAnd => OperatorKind::BitwiseAnd,
+            OpCode::BitwiseOr => OperatorKind::BitwiseOr,
+            OpCode::BitwiseXor => OperatorKind::BitwiseXor,
+            OpCode::ShiftLeft => OperatorKind::ShiftLeft,
+            OpCode::ShiftRight => OperatorKind::ShiftRight,
+            _ => return None,
+        };
+        Some(op)
+    }
+
+    pub fn is_long(self) -> bool {
+        matches!(
+            self,
+            OpCode::ConstantLong
+                | OpCode::DefineGlobalLong
+                | OpCode::GetGlobalLong
+                | OpCode::SetGlobalLong
+                | OpCode::ClosureLong
+                | OpCode::InvokeLong
+        )
+    }
+}
+
+/// The native functions the VM runs itself, rather than calling Fox code.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum Builtin {
+    Bin,
+    Float,
+    Sqrt,
+    Int,
+    Map,
+    Filter,
+}
+
+impl Builtin {
+    const ALL: [Builtin; 6] = [Builtin::Bin, Builtin::Float, Builtin::Sqrt, Builtin::Int, Builtin::Map, Builtin::Filter];
+
+    /// The builtin a Fox call by this name refers to.
+    pub fn from_name(name: &str) -> Option<Builtin> {
+        Self::ALL.into_iter().find(|builtin| builtin.name() == name)
+    }
+
+    /// Decodes a `Builtin` operand.
+    pub fn from_byte(byte: u8) -> Option<Builtin> {
+        Self::ALL.get(byte as usize).copied()
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Builtin::Bin => "bin",
+            Builtin::Float => "float",
+            Builtin::Sqrt => "sqrt",
+            Builtin::Int => "int",
+            Builtin::Map => "map",
+            Builtin::Filter => "filter",
+        }
+    }
+
+    pub fn arity(self) -> usize {
+        match self {
+            Builtin::Map | Builtin::Filter => 2,
+            _ => 1,
+        }
+    }
+}
+
+/// The instruction computing a binary operator, for the operators the VM has one for.
+impl TryFrom<OperatorKind> for OpCode {
+    type Error = String;
+
+    fn try_from(op: OperatorKind) -> Result<Self, Self::Error> {
+        let op_code = match op {
+            OperatorKind::Add => OpCode::Add,
+            OperatorKind::Subtract => OpCode::Subtract,
+            OperatorKind::Multiply => OpCode::Multiply,
+            OperatorKind::Divide => OpCode::Divide,
+            OperatorKind::Modulo => OpCode::OpModulo,
+            OperatorKind::IsEqual => OpCode::OpEqual,
+            OperatorKind::LessThan => OpCode::OpLess,
+            OperatorKind::GreaterThan => OpCode::OpGreater,
+            OperatorKind::BitwiseAnd => OpCode::BitwiseAnd,
+            OperatorKind::BitwiseOr => OpCode::BitwiseOr,
+            OperatorKind::BitwiseXor => OpCode::BitwiseXor,
+            OperatorKind::ShiftLeft => OpCode::ShiftLeft,
+            OperatorKind::ShiftRight => OpCode::ShiftRight,
+            _ => return Err(format!("Unsupported operator: {:?}", op)),
+        };
+        Ok(op_code)
+    }
+}
+
+/// A stretch of code compiled from one span of the source, the node it came from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LineRun {
+    pub span: Span,
+    /// The number of code bytes in the run.
+    pub length: usize,
+}
+
+#[derive(Debug, Clone)]
+pub struct Chunk {
+    pub code: Vec<u8>,
+    pub constants: Vec<Value>,
+    /// Where in the source the code comes from, run-length encoded: the runs cover the code in order.
+    /// An unknown span stands for code that doesn't come from a known place.
+    pub lines: Vec<LineRun>,
+    // the span the bytes written next are compiled from
+    span: Span,
+    // where each constant already in the pool is, so adding it again reuses it. Keyed by the
+    // variant too, since `Int(1)` and `BigInt(1)` are equal as values but not as constants.
+    constant_indices: HashMap<(Discriminant<Value>, Value), usize>,
+}
+
+impl Default for Chunk {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Chunk {
+    pub fn new() -> Self {
+        Chunk {
+            code: Vec::new(),
+            constants: Vec::new(),
+            lines: Vec::new(),
+            span: Span::default(),
+            constant_indices: HashMap::new(),
+        }
+    }
+    
+    pub fn write(&mut self, byte: u8) {
+        self.code.push(byte);
+        match self.lines.last_mut() {
+            Some(run) if run.span == self.span => run.length += 1,
+            _ => self.lines.push(LineRun { span: self.span, length: 1 }),
+        }
+    }
+
+    /// Sets where in the source the code written from now on comes from.
+    pub fn set_span(&mut self, span: Span) {
+        self.span = span;
+    }
+
+    pub fn current_span(&self) -> Span {
+        self.span
+    }
+
+    /// The span of the code at `offset`, if it is known.
+    pub fn span_at(&self, offset: usize) -> Option<Span> {
+        let mut end = 0;
+        for run in &self.lines {
+            end += run.length;
+            if offset < end {
+                return Some(run.span).filter(|span| !span.is_unknown());
+            }
+        }
+        None
+    }
+
+    /// The source line of the code at `offset`, if it is known.
+    pub fn line_at(&self, offset: usize) -> Option<usize> {
+        self.span_at(offset).map(|span| span.line)
+    }
+
+    /// Adds a constant to the pool and returns its index. A constant that is already in the pool,
+    /// like the name of a variable used again, keeps its index.
+    pub fn add_constant(&mut self, value: Value) -> usize {
+        let key = (std::mem::discriminant(&value), value);
+        if let Some(index) = self.constant_indices.get(&key) {
+            return *index;
+        }
+        self.constants.push(key.1.clone());
+        self.constant_indices.insert(key, self.constants.len() - 1);
+        self.constants.len() - 1
+    }
+
+    /// Writes an instruction taking a constant index, switching to its long form when the index
+    /// doesn't fit in a byte.
+    pub fn write_constant_op(&mut self, op: OpCode, index: usize) {
+        match u8::try_from(index) {
+            Ok(index) => {
+                self.write(op as u8);
+                self.write(index);
+            }
+            Err(_) => {
+                let long = op.long().expect("the instruction doesn't take a constant");
+                self.write(long as u8);
+                self.write_u24(index);
+            }
+        }
+    }
+
+    /// Writes a three byte operand, most significant byte first.
+    pub fn write_u24(&mut self, value: usize) {
+        self.write((value >> 16) as u8);
+        self.write((value >> 8) as u8);
+        self.write(value as u8);
+    }
+
+    pub fn read_u24(&self, offset: usize) -> usize {
+        ((self.code[offset] as usize) << 16) | ((self.code[offset + 1] as usize) << 8) | self.code[offset + 2] as usize
+    }
+
+    /// The constant index of the `opcode` instruction at `offset`, and how many bytes it takes.
+    pub fn read_constant_index(&self, opcode: OpCode, offset: usize) -> (usize, usize) {
+        if opcode.is_long() {
+            (self.read_u24(offset + 1), 3)
+        } else {
+            (self.code[offset + 1] as usize, 1)
+        }
+    }
+}
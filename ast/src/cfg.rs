@@ -0,0 +1,186 @@
+//! The control flow graph of a `FirFunction`, and the dominator tree over it.
+//!
+//! Blocks are referred to by their index in `FirFunction::blocks`, the entry block is 0.
+
+use std::collections::{HashMap, HashSet};
+use crate::fir::FirFunction;
+
+pub struct Cfg {
+    /// The blocks each block can jump to, without duplicates.
+    pub succs: Vec<Vec<usize>>,
+    /// The blocks that can jump to each block.
+    pub preds: Vec<Vec<usize>>,
+}
+
+impl Cfg {
+    pub fn new(func: &FirFunction) -> Self {
+        let index: HashMap<&str, usize> = func.blocks.iter().enumerate().map(|(i, block)| (block.label.as_str(), i)).collect();
+        let mut succs = vec![vec![]; func.blocks.len()];
+        let mut preds = vec![vec![]; func.blocks.len()];
+        for (b, block) in func.blocks.iter().enumerate() {
+            for label in block.successors() {
+                let Some(&succ) = index.get(label) else { continue };
+                if !succs[b].contains(&succ) {
+                    succs[b].push(succ);
+                    preds[succ].push(b);
+                }
+            }
+        }
+        Self { succs, preds }
+    }
+
+    /// The blocks reachable from the entry, each after the blocks leading to it except along back edges.
+    pub fn reverse_postorder(&self) -> Vec<usize> {
+        if self.succs.is_empty() {
+            return vec![];
+        }
+        let mut visited = vec![false; self.succs.len()];
+        let mut postorder = vec![];
+        // each entry is a block and how many of its successors have been visited
+        let mut stack = vec![(0, 0)];
+        visited[0] = true;
+        while let Some((b, next)) = stack.last_mut() {
+            let b = *b;
+            match self.succs[b].get(*next) {
+                Some(&succ) => {
+                    *next += 1;
+                    if !visited[succ] {
+                        visited[succ] = true;
+                        stack.push((succ, 0));
+                    }
+                }
+                None => {
+                    postorder.push(b);
+                    stack.pop();
+                }
+            }
+        }
+        postorder.reverse();
+        postorder
+    }
+}
+
+/// Removes the blocks control can't reach from the entry, returning how many there were.
+pub fn remove_unreachable_blocks(func: &mut FirFunction) -> usize {
+    let reachable: HashSet<usize> = Cfg::new(func).reverse_postorder().into_iter().collect();
+    let before = func.blocks.len();
+    let mut b = 0;
+    func.blocks.retain(|_| {
+        b += 1;
+        reachable.contains(&(b - 1))
+    });
+    before - func.blocks.len()
+}
+
+/// Block `a` dominates block `b` when every path from the entry to `b` goes through `a`.
+/// Computed with the iterative algorithm of Cooper, Harvey and Kennedy.
+pub struct Dominators {
+    // the immediate dominator of each block, the entry's is itself and unreachable blocks have none
+    idom: Vec<Option<usize>>,
+    children: Vec<Vec<usize>>,
+    frontiers: Vec<Vec<usize>>,
+}
+
+impl Dominators {
+    pub fn new(cfg: &Cfg) -> Self {
+        let blocks = cfg.succs.len();
+        let rpo = cfg.reverse_postorder();
+        let mut order = vec![usize::MAX; blocks];
+        for (i, &b) in rpo.iter().enumerate() {
+            order[b] = i;
+        }
+
+        let mut idom = vec![None; blocks];
+        if blocks > 0 {
+            idom[0] = Some(0);
+        }
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for &b in rpo.iter().skip(1) {
+                let mut new_idom = None;
+                for &pred in &cfg.preds[b] {
+                    if idom[pred].is_none() {
+                        continue;
+                    }
+                    new_idom = Some(match new_idom {
+                        None => pred,
+                        Some(other) => intersect(&idom, &order, pred, other),
+                    });
+                }
+                if new_idom.is_some() && idom[b] != new_idom {
+                    idom[b] = new_idom;
+                    changed = true;
+                }
+            }
+        }
+
+        let mut children = vec![vec![]; blocks];
+        for b in 1..blocks {
+            if let Some(parent) = idom[b] {
+                children[parent].push(b);
+            }
+        }
+
+        // a join point is in the frontier of every block dominating one of its predecessors but not itself
+        let mut frontiers: Vec<Vec<usize>> = vec![vec![]; blocks];
+        for &b in &rpo {
+            if cfg.preds[b].len() < 2 {
+                continue;
+            }
+            // nothing strictly dominates the entry, so for it the walk goes all the way up
+            let stop = if b == 0 { None } else { idom[b] };
+            for &pred in &cfg.preds[b] {
+                let mut runner = idom[pred].map(|_| pred);
+                while let Some(r) = runner.filter(|r| Some(*r) != stop) {
+                    if !frontiers[r].contains(&b) {
+                        frontiers[r].push(b);
+                    }
+                    runner = if r == 0 { None } else { idom[r] };
+                }
+            }
+        }
+        for frontier in &mut frontiers {
+            frontier.sort();
+        }
+        Self { idom, children, frontiers }
+    }
+
+    /// The closest block dominating `b` other than itself, None for the entry and unreachable blocks.
+    pub fn idom(&self, b: usize) -> Option<usize> {
+        self.idom[b].filter(|_| b != 0)
+    }
+
+    pub fn dominates(&self, a: usize, b: usize) -> bool {
+        let mut b = Some(b);
+        while let Some(current) = b {
+            if current == a {
+                return true;
+            }
+            b = self.idom(current);
+        }
+        false
+    }
+
+    /// The blocks `b` immediately dominates, its children in the dominator tree.
+    pub fn children(&self, b: usize) -> &[usize] {
+        &self.children[b]
+    }
+
+    /// The blocks where the dominance of `b` ends: those it doesn't strictly dominate, but dominates a predecessor of.
+    pub fn frontier(&self, b: usize) -> &[usize] {
+        &self.frontiers[b]
+    }
+}
+
+fn intersect(idom: &[Option<usize>], order: &[usize], mut a: usize, mut b: usize) -> usize {
+    while a != b {
+        while order[a] > order[b] {
+            a = idom[a].unwrap();
+        }
+        while order[b] > order[a] {
+            b = idom[b].unwrap();
+        }
+    }
+    a
+}
@@ -0,0 +1,145 @@
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use crate::node::Node;
+
+// a binding is shared between the frame that declared it and every closure that
+// captured it, so an assignment through either is seen by both
+type Cell = Arc<Mutex<Node>>;
+type Frame = HashMap<String, Cell>;
+
+/// A stack of local frames sitting on top of the global declarations.
+/// Blocks, loop bodies and function bodies each push their own frame, so a
+/// `let` inside them shadows outer bindings instead of overwriting them.
+#[derive(Debug, Default)]
+pub struct Environment {
+    frames: Vec<Frame>,
+}
+
+impl Environment {
+    pub fn new() -> Self {
+        Self { frames: Vec::new() }
+    }
+
+    pub fn push(&mut self) {
+        self.frames.push(Frame::new());
+    }
+
+    pub fn pop(&mut self) {
+        self.frames.pop();
+    }
+
+    /// Number of local frames; zero means we are evaluating at the top level.
+    pub fn depth(&self) -> usize {
+        self.frames.len()
+    }
+
+    /// Swaps in the chain a function captured, plus a fresh frame for the call, so the
+    /// callee cannot see the caller's locals. The returned chain must be handed back to `leave_call`.
+    pub fn enter_call(&mut self, captured: &Captured) -> Vec<Frame> {
+        let caller = std::mem::replace(&mut self.frames, captured.frames.as_ref().clone());
+        self.push();
+        caller
+    }
+
+    pub fn leave_call(&mut self, caller: Vec<Frame>) {
+        self.frames = caller;
+    }
+
+    /// The bindings visible from here, for a function created in this scope to close over.
+    pub fn capture(&self) -> Captured {
+        Captured { frames: Arc::new(self.frames.clone()), own_name: None }
+    }
+
+    /// Like `capture`, for a function that calls itself by `name`. Its own binding is left out,
+    /// since holding it would make the closure own itself and never be freed; the caller binds
+    /// the name again for each call instead.
+    pub fn capture_recursive(&self, name: &str) -> Captured {
+        let frames = self
+            .frames
+            .iter()
+            .map(|frame| frame.iter().filter(|(n, _)| *n != name).map(|(n, cell)| (n.clone(), cell.clone())).collect())
+            .collect();
+        Captured { frames: Arc::new(frames), own_name: Some(name.to_string()) }
+    }
+
+    /// Looks a name up from the innermost frame outwards.
+    pub fn get(&self, name: &str) -> Option<Node> {
+        self.cell(name).map(|cell| cell.lock().expect("poisoned binding").clone())
+    }
+
+    /// Updates the nearest binding of a name, handing the value back when there is none.
+    pub fn set(&mut self, name: &str, value: Node) -> Result<(), Node> {
+        match self.cell(name) {
+            Some(cell) => {
+                *cell.lock().expect("poisoned binding") = value;
+                Ok(())
+            }
+            None => Err(value),
+        }
+    }
+
+    /// Binds a name in the innermost frame, shadowing any outer binding. Declaring a
+    /// name twice in the same frame updates the existing binding.
+    pub fn declare(&mut self, name: String, value: Node) {
+        if let Some(frame) = self.frames.last_mut() {
+            match frame.get(&name) {
+                Some(cell) => *cell.lock().expect("poisoned binding") = value,
+                None => {
+                    frame.insert(name, Arc::new(Mutex::new(value)));
+                }
+            }
+        }
+    }
+
+    fn cell(&self, name: &str) -> Option<&Cell> {
+        self.frames.iter().rev().find_map(|frame| frame.get(name))
+    }
+}
+
+/// The frames a closure captured. Closures compare by identity, like function values in the VM.
+#[derive(Clone, Default)]
+pub struct Captured {
+    frames: Arc<Vec<Frame>>,
+    own_name: Option<String>,
+}
+
+impl Captured {
+    /// The name a recursive closure calls itself by, which its frames don't hold.
+    pub fn own_name(&self) -> Option<&str> {
+        self.own_name.as_deref()
+    }
+
+    /// Whether any of the captured frames binds `name`.
+    pub fn binds(&self, name: &str) -> bool {
+        self.frames.iter().any(|frame| frame.contains_key(name))
+    }
+}
+
+impl fmt::Debug for Captured {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let names: Vec<&String> = self.frames.iter().flat_map(|frame| frame.keys()).collect();
+        write!(f, "Captured({:?})", names)
+    }
+}
+
+impl PartialEq for Captured {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.frames, &other.frames)
+    }
+}
+
+impl Eq for Captured {}
+
+impl PartialOrd for Captured {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Captured {
+    fn cmp(&self, other: &Self) -> Ordering {
+        Arc::as_ptr(&self.frames).cmp(&Arc::as_ptr(&other.frames))
+    }
+}
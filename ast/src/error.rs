@@ -0,0 +1,95 @@
+use std::fmt;
+use crate::span::Span;
+
+/// The ways evaluating a program can fail.
+#[derive(Debug, Clone, PartialEq)]
+pub enum RuntimeErrorKind {
+    UndefinedVariable(String),
+    TypeMismatch { expected: String, found: String },
+    IndexOutOfBounds { index: usize, len: usize },
+    MissingKey(String),
+    DivisionByZero,
+    /// A value didn't fit in a fixed-width type, like a shift past the width of a bin.
+    Overflow(String),
+    ArityMismatch { name: String, expected: usize, found: usize },
+    StackOverflow { limit: usize },
+    /// No stack could be set aside for evaluating with a `max_call_depth` of `depth`.
+    StackUnavailable { depth: usize },
+    UnknownMethod(String),
+    UnsupportedOperator(String),
+    BreakOutsideLoop,
+    ContinueOutsideLoop,
+}
+
+/// An error raised while evaluating, along with where in the source it happened when that is known.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RuntimeError {
+    pub kind: RuntimeErrorKind,
+    pub span: Option<Span>,
+}
+
+impl RuntimeError {
+    pub fn new(kind: RuntimeErrorKind) -> Self {
+        Self { kind, span: None }
+    }
+
+    pub fn type_mismatch(expected: impl Into<String>, found: impl Into<String>) -> Self {
+        Self::new(RuntimeErrorKind::TypeMismatch {
+            expected: expected.into(),
+            found: found.into(),
+        })
+    }
+
+    /// Attaches a span unless a more precise one was already recorded further down.
+    pub fn with_span(mut self, span: Span) -> Self {
+        self.span.get_or_insert(span);
+        self
+    }
+}
+
+impl From<RuntimeErrorKind> for RuntimeError {
+    fn from(kind: RuntimeErrorKind) -> Self {
+        Self::new(kind)
+    }
+}
+
+impl fmt::Display for RuntimeErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RuntimeErrorKind::UndefinedVariable(name) => write!(f, "undefined variable `{}`", name),
+            RuntimeErrorKind::TypeMismatch { expected, found } => {
+                write!(f, "type mismatch: expected {}, found {}", expected, found)
+            }
+            RuntimeErrorKind::IndexOutOfBounds { index, len } => {
+                write!(f, "index {} out of bounds for length {}", index, len)
+            }
+            RuntimeErrorKind::MissingKey(key) => write!(f, "missing key {}", key),
+            RuntimeErrorKind::DivisionByZero => write!(f, "division by zero"),
+            RuntimeErrorKind::Overflow(what) => write!(f, "integer overflow: {}", what),
+            RuntimeErrorKind::ArityMismatch { name, expected, found } => {
+                write!(f, "`{}` expects {} arguments but got {}", name, expected, found)
+            }
+            RuntimeErrorKind::StackOverflow { limit } => {
+                write!(f, "stack overflow: exceeded the maximum call depth of {}", limit)
+            }
+            RuntimeErrorKind::StackUnavailable { depth } => {
+                write!(f, "could not set aside a stack for a call depth of {}", depth)
+            }
+            RuntimeErrorKind::UnknownMethod(name) => write!(f, "unknown method `{}`", name),
+            RuntimeErrorKind::UnsupportedOperator(op) => write!(f, "unsupported operator {}", op),
+            RuntimeErrorKind::BreakOutsideLoop => write!(f, "`break` outside of a loop"),
+            RuntimeErrorKind::ContinueOutsideLoop => write!(f, "`continue` outside of a loop"),
+        }
+    }
+}
+
+impl fmt::Display for RuntimeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.span {
+            Some(span) => write!(f, "{} at {}:{}", self.kind, span.line, span.column),
+            None => write!(f, "{}", self.kind),
+        }
+    }
+}
+
+impl std::error::Error for RuntimeError {}
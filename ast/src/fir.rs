@@ -12,22 +12,28 @@ pub enum FirValue {
 
 #[derive(Debug, Clone, PartialEq)]
 pub enum FirInstr {
-    /// Arithmetic and logic
-    Add(FirValue, FirValue),
-    Sub(FirValue, FirValue),
-    Mul(FirValue, FirValue),
-    Div(FirValue, FirValue),
-    Mod(FirValue, FirValue),
-    Eq(FirValue, FirValue),
-    Lt(FirValue, FirValue),
-    Gt(FirValue, FirValue),
+    /// Arithmetic and logic, `dst = add lhs, rhs` puts the result in the local `dst`
+    Add(u32, FirValue, FirValue),
+    Sub(u32, FirValue, FirValue),
+    Mul(u32, FirValue, FirValue),
+    Div(u32, FirValue, FirValue),
+    Mod(u32, FirValue, FirValue),
+    Eq(u32, FirValue, FirValue),
+    Lt(u32, FirValue, FirValue),
+    Gt(u32, FirValue, FirValue),
 
     /// Variable access
-    LoadLocal(String),
+    LoadLocal(u32, String),
     StoreLocal(String, FirValue),
+    /// `dst = copy value`
+    Copy(u32, FirValue),
+    /// `dst = phi [label: value, ...]`, the value coming from the block control arrived from.
+    /// Only found in SSA form, at the start of a block.
+    Phi(u32, Vec<(String, FirValue)>),
 
     /// Function calls
     Call {
+        dst: u32,
         func: String,
         args: Vec<FirValue>,
     },
@@ -35,28 +41,114 @@ pub enum FirInstr {
     /// Return statement
     Return(Option<FirValue>),
 
-    /// Control flow (basic for now)
+    /// Control flow, these end a block
     Jump(String),
     JumpIf {
         cond: FirValue,
         then_label: String,
-        else_label: Option<String>,
+        else_label: String,
     },
 
     /// no-op
     Nop,
 }
 
-#[derive(Debug, Clone)]
+impl FirInstr {
+    /// The local the instruction writes its result to, if it has one.
+    pub fn dst(&self) -> Option<u32> {
+        match self {
+            FirInstr::Add(dst, ..)
+            | FirInstr::Sub(dst, ..)
+            | FirInstr::Mul(dst, ..)
+            | FirInstr::Div(dst, ..)
+            | FirInstr::Mod(dst, ..)
+            | FirInstr::Eq(dst, ..)
+            | FirInstr::Lt(dst, ..)
+            | FirInstr::Gt(dst, ..)
+            | FirInstr::LoadLocal(dst, _)
+            | FirInstr::Copy(dst, _)
+            | FirInstr::Phi(dst, _)
+            | FirInstr::Call { dst, .. } => Some(*dst),
+            _ => None,
+        }
+    }
+
+    /// The values the instruction reads.
+    pub fn operands(&self) -> Vec<&FirValue> {
+        match self {
+            FirInstr::Add(_, lhs, rhs)
+            | FirInstr::Sub(_, lhs, rhs)
+            | FirInstr::Mul(_, lhs, rhs)
+            | FirInstr::Div(_, lhs, rhs)
+            | FirInstr::Mod(_, lhs, rhs)
+            | FirInstr::Eq(_, lhs, rhs)
+            | FirInstr::Lt(_, lhs, rhs)
+            | FirInstr::Gt(_, lhs, rhs) => vec![lhs, rhs],
+            FirInstr::StoreLocal(_, value) | FirInstr::Copy(_, value) | FirInstr::Return(Some(value)) | FirInstr::JumpIf { cond: value, .. } => vec![value],
+            FirInstr::Phi(_, incoming) => incoming.iter().map(|(_, value)| value).collect(),
+            FirInstr::Call { args, .. } => args.iter().collect(),
+            FirInstr::LoadLocal(..) | FirInstr::Return(None) | FirInstr::Jump(_) | FirInstr::Nop => vec![],
+        }
+    }
+
+    pub fn operands_mut(&mut self) -> Vec<&mut FirValue> {
+        match self {
+            FirInstr::Add(_, lhs, rhs)
+            | FirInstr::Sub(_, lhs, rhs)
+            | FirInstr::Mul(_, lhs, rhs)
+            | FirInstr::Div(_, lhs, rhs)
+            | FirInstr::Mod(_, lhs, rhs)
+            | FirInstr::Eq(_, lhs, rhs)
+            | FirInstr::Lt(_, lhs, rhs)
+            | FirInstr::Gt(_, lhs, rhs) => vec![lhs, rhs],
+            FirInstr::StoreLocal(_, value) | FirInstr::Copy(_, value) | FirInstr::Return(Some(value)) | FirInstr::JumpIf { cond: value, .. } => vec![value],
+            FirInstr::Phi(_, incoming) => incoming.iter_mut().map(|(_, value)| value).collect(),
+            FirInstr::Call { args, .. } => args.iter_mut().collect(),
+            FirInstr::LoadLocal(..) | FirInstr::Return(None) | FirInstr::Jump(_) | FirInstr::Nop => vec![],
+        }
+    }
+
+    /// Whether the instruction ends a block: every block ends in exactly one of these.
+    pub fn is_terminator(&self) -> bool {
+        matches!(self, FirInstr::Return(_) | FirInstr::Jump(_) | FirInstr::JumpIf { .. })
+    }
+
+    /// The labels of the blocks control can continue in after the instruction, if it is a terminator.
+    pub fn successors(&self) -> Vec<&str> {
+        match self {
+            FirInstr::Jump(label) => vec![label],
+            FirInstr::JumpIf { then_label, else_label, .. } => vec![then_label, else_label],
+            _ => vec![],
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
 pub struct FirBlock {
     pub label: String,
     pub instrs: Vec<FirInstr>,
 }
 
-#[derive(Debug, Clone)]
+impl FirBlock {
+    pub fn new(label: impl Into<String>) -> Self {
+        Self { label: label.into(), instrs: vec![] }
+    }
+
+    pub fn terminator(&self) -> Option<&FirInstr> {
+        self.instrs.last().filter(|instr| instr.is_terminator())
+    }
+
+    pub fn successors(&self) -> Vec<&str> {
+        self.terminator().map(FirInstr::successors).unwrap_or_default()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
 pub struct FirFunction {
     pub name: String,
     pub params: Vec<String>,
+    /// The name of every local, indexed by the number `FirValue::Local` refers to it with.
+    /// The parameters come first, temporaries are named `tmp.N` after their number.
     pub locals: Vec<String>,
     pub blocks: Vec<FirBlock>,
 }
@@ -67,21 +159,38 @@ impl FirFunction {
             name: name.into(),
             params,
             locals: vec![],
-            blocks: vec![FirBlock {
-                label: "entry".into(),
-                instrs: vec![],
-            }],
+            blocks: vec![FirBlock::new("entry")],
         }
     }
 
-    pub fn emit(&mut self, instr: FirInstr) {
-        if let Some(block) = self.blocks.last_mut() {
-            block.instrs.push(instr);
-        }
+    /// Adds a local, returning its number.
+    pub fn add_local(&mut self, name: impl Into<String>) -> u32 {
+        self.locals.push(name.into());
+        (self.locals.len() - 1) as u32
+    }
+
+    pub fn add_temp(&mut self) -> u32 {
+        let id = self.locals.len();
+        self.add_local(format!("tmp.{}", id))
+    }
+
+    /// The number of the local called `name`, which `StoreLocal` writes to.
+    pub fn local(&self, name: &str) -> Option<u32> {
+        self.locals.iter().position(|local| local == name).map(|id| id as u32)
+    }
+
+    /// Appends an empty block, returning its index.
+    pub fn add_block(&mut self, label: impl Into<String>) -> usize {
+        self.blocks.push(FirBlock::new(label));
+        self.blocks.len() - 1
+    }
+
+    pub fn block(&self, label: &str) -> Option<&FirBlock> {
+        self.blocks.iter().find(|block| block.label == label)
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct FirModule {
     pub functions: Vec<FirFunction>,
 }
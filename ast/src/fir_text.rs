@@ -0,0 +1,568 @@
+//! The textual form of FIR, for reading passes' output and writing tests. `FirModule` and
+//! `FirFunction` print in it with `Display` and are read back with `str::parse`, which gives the
+//! same FIR again.
+//!
+//! ```text
+//! fn max(a, b) {
+//!   locals %a, %b, %tmp.2
+//! entry:
+//!   %tmp.2 = gt %a, %b
+//!   br %tmp.2, then1, join2
+//! then1:
+//!   ret %a
+//! join2:
+//!   ret %b
+//! }
+//! ```
+//!
+//! Locals are written `%name`, and listed in order on the `locals` line, so their numbers follow
+//! from their position. The instructions are:
+//!
+//! - `%d = add a, b`, and likewise `sub`, `mul`, `div`, `mod`, `eq`, `lt` and `gt`
+//! - `%d = load %x` and `store %x, v`
+//! - `%d = copy v`
+//! - `%d = phi [label: v, label: v]`
+//! - `%d = call name(v, v)`
+//! - `ret v`, or `ret` without a value
+//! - `jmp label` and `br cond, then_label, else_label`
+//! - `nop`
+//!
+//! Constants are written like in Fox: `1`, `-2`, `1.5`, `true` and `"text"`. Floats always
+//! have a `.` or an exponent, or are `inf`, `-inf` or `NaN`.
+//!
+//! Functions in a module are separated by a blank line. Hand-written FIR can have comments,
+//! which run from a `;` to the end of the line, and may leave out the `locals` line when
+//! there are none.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::str::FromStr;
+use crate::fir::{FirBlock, FirFunction, FirInstr, FirModule, FirValue};
+
+// a value, with locals written by name
+struct Value<'a>(&'a FirFunction, &'a FirValue);
+
+impl fmt::Display for Value<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.1 {
+            FirValue::Local(id) => write!(f, "{}", Local(self.0, *id)),
+            FirValue::ConstInt(n) => write!(f, "{}", n),
+            FirValue::ConstFloat(x) => write!(f, "{:?}", x),
+            FirValue::ConstBool(b) => write!(f, "{}", b),
+            FirValue::ConstString(s) => write!(f, "{:?}", s),
+        }
+    }
+}
+
+struct Local<'a>(&'a FirFunction, u32);
+
+impl fmt::Display for Local<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.0.locals.get(self.1 as usize) {
+            Some(name) => write!(f, "%{}", name),
+            // a local missing from the list, which can't be read back
+            None => write!(f, "%{}", self.1),
+        }
+    }
+}
+
+struct Instr<'a>(&'a FirFunction, &'a FirInstr);
+
+impl fmt::Display for Instr<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let func = self.0;
+        let value = |value| Value(func, value);
+        let binary = |f: &mut fmt::Formatter<'_>, op: &str, dst: &u32, lhs, rhs| {
+            write!(f, "{} = {} {}, {}", Local(func, *dst), op, value(lhs), value(rhs))
+        };
+        match self.1 {
+            FirInstr::Add(dst, lhs, rhs) => binary(f, "add", dst, lhs, rhs),
+            FirInstr::Sub(dst, lhs, rhs) => binary(f, "sub", dst, lhs, rhs),
+            FirInstr::Mul(dst, lhs, rhs) => binary(f, "mul", dst, lhs, rhs),
+            FirInstr::Div(dst, lhs, rhs) => binary(f, "div", dst, lhs, rhs),
+            FirInstr::Mod(dst, lhs, rhs) => binary(f, "mod", dst, lhs, rhs),
+            FirInstr::Eq(dst, lhs, rhs) => binary(f, "eq", dst, lhs, rhs),
+            FirInstr::Lt(dst, lhs, rhs) => binary(f, "lt", dst, lhs, rhs),
+            FirInstr::Gt(dst, lhs, rhs) => binary(f, "gt", dst, lhs, rhs),
+            FirInstr::LoadLocal(dst, name) => write!(f, "{} = load %{}", Local(func, *dst), name),
+            FirInstr::StoreLocal(name, v) => write!(f, "store %{}, {}", name, value(v)),
+            FirInstr::Copy(dst, v) => write!(f, "{} = copy {}", Local(func, *dst), value(v)),
+            FirInstr::Phi(dst, incoming) => {
+                let incoming: Vec<String> = incoming.iter().map(|(label, v)| format!("{}: {}", label, value(v))).collect();
+                write!(f, "{} = phi [{}]", Local(func, *dst), incoming.join(", "))
+            }
+            FirInstr::Call { dst, func: name, args } => {
+                let args: Vec<String> = args.iter().map(|arg| value(arg).to_string()).collect();
+                write!(f, "{} = call {}({})", Local(func, *dst), name, args.join(", "))
+            }
+            FirInstr::Return(Some(v)) => write!(f, "ret {}", value(v)),
+            FirInstr::Return(None) => write!(f, "ret"),
+            FirInstr::Jump(label) => write!(f, "jmp {}", label),
+            FirInstr::JumpIf { cond, then_label, else_label } => write!(f, "br {}, {}, {}", value(cond), then_label, else_label),
+            FirInstr::Nop => write!(f, "nop"),
+        }
+    }
+}
+
+impl fmt::Display for FirFunction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "fn {}({}) {{", self.name, self.params.join(", "))?;
+        if !self.locals.is_empty() {
+            let locals: Vec<String> = self.locals.iter().map(|local| format!("%{}", local)).collect();
+            writeln!(f, "  locals {}", locals.join(", "))?;
+        }
+        for block in &self.blocks {
+            writeln!(f, "{}:", block.label)?;
+            for instr in &block.instrs {
+                writeln!(f, "  {}", Instr(self, instr))?;
+            }
+        }
+        write!(f, "}}")
+    }
+}
+
+impl fmt::Display for FirModule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, func) in self.functions.iter().enumerate() {
+            if i > 0 {
+                writeln!(f)?;
+            }
+            writeln!(f, "{}", func)?;
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for FirModule {
+    type Err = String;
+
+    /// Reads a module in the textual form, failing with the line of the first error.
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        let mut module = FirModule::new();
+        let mut lines = text.lines().enumerate().map(|(i, line)| (i + 1, strip_comment(line).trim())).filter(|(_, line)| !line.is_empty());
+        while let Some((number, line)) = lines.next() {
+            let func = parse_function(number, line, &mut lines).map_err(|(number, message)| format!("line {}: {}", number, message))?;
+            module.add_function(func);
+        }
+        Ok(module)
+    }
+}
+
+impl FromStr for FirFunction {
+    type Err = String;
+
+    /// Reads a module holding exactly one function.
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        let mut module: FirModule = text.parse()?;
+        match module.functions.len() {
+            1 => Ok(module.functions.remove(0)),
+            count => Err(format!("expected one function, found {}", count)),
+        }
+    }
+}
+
+// an error and the line it is on
+type LineError = (usize, String);
+
+fn strip_comment(line: &str) -> &str {
+    let mut in_string = false;
+    let mut escaped = false;
+    for (i, c) in line.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' if in_string => escaped = true,
+            '"' => in_string = !in_string,
+            ';' if !in_string => return &line[..i],
+            _ => {}
+        }
+    }
+    line
+}
+
+fn parse_function<'a>(number: usize, header: &str, lines: &mut impl Iterator<Item = (usize, &'a str)>) -> Result<FirFunction, LineError> {
+    let at = |message: String| (number, message);
+    let mut tokens = Tokens::new(header).map_err(at)?;
+    tokens.keyword("fn").map_err(at)?;
+    let name = tokens.word("a function name").map_err(at)?;
+    tokens.punct('(').map_err(at)?;
+    let mut params = vec![];
+    while !tokens.eat(')') {
+        if !params.is_empty() {
+            tokens.punct(',').map_err(at)?;
+        }
+        params.push(tokens.word("a parameter").map_err(at)?);
+    }
+    tokens.punct('{').map_err(at)?;
+    tokens.end().map_err(at)?;
+
+    let mut func = FirFunction { name, params, locals: vec![], blocks: vec![] };
+    let mut locals: HashMap<String, u32> = HashMap::new();
+    let mut jumps = vec![];
+    let mut end = number;
+    for (number, line) in lines.by_ref() {
+        end = number;
+        let at = |message: String| (number, message);
+        if line == "}" {
+            return check_jumps(number, func, jumps);
+        }
+        let mut tokens = Tokens::new(line).map_err(at)?;
+        if tokens.is_keyword("locals") {
+            tokens.next();
+            loop {
+                let local = tokens.local_name().map_err(at)?;
+                if locals.insert(local.clone(), func.locals.len() as u32).is_some() {
+                    return Err(at(format!("the local %{} is declared twice", local)));
+                }
+                func.locals.push(local);
+                if !tokens.eat(',') {
+                    break;
+                }
+            }
+        } else if let [Token::Word(label), Token::Punct(':')] = tokens.tokens.as_slice() {
+            if func.block(label).is_some() {
+                return Err(at(format!("the block {} is defined twice", label)));
+            }
+            func.blocks.push(FirBlock::new(label.clone()));
+            continue;
+        } else {
+            let instr = Instruction { tokens: &mut tokens, locals: &locals }.parse().map_err(at)?;
+            let Some(block) = func.blocks.last_mut() else {
+                return Err(at("an instruction before the first block label".to_string()));
+            };
+            for label in instr.successors() {
+                jumps.push((number, label.to_string()));
+            }
+            block.instrs.push(instr);
+        }
+        tokens.end().map_err(at)?;
+    }
+    Err((end, format!("`fn {}` is missing its closing `}}`", func.name)))
+}
+
+// checks the jumps go to blocks of the function, once it has ended on line `end`
+fn check_jumps(end: usize, func: FirFunction, jumps: Vec<LineError>) -> Result<FirFunction, LineError> {
+    if func.blocks.is_empty() {
+        return Err((end, format!("`{}` has no blocks", func.name)));
+    }
+    for (number, label) in jumps {
+        if func.block(&label).is_none() {
+            return Err((number, format!("a jump to the missing block {}", label)));
+        }
+    }
+    Ok(func)
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    // a local, without its `%`
+    Local(String),
+    // names, labels, keywords and numbers
+    Word(String),
+    Str(String),
+    Punct(char),
+}
+
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Token::Local(name) => write!(f, "%{}", name),
+            Token::Word(word) => write!(f, "{}", word),
+            Token::Str(s) => write!(f, "{:?}", s),
+            Token::Punct(c) => write!(f, "{}", c),
+        }
+    }
+}
+
+struct Tokens {
+    tokens: Vec<Token>,
+    position: usize,
+}
+
+fn is_word_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_' || c == '.'
+}
+
+impl Tokens {
+    fn new(line: &str) -> Result<Self, String> {
+        let mut tokens = vec![];
+        let mut chars = line.chars().peekable();
+        while let Some(&c) = chars.peek() {
+            if c.is_whitespace() {
+                chars.next();
+            } else if "=,[]:(){}".contains(c) {
+                tokens.push(Token::Punct(c));
+                chars.next();
+            } else if c == '%' {
+                chars.next();
+                let mut name = String::new();
+                while let Some(c) = chars.next_if(|c| is_word_char(*c)) {
+                    name.push(c);
+                }
+                if name.is_empty() {
+                    return Err("expected a local name after `%`".to_string());
+                }
+                tokens.push(Token::Local(name));
+            } else if c == '"' {
+                chars.next();
+                tokens.push(Token::Str(string(&mut chars)?));
+            } else if c == '-' || is_word_char(c) {
+                let mut word = String::new();
+                word.extend(chars.next_if_eq(&'-'));
+                loop {
+                    if let Some(c) = chars.next_if(|c| is_word_char(*c)) {
+                        word.push(c);
+                    } else if word.ends_with(['e', 'E']) && word.trim_start_matches('-').starts_with(|c: char| c.is_ascii_digit()) {
+                        // the sign of a float's exponent
+                        match chars.next_if(|c| *c == '-' || *c == '+') {
+                            Some(sign) => word.push(sign),
+                            None => break,
+                        }
+                    } else {
+                        break;
+                    }
+                }
+                tokens.push(Token::Word(word));
+            } else {
+                return Err(format!("unexpected `{}`", c));
+            }
+        }
+        Ok(Self { tokens, position: 0 })
+    }
+
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.position)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.position).cloned();
+        self.position += 1;
+        token
+    }
+
+    fn found(&self) -> String {
+        match self.peek() {
+            Some(token) => format!("`{}`", token),
+            None => "the end of the line".to_string(),
+        }
+    }
+
+    fn eat(&mut self, c: char) -> bool {
+        let found = self.peek() == Some(&Token::Punct(c));
+        if found {
+            self.position += 1;
+        }
+        found
+    }
+
+    fn punct(&mut self, c: char) -> Result<(), String> {
+        if self.eat(c) { Ok(()) } else { Err(format!("expected `{}`, found {}", c, self.found())) }
+    }
+
+    fn is_keyword(&self, keyword: &str) -> bool {
+        matches!(self.peek(), Some(Token::Word(word)) if word == keyword)
+    }
+
+    fn keyword(&mut self, keyword: &str) -> Result<(), String> {
+        if self.is_keyword(keyword) {
+            self.position += 1;
+            Ok(())
+        } else {
+            Err(format!("expected `{}`, found {}", keyword, self.found()))
+        }
+    }
+
+    fn word(&mut self, what: &str) -> Result<String, String> {
+        match self.peek() {
+            Some(Token::Word(word)) => {
+                let word = word.clone();
+                self.position += 1;
+                Ok(word)
+            }
+            _ => Err(format!("expected {}, found {}", what, self.found())),
+        }
+    }
+
+    fn local_name(&mut self) -> Result<String, String> {
+        match self.peek() {
+            Some(Token::Local(name)) => {
+                let name = name.clone();
+                self.position += 1;
+                Ok(name)
+            }
+            _ => Err(format!("expected a local, found {}", self.found())),
+        }
+    }
+
+    fn end(&self) -> Result<(), String> {
+        match self.peek() {
+            None => Ok(()),
+            Some(_) => Err(format!("unexpected {} at the end of the line", self.found())),
+        }
+    }
+}
+
+// reads the rest of a string written like `{:?}` writes it, after the opening quote
+fn string(chars: &mut std::iter::Peekable<std::str::Chars>) -> Result<String, String> {
+    let mut s = String::new();
+    loop {
+        match chars.next() {
+            None => return Err("unterminated string".to_string()),
+            Some('"') => return Ok(s),
+            Some('\\') => {
+                let escaped = match chars.next() {
+                    Some('n') => '\n',
+                    Some('r') => '\r',
+                    Some('t') => '\t',
+                    Some('0') => '\0',
+                    Some(c @ ('\\' | '"' | '\'')) => c,
+                    Some('u') => {
+                        let mut hex = String::new();
+                        if chars.next() != Some('{') {
+                            return Err("expected `{` after `\\u`".to_string());
+                        }
+                        for c in chars.by_ref() {
+                            if c == '}' {
+                                break;
+                            }
+                            hex.push(c);
+                        }
+                        u32::from_str_radix(&hex, 16).ok().and_then(char::from_u32).ok_or_else(|| format!("invalid escape `\\u{{{}}}`", hex))?
+                    }
+                    other => return Err(format!("invalid escape `\\{}`", other.map(String::from).unwrap_or_default())),
+                };
+                s.push(escaped);
+            }
+            Some(c) => s.push(c),
+        }
+    }
+}
+
+struct Instruction<'a> {
+    tokens: &'a mut Tokens,
+    locals: &'a HashMap<String, u32>,
+}
+
+impl Instruction<'_> {
+    fn parse(&mut self) -> Result<FirInstr, String> {
+        if let Some(Token::Local(_)) = self.tokens.peek() {
+            let dst = self.local()?;
+            self.tokens.punct('=')?;
+            let op = self.tokens.word("an instruction")?;
+            return self.with_dst(dst, &op);
+        }
+        let op = self.tokens.word("an instruction")?;
+        let instr = match op.as_str() {
+            "store" => {
+                let name = self.declared()?;
+                self.tokens.punct(',')?;
+                FirInstr::StoreLocal(name, self.value()?)
+            }
+            "ret" if self.tokens.peek().is_none() => FirInstr::Return(None),
+            "ret" => FirInstr::Return(Some(self.value()?)),
+            "jmp" => FirInstr::Jump(self.tokens.word("a label")?),
+            "br" => {
+                let cond = self.value()?;
+                self.tokens.punct(',')?;
+                let then_label = self.tokens.word("a label")?;
+                self.tokens.punct(',')?;
+                FirInstr::JumpIf { cond, then_label, else_label: self.tokens.word("a label")? }
+            }
+            "nop" => FirInstr::Nop,
+            _ => return Err(format!("unknown instruction `{}`", op)),
+        };
+        Ok(instr)
+    }
+
+    fn with_dst(&mut self, dst: u32, op: &str) -> Result<FirInstr, String> {
+        let binary: Option<fn(u32, FirValue, FirValue) -> FirInstr> = match op {
+            "add" => Some(FirInstr::Add),
+            "sub" => Some(FirInstr::Sub),
+            "mul" => Some(FirInstr::Mul),
+            "div" => Some(FirInstr::Div),
+            "mod" => Some(FirInstr::Mod),
+            "eq" => Some(FirInstr::Eq),
+            "lt" => Some(FirInstr::Lt),
+            "gt" => Some(FirInstr::Gt),
+            _ => None,
+        };
+        if let Some(binary) = binary {
+            let lhs = self.value()?;
+            self.tokens.punct(',')?;
+            return Ok(binary(dst, lhs, self.value()?));
+        }
+
+        let instr = match op {
+            "load" => FirInstr::LoadLocal(dst, self.declared()?),
+            "copy" => FirInstr::Copy(dst, self.value()?),
+            "phi" => {
+                self.tokens.punct('[')?;
+                let mut incoming = vec![];
+                while !self.tokens.eat(']') {
+                    if !incoming.is_empty() {
+                        self.tokens.punct(',')?;
+                    }
+                    let label = self.tokens.word("a label")?;
+                    self.tokens.punct(':')?;
+                    incoming.push((label, self.value()?));
+                }
+                FirInstr::Phi(dst, incoming)
+            }
+            "call" => {
+                let func = self.tokens.word("a function name")?;
+                self.tokens.punct('(')?;
+                let mut args = vec![];
+                while !self.tokens.eat(')') {
+                    if !args.is_empty() {
+                        self.tokens.punct(',')?;
+                    }
+                    args.push(self.value()?);
+                }
+                FirInstr::Call { dst, func, args }
+            }
+            _ => return Err(format!("unknown instruction `{}`", op)),
+        };
+        Ok(instr)
+    }
+
+    // the name of a declared local, for loads and stores
+    fn declared(&mut self) -> Result<String, String> {
+        let name = self.tokens.local_name()?;
+        if !self.locals.contains_key(&name) {
+            return Err(format!("unknown local %{}", name));
+        }
+        Ok(name)
+    }
+
+    fn local(&mut self) -> Result<u32, String> {
+        let name = self.declared()?;
+        Ok(self.locals[&name])
+    }
+
+    fn value(&mut self) -> Result<FirValue, String> {
+        let value = match self.tokens.peek() {
+            Some(Token::Local(_)) => return Ok(FirValue::Local(self.local()?)),
+            Some(Token::Str(s)) => FirValue::ConstString(s.clone()),
+            Some(Token::Word(word)) => constant(word)?,
+            _ => return Err(format!("expected a value, found {}", self.tokens.found())),
+        };
+        self.tokens.next();
+        Ok(value)
+    }
+}
+
+fn constant(word: &str) -> Result<FirValue, String> {
+    let digits = word.strip_prefix('-').unwrap_or(word);
+    let value = match word {
+        "true" => FirValue::ConstBool(true),
+        "false" => FirValue::ConstBool(false),
+        _ if !digits.is_empty() && digits.bytes().all(|b| b.is_ascii_digit()) => {
+            FirValue::ConstInt(word.parse().map_err(|_| format!("the int {} is out of range", word))?)
+        }
+        "inf" | "-inf" | "NaN" => FirValue::ConstFloat(word.parse().expect("a float")),
+        _ if digits.starts_with(|c: char| c.is_ascii_digit()) => {
+            FirValue::ConstFloat(word.parse().map_err(|_| format!("invalid number `{}`", word))?)
+        }
+        _ => return Err(format!("expected a value, found `{}`", word)),
+    };
+    Ok(value)
+}
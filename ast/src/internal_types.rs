@@ -1,27 +1,39 @@
 use std::collections::{BTreeMap};
+use crate::error::RuntimeError;
 use crate::node::Node;
 use crate::value::Value;
 
-pub(crate) fn fetch_array(node: Node) -> Result<Vec<Node>, String> {
+/// A short, user facing name for the type of an evaluated node, used in error messages.
+pub fn type_of(node: &Node) -> &str {
+    match node {
+        Node::Atomic { value } => value.type_name(),
+        Node::Array { .. } => "array",
+        Node::HMap { .. } => "map",
+        Node::FunctionDecl { .. } | Node::Lambda { .. } | Node::Closure { .. } => "function",
+        _ => node.node_type(),
+    }
+}
+
+pub(crate) fn fetch_array(node: Node) -> Result<Vec<Node>, RuntimeError> {
     if let Node::Array { elements } = node {
         Ok(elements)
     } else {
-        Err("expected array".to_string())
+        Err(RuntimeError::type_mismatch("array", type_of(&node)))
     }
 }
 
-pub(crate) fn fetch_hash_map(node: Node) -> Result<BTreeMap<Node, Node>, String> {
+pub(crate) fn fetch_hash_map(node: Node) -> Result<BTreeMap<Node, Node>, RuntimeError> {
     match node {
         Node::HMap { values } => {
             Ok(values)
         }
         _ => {
-            Err(format!("expected hash map, got {:?}", node))
+            Err(RuntimeError::type_mismatch("map", type_of(&node)))
         }
     }
 }
 
-pub(crate) fn fetch_string(node: Node) -> Result<String, String> {
+pub(crate) fn fetch_string(node: Node) -> Result<String, RuntimeError> {
     match node {
         Node::Identifier { value } => Ok(value),
         Node::Ident{name, ..} => Ok(name),
@@ -33,55 +45,34 @@ pub(crate) fn fetch_string(node: Node) -> Result<String, String> {
             if let Value::Str(s) = value {
                 Ok(s)
             } else {
-                Err("expected string".to_string())
+                Err(RuntimeError::type_mismatch("string", value.type_name()))
             }
         }
         Node::AssignStmt { left, .. } => {
             let left = fetch_string(*left)?;
             Ok(left)
         }
-        _ => Err(format!("unexpected node {:?}", node)),
+        _ => Err(RuntimeError::type_mismatch("name", type_of(&node))),
     }
 }
 
-pub fn fetch_integer(node: Node) -> Result<i32, String> {
+pub fn fetch_integer(node: Node) -> Result<i32, RuntimeError> {
     match node {
-        Node::Atomic { value } => {
-            if let Value::Int(i) = value {
-                Ok(i)
-            } else {
-                Err(format!("expected integer, got {:?}", value))
-            }
-        }
-        _ => {
-            println!("expected integer but got: {:?}", node);
-            Err(format!("expected integer, got {:?}", node)) 
-        },
+        Node::Atomic { value: Value::Int(i) } => Ok(i),
+        _ => Err(RuntimeError::type_mismatch("int", type_of(&node))),
     }
 }
 
-pub(crate) fn fetch_boolean(node: Node) -> Result<bool, String> {
+pub(crate) fn fetch_boolean(node: Node) -> Result<bool, RuntimeError> {
     match node {
-        Node::Atomic { value } => {
-            if let Value::Bool(b) = value {
-                Ok(b)
-            } else {
-                Err("expected boolean".to_string())
-            }
-        }
-        _ => Err("expected boolean".to_string()),
+        Node::Atomic { value: Value::Bool(b) } => Ok(b),
+        _ => Err(RuntimeError::type_mismatch("bool", type_of(&node))),
     }
 }
 
-pub fn fetch_binary(node: Node) -> Result<u32, String> {
+pub fn fetch_binary(node: Node) -> Result<u32, RuntimeError> {
     match node {
-        Node::Atomic { value } => {
-            if let Value::Bin(b) = value {
-                Ok(b)
-            } else {
-                Err(format!("expected binary operator but got {:?}", value))
-            }
-        },
-        _ => Err(format!("expected binary operator but got {:?}", node)),
+        Node::Atomic { value: Value::Bin(b) } => Ok(b),
+        _ => Err(RuntimeError::type_mismatch("bin", type_of(&node))),
     }
-}
\ No newline at end of file
+}
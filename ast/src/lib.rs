@@ -1,6 +1,15 @@
 pub mod value;
+pub mod bigint;
+pub mod bytecode;
 pub mod node;
 pub mod ast;
+pub mod environment;
+pub mod error;
+pub mod span;
 pub mod internal_types;
-mod fir;
-mod lower;
\ No newline at end of file
+pub mod fir;
+pub mod lower;
+pub mod cfg;
+pub mod ssa;
+pub mod fir_text;
+pub mod passes;
\ No newline at end of file
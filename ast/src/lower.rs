@@ -5,25 +5,101 @@ use crate::value::Value;
 
 pub struct LoweringContext {
     next_local: u32,
-    locals: HashMap<String, u32>,
+    // the locals each name refers to, a map per block around the code being lowered, innermost last
+    scopes: Vec<HashMap<String, u32>>,
+    // every local's name, by number, becomes `FirFunction::locals`
+    names: Vec<String>,
+    // numbers new blocks, so every label in a function is unique
+    next_label: usize,
+    // the block instructions go to; None right after a terminator, where code can't be reached
+    current: Option<usize>,
+    // the loops around the code being lowered, innermost last
+    loops: Vec<LoopLabels>,
+}
+
+struct LoopLabels {
+    continue_label: String,
+    break_label: String,
 }
 
 impl LoweringContext {
     pub fn new() -> Self {
-        Self { next_local: 0, locals: HashMap::new() }
+        Self { next_local: 0, scopes: vec![HashMap::new()], names: vec![], next_label: 1, current: Some(0), loops: vec![] }
     }
 
+    /// Declares `name` in the innermost block. A name shadowing another local gets a number in
+    /// FIR, like `x.4`, so every local's name is unique.
     pub fn new_local(&mut self, name: &str) -> u32 {
         let id = self.next_local;
         self.next_local += 1;
-        self.locals.insert(name.to_string(), id);
+        let fir_name = if self.names.iter().any(|other| other == name) { format!("{}.{}", name, id) } else { name.to_string() };
+        self.scopes.last_mut().expect("a scope").insert(name.to_string(), id);
+        self.names.push(fir_name);
         id
     }
 
+    /// A local holding an intermediate result, which no variable name refers to.
+    pub fn new_temp(&mut self) -> u32 {
+        let id = self.next_local;
+        self.next_local += 1;
+        // a `.` can't appear in an identifier, so temporaries never clash with variables
+        self.names.push(format!("tmp.{}", id));
+        id
+    }
+
+    /// The local `name` refers to, declared in the innermost block that has one.
     pub fn get_local(&self, name: &str) -> Option<u32> {
-        self.locals.get(name).cloned()
+        self.scopes.iter().rev().find_map(|scope| scope.get(name)).cloned()
+    }
+
+    /// The name of local `id` in FIR, which `StoreLocal` and `LoadLocal` refer to it by.
+    pub fn local_name(&self, id: u32) -> String {
+        self.names[id as usize].clone()
+    }
+
+    pub fn push_scope(&mut self) {
+        self.scopes.push(HashMap::new());
+    }
+
+    pub fn pop_scope(&mut self) {
+        self.scopes.pop();
+    }
+
+    /// Adds a block to `func` labelled `hint` and a number unique in the function, like `then3`.
+    pub fn new_block(&mut self, func: &mut FirFunction, hint: &str) -> String {
+        let label = format!("{}{}", hint, self.next_label);
+        self.next_label += 1;
+        func.add_block(label.clone());
+        label
+    }
+
+    /// Continues lowering at the end of the block labelled `label`.
+    pub fn switch_to(&mut self, func: &FirFunction, label: &str) {
+        self.current = func.blocks.iter().position(|block| block.label == label);
+    }
+
+    pub fn emit(&mut self, func: &mut FirFunction, instr: FirInstr) {
+        // code following a return, break or continue still gets a block, which nothing jumps to
+        let current = match self.current {
+            Some(current) => current,
+            None => {
+                self.new_block(func, "unreachable");
+                func.blocks.len() - 1
+            }
+        };
+        let terminates = instr.is_terminator();
+        func.blocks[current].instrs.push(instr);
+        self.current = if terminates { None } else { Some(current) };
+    }
+
+    // ends the current block with a jump, unless it already ended
+    fn jump_to(&mut self, func: &mut FirFunction, label: &str) {
+        if self.current.is_some() {
+            self.emit(func, FirInstr::Jump(label.to_string()));
+        }
     }
 }
+
 fn ident_name(node: &Node) -> Option<String> {
     match node {
         Node::Identifier { value, .. } => Some(value.clone()),
@@ -32,139 +108,270 @@ fn ident_name(node: &Node) -> Option<String> {
     }
 }
 
-fn expect_ident_name(node: &Node, what: &str) -> String {
-    ident_name(node).unwrap_or_else(|| panic!("Expected identifier for {}, got {:?}", what, node))
+fn expect_ident_name(node: &Node, what: &str) -> Result<String, String> {
+    ident_name(node).ok_or_else(|| format!("Expected identifier for {}, got {:?}", what, node))
+}
+
+fn unsupported(node: &Node) -> String {
+    format!("lowering to FIR is not supported for {:?}", node)
 }
 
-pub fn lower_expr(expr: &Node, func: &mut FirFunction, ctx: &mut LoweringContext) -> FirValue {
-    match expr {
+pub fn lower_expr(expr: &Node, func: &mut FirFunction, ctx: &mut LoweringContext) -> Result<FirValue, String> {
+    let value = match expr {
         Node::Atomic { value } => match value {
             Value::Int(n) => FirValue::ConstInt(*n),
+            Value::Float(f) => FirValue::ConstFloat(*f),
             Value::Bool(b) => FirValue::ConstBool(*b),
             Value::Str(s) => FirValue::ConstString(s.clone()),
             Value::Bin(u) => FirValue::ConstInt(*u as i32), // map bins to i32 for now
-            // add other literals as needed
-            _ => FirValue::ConstInt(0),
+            _ => return Err(unsupported(expr)),
         },
 
-        Node::Identifier { value } => {
-            if let Some(idx) = ctx.get_local(value) {
-                FirValue::Local(idx)
-            } else {
-                panic!("Unknown variable: {}", value)
-            }
-        }
+        Node::Identifier { value: name } | Node::Ident { name, .. } => match ctx.get_local(name) {
+            Some(idx) => FirValue::Local(idx),
+            None => return Err(format!("Unknown variable: {}", name)),
+        },
 
-        Node::Ident { name, .. } => {
-            if let Some(idx) = ctx.get_local(name) {
-                FirValue::Local(idx)
-            } else {
-                panic!("Unknown variable: {}", name)
-            }
+        // the right side only runs when the left one doesn't decide the result, so it gets a block of its own
+        Node::BinaryExpression { left, operator: operator @ (OperatorKind::And | OperatorKind::Or), right, .. } => {
+            // reserved before the operands are lowered, so an `&&` inside them gets a local of its own
+            let result_local = ctx.new_temp();
+            let result = ctx.local_name(result_local);
+            let lhs = lower_expr(left, func, ctx)?;
+            ctx.emit(func, FirInstr::StoreLocal(result.clone(), lhs.clone()));
+
+            let rhs_label = ctx.new_block(func, "rhs");
+            let join = ctx.new_block(func, "join");
+            let (then_label, else_label) = match operator {
+                OperatorKind::And => (rhs_label.clone(), join.clone()),
+                _ => (join.clone(), rhs_label.clone()),
+            };
+            ctx.emit(func, FirInstr::JumpIf { cond: lhs, then_label, else_label });
+
+            ctx.switch_to(func, &rhs_label);
+            let rhs = lower_expr(right, func, ctx)?;
+            ctx.emit(func, FirInstr::StoreLocal(result, rhs));
+            ctx.jump_to(func, &join);
+
+            ctx.switch_to(func, &join);
+            FirValue::Local(result_local)
         }
 
-        Node::BinaryExpression { left, operator, right } => {
-            let lhs = lower_expr(left, func, ctx);
-            let rhs = lower_expr(right, func, ctx);
-            let tmp = ctx.new_local("_tmp");
+        Node::BinaryExpression { left, operator, right, .. } => {
+            let lhs = lower_expr(left, func, ctx)?;
+            let rhs = lower_expr(right, func, ctx)?;
+            let tmp = ctx.new_temp();
 
             let instr = match operator {
-                OperatorKind::Add => FirInstr::Add(lhs.clone(), rhs.clone()),
-                OperatorKind::Subtract => FirInstr::Sub(lhs.clone(), rhs.clone()),
-                OperatorKind::Multiply => FirInstr::Mul(lhs.clone(), rhs.clone()),
-                OperatorKind::Divide => FirInstr::Div(lhs.clone(), rhs.clone()),
-                OperatorKind::Modulo => FirInstr::Mod(lhs.clone(), rhs.clone()),
-                OperatorKind::IsEqual => FirInstr::Eq(lhs.clone(), rhs.clone()),
-                OperatorKind::LessThan => FirInstr::Lt(lhs.clone(), rhs.clone()),
-                OperatorKind::GreaterThan => FirInstr::Gt(lhs.clone(), rhs.clone()),
+                OperatorKind::Add => FirInstr::Add(tmp, lhs, rhs),
+                OperatorKind::Subtract => FirInstr::Sub(tmp, lhs, rhs),
+                OperatorKind::Multiply => FirInstr::Mul(tmp, lhs, rhs),
+                OperatorKind::Divide => FirInstr::Div(tmp, lhs, rhs),
+                OperatorKind::Modulo => FirInstr::Mod(tmp, lhs, rhs),
+                OperatorKind::IsEqual => FirInstr::Eq(tmp, lhs, rhs),
+                OperatorKind::LessThan => FirInstr::Lt(tmp, lhs, rhs),
+                OperatorKind::GreaterThan => FirInstr::Gt(tmp, lhs, rhs),
                 // you can add bitwise ops later as separate FIR opcodes
-                _ => FirInstr::Nop,
+                _ => return Err(unsupported(expr)),
             };
 
-            func.emit(instr);
+            ctx.emit(func, instr);
             FirValue::Local(tmp)
         }
 
         Node::Call { name, arguments, .. } => {
-            let args: Vec<FirValue> = arguments.iter().map(|a| lower_expr(a, func, ctx)).collect();
-            func.emit(FirInstr::Call { func: name.clone(), args: args.clone() });
-            FirValue::Local(ctx.new_local("_call_tmp"))
+            let args = arguments.iter().map(|a| lower_expr(a, func, ctx)).collect::<Result<Vec<_>, _>>()?;
+            let dst = ctx.new_temp();
+            ctx.emit(func, FirInstr::Call { dst, func: name.clone(), args });
+            FirValue::Local(dst)
         }
 
-        _ => FirValue::ConstInt(0),
-    }
+        _ => return Err(unsupported(expr)),
+    };
+    Ok(value)
 }
 
-pub fn lower_stmt(stmt: &Node, func: &mut FirFunction, ctx: &mut LoweringContext) {
+pub fn lower_stmt(stmt: &Node, func: &mut FirFunction, ctx: &mut LoweringContext) -> Result<(), String> {
     match stmt {
-        Node::AssignStmt { left, right, .. } => {
-            // left: Box<Node>
-            if let Some(var) = ident_name(&**left) {
-                let val = lower_expr(right, func, ctx);
-                // ensure local exists
-                if ctx.get_local(&var).is_none() {
-                    ctx.new_local(&var);
-                }
-                func.emit(FirInstr::StoreLocal(var, val));
-            } else {
-                panic!("Assignment left side must be an identifier, got {:?}", left);
-            }
+        Node::AssignStmt { left, right, kind, .. } => {
+            let Some(var) = ident_name(left) else {
+                return Err(unsupported(stmt));
+            };
+            let val = lower_expr(right, func, ctx)?;
+            // like the interpreter, `let` declares a new variable unless the block already has one of
+            // that name, and assigning to a name that isn't in scope declares it in the block
+            let declared_here = ctx.scopes.last().and_then(|scope| scope.get(&var)).cloned();
+            let local = match ctx.get_local(&var) {
+                Some(local) if kind != "let" => local,
+                _ => match declared_here {
+                    Some(local) => local,
+                    None => ctx.new_local(&var),
+                },
+            };
+            ctx.emit(func, FirInstr::StoreLocal(ctx.local_name(local), val));
         }
 
         // Return has value: Box<Node> (not Option)
-        Node::Return { value } => {
-            let ret_val = Some(lower_expr(&**value, func, ctx));
-            func.emit(FirInstr::Return(ret_val));
+        Node::Return { value, .. } => {
+            let ret_val = lower_expr(value, func, ctx)?;
+            ctx.emit(func, FirInstr::Return(Some(ret_val)));
         }
 
-        Node::Call { .. } => {
-            // side-effectful call as a statement
-            let _ = lower_expr(stmt, func, ctx);
-        }
-
-        // Conditional uses Vec<Node> for branches
+        // the branches meet again in a join block, which is where lowering carries on
         Node::Conditional { condition, consequence, alternative, .. } => {
-            let cond_val = lower_expr(condition, func, ctx);
-            let then_label = "then".to_string();
-            let else_label = if alternative.is_empty() { None } else { Some("else".to_string()) };
-            func.emit(FirInstr::JumpIf { cond: cond_val, then_label, else_label });
+            let cond = lower_expr(condition, func, ctx)?;
+            let then_label = ctx.new_block(func, "then");
+            let else_label = if alternative.is_empty() { None } else { Some(ctx.new_block(func, "else")) };
+            let join = ctx.new_block(func, "join");
+            let else_target = else_label.clone().unwrap_or_else(|| join.clone());
+            ctx.emit(func, FirInstr::JumpIf { cond, then_label: then_label.clone(), else_label: else_target });
 
-            for s in consequence {
-                lower_stmt(s, func, ctx);
-            }
-            if !alternative.is_empty() {
-                for s in alternative {
-                    lower_stmt(s, func, ctx);
-                }
+            ctx.switch_to(func, &then_label);
+            lower_block(consequence, func, ctx)?;
+            ctx.jump_to(func, &join);
+
+            if let Some(else_label) = else_label {
+                ctx.switch_to(func, &else_label);
+                lower_block(alternative, func, ctx)?;
+                ctx.jump_to(func, &join);
             }
+            ctx.switch_to(func, &join);
         }
 
-        _ => { todo!("not implemented yet") }
-    }
-}
+        Node::WhileLoop { condition, body, .. } => {
+            let header = ctx.new_block(func, "header");
+            ctx.jump_to(func, &header);
+            ctx.switch_to(func, &header);
+            let cond = lower_expr(condition, func, ctx)?;
+            let body_label = ctx.new_block(func, "body");
+            let latch = ctx.new_block(func, "latch");
+            let exit = ctx.new_block(func, "exit");
+            ctx.emit(func, FirInstr::JumpIf { cond, then_label: body_label.clone(), else_label: exit.clone() });
+
+            lower_loop_body(body, &body_label, &latch, &exit, func, ctx)?;
+            ctx.switch_to(func, &latch);
+            ctx.emit(func, FirInstr::Jump(header));
+            ctx.switch_to(func, &exit);
+        }
+
+        Node::Loop { body, .. } => {
+            let body_label = ctx.new_block(func, "body");
+            let latch = ctx.new_block(func, "latch");
+            let exit = ctx.new_block(func, "exit");
+            ctx.jump_to(func, &body_label);
+
+            lower_loop_body(body, &body_label, &latch, &exit, func, ctx)?;
+            ctx.switch_to(func, &latch);
+            ctx.emit(func, FirInstr::Jump(body_label));
+            ctx.switch_to(func, &exit);
+        }
+
+        // like the interpreter: the bounds are evaluated once, and a hidden counter drives the loop
+        Node::ForLoop { variable, range, body, .. } => {
+            let start = lower_expr(&range.0, func, ctx)?;
+            let end = lower_expr(&range.1, func, ctx)?;
+            // a `.` can't appear in an identifier, so these never clash with the program's names
+            let counter = format!("{}.counter{}", variable, ctx.next_label);
+            let end_name = format!("{}.end{}", variable, ctx.next_label);
+            let counter_local = ctx.new_local(&counter);
+            let end_local = ctx.new_local(&end_name);
+            // the loop variable is a new local, gone once the loop ends
+            ctx.push_scope();
+            let variable_local = ctx.new_local(variable);
+            ctx.emit(func, FirInstr::StoreLocal(counter.clone(), start));
+            ctx.emit(func, FirInstr::StoreLocal(end_name, end));
+
+            let header = ctx.new_block(func, "header");
+            let body_label = ctx.new_block(func, "body");
+            let latch = ctx.new_block(func, "latch");
+            let exit = ctx.new_block(func, "exit");
+            ctx.jump_to(func, &header);
 
-pub fn lower_function(node: &Node) -> FirFunction {
-    if let Node::FunctionDecl { name, arguments, body, .. } = node {
-        // name: Box<Node> — extract string
-        let func_name = expect_ident_name(&**name, "function name");
+            ctx.switch_to(func, &header);
+            let in_range = ctx.new_temp();
+            ctx.emit(func, FirInstr::Lt(in_range, FirValue::Local(counter_local), FirValue::Local(end_local)));
+            ctx.emit(func, FirInstr::JumpIf { cond: FirValue::Local(in_range), then_label: body_label.clone(), else_label: exit.clone() });
 
-        let params: Vec<String> = arguments
-            .iter()
-            .map(|arg| expect_ident_name(arg, "function parameter"))
-            .collect();
+            ctx.switch_to(func, &body_label);
+            ctx.emit(func, FirInstr::StoreLocal(ctx.local_name(variable_local), FirValue::Local(counter_local)));
+            lower_loop_body(body, &body_label, &latch, &exit, func, ctx)?;
+            ctx.pop_scope();
 
-        let mut func = FirFunction::new(func_name, params.clone());
-        let mut ctx = LoweringContext::new();
-        for p in &params {
-            ctx.new_local(p);
+            ctx.switch_to(func, &latch);
+            let next = ctx.new_temp();
+            ctx.emit(func, FirInstr::Add(next, FirValue::Local(counter_local), FirValue::ConstInt(1)));
+            ctx.emit(func, FirInstr::StoreLocal(counter, FirValue::Local(next)));
+            ctx.emit(func, FirInstr::Jump(header));
+            ctx.switch_to(func, &exit);
         }
 
-        for stmt in body {
-            lower_stmt(stmt, &mut func, &mut ctx);
+        Node::Break { .. } | Node::Continue { .. } => {
+            let Some(labels) = ctx.loops.last() else {
+                return Err(format!("{} outside of a loop", if matches!(stmt, Node::Break { .. }) { "`break`" } else { "`continue`" }));
+            };
+            let target = match stmt {
+                Node::Break { .. } => labels.break_label.clone(),
+                _ => labels.continue_label.clone(),
+            };
+            ctx.emit(func, FirInstr::Jump(target));
         }
 
-        func
-    } else {
-        panic!("Expected FunctionDecl node");
+        // any other expression is evaluated for its side effects
+        _ => {
+            lower_expr(stmt, func, ctx)?;
+        }
     }
-}
\ No newline at end of file
+    Ok(())
+}
+
+// lowers the statements of a block, whose declarations are gone once it ends
+fn lower_block(stmts: &[Node], func: &mut FirFunction, ctx: &mut LoweringContext) -> Result<(), String> {
+    ctx.push_scope();
+    for stmt in stmts {
+        lower_stmt(stmt, func, ctx)?;
+    }
+    ctx.pop_scope();
+    Ok(())
+}
+
+// lowers a loop body into `body`, where `continue` goes to the latch and `break` to the exit
+fn lower_loop_body(stmts: &[Node], body: &str, latch: &str, exit: &str, func: &mut FirFunction, ctx: &mut LoweringContext) -> Result<(), String> {
+    ctx.loops.push(LoopLabels { continue_label: latch.to_string(), break_label: exit.to_string() });
+    ctx.switch_to(func, body);
+    let result = lower_block(stmts, func, ctx);
+    ctx.loops.pop();
+    result?;
+    ctx.jump_to(func, latch);
+    Ok(())
+}
+
+/// Lowers a function declaration to a control flow graph: every block ends in exactly one terminator,
+/// and running off the end of the body returns nothing.
+pub fn lower_function(node: &Node) -> Result<FirFunction, String> {
+    let Node::FunctionDecl { name, arguments, body, .. } = node else {
+        return Err(format!("Expected FunctionDecl node, got {:?}", node));
+    };
+    // name: Box<Node> — extract string
+    let func_name = expect_ident_name(name, "function name")?;
+
+    let params = arguments
+        .iter()
+        .map(|arg| expect_ident_name(arg, "function parameter"))
+        .collect::<Result<Vec<String>, String>>()?;
+
+    let mut func = FirFunction::new(func_name, params.clone());
+    let mut ctx = LoweringContext::new();
+    for p in &params {
+        ctx.new_local(p);
+    }
+
+    // the body shares the parameters' scope, so a `let` of a parameter's name rebinds it
+    for stmt in body {
+        lower_stmt(stmt, &mut func, &mut ctx)?;
+    }
+    if ctx.current.is_some() {
+        ctx.emit(&mut func, FirInstr::Return(None));
+    }
+    func.locals = ctx.names;
+    Ok(func)
+}
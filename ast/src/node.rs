@@ -1,6 +1,8 @@
+use std::cmp::Ordering;
 use std::collections::BTreeMap;
 use std::fmt;
 use std::fmt::Display;
+use crate::span::Span;
 
 impl Display for OperatorKind {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
@@ -64,17 +66,18 @@ pub enum OperatorKind {
     
 }
 
-#[derive(Debug, Clone, PartialOrd)]
-#[derive(Eq, PartialEq, Ord)]
+#[derive(Debug, Clone)]
 pub enum Node {
     BinaryExpression {
         left: Box<Node>,
         operator: OperatorKind,
         right: Box<Node>,
+        span: Span,
     },
     UnaryExpression {
         operator: OperatorKind,
         right: Box<Node>,
+        span: Span,
     },
     Identifier {
         value: String,
@@ -83,10 +86,12 @@ pub enum Node {
         left: Box<Node>,
         right: Box<Node>,
         kind: String,
+        span: Span,
     },
     Ident {
         name: String,
         kind: String,
+        span: Span,
     },
     Atomic {
         value: crate::value::Value,
@@ -95,12 +100,14 @@ pub enum Node {
         name: String,
         arguments: Vec<Node>,
         returns: Vec<Node>,
+        span: Span,
     },
     MethodCall {
         name: String,
         target: String, // what the method is called on
         arguments: Vec<Node>,
         returns: Vec<Node>,
+        span: Span,
     },
     MMExpression {
         expression: String,
@@ -112,12 +119,24 @@ pub enum Node {
         condition: Box<Node>,
         consequence: Vec<Node>,
         alternative: Vec<Node>,
+        span: Span,
     },
     ForLoop {
         variable: String,
         // range: (i32, i32),
         range: (Box<Node>, Box<Node>),
         body: Vec<Node>,
+        span: Span,
+    },
+    WhileLoop {
+        condition: Box<Node>,
+        body: Vec<Node>,
+        span: Span,
+    },
+    // `loop { }`, only left through `break` or `return`
+    Loop {
+        body: Vec<Node>,
+        span: Span,
     },
     Array {
         elements: Vec<Node>,
@@ -128,30 +147,94 @@ pub enum Node {
         kind: String,
     },
     Break {
-
+        span: Span,
+    },
+    Continue {
+        span: Span,
     },
     IndexExpression {
         left: Box<Node>,
         index: Box<Node>,
+        span: Span,
     },
     FunctionDecl {
         name: Box<Node>,
         arguments: Vec<Node>,
         returns: Vec<Node>,
         body: Vec<Node>,
+        span: Span,
+    },
+    /// An anonymous function, `fn(x) { ... }`, in expression position.
+    Lambda {
+        arguments: Vec<Node>,
+        body: Vec<Node>,
+        span: Span,
+    },
+    /// A function value together with the bindings it captured where it was created.
+    Closure {
+        function: Box<Node>,
+        captured: crate::environment::Captured,
     },
     Return {
         value: Box<Node>,
+        span: Span,
     },
     HMap {
         values: BTreeMap<Node, Node>,
     },
 }
 
+// where a node was written doesn't make it different code, so spans are left out of comparisons
+impl Ord for Node {
+    fn cmp(&self, other: &Self) -> Ordering {
+        use Node::*;
+        match (self, other) {
+            (BinaryExpression { left: l1, operator: o1, right: r1, .. }, BinaryExpression { left: l2, operator: o2, right: r2, .. }) => (l1, o1, r1).cmp(&(l2, o2, r2)),
+            (UnaryExpression { operator: o1, right: r1, .. }, UnaryExpression { operator: o2, right: r2, .. }) => (o1, r1).cmp(&(o2, r2)),
+            (Identifier { value: v1 }, Identifier { value: v2 }) => v1.cmp(v2),
+            (AssignStmt { left: l1, right: r1, kind: k1, .. }, AssignStmt { left: l2, right: r2, kind: k2, .. }) => (l1, r1, k1).cmp(&(l2, r2, k2)),
+            (Ident { name: n1, kind: k1, .. }, Ident { name: n2, kind: k2, .. }) => (n1, k1).cmp(&(n2, k2)),
+            (Atomic { value: v1 }, Atomic { value: v2 }) => v1.cmp(v2),
+            (Call { name: n1, arguments: a1, returns: r1, .. }, Call { name: n2, arguments: a2, returns: r2, .. }) => (n1, a1, r1).cmp(&(n2, a2, r2)),
+            (MethodCall { name: n1, target: t1, arguments: a1, returns: r1, .. }, MethodCall { name: n2, target: t2, arguments: a2, returns: r2, .. }) => (n1, t1, a1, r1).cmp(&(n2, t2, a2, r2)),
+            (MMExpression { expression: e1 }, MMExpression { expression: e2 }) => e1.cmp(e2),
+            (Type { name: n1 }, Type { name: n2 }) => n1.cmp(n2),
+            (Conditional { condition: c1, consequence: t1, alternative: e1, .. }, Conditional { condition: c2, consequence: t2, alternative: e2, .. }) => (c1, t1, e1).cmp(&(c2, t2, e2)),
+            (ForLoop { variable: v1, range: r1, body: b1, .. }, ForLoop { variable: v2, range: r2, body: b2, .. }) => (v1, r1, b1).cmp(&(v2, r2, b2)),
+            (WhileLoop { condition: c1, body: b1, .. }, WhileLoop { condition: c2, body: b2, .. }) => (c1, b1).cmp(&(c2, b2)),
+            (Loop { body: b1, .. }, Loop { body: b2, .. }) => b1.cmp(b2),
+            (Array { elements: e1 }, Array { elements: e2 }) => e1.cmp(e2),
+            (Object { name: n1, kind: k1 }, Object { name: n2, kind: k2 }) => (n1, k1).cmp(&(n2, k2)),
+            (IndexExpression { left: l1, index: i1, .. }, IndexExpression { left: l2, index: i2, .. }) => (l1, i1).cmp(&(l2, i2)),
+            (FunctionDecl { name: n1, arguments: a1, returns: r1, body: b1, .. }, FunctionDecl { name: n2, arguments: a2, returns: r2, body: b2, .. }) => (n1, a1, r1, b1).cmp(&(n2, a2, r2, b2)),
+            (Lambda { arguments: a1, body: b1, .. }, Lambda { arguments: a2, body: b2, .. }) => (a1, b1).cmp(&(a2, b2)),
+            (Closure { function: f1, captured: c1 }, Closure { function: f2, captured: c2 }) => (f1, c1).cmp(&(f2, c2)),
+            (Return { value: v1, .. }, Return { value: v2, .. }) => v1.cmp(v2),
+            (HMap { values: v1 }, HMap { values: v2 }) => v1.cmp(v2),
+            // EmptyNode, Break and Continue have nothing but their span
+            _ => self.rank().cmp(&other.rank()),
+        }
+    }
+}
+
+impl PartialOrd for Node {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for Node {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Node {}
+
 impl Display for Node {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
-            Node::BinaryExpression { left, operator, right } => {
+            Node::BinaryExpression { left, operator, right, .. } => {
                 match operator {
                     OperatorKind::ForAll => {
                         write!(f, "∀{}{}", left, right)
@@ -176,7 +259,7 @@ impl Display for Node {
                     }
                 }
             }
-            Node::UnaryExpression { operator, right } => {
+            Node::UnaryExpression { operator, right, .. } => {
                 write!(f, "({} {})", operator, right)
             }
             Node::Identifier { value } => {
@@ -189,7 +272,7 @@ impl Display for Node {
             Node::Atomic { value } => {
                 write!(f, "{}", value)
             }
-            Node::Call { name, arguments, returns: _returns } => {
+            Node::Call { name, arguments, .. } => {
                 write!(f, "{}({:?})", name, arguments)
             }
             Node::MMExpression { expression } => {
@@ -201,7 +284,7 @@ impl Display for Node {
             Node::Conditional { .. } => {
                 todo!()
             }
-            Node::ForLoop { variable, range, body } => {
+            Node::ForLoop { variable, range, body, .. } => {
                 write!(f, "for {} in {}..{} {{ {:?} }}", variable, range.0, range.1, body)
             }
             Node::EmptyNode => {
@@ -213,31 +296,75 @@ impl Display for Node {
             Node::Array { elements } => {
                 write!(f, "[{:?}]", elements)
             }
-            Node::Break {} => {
+            Node::WhileLoop { condition, body, .. } => {
+                write!(f, "while {} {{ {:?} }}", condition, body)
+            }
+            Node::Loop { body, .. } => {
+                write!(f, "loop {{ {:?} }}", body)
+            }
+            Node::Break { .. } => {
                 write!(f, "break")
             }
-            Node::IndexExpression { left, index } => {
+            Node::Continue { .. } => {
+                write!(f, "continue")
+            }
+            Node::IndexExpression { left, index, .. } => {
                 write!(f, "{}[{}]", left, index)
             }
-            Node::Ident { name, kind } => {
+            Node::Ident { name, kind, .. } => {
                 write!(f, "{} : {}", name, kind)
             }
-            Node::FunctionDecl { name, arguments, returns, body } => {
+            Node::FunctionDecl { name, arguments, returns, body, .. } => {
                 write!(f, "fn {}({:?}) -> {:?} {{ {:?} }}", name, arguments, returns, body)
             }
-            Node::Return { value } => {
+            Node::Lambda { arguments, body, .. } => {
+                write!(f, "fn({:?}) {{ {:?} }}", arguments, body)
+            }
+            Node::Closure { function, .. } => write!(f, "{}", function),
+            Node::Return { value, .. } => {
                 write!(f, "return {:?}", value)
             }
             Node::HMap { values } => {
                 write!(f, "{:?}", values)
             }
-            Node::MethodCall { name, target, arguments, returns } => {
+            Node::MethodCall { name, target, arguments, returns, .. } => {
                 write!(f, "{}.{}({:?}) -> {:?}", target, name, arguments, returns)
             }
         }
     }
 }
 impl Node {
+    // the position of the variant in the declaration, which orders nodes of different kinds
+    fn rank(&self) -> u8 {
+        match self {
+            Node::BinaryExpression { .. } => 0,
+            Node::UnaryExpression { .. } => 1,
+            Node::Identifier { .. } => 2,
+            Node::AssignStmt { .. } => 3,
+            Node::Ident { .. } => 4,
+            Node::Atomic { .. } => 5,
+            Node::Call { .. } => 6,
+            Node::MethodCall { .. } => 7,
+            Node::MMExpression { .. } => 8,
+            Node::Type { .. } => 9,
+            Node::Conditional { .. } => 10,
+            Node::ForLoop { .. } => 11,
+            Node::WhileLoop { .. } => 12,
+            Node::Loop { .. } => 13,
+            Node::Array { .. } => 14,
+            Node::EmptyNode => 15,
+            Node::Object { .. } => 16,
+            Node::Break { .. } => 17,
+            Node::Continue { .. } => 18,
+            Node::IndexExpression { .. } => 19,
+            Node::FunctionDecl { .. } => 20,
+            Node::Lambda { .. } => 21,
+            Node::Closure { .. } => 22,
+            Node::Return { .. } => 23,
+            Node::HMap { .. } => 24,
+        }
+    }
+
 
     pub fn operator(&self) -> OperatorKind {
         match self {
@@ -268,11 +395,11 @@ impl Node {
             },
             Node::BinaryExpression { left, .. } => Ok(Box::from(*left.clone())),
             Node::Object { name, kind } => Ok(Box::from(Node::Object { name: name.clone(), kind: kind.clone() })),
-            Node::IndexExpression { left, index } => Ok(Box::from(Node::IndexExpression { left: left.clone(), index: index.clone() })),
-            Node::Call { name, arguments, .. } => {
-                Ok(Box::from(Node::Call { name: name.clone(), arguments: arguments.clone(), returns: vec![] }))
+            Node::IndexExpression { .. } => Ok(Box::from(self.clone())),
+            Node::Call { name, arguments, span, .. } => {
+                Ok(Box::from(Node::Call { name: name.clone(), arguments: arguments.clone(), returns: vec![], span: *span }))
             }
-            Node::Ident { name, kind } => Ok(Box::from(Node::Ident { name: name.clone(), kind: kind.clone() })),
+            Node::Ident { .. } => Ok(Box::from(self.clone())),
             _ => {
                 Err(format!("unexpected token {:?}", self))
             },
@@ -287,6 +414,29 @@ impl Node {
         }
     }
 
+    /// Where this node was written, for the variants that record it.
+    pub fn span(&self) -> Option<Span> {
+        match self {
+            Node::BinaryExpression { span, .. }
+            | Node::UnaryExpression { span, .. }
+            | Node::AssignStmt { span, .. }
+            | Node::Ident { span, .. }
+            | Node::Call { span, .. }
+            | Node::MethodCall { span, .. }
+            | Node::Conditional { span, .. }
+            | Node::ForLoop { span, .. }
+            | Node::WhileLoop { span, .. }
+            | Node::Loop { span, .. }
+            | Node::Break { span }
+            | Node::Continue { span }
+            | Node::IndexExpression { span, .. }
+            | Node::FunctionDecl { span, .. }
+            | Node::Lambda { span, .. }
+            | Node::Return { span, .. } => Some(*span).filter(|span| !span.is_unknown()),
+            _ => None,
+        }
+    }
+
     pub fn val(&self) -> crate::value::Value {
         match self {
             Node::Atomic { value } => value.clone(),
@@ -0,0 +1,450 @@
+//! Optimization passes over FIR, and the pass manager running them over a module.
+//!
+//! The passes work on FIR straight from lowering as well as in SSA form, see `ssa::to_ssa`.
+
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::time::{Duration, Instant};
+use crate::cfg::{remove_unreachable_blocks, Cfg};
+use crate::fir::{FirFunction, FirInstr, FirModule, FirValue};
+
+/// Round limit of a pass manager, which stops earlier once a round changes nothing.
+pub const DEFAULT_MAX_ROUNDS: usize = 8;
+
+/// A transformation of one function at a time.
+pub trait Pass {
+    /// The name the pass is configured and reported by.
+    fn name(&self) -> &'static str;
+
+    /// Transforms `func`, returning how many changes it made.
+    fn run(&mut self, func: &mut FirFunction) -> usize;
+}
+
+/// What a pass did while the pass manager ran it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PassStats {
+    pub name: &'static str,
+    /// How many rounds it ran in, each over every function of the module.
+    pub runs: usize,
+    pub changes: usize,
+    pub time: Duration,
+}
+
+impl fmt::Display for PassStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:<24} {:>6} changes in {} runs, {:?}", self.name, self.changes, self.runs, self.time)
+    }
+}
+
+/// Runs its passes in order over every function of a module, in rounds until nothing changes.
+pub struct PassManager {
+    passes: Vec<Box<dyn Pass>>,
+    max_rounds: usize,
+}
+
+impl PassManager {
+    pub fn new() -> Self {
+        Self { passes: vec![], max_rounds: DEFAULT_MAX_ROUNDS }
+    }
+
+    /// Every pass, in an order where each one leaves work for the next.
+    pub fn standard() -> Self {
+        Self::from_names(&["constant-folding", "copy-propagation", "branch-simplification", "unreachable-blocks", "dead-store-elimination"])
+            .expect("the standard passes exist")
+    }
+
+    /// A pass manager running the passes `names` refers to, see `from_name`.
+    pub fn from_names(names: &[&str]) -> Result<Self, String> {
+        let mut manager = Self::new();
+        for name in names {
+            manager.add(from_name(name).ok_or_else(|| format!("unknown pass `{}`", name))?);
+        }
+        Ok(manager)
+    }
+
+    pub fn add(&mut self, pass: Box<dyn Pass>) {
+        self.passes.push(pass);
+    }
+
+    pub fn set_max_rounds(&mut self, max_rounds: usize) {
+        self.max_rounds = max_rounds;
+    }
+
+    /// Runs the passes over `module`, returning the statistics of each pass in order.
+    pub fn run(&mut self, module: &mut FirModule) -> Vec<PassStats> {
+        let mut stats: Vec<PassStats> = self.passes.iter().map(|pass| PassStats { name: pass.name(), runs: 0, changes: 0, time: Duration::ZERO }).collect();
+        for _ in 0..self.max_rounds {
+            let mut changed = false;
+            for (pass, stats) in self.passes.iter_mut().zip(&mut stats) {
+                let start = Instant::now();
+                for func in &mut module.functions {
+                    let changes = pass.run(func);
+                    stats.changes += changes;
+                    changed |= changes > 0;
+                }
+                stats.runs += 1;
+                stats.time += start.elapsed();
+            }
+            if !changed {
+                break;
+            }
+        }
+        stats
+    }
+}
+
+impl Default for PassManager {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// The pass called `name`: `constant-folding`, `copy-propagation`, `dead-store-elimination`,
+/// `unreachable-blocks` or `branch-simplification`.
+pub fn from_name(name: &str) -> Option<Box<dyn Pass>> {
+    match name {
+        "constant-folding" => Some(Box::new(ConstantFolding)),
+        "copy-propagation" => Some(Box::new(CopyPropagation)),
+        "dead-store-elimination" => Some(Box::new(DeadStoreElimination)),
+        "unreachable-blocks" => Some(Box::new(UnreachableBlocks)),
+        "branch-simplification" => Some(Box::new(BranchSimplification)),
+        _ => None,
+    }
+}
+
+/// Replaces arithmetic and comparisons of constant ints and bools with a copy of the result.
+/// Divisions by zero and results overflowing an int are left for the program to run into.
+pub struct ConstantFolding;
+
+impl Pass for ConstantFolding {
+    fn name(&self) -> &'static str {
+        "constant-folding"
+    }
+
+    fn run(&mut self, func: &mut FirFunction) -> usize {
+        let mut changes = 0;
+        for instr in func.blocks.iter_mut().flat_map(|block| &mut block.instrs) {
+            if let (Some(dst), Some(value)) = (instr.dst(), fold(instr)) {
+                *instr = FirInstr::Copy(dst, value);
+                changes += 1;
+            }
+        }
+        changes
+    }
+}
+
+fn fold(instr: &FirInstr) -> Option<FirValue> {
+    use FirValue::{ConstBool, ConstInt};
+    let value = match instr {
+        FirInstr::Add(_, ConstInt(a), ConstInt(b)) => ConstInt(a.checked_add(*b)?),
+        FirInstr::Sub(_, ConstInt(a), ConstInt(b)) => ConstInt(a.checked_sub(*b)?),
+        FirInstr::Mul(_, ConstInt(a), ConstInt(b)) => ConstInt(a.checked_mul(*b)?),
+        FirInstr::Div(_, ConstInt(a), ConstInt(b)) => ConstInt(a.checked_div(*b)?),
+        FirInstr::Mod(_, ConstInt(a), ConstInt(b)) => ConstInt(a.checked_rem(*b)?),
+        FirInstr::Eq(_, ConstInt(a), ConstInt(b)) => ConstBool(a == b),
+        FirInstr::Eq(_, ConstBool(a), ConstBool(b)) => ConstBool(a == b),
+        FirInstr::Lt(_, ConstInt(a), ConstInt(b)) => ConstBool(a < b),
+        FirInstr::Gt(_, ConstInt(a), ConstInt(b)) => ConstBool(a > b),
+        _ => return None,
+    };
+    Some(value)
+}
+
+/// Replaces reads of a local holding a copy with what it copied, when both are written only once,
+/// so the copy always holds the same value. Stores count as copies to the variable they write, and
+/// so do phis taking the same value from every block.
+pub struct CopyPropagation;
+
+impl Pass for CopyPropagation {
+    fn name(&self) -> &'static str {
+        "copy-propagation"
+    }
+
+    fn run(&mut self, func: &mut FirFunction) -> usize {
+        let writes = write_counts(func);
+        let mut copies: HashMap<u32, FirValue> = HashMap::new();
+        for instr in func.blocks.iter().flat_map(|block| &block.instrs) {
+            let (dst, value) = match instr {
+                FirInstr::Copy(dst, value) => (*dst, value),
+                FirInstr::StoreLocal(name, value) => match func.local(name) {
+                    Some(dst) => (dst, value),
+                    None => continue,
+                },
+                FirInstr::Phi(dst, incoming) => match incoming.split_first() {
+                    Some(((_, value), rest)) if rest.iter().all(|(_, other)| other == value) => (*dst, value),
+                    _ => continue,
+                },
+                _ => continue,
+            };
+            let stable = match value {
+                FirValue::Local(src) => *src != dst && writes.get(src).copied().unwrap_or(0) <= 1,
+                _ => true,
+            };
+            if stable && writes.get(&dst) == Some(&1) {
+                copies.insert(dst, value.clone());
+            }
+        }
+
+        // follows chains of copies to the value at their start
+        let resolve = |value: &FirValue| {
+            let mut value = value.clone();
+            for _ in 0..=copies.len() {
+                match &value {
+                    FirValue::Local(id) if copies.contains_key(id) => value = copies[id].clone(),
+                    _ => break,
+                }
+            }
+            value
+        };
+        let mut changes = 0;
+        for instr in func.blocks.iter_mut().flat_map(|block| &mut block.instrs) {
+            for operand in instr.operands_mut() {
+                let value = resolve(operand);
+                if value != *operand {
+                    *operand = value;
+                    changes += 1;
+                }
+            }
+        }
+        changes
+    }
+}
+
+/// Removes instructions writing a local that isn't read before it is written again or the function
+/// returns, found by a liveness analysis. Calls stay for their side effects, and divisions for
+/// the error they raise when the divisor turns out to be zero.
+pub struct DeadStoreElimination;
+
+impl Pass for DeadStoreElimination {
+    fn name(&self) -> &'static str {
+        "dead-store-elimination"
+    }
+
+    fn run(&mut self, func: &mut FirFunction) -> usize {
+        let mut changes = 0;
+        // removing an instruction can leave the ones computing its operands in other blocks dead
+        loop {
+            let live_out = live_out(func);
+            let before = changes;
+            for (b, live) in live_out.into_iter().enumerate() {
+                let mut live = live;
+                let mut instrs = std::mem::take(&mut func.blocks[b].instrs);
+                for i in (0..instrs.len()).rev() {
+                    let written = written(func, &instrs[i]);
+                    if written.is_some_and(|id| !live.contains(&id)) && !has_effects(&instrs[i]) {
+                        instrs.remove(i);
+                        changes += 1;
+                        continue;
+                    }
+                    transfer(func, &instrs[i], &mut live);
+                }
+                func.blocks[b].instrs = instrs;
+            }
+            if changes == before {
+                return changes;
+            }
+        }
+    }
+}
+
+// whether running an instruction does more than write its result, so it has to stay even when
+// nothing reads that. Only a non-zero int or a float is known not to fail as a divisor
+fn has_effects(instr: &FirInstr) -> bool {
+    match instr {
+        FirInstr::Call { .. } => true,
+        FirInstr::Div(_, _, divisor) | FirInstr::Mod(_, _, divisor) => {
+            !matches!(divisor, FirValue::ConstInt(d) if *d != 0) && !matches!(divisor, FirValue::ConstFloat(_))
+        }
+        _ => false,
+    }
+}
+
+// the local an instruction writes, stores included
+fn written(func: &FirFunction, instr: &FirInstr) -> Option<u32> {
+    match instr {
+        FirInstr::StoreLocal(name, _) => func.local(name),
+        _ => instr.dst(),
+    }
+}
+
+// updates the locals live after `instr` to those live before it. The operands of a phi are read
+// on the way from the other blocks, see `live_out`
+fn transfer(func: &FirFunction, instr: &FirInstr, live: &mut HashSet<u32>) {
+    if let Some(id) = written(func, instr) {
+        live.remove(&id);
+    }
+    match instr {
+        FirInstr::Phi(..) => {}
+        FirInstr::LoadLocal(_, name) => live.extend(func.local(name)),
+        _ => live.extend(instr.operands().into_iter().filter_map(|operand| match operand {
+            FirValue::Local(id) => Some(*id),
+            _ => None,
+        })),
+    }
+}
+
+// the locals live at the end of each block: read by a successor before it writes them,
+// including the values its phis take coming from the block
+fn live_out(func: &FirFunction) -> Vec<HashSet<u32>> {
+    let cfg = Cfg::new(func);
+    let mut live_in: Vec<HashSet<u32>> = vec![HashSet::new(); func.blocks.len()];
+    let mut live_out: Vec<HashSet<u32>> = vec![HashSet::new(); func.blocks.len()];
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for b in (0..func.blocks.len()).rev() {
+            let mut out = HashSet::new();
+            for &succ in &cfg.succs[b] {
+                out.extend(live_in[succ].iter().copied());
+                for instr in &func.blocks[succ].instrs {
+                    if let FirInstr::Phi(_, incoming) = instr {
+                        for (label, value) in incoming {
+                            if let (true, FirValue::Local(id)) = (*label == func.blocks[b].label, value) {
+                                out.insert(*id);
+                            }
+                        }
+                    }
+                }
+            }
+            let mut live = out.clone();
+            for instr in func.blocks[b].instrs.iter().rev() {
+                transfer(func, instr, &mut live);
+            }
+            if live != live_in[b] || out != live_out[b] {
+                live_in[b] = live;
+                live_out[b] = out;
+                changed = true;
+            }
+        }
+    }
+    live_out
+}
+
+/// Removes the blocks control can't reach, and the values phis would take coming from them.
+pub struct UnreachableBlocks;
+
+impl Pass for UnreachableBlocks {
+    fn name(&self) -> &'static str {
+        "unreachable-blocks"
+    }
+
+    fn run(&mut self, func: &mut FirFunction) -> usize {
+        let removed = remove_unreachable_blocks(func);
+        if removed > 0 {
+            let labels: HashSet<String> = func.blocks.iter().map(|block| block.label.clone()).collect();
+            for instr in func.blocks.iter_mut().flat_map(|block| &mut block.instrs) {
+                if let FirInstr::Phi(_, incoming) = instr {
+                    incoming.retain(|(label, _)| labels.contains(label));
+                }
+            }
+        }
+        removed
+    }
+}
+
+/// Turns branches on a constant, or to the same block either way, into jumps, jumps over blocks
+/// that only jump on, and merges a block into its predecessor when that one jumps straight to it.
+pub struct BranchSimplification;
+
+impl Pass for BranchSimplification {
+    fn name(&self) -> &'static str {
+        "branch-simplification"
+    }
+
+    fn run(&mut self, func: &mut FirFunction) -> usize {
+        let mut changes = 0;
+        for b in 0..func.blocks.len() {
+            let Some(FirInstr::JumpIf { cond, then_label, else_label }) = func.blocks[b].instrs.last() else { continue };
+            let (taken, dropped) = match cond {
+                FirValue::ConstBool(true) => (then_label.clone(), else_label.clone()),
+                FirValue::ConstBool(false) => (else_label.clone(), then_label.clone()),
+                _ if then_label == else_label => (then_label.clone(), then_label.clone()),
+                _ => continue,
+            };
+            if dropped != taken {
+                let from = func.blocks[b].label.clone();
+                remove_incoming(func, &dropped, &from);
+            }
+            *func.blocks[b].instrs.last_mut().unwrap() = FirInstr::Jump(taken);
+            changes += 1;
+        }
+
+        // blocks that only jump on, to a block without phis, which would need a value for the new predecessor
+        let phis: HashSet<String> = func.blocks.iter().filter(|block| matches!(block.instrs.first(), Some(FirInstr::Phi(..)))).map(|block| block.label.clone()).collect();
+        let forwards: HashMap<String, String> = func.blocks.iter().skip(1).filter_map(|block| match block.instrs.as_slice() {
+            [FirInstr::Jump(next)] if *next != block.label && !phis.contains(next) => Some((block.label.clone(), next.clone())),
+            _ => None,
+        }).collect();
+        for block in &mut func.blocks {
+            let targets = match block.instrs.last_mut() {
+                Some(FirInstr::Jump(label)) => vec![label],
+                Some(FirInstr::JumpIf { then_label, else_label, .. }) => vec![then_label, else_label],
+                _ => continue,
+            };
+            for target in targets {
+                if let Some(next) = forwards.get(target.as_str()) {
+                    *target = next.clone();
+                    changes += 1;
+                }
+            }
+        }
+
+        while let Some((b, target)) = mergeable(func) {
+            let merged = func.blocks.remove(target);
+            let block = &mut func.blocks[if target < b { b - 1 } else { b }];
+            block.instrs.pop();
+            block.instrs.extend(merged.instrs);
+            let into = block.label.clone();
+            for instr in func.blocks.iter_mut().flat_map(|block| &mut block.instrs) {
+                if let FirInstr::Phi(_, incoming) = instr {
+                    for (label, _) in incoming.iter_mut().filter(|(label, _)| *label == merged.label) {
+                        *label = into.clone();
+                    }
+                }
+            }
+            changes += 1;
+        }
+        changes
+    }
+}
+
+// a block ending in a jump to a block that only it jumps to, other than the entry
+fn mergeable(func: &FirFunction) -> Option<(usize, usize)> {
+    let cfg = Cfg::new(func);
+    func.blocks.iter().enumerate().find_map(|(b, block)| {
+        let Some(FirInstr::Jump(label)) = block.instrs.last() else { return None };
+        let target = func.blocks.iter().position(|block| block.label == *label)?;
+        let phis = matches!(func.blocks[target].instrs.first(), Some(FirInstr::Phi(..)));
+        (target != 0 && target != b && cfg.preds[target] == [b] && !phis).then_some((b, target))
+    })
+}
+
+// drops the values the phis of block `label` take coming from `from`, which no longer jumps there
+fn remove_incoming(func: &mut FirFunction, label: &str, from: &str) {
+    let Some(block) = func.blocks.iter_mut().find(|block| block.label == label) else { return };
+    for instr in &mut block.instrs {
+        if let FirInstr::Phi(_, incoming) = instr {
+            incoming.retain(|(pred, _)| pred != from);
+        }
+    }
+}
+
+// how many times each local is written, counting a parameter's argument as a write
+fn write_counts(func: &FirFunction) -> HashMap<u32, usize> {
+    let mut writes = HashMap::new();
+    for param in &func.params {
+        if let Some(id) = func.local(param) {
+            *writes.entry(id).or_insert(0) += 1;
+        }
+    }
+    for instr in func.blocks.iter().flat_map(|block| &block.instrs) {
+        let written = match instr {
+            FirInstr::StoreLocal(name, _) => func.local(name),
+            _ => instr.dst(),
+        };
+        if let Some(id) = written {
+            *writes.entry(id).or_insert(0) += 1;
+        }
+    }
+    writes
+}
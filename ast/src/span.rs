@@ -0,0 +1,30 @@
+/// A region of the source text: byte offsets plus the line and column of the first character.
+/// Lines and columns are 1-based, `start..end` is a half-open byte range.
+///
+/// Nodes leave their spans out when they are compared, see `Node`'s `Ord`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+    pub line: usize,
+    pub column: usize,
+}
+
+impl Span {
+    pub fn new(start: usize, end: usize, line: usize, column: usize) -> Self {
+        Self { start, end, line, column }
+    }
+
+    /// Covers everything from the start of `self` to the end of `other`.
+    pub fn to(self, other: Span) -> Span {
+        Span {
+            end: other.end.max(self.end),
+            ..self
+        }
+    }
+
+    /// True for spans that were never filled in, e.g. nodes built by hand rather than parsed.
+    pub fn is_unknown(&self) -> bool {
+        self.line == 0
+    }
+}
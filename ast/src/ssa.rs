@@ -0,0 +1,201 @@
+//! Converting a `FirFunction` into static single assignment form and back.
+//!
+//! In SSA form every local is written by exactly one instruction. Each `StoreLocal` of a variable
+//! becomes a `Copy` to a new version of it, named like `x.v2`, and where different versions meet
+//! at a join point a `Phi` picks the one of the block control came from.
+
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use crate::cfg::{remove_unreachable_blocks, Cfg, Dominators};
+use crate::fir::{FirFunction, FirInstr, FirValue};
+
+/// Converts `func` into SSA form, using the algorithm of Cytron et al: phis go in the iterated
+/// dominance frontier of the blocks storing a variable, then a walk of the dominator tree renames.
+/// Only variables some block reads before storing them get phis, others never live across blocks.
+///
+/// Blocks control can't reach are removed first. Reading a variable before any store on some path
+/// reads its original local, which for a parameter is the argument.
+pub fn to_ssa(func: &mut FirFunction) {
+    remove_unreachable_blocks(func);
+    let cfg = Cfg::new(func);
+    let doms = Dominators::new(&cfg);
+
+    // the variables are the locals a StoreLocal writes, with the blocks doing it
+    let mut stored = vec![];
+    for (b, block) in func.blocks.iter().enumerate() {
+        for instr in &block.instrs {
+            if let FirInstr::StoreLocal(name, _) = instr {
+                stored.push((name.clone(), b));
+            }
+        }
+    }
+    let mut stores: BTreeMap<u32, BTreeSet<usize>> = BTreeMap::new();
+    for (name, b) in stored {
+        let var = local(func, &name);
+        stores.entry(var).or_default().insert(b);
+    }
+    let live_in = read_before_stored(func);
+
+    // the variables each block needs a phi for, in the order the phis start the block
+    let mut phis: Vec<Vec<u32>> = vec![vec![]; func.blocks.len()];
+    for (&var, blocks) in stores.iter().filter(|(var, _)| live_in.contains(var)) {
+        let mut worklist: Vec<usize> = blocks.iter().copied().collect();
+        while let Some(b) = worklist.pop() {
+            for &join in doms.frontier(b) {
+                if !phis[join].contains(&var) {
+                    phis[join].push(var);
+                    // the phi is a new store, whose frontier needs phis too
+                    if !blocks.contains(&join) {
+                        worklist.push(join);
+                    }
+                }
+            }
+        }
+    }
+    for (b, vars) in phis.iter().enumerate() {
+        let instrs = &mut func.blocks[b].instrs;
+        instrs.splice(0..0, vars.iter().map(|var| FirInstr::Phi(*var, vec![])));
+    }
+
+    let mut renamer = Renamer {
+        current: stores.keys().map(|var| (*var, vec![FirValue::Local(*var)])).collect(),
+        versions: HashMap::new(),
+        phis,
+        cfg,
+        doms,
+    };
+    if !func.blocks.is_empty() {
+        renamer.rename(func, 0);
+    }
+}
+
+// the locals some block reads before storing, the only ones whose value can come from another block
+fn read_before_stored(func: &FirFunction) -> BTreeSet<u32> {
+    let mut live_in = BTreeSet::new();
+    for block in &func.blocks {
+        let mut stored = BTreeSet::new();
+        for instr in &block.instrs {
+            let read: Vec<u32> = match instr {
+                FirInstr::LoadLocal(_, name) => func.local(name).into_iter().collect(),
+                _ => instr.operands().into_iter().filter_map(|operand| match operand {
+                    FirValue::Local(id) => Some(*id),
+                    _ => None,
+                }).collect(),
+            };
+            live_in.extend(read.into_iter().filter(|id| !stored.contains(id)));
+            if let FirInstr::StoreLocal(name, _) = instr {
+                stored.extend(func.local(name));
+            }
+        }
+    }
+    live_in
+}
+
+fn local(func: &mut FirFunction, name: &str) -> u32 {
+    match func.local(name) {
+        Some(var) => var,
+        None => func.add_local(name),
+    }
+}
+
+struct Renamer {
+    // the version of each variable holding its value at the point being renamed, innermost last
+    current: HashMap<u32, Vec<FirValue>>,
+    // how many versions of each variable there are
+    versions: HashMap<u32, usize>,
+    // the variables of the phis starting each block
+    phis: Vec<Vec<u32>>,
+    cfg: Cfg,
+    doms: Dominators,
+}
+
+impl Renamer {
+    fn new_version(&mut self, func: &mut FirFunction, var: u32) -> u32 {
+        let version = self.versions.entry(var).or_insert(0);
+        *version += 1;
+        let name = format!("{}.v{}", func.locals[var as usize], version);
+        let dst = func.add_local(name);
+        self.current.get_mut(&var).expect("a variable").push(FirValue::Local(dst));
+        dst
+    }
+
+    fn value(&self, value: &FirValue) -> FirValue {
+        match value {
+            FirValue::Local(var) => self.current.get(var).and_then(|versions| versions.last()).cloned().unwrap_or(value.clone()),
+            _ => value.clone(),
+        }
+    }
+
+    // renames block `b` and then the blocks it dominates, so every use sees the store reaching it
+    fn rename(&mut self, func: &mut FirFunction, b: usize) {
+        let mut defined = vec![];
+        let mut instrs = std::mem::take(&mut func.blocks[b].instrs);
+        for instr in &mut instrs {
+            match instr {
+                FirInstr::Phi(dst, _) => {
+                    let var = *dst;
+                    *dst = self.new_version(func, var);
+                    defined.push(var);
+                }
+                FirInstr::StoreLocal(name, value) => {
+                    let value = self.value(value);
+                    let var = local(func, name);
+                    *instr = FirInstr::Copy(self.new_version(func, var), value);
+                    defined.push(var);
+                }
+                FirInstr::LoadLocal(dst, name) => {
+                    let var = local(func, name);
+                    *instr = FirInstr::Copy(*dst, self.value(&FirValue::Local(var)));
+                }
+                _ => {
+                    for operand in instr.operands_mut() {
+                        *operand = self.value(operand);
+                    }
+                }
+            }
+        }
+        let label = func.blocks[b].label.clone();
+        func.blocks[b].instrs = instrs;
+
+        for succ in self.cfg.succs[b].clone() {
+            for (i, var) in self.phis[succ].iter().enumerate() {
+                let value = self.value(&FirValue::Local(*var));
+                if let FirInstr::Phi(_, incoming) = &mut func.blocks[succ].instrs[i] {
+                    incoming.push((label.clone(), value));
+                }
+            }
+        }
+
+        for child in self.doms.children(b).to_vec() {
+            self.rename(func, child);
+        }
+        for var in defined {
+            self.current.get_mut(&var).expect("a variable").pop();
+        }
+    }
+}
+
+/// Takes `func` out of SSA form by replacing every phi with copies.
+///
+/// Each predecessor copies its incoming value to a new temporary before jumping, and the phi's
+/// block copies the temporary to the phi's local. As no temporary is read before all of them are
+/// written, phis reading each other's locals in a loop still see the values from before the jump.
+pub fn out_of_ssa(func: &mut FirFunction) {
+    let mut copies: Vec<(String, FirInstr)> = vec![];
+    for b in 0..func.blocks.len() {
+        for i in 0..func.blocks[b].instrs.len() {
+            let FirInstr::Phi(dst, incoming) = &func.blocks[b].instrs[i] else { continue };
+            let (dst, incoming) = (*dst, incoming.clone());
+            let tmp = func.add_temp();
+            for (pred, value) in incoming {
+                copies.push((pred, FirInstr::Copy(tmp, value)));
+            }
+            func.blocks[b].instrs[i] = FirInstr::Copy(dst, FirValue::Local(tmp));
+        }
+    }
+
+    for (pred, copy) in copies {
+        let Some(block) = func.blocks.iter_mut().find(|block| block.label == pred) else { continue };
+        let at = block.instrs.len() - usize::from(block.terminator().is_some());
+        block.instrs.insert(at, copy);
+    }
+}
@@ -1,25 +1,312 @@
+use std::cmp::Ordering;
 use std::fmt;
 use std::fmt::Display;
+use std::hash::{Hash, Hasher};
+use std::sync::{Arc, Mutex};
+use crate::bigint::BigInt;
+use crate::bytecode::Chunk;
+use crate::error::RuntimeErrorKind;
 
-#[derive(Debug, Clone, Eq, Hash)]
-#[derive(PartialEq)]
-#[derive(PartialOrd)]
-#[derive(Ord)]
+#[derive(Debug, Clone)]
 pub enum Value {
     Int(i32),
+    /// An integer too large for `Int`. Results that fit are always turned back into an `Int`,
+    /// so a `BigInt` value is never in the `i32` range.
+    BigInt(BigInt),
+    Float(f64),
     Str(String),
     Bool(bool),
     Bin(u32),
+    /// A function compiled for the VM. Functions are compared by identity.
+    Function(Arc<Function>),
+    /// A function together with the variables it captured. This is what the VM calls;
+    /// a plain `Function` only lives in the constant pool. Closures are compared by identity.
+    Closure(Arc<Closure>),
+    /// An array or map on the VM heap. Only the VM can look inside, so objects compare by identity.
+    Object(ObjectRef),
     Null,
 }
 
+/// The index of an object on the VM heap.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct ObjectRef(pub usize);
+
+/// A compiled function, owning its bytecode.
+#[derive(Debug)]
+pub struct Function {
+    pub name: String,
+    pub arity: usize,
+    /// How many variables from enclosing functions the function captures.
+    pub upvalue_count: usize,
+    pub chunk: Chunk,
+}
+
+/// A function paired with the upvalues it captured when it was created.
+#[derive(Debug)]
+pub struct Closure {
+    pub function: Arc<Function>,
+    pub upvalues: Vec<Arc<Mutex<Upvalue>>>,
+}
+
+/// A captured variable. It points at the variable's stack slot while the enclosing call is
+/// running and holds the value itself once that call returns.
+#[derive(Debug, Clone)]
+pub enum Upvalue {
+    /// An absolute index into the VM stack.
+    Open(usize),
+    Closed(Value),
+}
+
 impl Value {
     pub fn from_string(s: String) -> Self {
         if let Ok(i) = s.parse::<i32>() {
             return Value::Int(i);
         }
+        if let Ok(big) = s.parse::<BigInt>() {
+            return Value::from(big);
+        }
+        // only plain decimals, so words like `inf` and `nan` stay strings
+        if s.starts_with(|c: char| c.is_ascii_digit()) {
+            if let Ok(f) = s.parse::<f64>() {
+                return Value::Float(f);
+            }
+        }
         Value::Str(s)
     }
+
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Value::Int(_) | Value::BigInt(_) => "int",
+            Value::Float(_) => "float",
+            Value::Str(_) => "string",
+            Value::Bool(_) => "bool",
+            Value::Bin(_) => "bin",
+            Value::Function(_) | Value::Closure(_) => "function",
+            Value::Object(_) => "object",
+            Value::Null => "null",
+        }
+    }
+
+    /// The value as a float if it is a number, ints are widened.
+    pub fn as_f64(&self) -> Option<f64> {
+        match self {
+            Value::Int(i) => Some(*i as f64),
+            Value::BigInt(big) => Some(big.to_f64()),
+            Value::Float(f) => Some(*f),
+            _ => None,
+        }
+    }
+
+    /// The value as a `BigInt` if it is an integer of either size.
+    pub fn as_bigint(&self) -> Option<BigInt> {
+        match self {
+            Value::Int(i) => Some(BigInt::from(*i)),
+            Value::BigInt(big) => Some(big.clone()),
+            _ => None,
+        }
+    }
+
+    pub fn is_integer(&self) -> bool {
+        matches!(self, Value::Int(_) | Value::BigInt(_))
+    }
+
+    /// Applies an arithmetic operator to two numbers. Ints that overflow are promoted to a
+    /// `BigInt`, and a float on either side makes the result a float.
+    ///
+    /// Returns None when either side isn't a number. Integer division by zero has to be
+    /// ruled out by the caller, see `is_integer_division_by_zero`.
+    pub fn arithmetic(op: Arithmetic, left: &Value, right: &Value) -> Option<Value> {
+        if let (Value::Int(left), Value::Int(right)) = (left, right) {
+            let result = match op {
+                Arithmetic::Add => left.checked_add(*right),
+                Arithmetic::Subtract => left.checked_sub(*right),
+                Arithmetic::Multiply => left.checked_mul(*right),
+                Arithmetic::Divide => left.checked_div(*right),
+                Arithmetic::Modulo => left.checked_rem(*right),
+            };
+            if let Some(result) = result {
+                return Some(Value::Int(result));
+            }
+        }
+        if let (Some(left), Some(right)) = (left.as_bigint(), right.as_bigint()) {
+            let result = match op {
+                Arithmetic::Add => &left + &right,
+                Arithmetic::Subtract => &left - &right,
+                Arithmetic::Multiply => &left * &right,
+                Arithmetic::Divide => left.div_rem(&right).0,
+                Arithmetic::Modulo => left.div_rem(&right).1,
+            };
+            return Some(Value::from(result));
+        }
+        // at least one side is a float, the other is widened
+        let (left, right) = (left.as_f64()?, right.as_f64()?);
+        let result = match op {
+            Arithmetic::Add => left + right,
+            Arithmetic::Subtract => left - right,
+            Arithmetic::Multiply => left * right,
+            Arithmetic::Divide => left / right,
+            Arithmetic::Modulo => left % right,
+        };
+        Some(Value::Float(result))
+    }
+
+    /// True for `/` and `%` on two integers with a zero divisor. Floats follow IEEE 754 instead
+    /// and divide to an infinity or NaN.
+    pub fn is_integer_division_by_zero(op: Arithmetic, left: &Value, right: &Value) -> bool {
+        matches!(op, Arithmetic::Divide | Arithmetic::Modulo)
+            && left.is_integer()
+            && matches!(right, Value::Int(0))
+    }
+
+    /// Applies a bitwise operator. Ints are reinterpreted as bins and the result is always a bin.
+    /// `Ok(None)` when the operand types don't fit the operator, an error when a shift overflows.
+    pub fn bitwise(op: Bitwise, left: &Value, right: &Value) -> Result<Option<Value>, RuntimeErrorKind> {
+        let shift = matches!(op, Bitwise::ShiftLeft | Bitwise::ShiftRight);
+        let (left, right) = match (left, right) {
+            (Value::Bin(left), Value::Bin(right)) if !shift => (*left, *right),
+            (Value::Bin(left), Value::Int(right)) if shift => (*left, *right as u32),
+            (Value::Int(left), Value::Bin(right)) if op != Bitwise::ShiftRight => (*left as u32, *right),
+            (Value::Int(left), Value::Int(right)) if op != Bitwise::ShiftRight => (*left as u32, *right as u32),
+            _ => return Ok(None),
+        };
+        let checked_shift = |shift: fn(u32, u32) -> Option<u32>| {
+            shift(left, right).ok_or_else(|| RuntimeErrorKind::Overflow(format!("shift by {} bits", right as i32)))
+        };
+        let value = match op {
+            Bitwise::And => left & right,
+            Bitwise::Or => left | right,
+            Bitwise::Xor => left ^ right,
+            Bitwise::ShiftLeft => checked_shift(u32::checked_shl)?,
+            Bitwise::ShiftRight => checked_shift(u32::checked_shr)?,
+        };
+        Ok(Some(Value::Bin(value)))
+    }
+
+    /// The value as a bin, like `bin()` in Fox.
+    pub fn to_bin(&self) -> Result<Value, RuntimeErrorKind> {
+        match self {
+            Value::Int(i) => Ok(Value::Bin(*i as u32)),
+            Value::Bin(b) => Ok(Value::Bin(*b)),
+            Value::BigInt(big) => Err(RuntimeErrorKind::Overflow(format!("{} does not fit in a bin", big))),
+            other => Err(RuntimeErrorKind::TypeMismatch { expected: "int".to_string(), found: other.type_name().to_string() }),
+        }
+    }
+
+    /// The value as an integer, like `int()` in Fox. Floats are truncated towards zero.
+    pub fn to_int(&self) -> Result<Value, RuntimeErrorKind> {
+        match self {
+            Value::Int(_) | Value::BigInt(_) => Ok(self.clone()),
+            Value::Float(f) => {
+                if !(f.trunc() >= i32::MIN as f64 && f.trunc() <= i32::MAX as f64) {
+                    return Err(RuntimeErrorKind::Overflow(format!("{:?} does not fit in an int", f)));
+                }
+                Ok(Value::Int(*f as i32))
+            }
+            Value::Bin(b) => Ok(Value::Int(*b as i32)),
+            other => Err(RuntimeErrorKind::TypeMismatch { expected: "number".to_string(), found: other.type_name().to_string() }),
+        }
+    }
+
+    /// Orders two numbers, None if either isn't a number or a NaN is involved.
+    pub fn compare_numbers(left: &Value, right: &Value) -> Option<Ordering> {
+        match (left, right) {
+            (Value::Int(left), Value::Int(right)) => Some(left.cmp(right)),
+            (Value::Bin(left), Value::Bin(right)) => Some(left.cmp(right)),
+            _ => match (left.as_bigint(), right.as_bigint()) {
+                (Some(left), Some(right)) => Some(left.cmp(&right)),
+                _ => left.as_f64()?.partial_cmp(&right.as_f64()?),
+            },
+        }
+    }
+
+    /// Negates a number, `-i32::MIN` becomes a `BigInt`.
+    pub fn negate(&self) -> Option<Value> {
+        match self {
+            Value::Int(i) => Some(i.checked_neg().map_or_else(|| Value::from(-BigInt::from(*i)), Value::Int)),
+            Value::BigInt(big) => Some(Value::from(-big.clone())),
+            Value::Float(f) => Some(Value::Float(-f)),
+            _ => None,
+        }
+    }
+
+    /// Raises a number to a power. Integers raised to a non-negative integer stay exact,
+    /// anything else is computed with floats.
+    pub fn pow(&self, exponent: &Value) -> Option<Value> {
+        if let (Some(base), Value::Int(exponent)) = (self.as_bigint(), exponent) {
+            if let Ok(exponent) = u32::try_from(*exponent) {
+                return Some(Value::from(base.pow(exponent)));
+            }
+        }
+        Some(Value::Float(self.as_f64()?.powf(exponent.as_f64()?)))
+    }
+
+    // orders values of different types by their variant
+    fn rank(&self) -> u8 {
+        match self {
+            Value::Int(_) | Value::BigInt(_) => 0,
+            Value::Float(_) => 1,
+            Value::Str(_) => 2,
+            Value::Bool(_) => 3,
+            Value::Bin(_) => 4,
+            Value::Function(_) => 5,
+            Value::Closure(_) => 6,
+            Value::Object(_) => 7,
+            Value::Null => 8,
+        }
+    }
+}
+
+// Values are used as map keys, so they need a total order. Floats are compared with
+// `total_cmp`, which makes NaN equal to itself; `==` in Fox goes through `compare_value` instead.
+impl Ord for Value {
+    fn cmp(&self, other: &Self) -> Ordering {
+        match (self, other) {
+            (Value::Int(a), Value::Int(b)) => a.cmp(b),
+            (Value::BigInt(a), Value::BigInt(b)) => a.cmp(b),
+            (Value::Int(a), Value::BigInt(b)) => BigInt::from(*a).cmp(b),
+            (Value::BigInt(a), Value::Int(b)) => a.cmp(&BigInt::from(*b)),
+            (Value::Float(a), Value::Float(b)) => a.total_cmp(b),
+            (Value::Str(a), Value::Str(b)) => a.cmp(b),
+            (Value::Bool(a), Value::Bool(b)) => a.cmp(b),
+            (Value::Bin(a), Value::Bin(b)) => a.cmp(b),
+            (Value::Function(a), Value::Function(b)) => Arc::as_ptr(a).cmp(&Arc::as_ptr(b)),
+            (Value::Closure(a), Value::Closure(b)) => Arc::as_ptr(a).cmp(&Arc::as_ptr(b)),
+            (Value::Object(a), Value::Object(b)) => a.cmp(b),
+            _ => self.rank().cmp(&other.rank()),
+        }
+    }
+}
+
+impl PartialOrd for Value {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for Value {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Value {}
+
+impl Hash for Value {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.rank().hash(state);
+        match self {
+            Value::Int(i) => i.hash(state),
+            Value::BigInt(big) => big.hash(state),
+            Value::Float(f) => f.to_bits().hash(state),
+            Value::Str(s) => s.hash(state),
+            Value::Bool(b) => b.hash(state),
+            Value::Bin(b) => b.hash(state),
+            Value::Function(function) => Arc::as_ptr(function).hash(state),
+            Value::Closure(closure) => Arc::as_ptr(closure).hash(state),
+            Value::Object(object) => object.hash(state),
+            Value::Null => (),
+        }
+    }
 }
 
 
@@ -27,21 +314,62 @@ impl Display for Value {
     fn fmt(&self,f: &mut fmt::Formatter) -> fmt::Result {
         match self {
             Value::Int (i) => write!(f, "{}", i),
-            // Value::Float(fl) => write!(f, "{}", fl),
+            Value::BigInt(big) => write!(f, "{}", big),
+            // `{:?}` keeps the `.0` on whole numbers, so `2.0` doesn't print like the int `2`
+            Value::Float(fl) => write!(f, "{:?}", fl),
             Value::Str(s) => write!(f, "{}", s),
             Value::Bool(b) => write!(f, "{}", b),
             Value::Bin(b) => write!(f, "{:b}", b),
+            Value::Function(function) => write!(f, "<fn {}>", function.name),
+            Value::Closure(closure) => write!(f, "<fn {}>", closure.function.name),
+            Value::Object(object) => write!(f, "<object {}>", object.0),
             Value::Null => write!(f, "null"),
         }
     }
 }
 
+impl From<BigInt> for Value {
+    /// Keeps the canonical form: an `Int` whenever the value fits.
+    fn from(big: BigInt) -> Self {
+        match big.to_i32() {
+            Some(i) => Value::Int(i),
+            None => Value::BigInt(big),
+        }
+    }
+}
+
+/// The arithmetic operators shared by the interpreter and the VM.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Arithmetic {
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+    Modulo,
+}
+
+/// The bitwise operators shared by the interpreter and the VM.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Bitwise {
+    And,
+    Or,
+    Xor,
+    ShiftLeft,
+    ShiftRight,
+}
+
 pub fn compare_value(first: &Value, second: &Value) -> bool {
     match (first, second) {
         (Value::Int(i), Value::Int(j)) => i == j,
-        //(Value::Float(f), Value::Float(g)) => f == g,
+        (Value::BigInt(a), Value::BigInt(b)) => a == b,
+        (Value::Function(a), Value::Function(b)) => Arc::ptr_eq(a, b),
+        (Value::Closure(a), Value::Closure(b)) => Arc::ptr_eq(a, b),
+        (Value::Object(a), Value::Object(b)) => a == b,
+        (Value::Float(f), Value::Float(g)) => f == g,
+        (Value::Int(i), Value::Float(f)) | (Value::Float(f), Value::Int(i)) => *i as f64 == *f,
+        (Value::BigInt(b), Value::Float(f)) | (Value::Float(f), Value::BigInt(b)) => b.to_f64() == *f,
         (Value::Str(s), Value::Str(t)) => s == t,
         (Value::Bool(b), Value::Bool(c)) => b == c,
         _ => false,
     }
-}
\ No newline at end of file
+}
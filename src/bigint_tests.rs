@@ -0,0 +1,119 @@
+use crate::scope_tests::eval;
+use crate::error_tests::eval_err;
+use ast::bigint::BigInt;
+use ast::error::RuntimeErrorKind;
+use ast::value::Value;
+
+fn big(s: &str) -> BigInt {
+    s.parse().expect("not an integer")
+}
+
+#[test]
+fn parses_and_prints() {
+    for s in ["0", "7", "-7", "1000000000", "123456789012345678901234567890", "-100000000000000000000"] {
+        assert_eq!(big(s).to_string(), s);
+    }
+    assert_eq!(big("-0").to_string(), "0");
+    assert_eq!(big("000123").to_string(), "123");
+    assert!("12a".parse::<BigInt>().is_err());
+    assert!("".parse::<BigInt>().is_err());
+}
+
+#[test]
+fn arithmetic() {
+    let a = big("123456789012345678901234567890");
+    let b = big("987654321098765432109876543210");
+    assert_eq!((&a + &b).to_string(), "1111111110111111111011111111100");
+    assert_eq!((&a - &b).to_string(), "-864197532086419753208641975320");
+    assert_eq!((&a * &b).to_string(), "121932631137021795226185032733622923332237463801111263526900");
+    assert_eq!((&b - &b), BigInt::zero());
+    assert_eq!(BigInt::from(2).pow(100).to_string(), "1267650600228229401496703205376");
+}
+
+#[test]
+fn division_truncates_towards_zero() {
+    let (q, r) = big("1267650600228229401496703205376").div_rem(&big("1000000007"));
+    assert_eq!(q.to_string(), "1267650591354675262013");
+    assert_eq!(r.to_string(), "976371285");
+    assert_eq!(&(&q * &big("1000000007")) + &r, big("1267650600228229401496703205376"));
+
+    let (q, r) = BigInt::from(-7).div_rem(&BigInt::from(2));
+    assert_eq!((q, r), (BigInt::from(-3), BigInt::from(-1)));
+    let (q, r) = big("-100000000000000000000").div_rem(&big("-30000000000"));
+    assert_eq!((q.to_string(), r.to_string()), ("3333333333".to_string(), "-10000000000".to_string()));
+}
+
+#[test]
+fn ordering() {
+    assert!(big("-100000000000") < BigInt::from(-1));
+    assert!(BigInt::from(-1) < BigInt::zero());
+    assert!(big("99999999999") < big("100000000000"));
+    assert_eq!(big("5000000000").to_i32(), None);
+    assert_eq!(big("-2147483648").to_i32(), Some(i32::MIN));
+}
+
+#[test]
+fn overflow_promotes_and_results_that_fit_demote() {
+    assert_eq!(eval("let x = 2147483647 + 1;", "x"), Value::BigInt(big("2147483648")));
+    assert_eq!(eval("let x = 2147483647 + 1 - 1;", "x"), Value::Int(i32::MAX));
+    assert_eq!(eval("let x = 65536 * 65536;", "x").to_string(), "4294967296");
+    assert_eq!(eval("let x = 10000000000;", "x"), Value::BigInt(big("10000000000")));
+    assert_eq!(eval("let x = -2147483647 - 1; let y = -x;", "y").to_string(), "2147483648");
+    assert_eq!(eval("let x = 10000000000 > 5;", "x"), Value::Bool(true));
+    assert_eq!(eval("let x = 10000000000 == 10000000000;", "x"), Value::Bool(true));
+    assert_eq!(eval("let x = 10000000000 % 7;", "x"), Value::Int(4));
+    assert_eq!(eval("let x = 10000000000 * 0.5;", "x"), Value::Float(5000000000.0));
+}
+
+#[test]
+fn pow() {
+    assert_eq!(eval("let x = pow(3, 4);", "x"), Value::Int(81));
+    assert_eq!(eval("let x = pow(2, -1);", "x"), Value::Float(0.5));
+    assert_eq!(eval("let x = pow(4.0, 0.5);", "x"), Value::Float(2.0));
+    assert_eq!(eval("let x = pow(-2, 63);", "x").to_string(), "-9223372036854775808");
+}
+
+#[test]
+fn digit_sum_of_two_to_the_thousand() {
+    let input = "let n = pow(2, 1000);
+    let sum = 0;
+    while (n > 0) {
+        sum = sum + n % 10;
+        n = n / 10;
+    }";
+    assert_eq!(eval(input, "sum"), Value::Int(1366));
+}
+
+#[test]
+fn factorial_digit_sum() {
+    let input = "let f = 1;
+    for i in 1..101 {
+        f = f * i;
+    }
+    let sum = 0;
+    while (f > 0) {
+        sum = sum + f % 10;
+        f = f / 10;
+    }";
+    assert_eq!(eval(input, "sum"), Value::Int(648));
+}
+
+#[test]
+fn large_fibonacci() {
+    let input = "let a = 0;
+    let b = 1;
+    for i in 0..100 {
+        let t = a + b;
+        a = b;
+        b = t;
+    }";
+    assert_eq!(eval(input, "a").to_string(), "354224848179261915075");
+}
+
+#[test]
+fn fixed_width_operations_report_overflow() {
+    assert!(matches!(eval_err("let x = 1 << 40;").kind, RuntimeErrorKind::Overflow(_)));
+    assert!(matches!(eval_err("let x = bin(10000000000);").kind, RuntimeErrorKind::Overflow(_)));
+    assert!(matches!(eval_err("let x = int(pow(10.0, 20));").kind, RuntimeErrorKind::Overflow(_)));
+    assert_eq!(eval_err("let x = 10000000000 / 0;").kind, RuntimeErrorKind::DivisionByZero);
+}
@@ -0,0 +1,106 @@
+use crate::scope_tests::{eval, eval_ast};
+use ast::node::Node;
+use ast::value::Value;
+
+const MAKE_COUNTER: &str = "fn make_counter() {
+    let count = 0;
+    fn next() {
+        count = count + 1;
+        return count;
+    }
+    return next;
+}
+";
+
+#[test]
+fn counter_keeps_its_state() {
+    let input = format!("{}let c = make_counter(); c(); c(); let x = c();", MAKE_COUNTER);
+    assert_eq!(eval(&input, "x"), Value::Int(3));
+}
+
+#[test]
+fn counters_are_independent() {
+    let input = format!("{}let a = make_counter(); let b = make_counter(); a(); a(); b(); let x = a() * 10 + b();", MAKE_COUNTER);
+    assert_eq!(eval(&input, "x"), Value::Int(32));
+}
+
+#[test]
+fn closures_share_a_captured_variable() {
+    let input = "fn pair() {
+        let n = 0;
+        fn inc() { n = n + 1; return n; }
+        fn get() { return n; }
+        inc();
+        inc();
+        return get;
+    }
+    let get = pair();
+    let x = get();";
+    assert_eq!(eval(input, "x"), Value::Int(2));
+}
+
+#[test]
+fn lambdas_capture_their_scope() {
+    let input = "fn adder(n) {
+        return fn(x) { return x + n; };
+    }
+    let add5 = adder(5);
+    let x = add5(10);";
+    assert_eq!(eval(input, "x"), Value::Int(15));
+}
+
+#[test]
+fn local_functions_can_recurse() {
+    let input = "fn outer(n) {
+        fn fact(k) {
+            if (k < 2) { return 1; }
+            return k * fact(k - 1);
+        }
+        return fact(n);
+    }
+    let x = outer(5);";
+    assert_eq!(eval(input, "x"), Value::Int(120));
+}
+
+#[test]
+fn recursive_closures_do_not_capture_themselves() {
+    let input = "fn outer() {
+        let base = 1;
+        fn fact(k) {
+            if (k < 2) { return base; }
+            return k * fact(k - 1);
+        }
+        return fact;
+    }
+    let f = outer();
+    let x = f(5);";
+    let ast = eval_ast(input);
+    assert_eq!(ast.declarations.get("x").unwrap().val(), Value::Int(120));
+
+    // holding its own binding would make the closure own itself, so it could never be freed
+    let Some(Node::Closure { captured, .. }) = ast.declarations.get("f") else {
+        panic!("expected `f` to be a closure");
+    };
+    assert_eq!(captured.own_name(), Some("fact"));
+    assert!(!captured.binds("fact"));
+    assert!(captured.binds("base"));
+}
+
+#[test]
+fn map_and_filter_take_callbacks() {
+    let input = "let factor = 3;
+    let xs = map([1, 2, 3], fn(x) { return x * factor; });
+    let x = xs[0] + xs[1] * 10 + xs[2] * 100;
+    let ys = filter([1, 2, 3, 4, 5, 6], fn(x) { return x % 2 == 0; });
+    let y = len(ys) * 100 + ys[0] + ys[2];";
+    assert_eq!(eval(input, "x"), Value::Int(963));
+    assert_eq!(eval(input, "y"), Value::Int(308));
+}
+
+#[test]
+fn map_accepts_named_functions() {
+    let input = "fn square(x) { return x * x; }
+    let xs = map([1, 2, 3], square);
+    let x = xs[2];";
+    assert_eq!(eval(input, "x"), Value::Int(9));
+}
@@ -1,41 +1,311 @@
-use crate::bytecode::{Chunk, OpCode, Value};
+use crate::bytecode::{Builtin, Chunk, OpCode, Value, MAX_CONSTANTS, MAX_JUMP};
 use ast::node::{Node, OperatorKind};
 use ast::ast::Ast;
-pub struct Compiler;
+use ast::span::Span;
+use ast::value::Function;
+use std::collections::HashSet;
+use std::sync::Arc;
+
+#[derive(Default)]
+pub struct Compiler {
+    // the loops enclosing the code being compiled, innermost last
+    loops: Vec<LoopContext>,
+    // locals in scope, in stack slot order; a local's index is its slot
+    locals: Vec<Local>,
+    // 0 at the top level, where `let` defines globals
+    scope_depth: usize,
+    // names defined at the top level so far, so blocks can tell updating a global from binding a new local
+    globals: HashSet<String>,
+    // the variables of enclosing functions this function captures, in upvalue index order
+    upvalues: Vec<UpvalueRef>,
+    // the compiler of the function this one is nested in, whose locals can be captured
+    enclosing: Option<Box<Compiler>>,
+    // the span of the innermost node an error came from, recorded as the error passes through it
+    error_span: Option<Span>,
+}
+
+/// An error found while compiling, with the span of the node it is about when that is known.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CompileError {
+    pub message: String,
+    pub span: Option<Span>,
+}
+
+impl std::fmt::Display for CompileError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl std::error::Error for CompileError {}
+
+struct LoopContext {
+    // where `continue` jumps back to
+    start: usize,
+    // `break` jumps, patched to the end of the loop once it is known
+    breaks: Vec<usize>,
+    // locals declared before the loop, the rest are popped by `break` and `continue`
+    locals: usize,
+}
+
+struct Local {
+    name: String,
+    depth: usize,
+    // whether a closure captured it, so its upvalue has to be closed before the slot is dropped
+    captured: bool,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+struct UpvalueRef {
+    // a slot of the enclosing function if `is_local`, otherwise one of its upvalues
+    index: u8,
+    is_local: bool,
+}
+
+// slots are addressed with a one byte operand
+const MAX_LOCALS: usize = u8::MAX as usize + 1;
+// and so are upvalues
+const MAX_UPVALUES: usize = u8::MAX as usize + 1;
 
 impl Compiler {
     fn emit_jump(op: OpCode, chunk: &mut Chunk) -> usize {
         chunk.write(op as u8);
-        chunk.write(0xff);
-        chunk.write(0xff);
-        chunk.code.len() - 2
+        chunk.write_u24(MAX_JUMP);
+        chunk.code.len() - 3
     }
 
     /// Overwrites the placeholder at the given offset with the calculated jump distance.
-    fn patch_jump(offset: usize, chunk: &mut Chunk) {
-        let jump = chunk.code.len() - offset - 2;
+    fn patch_jump(offset: usize, chunk: &mut Chunk) -> Result<(), String> {
+        let jump = chunk.code.len() - offset - 3;
+        if jump > MAX_JUMP {
+            return Err(format!("Jump too large, at most {} bytes of code can be jumped over.", MAX_JUMP));
+        }
+
+        let [_, high, middle, low] = (jump as u32).to_be_bytes();
+        chunk.code[offset..offset + 3].copy_from_slice(&[high, middle, low]);
+        Ok(())
+    }
+
+    /// Emits a backwards jump to `start`.
+    fn emit_loop(start: usize, chunk: &mut Chunk) -> Result<(), String> {
+        chunk.write(OpCode::Loop as u8);
+
+        // + 3 to also jump over the operand itself
+        let offset = chunk.code.len() - start + 3;
+        if offset > MAX_JUMP {
+            return Err(format!("Loop body too large, at most {} bytes of code can be jumped over.", MAX_JUMP));
+        }
+
+        chunk.write_u24(offset);
+        Ok(())
+    }
+
+    // adds the constant, or finds it in the pool already, and emits `op` with its index
+    fn emit_constant(op: OpCode, value: Value, chunk: &mut Chunk) -> Result<(), String> {
+        let index = chunk.add_constant(value);
+        if index >= MAX_CONSTANTS {
+            return Err(format!("Too many constants in one chunk, at most {} are allowed.", MAX_CONSTANTS));
+        }
+        chunk.write_constant_op(op, index);
+        Ok(())
+    }
+
+    fn begin_scope(&mut self) {
+        self.scope_depth += 1;
+    }
+
+    // drops the locals of the innermost scope while keeping the block's value on top of the stack
+    fn end_scope(&mut self, chunk: &mut Chunk) {
+        self.scope_depth -= 1;
+
+        let first = self.locals.iter().position(|local| local.depth > self.scope_depth);
+        if let Some(first) = first {
+            self.close_upvalues(first, chunk);
+            // move the value into the first slot, then pop everything above it
+            chunk.write(OpCode::SetLocal as u8);
+            chunk.write(first as u8);
+            Self::emit_pops(self.locals.len() - first, chunk);
+            self.locals.truncate(first);
+        }
+    }
+
+    // closures still holding on to locals from `first` upwards get their own copy before the slots go away
+    fn close_upvalues(&self, first: usize, chunk: &mut Chunk) {
+        if self.locals[first..].iter().any(|local| local.captured) {
+            chunk.write(OpCode::CloseUpvalues as u8);
+            chunk.write(first as u8);
+        }
+    }
+
+    // the innermost local with this name, searching outwards
+    fn resolve_local(&self, name: &str) -> Option<u8> {
+        self.locals.iter().rposition(|local| local.name == name).map(|slot| slot as u8)
+    }
+
+    // a variable of an enclosing function, captured through every function in between
+    fn resolve_upvalue(&mut self, name: &str) -> Result<Option<u8>, String> {
+        let Some(enclosing) = self.enclosing.as_mut() else {
+            return Ok(None);
+        };
+        if let Some(slot) = enclosing.resolve_local(name) {
+            enclosing.locals[slot as usize].captured = true;
+            return self.add_upvalue(UpvalueRef { index: slot, is_local: true }).map(Some);
+        }
+        match enclosing.resolve_upvalue(name)? {
+            Some(index) => self.add_upvalue(UpvalueRef { index, is_local: false }).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    // capturing the same variable twice shares one upvalue
+    fn add_upvalue(&mut self, upvalue: UpvalueRef) -> Result<u8, String> {
+        if let Some(index) = self.upvalues.iter().position(|existing| *existing == upvalue) {
+            return Ok(index as u8);
+        }
+        if self.upvalues.len() == MAX_UPVALUES {
+            return Err(format!("Too many captured variables, a function can close over at most {}.", MAX_UPVALUES));
+        }
+        self.upvalues.push(upvalue);
+        Ok((self.upvalues.len() - 1) as u8)
+    }
+
+    // the value of the new local is already on top of the stack, which becomes its slot
+    fn add_local(&mut self, name: &str) -> Result<(), String> {
+        if self.locals.len() == MAX_LOCALS {
+            return Err(format!("Too many local variables, at most {} are in scope at once.", MAX_LOCALS));
+        }
+        self.locals.push(Local { name: name.to_string(), depth: self.scope_depth, captured: false });
+        Ok(())
+    }
+
+    fn compile_variable_assignment(&mut self, name: &str, is_let: bool, chunk: &mut Chunk) -> Result<(), String> {
+        if self.scope_depth == 0 {
+            // globals are upserted
+            self.globals.insert(name.to_string());
+            return Self::emit_constant(OpCode::DefineGlobal, Value::Str(name.to_string()), chunk);
+        }
+
+        // `let` shadows anything outside the current scope, but rebinds a name declared in it
+        let slot = match self.resolve_local(name) {
+            Some(slot) if !is_let || self.locals[slot as usize].depth == self.scope_depth => Some(slot),
+            _ => None,
+        };
+        if let Some(slot) = slot {
+            chunk.write(OpCode::SetLocal as u8);
+            chunk.write(slot);
+            chunk.write(OpCode::Pop as u8);
+        } else if let Some(index) = self.resolve_upvalue(name)?.filter(|_| !is_let) {
+            chunk.write(OpCode::SetUpvalue as u8);
+            chunk.write(index);
+            chunk.write(OpCode::Pop as u8);
+        } else if !is_let && self.globals.contains(name) {
+            Self::emit_constant(OpCode::SetGlobal, Value::Str(name.to_string()), chunk)?;
+            chunk.write(OpCode::Pop as u8);
+        } else {
+            // assigning to a name that isn't in scope binds it in the current block
+            self.add_local(name)?;
+        }
+        Ok(())
+    }
 
-        if jump > u16::MAX as usize {
-            panic!("Jump too large!");
+    fn compile_variable(&mut self, name: &str, chunk: &mut Chunk) -> Result<(), String> {
+        if let Some(slot) = self.resolve_local(name) {
+            chunk.write(OpCode::GetLocal as u8);
+            chunk.write(slot);
+        } else if let Some(index) = self.resolve_upvalue(name)? {
+            chunk.write(OpCode::GetUpvalue as u8);
+            chunk.write(index);
+        } else {
+            Self::emit_constant(OpCode::GetGlobal, Value::Str(name.to_string()), chunk)?;
         }
+        Ok(())
+    }
 
-        chunk.code[offset] = ((jump >> 8) & 0xff) as u8;
-        chunk.code[offset + 1] = (jump & 0xff) as u8;
+    // pops the locals from slot `first` upwards without forgetting them, for jumps out of their scope
+    fn drop_locals_from(&self, first: usize, chunk: &mut Chunk) {
+        if first < self.locals.len() {
+            self.close_upvalues(first, chunk);
+        }
+        Self::emit_pops(self.locals.len() - first, chunk);
     }
 
-    pub fn compile(ast: &Ast) -> Result<Chunk, String> {
+    fn emit_pops(count: usize, chunk: &mut Chunk) {
+        for _ in 0..count {
+            chunk.write(OpCode::Pop as u8);
+        }
+    }
+
+    // compiles a function and emits the instruction creating a closure over it
+    fn compile_closure(&mut self, name: &str, parameters: &[Node], body: &[Node], chunk: &mut Chunk) -> Result<(), String> {
+        // a function gets a compiler of its own, so its locals are numbered from its own frame,
+        // and it keeps ours as `enclosing` to resolve the variables it captures
+        let globals = self.globals.clone();
+        let enclosing = std::mem::take(self);
+        *self = Compiler {
+            scope_depth: 1,
+            globals,
+            enclosing: Some(Box::new(enclosing)),
+            ..Compiler::default()
+        };
+        let function = self.compile_function(name, parameters, body, chunk.current_span());
+        let enclosing = self.enclosing.take().expect("the enclosing compiler was just set");
+        let nested = std::mem::replace(self, *enclosing);
+        self.error_span = nested.error_span;
+        let upvalues = nested.upvalues;
+        let function = function?;
+
+        Self::emit_constant(OpCode::Closure, Value::Function(Arc::new(function)), chunk)?;
+        for upvalue in upvalues {
+            chunk.write(upvalue.is_local as u8);
+            chunk.write(upvalue.index);
+        }
+        Ok(())
+    }
+
+    fn compile_function(&mut self, name: &str, parameters: &[Node], body: &[Node], span: Span) -> Result<Function, String> {
+        // slot 0 holds the function being called, the arguments follow it
+        self.locals.push(Local { name: String::new(), depth: 1, captured: false });
+        for parameter in parameters {
+            let (Node::Ident { name, .. } | Node::Identifier { value: name }) = parameter else {
+                return Err(format!("Invalid parameter: {:?}", parameter));
+            };
+            self.add_local(name)?;
+        }
+
+        let mut chunk = Chunk::new();
+        chunk.set_span(span);
+        self.compile_statements(body, &mut chunk)?;
+        // like in the interpreter, running off the end returns nothing rather than the last value
+        chunk.write(OpCode::Pop as u8);
+        chunk.write(OpCode::Nil as u8);
+        chunk.write(OpCode::Return as u8);
+
+        Ok(Function { name: name.to_string(), arity: parameters.len(), upvalue_count: self.upvalues.len(), chunk })
+    }
+
+    pub fn compile(ast: &Ast) -> Result<Chunk, CompileError> {
+        let mut compiler = Compiler::default();
         let mut chunk = Chunk::new();
+        match compiler.compile_script(ast, &mut chunk) {
+            Ok(()) => Ok(chunk),
+            Err(message) => Err(CompileError { message, span: compiler.error_span }),
+        }
+    }
+
+    fn compile_script(&mut self, ast: &Ast, chunk: &mut Chunk) -> Result<(), String> {
+        // functions can assign to globals the script declares after them
+        self.globals = ast.nodes.iter().filter_map(global_name).collect();
 
         if let Some((last_node, preceding_nodes)) = ast.nodes.split_last() {
             for node in preceding_nodes {
-                Self::compile_node(node, &mut chunk)?;
+                self.compile_node(node, chunk)?;
                 
                 if is_expression_node(node) {
                     chunk.write(OpCode::Pop as u8);
                 }
             }
             
-            Self::compile_node(last_node, &mut chunk)?;
+            self.compile_node(last_node, chunk)?;
 
             // If the final node is a statement (not an expression), it produces
             // no value, so we push `nil` as the default result of the script.
@@ -49,145 +319,256 @@ impl Compiler {
         // Add a final instruction to stop the VM
         chunk.write(OpCode::Return as u8);
 
-        Ok(chunk)
+        Ok(())
     }
     
-    fn compile_node(node: &Node, chunk: &mut Chunk) -> Result<(), String> {
+    fn compile_node(&mut self, node: &Node, chunk: &mut Chunk) -> Result<(), String> {
+        // code is attributed to the span of the node it comes from, nodes without a span use their parent's
+        let enclosing_span = chunk.current_span();
+        if let Some(span) = node.span() {
+            chunk.set_span(span);
+        }
+        let result = self.compile_node_code(node, chunk);
+        chunk.set_span(enclosing_span);
+        if result.is_err() && self.error_span.is_none() {
+            self.error_span = node.span().filter(|span| !span.is_unknown());
+        }
+        result
+    }
+
+    // compiles a value that is about to be stored: arrays and maps are values in Fox, so one read
+    // from somewhere else is copied rather than shared, like the interpreter does
+    fn compile_value(&mut self, node: &Node, chunk: &mut Chunk) -> Result<(), String> {
+        self.compile_node(node, chunk)?;
+        if !matches!(node, Node::Atomic { .. } | Node::Array { .. } | Node::HMap { .. } | Node::Lambda { .. }) {
+            chunk.write(OpCode::Copy as u8);
+        }
+        Ok(())
+    }
+
+    fn compile_node_code(&mut self, node: &Node, chunk: &mut Chunk) -> Result<(), String> {
         match node {
-            Node::Atomic { value } => {
-                match value {
-                    Value::Int(i) => {
-                        let constant_index = chunk.add_constant(Value::Int(*i));
-                        chunk.write(OpCode::Constant as u8);
-                        chunk.write(constant_index);
-                    },
-                    Value::Str(s) => {
-                        let constant_index = chunk.add_constant(Value::Str(s.clone()));
-                        chunk.write(OpCode::Constant as u8);
-                        chunk.write(constant_index);
-                    },
-                    Value::Bool(b) => {
-                        let constant_index = chunk.add_constant(Value::Bool(*b));
-                        chunk.write(OpCode::Constant as u8);
-                        chunk.write(constant_index);
-                    },
-                    _ => {
-                        return Err(format!("Unsupported value type: {:?}", value));
-                    },
-                };
-                
+            Node::Atomic { value } => match value {
+                Value::Int(_) | Value::Str(_) | Value::Bool(_) | Value::Float(_) | Value::BigInt(_) | Value::Bin(_) => {
+                    Self::emit_constant(OpCode::Constant, value.clone(), chunk)?;
+                }
+                _ => {
+                    return Err(format!("Unsupported value type: {:?}", value));
+                }
+            },
+            // the right side is only evaluated when the left one doesn't decide the result
+            Node::BinaryExpression { left, operator: OperatorKind::And, right, .. } => {
+                self.compile_node(left, chunk)?;
+                let false_jump = Self::emit_jump(OpCode::JumpIfFalse, chunk);
+                self.compile_node(right, chunk)?;
+                let end_jump = Self::emit_jump(OpCode::Jump, chunk);
+                Self::patch_jump(false_jump, chunk)?;
+                chunk.write(OpCode::OpFalse as u8);
+                Self::patch_jump(end_jump, chunk)?;
             }
-            Node::BinaryExpression { left, operator, right } => {
-                Self::compile_node(left, chunk)?;
-                Self::compile_node(right, chunk)?;
+            Node::BinaryExpression { left, operator: OperatorKind::Or, right, .. } => {
+                self.compile_node(left, chunk)?;
+                let right_jump = Self::emit_jump(OpCode::JumpIfFalse, chunk);
+                chunk.write(OpCode::OpTrue as u8);
+                let end_jump = Self::emit_jump(OpCode::Jump, chunk);
+                Self::patch_jump(right_jump, chunk)?;
+                self.compile_node(right, chunk)?;
+                Self::patch_jump(end_jump, chunk)?;
+            }
+            Node::BinaryExpression { left, operator, right, .. } => {
+                self.compile_node(left, chunk)?;
+                self.compile_node(right, chunk)?;
                 
-                let op_code = OpCode::from(operator.clone());
+                let op_code = OpCode::try_from(operator.clone())?;
                 chunk.write(op_code as u8);
             }
-            Node::UnaryExpression { operator, right } => {
-                Self::compile_node(right, chunk)?;
+            Node::UnaryExpression { operator, right, .. } => {
+                self.compile_node(right, chunk)?;
                 match operator {
                     OperatorKind::Subtract => chunk.write(OpCode::Negate as u8),
-                    _ => return Err("Unsupported unary operator".to_string()),
+                    OperatorKind::Negation => chunk.write(OpCode::OpNot as u8),
+                    _ => return Err(format!("Unsupported unary operator: {:?}", operator)),
                 }
             }
-            Node::AssignStmt { left, right, .. } => {
-                Self::compile_node(right, chunk)?;
-                
+            Node::AssignStmt { left, right, kind, .. } => {
                 match &**left {
-                    Node::Identifier { value: name } => {
-                        let name_index = chunk.add_constant(Value::Str(name.clone()));
-
-                        chunk.write(OpCode::DefineGlobal as u8);
-                        chunk.write(name_index);
+                    Node::Identifier { value: name } | Node::Ident { name, .. } => {
+                        self.compile_value(right, chunk)?;
+                        self.compile_variable_assignment(name, kind == "let", chunk)?;
                     }
-                    Node::Ident { name, kind } => {
-                        let name_index = chunk.add_constant(Value::Str(name.clone()));
-                        chunk.write(OpCode::DefineGlobal as u8);
-                        chunk.write(name_index);
+                    // `x[i] = value`, the container is evaluated first like in the interpreter
+                    Node::IndexExpression { left: container, index, .. } => {
+                        self.compile_node(container, chunk)?;
+                        self.compile_node(index, chunk)?;
+                        self.compile_value(right, chunk)?;
+                        chunk.write(OpCode::SetIndex as u8);
+                        chunk.write(OpCode::Pop as u8);
                     }
                     _ => return Err(format!("Invalid assignment target: {:?}", left)),
                 }
             }
             Node::Identifier { value: name } => {
-                let name_index = chunk.add_constant(Value::Str(name.clone()));
-
-                chunk.write(OpCode::GetGlobal as u8);
-                chunk.write(name_index);
-            }
-            Node::Atomic { value } => {
-                let const_index = chunk.add_constant(value.clone());
-                chunk.write(OpCode::Constant as u8);
-                chunk.write(const_index);
+                self.compile_variable(name, chunk)?;
             }
-            Node::Conditional { condition, consequence, alternative } => {
-                Self::compile_node(condition, chunk)?;
+            Node::Conditional { condition, consequence, alternative, .. } => {
+                self.compile_node(condition, chunk)?;
 
                 let else_jump = Self::emit_jump(OpCode::JumpIfFalse, chunk);
                 
-                Self::compile_block(consequence, chunk)?;
+                self.compile_block(consequence, chunk)?;
                 
                 let end_jump = Self::emit_jump(OpCode::Jump, chunk);
                 
-                Self::patch_jump(else_jump, chunk);
+                Self::patch_jump(else_jump, chunk)?;
                 
-                Self::compile_block(alternative, chunk)?;
+                self.compile_block(alternative, chunk)?;
                 
-                Self::patch_jump(end_jump, chunk);
+                Self::patch_jump(end_jump, chunk)?;
             }
-            Node::FunctionDecl {name, arguments, returns, body} => {
-                let name_index = chunk.add_constant(Value::Str(name.clone().to_string()));
-                chunk.write(OpCode::DefineGlobal as u8);
-                chunk.write(name_index);
+            Node::WhileLoop { condition, body, .. } => {
+                let start = chunk.code.len();
+                self.compile_node(condition, chunk)?;
+                let exit_jump = Self::emit_jump(OpCode::JumpIfFalse, chunk);
 
-                // Compile the function body
-                let mut function_chunk = Chunk::new();
-                
-                for arg in arguments {
-                    let arg_index = chunk.add_constant(Value::Str(arg.clone().to_string()));
-                    function_chunk.write(OpCode::DefineGlobal as u8);
-                    function_chunk.write(arg_index);
+                self.compile_loop_body(start, body, chunk)?;
+                Self::patch_jump(exit_jump, chunk)?;
+                self.patch_breaks(chunk)?;
+            }
+            Node::Loop { body, .. } => {
+                let start = chunk.code.len();
+                self.compile_loop_body(start, body, chunk)?;
+                self.patch_breaks(chunk)?;
+            }
+            Node::ForLoop { variable, range, body, .. } => {
+                self.compile_for_loop(variable, range, body, chunk)?;
+            }
+            // both drop the locals declared inside the loop before jumping out of its body
+            Node::Break { .. } => {
+                let Some(context) = self.loops.last() else {
+                    return Err("`break` outside of a loop".to_string());
+                };
+                let first = context.locals;
+                self.drop_locals_from(first, chunk);
+                let jump = Self::emit_jump(OpCode::Jump, chunk);
+                self.loops.last_mut().expect("checked above").breaks.push(jump);
+            }
+            Node::Continue { .. } => {
+                let Some(context) = self.loops.last() else {
+                    return Err("`continue` outside of a loop".to_string());
+                };
+                let (first, start) = (context.locals, context.start);
+                self.drop_locals_from(first, chunk);
+                Self::emit_loop(start, chunk)?;
+            }
+            Node::FunctionDecl { name, arguments, body, .. } => {
+                let (Node::Ident { name, .. } | Node::Identifier { value: name }) = &**name else {
+                    return Err(format!("Invalid function name: {:?}", name));
+                };
+                // known before the body is compiled, so assignments to it inside the body update the global
+                if self.scope_depth == 0 {
+                    self.globals.insert(name.clone());
+                    self.compile_closure(name, arguments, body, chunk)?;
+                    return self.compile_variable_assignment(name, true, chunk);
                 }
 
-                Self::compile_block(body, &mut function_chunk)?;
-                
-                // Add a return instruction at the end of the function
-                function_chunk.write(OpCode::Return as u8);
-
-                // Store the compiled function in the global scope
-                let func_index = chunk.add_constant(Value::Str(name.clone().to_string()));
-                chunk.write(OpCode::Constant as u8);
-                chunk.write(func_index);
+                // a local function can call itself: its slot is the one the closure is about to be pushed into
+                match self.resolve_local(name) {
+                    Some(slot) if self.locals[slot as usize].depth == self.scope_depth => {
+                        self.compile_closure(name, arguments, body, chunk)?;
+                        chunk.write(OpCode::SetLocal as u8);
+                        chunk.write(slot);
+                        chunk.write(OpCode::Pop as u8);
+                    }
+                    _ => {
+                        self.add_local(name)?;
+                        self.compile_closure(name, arguments, body, chunk)?;
+                    }
+                }
             }
-            Node::Call {name, arguments, returns} => {
-                let name_index = chunk.add_constant(Value::Str(name.clone()));
-                chunk.write(OpCode::GetGlobal as u8);
-                chunk.write(name_index);
-
-                for arg in arguments {
-                    Self::compile_node(arg, chunk)?;
+            Node::Lambda { arguments, body, .. } => {
+                self.compile_closure("lambda", arguments, body, chunk)?;
+            }
+            Node::Return { value, .. } => {
+                self.compile_node(value, chunk)?;
+                chunk.write(OpCode::Return as u8);
+            }
+            // `pow` has its own instruction rather than going through a call
+            Node::Call { name, arguments, .. } if name == "pow" && arguments.len() == 2 => {
+                self.compile_node(&arguments[0], chunk)?;
+                self.compile_node(&arguments[1], chunk)?;
+                chunk.write(OpCode::Power as u8);
+            }
+            Node::Call { name, arguments, .. } if name == "len" && arguments.len() == 1 => {
+                self.compile_node(&arguments[0], chunk)?;
+                chunk.write(OpCode::Len as u8);
+            }
+            Node::Call { name, arguments, .. } if Builtin::from_name(name).is_some() => {
+                let builtin = Builtin::from_name(name).expect("checked by the guard");
+                if arguments.len() > u8::MAX as usize {
+                    return Err(format!("Too many arguments in a call to `{}`.", name));
+                }
+                for argument in arguments {
+                    self.compile_node(argument, chunk)?;
+                }
+                chunk.write(OpCode::Builtin as u8);
+                chunk.write(builtin as u8);
+                chunk.write(arguments.len() as u8);
+            }
+            Node::Call { name, arguments, .. } if name == "print" => {
+                for argument in arguments {
+                    self.compile_node(argument, chunk)?;
+                    chunk.write(OpCode::Print as u8);
+                }
+                // a call is an expression, print's value is nil
+                chunk.write(OpCode::Nil as u8);
+            }
+            Node::Call { name, arguments, .. } => {
+                if arguments.len() > u8::MAX as usize {
+                    return Err(format!("Too many arguments in a call to `{}`.", name));
                 }
 
-                // Call the function
+                self.compile_variable(name, chunk)?;
+                for argument in arguments {
+                    self.compile_value(argument, chunk)?;
+                }
                 chunk.write(OpCode::Call as u8);
                 chunk.write(arguments.len() as u8);
             }
-            Node::Ident {name, kind} => {
-                let name_index = chunk.add_constant(Value::Str(name.clone()));
-                
-                match kind.as_str() {
-                    "global" | "var" => {
-                        chunk.write(OpCode::GetGlobal as u8);
-                        chunk.write(name_index);
-                    }
-                    "local" => {
-                        // For local variables, we would need a different handling
-                        // Here we assume local variables are not yet implemented
-                        return Err("Local variable handling not implemented".to_string());
-                    }
-                    _ => return Err(format!("Unsupported identifier kind: {}", kind)),
+            Node::Ident { name, .. } => {
+                self.compile_variable(name, chunk)?;
+            }
+            Node::Array { elements } => {
+                if elements.len() > u8::MAX as usize {
+                    return Err(format!("Too many elements in an array literal, at most {} are allowed.", u8::MAX));
                 }
+                for element in elements {
+                    self.compile_value(element, chunk)?;
+                }
+                chunk.write(OpCode::Array as u8);
+                chunk.write(elements.len() as u8);
+            }
+            // like the interpreter, `<>` always starts out empty
+            Node::HMap { .. } => {
+                chunk.write(OpCode::Map as u8);
             }
+            Node::IndexExpression { left, index, .. } => {
+                self.compile_node(left, chunk)?;
+                self.compile_node(index, chunk)?;
+                chunk.write(OpCode::GetIndex as u8);
+            }
+            Node::MethodCall { name, target, arguments, .. } => {
+                if arguments.len() > u8::MAX as usize {
+                    return Err(format!("Too many arguments in a call to `{}`.", name));
+                }
+                self.compile_variable(target, chunk)?;
+                for argument in arguments {
+                    self.compile_value(argument, chunk)?;
+                }
+                Self::emit_constant(OpCode::Invoke, Value::Str(name.clone()), chunk)?;
+                chunk.write(arguments.len() as u8);
+            }
+            // a type declaration does nothing when run, like in the interpreter
+            Node::Type { .. } => {}
             _ => {
                 return Err(format!("Unsupported expression node: {:?}", node));
             },
@@ -195,16 +576,86 @@ impl Compiler {
         Ok(())
     }
 
-    fn compile_block(nodes: &[Node], chunk: &mut Chunk) -> Result<(), String> {
+    // compiles the body and the jump back to `start`, the loop stays open for `patch_breaks`
+    fn compile_loop_body(&mut self, start: usize, body: &[Node], chunk: &mut Chunk) -> Result<(), String> {
+        self.loops.push(LoopContext { start, breaks: Vec::new(), locals: self.locals.len() });
+
+        self.compile_block(body, chunk)?;
+        // the body leaves a value behind like any other block
+        chunk.write(OpCode::Pop as u8);
+        Self::emit_loop(start, chunk)
+    }
+
+    // `for i in start..end`: like the interpreter, the bounds are evaluated once and a hidden counter
+    // drives the loop, so assigning to `i` in the body doesn't change how often it runs
+    fn compile_for_loop(&mut self, variable: &str, range: &(Box<Node>, Box<Node>), body: &[Node], chunk: &mut Chunk) -> Result<(), String> {
+        self.begin_scope();
+        // the hidden slots have names no identifier can have
+        let counter = self.locals.len() as u8;
+        self.compile_node(&range.0, chunk)?;
+        self.add_local("")?;
+        let end = counter + 1;
+        self.compile_node(&range.1, chunk)?;
+        self.add_local("")?;
+        let slot = counter + 2;
+        chunk.write(OpCode::GetLocal as u8);
+        chunk.write(counter);
+        self.add_local(variable)?;
+
+        // the increment comes first, so `continue` can jump back to it; the first iteration skips it
+        let check_jump = Self::emit_jump(OpCode::Jump, chunk);
+        let increment = chunk.code.len();
+        chunk.write(OpCode::GetLocal as u8);
+        chunk.write(counter);
+        Self::emit_constant(OpCode::Constant, Value::Int(1), chunk)?;
+        for op in [OpCode::Add as u8, OpCode::SetLocal as u8, counter, OpCode::SetLocal as u8, slot, OpCode::Pop as u8] {
+            chunk.write(op);
+        }
+        Self::patch_jump(check_jump, chunk)?;
+
+        for op in [OpCode::GetLocal as u8, counter, OpCode::GetLocal as u8, end, OpCode::OpLess as u8] {
+            chunk.write(op);
+        }
+        let exit_jump = Self::emit_jump(OpCode::JumpIfFalse, chunk);
+        self.compile_loop_body(increment, body, chunk)?;
+        Self::patch_jump(exit_jump, chunk)?;
+        self.patch_breaks(chunk)?;
+
+        // a loop is a statement, so there is no value to keep above the locals
+        self.scope_depth -= 1;
+        let first = counter as usize;
+        self.drop_locals_from(first, chunk);
+        self.locals.truncate(first);
+        Ok(())
+    }
+
+    // closes the innermost loop, pointing its `break`s at the current end of the chunk
+    fn patch_breaks(&mut self, chunk: &mut Chunk) -> Result<(), String> {
+        let context = self.loops.pop().expect("no loop to close");
+        for jump in context.breaks {
+            Self::patch_jump(jump, chunk)?;
+        }
+        Ok(())
+    }
+
+    fn compile_block(&mut self, nodes: &[Node], chunk: &mut Chunk) -> Result<(), String> {
+        self.begin_scope();
+        self.compile_statements(nodes, chunk)?;
+        self.end_scope(chunk);
+        Ok(())
+    }
+
+    // compiles statements in the current scope, leaving the value of the last one on the stack
+    fn compile_statements(&mut self, nodes: &[Node], chunk: &mut Chunk) -> Result<(), String> {
         if let Some((last_node, preceding_nodes)) = nodes.split_last() {
             for node in preceding_nodes {
-                Self::compile_node(node, chunk)?;
+                self.compile_node(node, chunk)?;
                 if is_expression_node(node) {
                     chunk.write(OpCode::Pop as u8);
                 }
             }
 
-            Self::compile_node(last_node, chunk)?;
+            self.compile_node(last_node, chunk)?;
             
             if !is_expression_node(last_node) {
                 chunk.write(OpCode::Nil as u8);
@@ -217,24 +668,39 @@ impl Compiler {
     }
 }
 
+// the global a top level statement declares, if it declares one
+fn global_name(node: &Node) -> Option<String> {
+    let target = match node {
+        Node::AssignStmt { left, .. } => left,
+        Node::FunctionDecl { name, .. } => name,
+        _ => return None,
+    };
+    match &**target {
+        Node::Ident { name, .. } | Node::Identifier { value: name } => Some(name.clone()),
+        _ => None,
+    }
+}
+
 fn is_expression_node(node: &Node) -> bool {
     matches!(
         node,
         Node::BinaryExpression { .. } |
         Node::UnaryExpression { .. } |
         Node::Identifier { .. } |
+        Node::Ident { .. } |
         Node::Atomic { .. } |
         Node::Call { .. } |
         Node::MethodCall { .. } |
         Node::IndexExpression { .. } |
         Node::Array { .. } |
         Node::HMap { .. } |
-        Node::Conditional { .. }
+        Node::Conditional { .. } |
+        Node::Lambda { .. }
     )
 }
 
 pub mod debug {
-    use super::{Chunk, OpCode};
+    use super::{Builtin, Chunk, OpCode, Value};
 
     /// Prints a human-readable representation of a bytecode chunk.
     pub fn disassemble_chunk(chunk: &Chunk, name: &str) {
@@ -244,13 +710,29 @@ pub mod debug {
         while offset < chunk.code.len() {
             offset = disassemble_instruction(chunk, offset);
         }
+
+        // functions declared in this chunk carry code of their own
+        for constant in &chunk.constants {
+            if let Value::Function(function) = constant {
+                disassemble_chunk(&function.chunk, &function.name);
+            }
+        }
     }
 
     /// Prints a single instruction and returns the offset of the next one.
     /// This is the core of the disassembler.
     pub fn disassemble_instruction(chunk: &Chunk, offset: usize) -> usize {
-        // Print the byte offset of the instruction
+        // Print the byte offset of the instruction, and its line unless it is the same as the previous one's
         print!("{:04} ", offset);
+        let line = chunk.line_at(offset);
+        if offset > 0 && line == chunk.line_at(offset - 1) {
+            print!("   | ");
+        } else {
+            match line {
+                Some(line) => print!("{:4} ", line),
+                None => print!("   ? "),
+            }
+        }
 
         let instruction = chunk.code[offset];
 
@@ -260,37 +742,53 @@ pub mod debug {
             OpCode::Return | OpCode::Add | OpCode::Subtract | OpCode::Multiply | OpCode::Divide => {
                 simple_instruction(opcode, offset)
             }
-            OpCode::Constant => constant_instruction(opcode, chunk, offset),
             OpCode::Negate => simple_instruction(opcode, offset),
-            OpCode::DefineGlobal => {
-                let constant_index = chunk.code[offset + 1] as usize;
-                let constant_value = &chunk.constants[constant_index];
-                println!("{:_<-16} {:4} '{}'", format!("{:?}", opcode), constant_index, constant_value);
-                offset + 2
-            }
-            OpCode::GetGlobal => {
-                let constant_index = chunk.code[offset + 1] as usize;
-                let constant_value = &chunk.constants[constant_index];
-                println!("{:_<-16} {:4} '{}'", format!("{:?}", opcode), constant_index, constant_value);
-                offset + 2
-            }
-            OpCode::SetGlobal => {
-                let constant_index = chunk.code[offset + 1] as usize;
-                let constant_value = &chunk.constants[constant_index];
-                println!("{:_<-16} {:4} '{}'", format!("{:?}", opcode), constant_index, constant_value);
+            OpCode::Constant | OpCode::ConstantLong
+            | OpCode::DefineGlobal | OpCode::DefineGlobalLong
+            | OpCode::GetGlobal | OpCode::GetGlobalLong
+            | OpCode::SetGlobal | OpCode::SetGlobalLong => constant_instruction(opcode, chunk, offset),
+            OpCode::GetLocal | OpCode::SetLocal | OpCode::GetUpvalue | OpCode::SetUpvalue | OpCode::CloseUpvalues => {
+                let slot = chunk.code[offset + 1];
+                println!("{:_<-16} {:4}", format!("{:?}", opcode), slot);
                 offset + 2
             }
             OpCode::OpTrue | OpCode::OpFalse | OpCode::OpNot => {
                 simple_instruction(opcode, offset)
             }
-            OpCode::OpEqual | OpCode::OpGreater | OpCode::OpLess | OpCode::OpModulo => {
+            OpCode::OpEqual | OpCode::OpGreater | OpCode::OpLess | OpCode::OpModulo | OpCode::Power | OpCode::Print => {
                 simple_instruction(opcode, offset)
             }
-            OpCode::JumpIfFalse | OpCode::Jump => {
-                let jump_offset = ((chunk.code[offset + 1] as usize) << 8) | (chunk.code[offset + 2] as usize);
-                println!("{:_<-16} {:4} -> {}", format!("{:?}", opcode), jump_offset, offset + jump_offset + 3);
+            OpCode::Map | OpCode::GetIndex | OpCode::SetIndex | OpCode::Len | OpCode::Copy => simple_instruction(opcode, offset),
+            OpCode::BitwiseAnd | OpCode::BitwiseOr | OpCode::BitwiseXor | OpCode::ShiftLeft | OpCode::ShiftRight => {
+                simple_instruction(opcode, offset)
+            }
+            OpCode::Array => {
+                let count = chunk.code[offset + 1];
+                println!("{:_<-16} {:4}", format!("{:?}", opcode), count);
+                offset + 2
+            }
+            OpCode::Invoke | OpCode::InvokeLong => {
+                let (constant_index, width) = chunk.read_constant_index(opcode, offset);
+                let arg_count = chunk.code[offset + 1 + width];
+                println!("{:_<-16} {:4} '{}' ({} args)", format!("{:?}", opcode), constant_index, chunk.constants[constant_index], arg_count);
+                offset + 2 + width
+            }
+            OpCode::Builtin => {
+                let builtin = Builtin::from_byte(chunk.code[offset + 1]).map_or("?", |builtin| builtin.name());
+                let arg_count = chunk.code[offset + 2];
+                println!("{:_<-16} {:>4} ({} args)", format!("{:?}", opcode), builtin, arg_count);
                 offset + 3
             }
+            OpCode::JumpIfFalse | OpCode::Jump => {
+                let jump_offset = chunk.read_u24(offset + 1);
+                println!("{:_<-16} {:4} -> {}", format!("{:?}", opcode), jump_offset, offset + jump_offset + 4);
+                offset + 4
+            }
+            OpCode::Loop => {
+                let jump_offset = chunk.read_u24(offset + 1);
+                println!("{:_<-16} {:4} -> {}", format!("{:?}", opcode), jump_offset, offset + 4 - jump_offset);
+                offset + 4
+            }
             OpCode::Pop => {
                 simple_instruction(opcode, offset)
             }
@@ -302,6 +800,23 @@ pub mod debug {
                 println!("{:_<-16} {:4}", format!("{:?}", opcode), arg_count);
                 offset + 2
             }
+            OpCode::Closure | OpCode::ClosureLong => {
+                let (constant_index, width) = chunk.read_constant_index(opcode, offset);
+                let constant_value = &chunk.constants[constant_index];
+                println!("{:_<-16} {:4} '{}'", format!("{:?}", opcode), constant_index, constant_value);
+                let upvalue_count = match constant_value {
+                    Value::Function(function) => function.upvalue_count,
+                    _ => 0,
+                };
+                // each captured variable is described by a pair of bytes
+                let mut offset = offset + 1 + width;
+                for _ in 0..upvalue_count {
+                    let kind = if chunk.code[offset] == 1 { "local" } else { "upvalue" };
+                    println!("{:04}    |                     {} {}", offset, kind, chunk.code[offset + 1]);
+                    offset += 2;
+                }
+                offset
+            }
         }
     }
 
@@ -311,34 +826,32 @@ pub mod debug {
         offset + 1
     }
 
-    /// Helper for printing instructions that have a one-byte operand (the constant index).
+    /// Helper for printing instructions whose only operand is a constant index.
     fn constant_instruction(opcode: OpCode, chunk: &Chunk, offset: usize) -> usize {
-        let constant_index = chunk.code[offset + 1] as usize;
+        let (constant_index, width) = chunk.read_constant_index(opcode, offset);
         let constant_value = &chunk.constants[constant_index];
         println!("{:_<-16} {:4} '{}'", format!("{:?}", opcode), constant_index, constant_value);
-        offset + 2
+        offset + 1 + width
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use std::collections::HashMap;
     use crate::lang_parser::LangParser;
+    use ast::span::Span;
 
     #[test]
     fn test_compile_simple_addition() {
         // 5 + 10
-        let ast = Ast {
-            nodes: vec![
+        let ast = Ast::from_nodes(vec![
                 Node::BinaryExpression {
                     left: Box::new(Node::Atomic { value: Value::Int(5) }),
                     operator: OperatorKind::Add,
                     right: Box::new(Node::Atomic { value: Value::Int(10) }),
+                    span: Span::default(),
                 }
-            ],
-            declarations: HashMap::new(),
-        };
+            ]);
         
         let chunk = Compiler::compile(&ast).expect("Compilation failed");
         
@@ -364,20 +877,19 @@ mod tests {
     #[test]
     fn test_compile_nested_expression() {
         // (5 - 2) * 10
-        let ast = Ast {
-            nodes: vec![
+        let ast = Ast::from_nodes(vec![
                 Node::BinaryExpression {
                     left: Box::new(Node::BinaryExpression {
                         left: Box::new(Node::Atomic { value: Value::Int(5) }),
                         operator: OperatorKind::Subtract,
                         right: Box::new(Node::Atomic { value: Value::Int(2) }),
+                        span: Span::default(),
                     }),
                     operator: OperatorKind::Multiply,
                     right: Box::new(Node::Atomic { value: Value::Int(10) }),
+                    span: Span::default(),
                 }
-            ],
-            declarations: HashMap::new(),
-        };
+            ]);
         
         let chunk = Compiler::compile(&ast).expect("Compilation failed");
         
@@ -406,13 +918,98 @@ mod tests {
         disassemble_chunk(&chunk, "Test Chunk");
     }
     
+    #[test]
+    fn block_locals_use_stack_slots() {
+        let input = "let c = 1;
+        if (c == 1) {
+            let a = 2;
+            let b = 3;
+            a + b
+        }";
+        let ast = LangParser::new(input).parse().expect("unexpected failure");
+        let chunk = Compiler::compile(&ast).expect("Compilation failed");
+
+        let reads_local = |slot: u8| chunk.code.windows(2).any(|w| w == [OpCode::GetLocal as u8, slot]);
+        assert!(reads_local(0) && reads_local(1), "a and b should be read from slots 0 and 1");
+        // only the global's name ends up in the constant pool
+        assert!(chunk.constants.contains(&Value::Str("c".to_string())));
+        assert!(!chunk.constants.contains(&Value::Str("a".to_string())));
+        assert!(!chunk.constants.contains(&Value::Str("b".to_string())));
+    }
+
+    #[test]
+    fn identical_constants_are_shared() {
+        let input = "let a = 1;
+        let b = a + a + 1;
+        b = b + 1.0 + \"a\";";
+        let ast = LangParser::new(input).parse().expect("unexpected failure");
+        let chunk = Compiler::compile(&ast).expect("Compilation failed");
+        // every use of `a` and `1` goes through one constant, but an int is not a float
+        assert_eq!(chunk.constants, vec![Value::Int(1), Value::Str("a".to_string()), Value::Str("b".to_string()), Value::Float(1.0)]);
+    }
+
+    #[test]
+    fn constant_indices_switch_to_long_operands() {
+        let input: String = (0..300).map(|i| format!("let x{} = {};", i, i)).collect();
+        let ast = LangParser::new(&input).parse().expect("unexpected failure");
+        let chunk = Compiler::compile(&ast).expect("Compilation failed");
+        assert_eq!(chunk.constants.len(), 600);
+        assert_eq!(chunk.code[..4], [OpCode::Constant as u8, 0, OpCode::DefineGlobal as u8, 1]);
+        // the 300th value is constant 598, past what a byte can address
+        let last = chunk.code.len() - 10;
+        assert_eq!(chunk.code[last..last + 8], [OpCode::ConstantLong as u8, 0, 2, 86, OpCode::DefineGlobalLong as u8, 0, 2, 87]);
+    }
+
+    #[test]
+    fn operators_without_an_instruction_are_errors() {
+        for input in ["let x = true → false;", "let x = true ↔ false;", "let x = 1 ∈ 2;"] {
+            let ast = LangParser::new(input).parse().expect("unexpected failure");
+            let e = Compiler::compile(&ast).expect_err("expected a compile error");
+            assert!(e.message.starts_with("Unsupported operator: "), "{}", e);
+            assert_eq!(e.span.map(|span| &input[span.start..span.end]), Some(&input[8..input.len() - 1]));
+        }
+    }
+
+    #[test]
+    fn code_is_mapped_to_source_lines() {
+        let input = "let a = 1;
+        let b = a + 2;
+
+        fn add(x, y) {
+            let z = x;
+            return z + y;
+        }";
+        let ast = LangParser::new(input).parse().expect("unexpected failure");
+        let chunk = Compiler::compile(&ast).expect("Compilation failed");
+
+        // the runs cover all of the code, in the order of the lines
+        let mut lines: Vec<usize> = chunk.lines.iter().map(|run| run.span.line).collect();
+        lines.dedup();
+        assert_eq!(lines, vec![1, 2, 4, 0]);
+        assert_eq!(chunk.lines.iter().map(|run| run.length).sum::<usize>(), chunk.code.len());
+        assert_eq!(chunk.line_at(0), Some(1));
+        // the final `Nil` and `Return` don't come from any line
+        assert_eq!(chunk.line_at(chunk.code.len() - 1), None);
+
+        let Some(Value::Function(add)) = chunk.constants.iter().find(|c| matches!(c, Value::Function(_))) else {
+            panic!("expected a function constant");
+        };
+        let mut lines: Vec<usize> = add.chunk.lines.iter().map(|run| run.span.line).collect();
+        lines.dedup();
+        assert_eq!(lines, vec![5, 6, 4]);
+
+        // and each instruction to the span of the node it computes
+        let spans: Vec<&str> = chunk.lines.iter().map(|run| &input[run.span.start..run.span.end]).collect();
+        assert!(spans.contains(&"a + 2"), "{:?}", spans);
+    }
+
     #[test]
     fn simple_function() {
         let input = "fn add(x, y) {
             print(\"Adding\");
         }";
         let mut ast = LangParser::new(input);
-        let mut ast = ast.parse().expect("unexpected failure");
+        let ast = ast.parse().expect("unexpected failure");
         let chunk = Compiler::compile(&ast).expect("Compilation failed");
         assert!(!chunk.code.is_empty(), "Compiled chunk should not be empty");
         use debug::disassemble_chunk;
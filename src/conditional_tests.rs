@@ -209,4 +209,125 @@ fn test_break() {
     
     let res = ast.declarations.get("b").expect("unexpected failure");
     assert_eq!(res.val(), ast::value::Value::Int(2));
-}
\ No newline at end of file
+}
+#[test]
+fn while_loop() {
+    let input = "
+    let a = 1;
+    let b = 2;
+    let sum = 0;
+    while b < 4000000 {
+        if (b % 2 == 0) {
+            sum = sum + b;
+        }
+        let next = a + b;
+        a = b;
+        b = next;
+    }";
+
+    let mut ast = LangParser::new(input);
+    let mut ast = ast.parse().expect("unexpected failure");
+
+    match ast.eval() {
+        Ok(_) => (),
+        Err(e) => panic!("{:?}", e),
+    }
+
+    let res = ast.declarations.get("sum").expect("unexpected failure");
+    assert_eq!(res.val(), ast::value::Value::Int(4613732));
+}
+
+#[test]
+fn infinite_loop_with_break() {
+    let input = "
+    let n = 27;
+    let steps = 0;
+    loop {
+        if (n == 1) {
+            break;
+        }
+        if (n % 2 == 0) {
+            n = n / 2;
+        } else {
+            n = 3 * n + 1;
+        }
+        steps = steps + 1;
+    }";
+
+    let mut ast = LangParser::new(input);
+    let mut ast = ast.parse().expect("unexpected failure");
+
+    match ast.eval() {
+        Ok(_) => (),
+        Err(e) => panic!("{:?}", e),
+    }
+
+    let res = ast.declarations.get("steps").expect("unexpected failure");
+    assert_eq!(res.val(), ast::value::Value::Int(111));
+}
+
+#[test]
+fn continue_skips_the_rest_of_the_body() {
+    let input = "
+    let odd = 0;
+    for i in 0..10 {
+        if (i % 2 == 0) {
+            continue;
+        }
+        odd = odd + i;
+    }
+    let i = 0;
+    let skipped = 0;
+    while i < 10 {
+        i = i + 1;
+        if (i > 3) {
+            continue;
+        }
+        skipped = skipped + 1;
+    }";
+
+    let mut ast = LangParser::new(input);
+    let mut ast = ast.parse().expect("unexpected failure");
+
+    match ast.eval() {
+        Ok(_) => (),
+        Err(e) => panic!("{:?}", e),
+    }
+
+    let res = ast.declarations.get("odd").expect("unexpected failure");
+    assert_eq!(res.val(), ast::value::Value::Int(25));
+    let res = ast.declarations.get("skipped").expect("unexpected failure");
+    assert_eq!(res.val(), ast::value::Value::Int(3));
+}
+
+#[test]
+fn else_if_chain() {
+    let input = "
+    let small = 0;
+    let medium = 0;
+    let large = 0;
+    for i in 0..10 {
+        if (i < 3) {
+            small = small + 1;
+        } else if (i < 6) {
+            medium = medium + 1;
+        } else {
+            large = large + 1;
+        }
+    }";
+
+    let mut ast = LangParser::new(input);
+    let mut ast = ast.parse().expect("unexpected failure");
+
+    match ast.eval() {
+        Ok(_) => (),
+        Err(e) => panic!("{:?}", e),
+    }
+
+    let res = ast.declarations.get("small").expect("unexpected failure");
+    assert_eq!(res.val(), ast::value::Value::Int(3));
+    let res = ast.declarations.get("medium").expect("unexpected failure");
+    assert_eq!(res.val(), ast::value::Value::Int(3));
+    let res = ast.declarations.get("large").expect("unexpected failure");
+    assert_eq!(res.val(), ast::value::Value::Int(4));
+}
@@ -162,7 +162,7 @@ pub fn reduce(node: Node) -> Result<(Node, Node), ReduceError>{
                 }
             }
         }
-        Node::BinaryExpression { left, operator: _operator, right } => {
+        Node::BinaryExpression { left, operator: _operator, right, .. } => {
             match node.operator() {
                 OperatorKind::Implies | OperatorKind::Biconditional => {
                     // |- A -> B becomes A |- B
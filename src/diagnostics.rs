@@ -0,0 +1,304 @@
+use std::fmt::Write;
+
+use ast::error::{RuntimeError, RuntimeErrorKind};
+use ast::span::Span;
+
+use crate::lexer::SyntaxError;
+use crate::parser::ParseError;
+use crate::compiler::CompileError;
+use crate::vm::VmError;
+
+/// A message about a location in the source, rendered the way rustc prints its errors:
+///
+/// ```text
+/// error: undefined variable `z`
+///  --> main.fox:2:13
+///   |
+/// 2 | let y = x + z;
+///   |             ^
+///   = help: declare it first, e.g. `let z = 0;`
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct Diagnostic {
+    pub message: String,
+    pub span: Option<Span>,
+    pub label: Option<String>,
+    pub notes: Vec<String>,
+    pub help: Vec<String>,
+}
+
+impl Diagnostic {
+    pub fn error(message: impl Into<String>) -> Self {
+        Self {
+            message: message.into(),
+            span: None,
+            label: None,
+            notes: Vec::new(),
+            help: Vec::new(),
+        }
+    }
+
+    pub fn with_span(mut self, span: Option<Span>) -> Self {
+        self.span = span.filter(|span| !span.is_unknown());
+        self
+    }
+
+    /// Text printed after the underline.
+    pub fn with_label(mut self, label: impl Into<String>) -> Self {
+        self.label = Some(label.into());
+        self
+    }
+
+    pub fn with_note(mut self, note: impl Into<String>) -> Self {
+        self.notes.push(note.into());
+        self
+    }
+
+    pub fn with_help(mut self, help: impl Into<String>) -> Self {
+        self.help.push(help.into());
+        self
+    }
+
+    /// Renders the diagnostic against the source it was produced from.
+    pub fn render(&self, file_name: &str, source: &str) -> String {
+        let mut out = String::new();
+        let _ = writeln!(out, "error: {}", self.message);
+
+        let Some(span) = self.span else {
+            let _ = writeln!(out, " --> {}", file_name);
+            self.render_footer(&mut out, 1);
+            return out;
+        };
+
+        let line_number = span.line.to_string();
+        let gutter = " ".repeat(line_number.len());
+        let _ = writeln!(out, "{}--> {}:{}:{}", gutter, file_name, span.line, span.column);
+        let _ = writeln!(out, "{} |", gutter);
+
+        if let Some(line) = source.lines().nth(span.line - 1) {
+            // the underline stops at the end of the first line for spans that cover several
+            let start = span.column - 1;
+            let line_len = line.chars().count();
+            let width = source
+                .get(span.start..span.end)
+                .map(|text| text.lines().next().unwrap_or("").chars().count())
+                .unwrap_or(1)
+                .clamp(1, line_len.saturating_sub(start).max(1));
+
+            let _ = writeln!(out, "{} | {}", line_number, line);
+            let _ = write!(out, "{} | {}{}", gutter, " ".repeat(start), "^".repeat(width));
+            if let Some(label) = &self.label {
+                let _ = write!(out, " {}", label);
+            }
+            out.push('\n');
+        }
+
+        self.render_footer(&mut out, gutter.len());
+        out
+    }
+
+    fn render_footer(&self, out: &mut String, indent: usize) {
+        let gutter = " ".repeat(indent);
+        for note in &self.notes {
+            let _ = writeln!(out, "{} = note: {}", gutter, note);
+        }
+        for help in &self.help {
+            let _ = writeln!(out, "{} = help: {}", gutter, help);
+        }
+    }
+}
+
+impl From<&SyntaxError> for Diagnostic {
+    fn from(error: &SyntaxError) -> Self {
+        Diagnostic::error(error.message.clone()).with_span(Some(error.span))
+    }
+}
+
+impl From<&RuntimeError> for Diagnostic {
+    fn from(error: &RuntimeError) -> Self {
+        let diagnostic = Diagnostic::error(error.kind.to_string()).with_span(error.span);
+
+        match &error.kind {
+            RuntimeErrorKind::UndefinedVariable(name) => {
+                diagnostic.with_help(format!("declare it first, e.g. `let {} = 0;`", name))
+            }
+            RuntimeErrorKind::DivisionByZero => diagnostic.with_label("the divisor is zero"),
+            RuntimeErrorKind::Overflow(_) => {
+                diagnostic.with_note("ints grow without limit, but bins and shifts are 32 bits wide")
+            }
+            RuntimeErrorKind::StackOverflow { .. } => {
+                diagnostic.with_help("check that the recursion has a base case, or raise `max_call_depth`")
+            }
+            RuntimeErrorKind::StackUnavailable { .. } => {
+                diagnostic.with_help(format!("lower `max_call_depth`, it can be at most {}", ast::ast::MAX_CALL_DEPTH_LIMIT))
+            }
+            RuntimeErrorKind::BreakOutsideLoop => {
+                diagnostic.with_note("`break` can only be used inside a `for`, `while` or `loop` body")
+            }
+            RuntimeErrorKind::ContinueOutsideLoop => {
+                diagnostic.with_note("`continue` can only be used inside a `for`, `while` or `loop` body")
+            }
+            _ => diagnostic,
+        }
+    }
+}
+
+/// Points at the node the failing instruction was compiled from, the calls that were running become notes.
+impl From<&VmError> for Diagnostic {
+    fn from(error: &VmError) -> Self {
+        let mut diagnostic = Diagnostic::error(error.message.clone()).with_span(error.span());
+        for line in error.trace_lines() {
+            diagnostic = diagnostic.with_note(line);
+        }
+        diagnostic
+    }
+}
+
+impl From<&CompileError> for Diagnostic {
+    fn from(error: &CompileError) -> Self {
+        Diagnostic::error(error.message.clone()).with_span(error.span)
+    }
+}
+
+/// Metamath parse errors don't know where they happened, pair them with `Parser::error_span`.
+impl From<&ParseError> for Diagnostic {
+    fn from(error: &ParseError) -> Self {
+        Diagnostic::error(error.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compiler::Compiler;
+    use crate::lang_parser::LangParser;
+    use crate::parser::Parser;
+    use crate::vm::VM;
+
+    fn eval_diagnostic(source: &str) -> String {
+        let mut parser = LangParser::new(source);
+        let mut ast = parser.parse().expect("unexpected failure");
+        let e = ast.eval().expect_err("expected evaluation to fail");
+        Diagnostic::from(&e).render("test.fox", source)
+    }
+
+    #[test]
+    fn runtime_error() {
+        let source = "let x = 1;\nlet y = x + z;";
+        let expected = "\
+error: undefined variable `z`
+ --> test.fox:2:13
+  |
+2 | let y = x + z;
+  |             ^
+  = help: declare it first, e.g. `let z = 0;`
+";
+        assert_eq!(eval_diagnostic(source), expected);
+    }
+
+    #[test]
+    fn runtime_error_underlines_the_whole_expression() {
+        let source = "let x = 10 / 0;";
+        let expected = "\
+error: division by zero
+ --> test.fox:1:9
+  |
+1 | let x = 10 / 0;
+  |         ^^^^^^ the divisor is zero
+";
+        assert_eq!(eval_diagnostic(source), expected);
+    }
+
+    #[test]
+    fn vm_error_points_at_its_node() {
+        let source = "fn f(a) {\n    return a - \"s\";\n}\nlet r = f(2);";
+        let ast = LangParser::new(source).parse().expect("unexpected failure");
+        let chunk = Compiler::compile(&ast).expect("compilation failed");
+        let e = VM::new().interpret(&chunk).expect_err("expected the VM to fail");
+        let rendered = Diagnostic::from(&e).render("test.fox", source);
+        let expected = "\
+error: type mismatch: expected operands for Subtract, found int and string
+ --> test.fox:2:12
+  |
+2 |     return a - \"s\";
+  |            ^^^^^^^
+  = note: [line 2] in f
+  = note: [line 4] in script
+";
+        assert_eq!(rendered, expected);
+    }
+
+    #[test]
+    fn compile_error_points_at_its_node() {
+        // inside a function, which has a compiler of its own
+        let source = "fn f() {\n    let x = 1 ∈ 2;\n}";
+        let ast = LangParser::new(source).parse().expect("unexpected failure");
+        let e = Compiler::compile(&ast).expect_err("expected a compile error");
+        let rendered = Diagnostic::from(&e).render("test.fox", source);
+        let expected = "\
+error: Unsupported operator: ElementOf
+ --> test.fox:2:13
+  |
+2 |     let x = 1 ∈ 2;
+  |             ^^^^^
+";
+        assert_eq!(rendered, expected);
+    }
+
+    #[test]
+    fn syntax_error() {
+        let source = "let x = 1;\nlet y = );";
+        let e = LangParser::new(source).parse().expect_err("expected a syntax error");
+        let rendered = Diagnostic::from(&e).render("test.fox", source);
+        let expected = "\
+error: unexpected token RightParenthesis `)`
+ --> test.fox:2:9
+  |
+2 | let y = );
+  |         ^
+";
+        assert_eq!(rendered, expected);
+    }
+
+    #[test]
+    fn lexer_error() {
+        let source = "let x = 1;\nlet s = \"oops;";
+        let e = LangParser::new(source).parse().expect_err("expected a syntax error");
+        let rendered = Diagnostic::from(&e).render("test.fox", source);
+        let expected = "\
+error: unterminated string literal
+ --> test.fox:2:9
+  |
+2 | let s = \"oops;
+  |         ^
+";
+        assert_eq!(rendered, expected);
+    }
+
+    #[test]
+    fn metamath_parse_error() {
+        let source = "(𝜑 → ".to_string();
+        let mut parser = Parser::new(source.clone());
+        let e = parser.parse().expect_err("expected a parse error");
+        let rendered = Diagnostic::from(&e)
+            .with_span(parser.error_span())
+            .render("axiom.mm", &source);
+        assert!(rendered.starts_with("error: "), "{}", rendered);
+        assert!(rendered.contains(" --> axiom.mm:1:"), "{}", rendered);
+    }
+
+    #[test]
+    fn without_a_span() {
+        let rendered = Diagnostic::error("Operands must be numbers.")
+            .with_note("raised by the VM")
+            .render("test.fox", "");
+        assert_eq!(rendered, "error: Operands must be numbers.\n --> test.fox\n  = note: raised by the VM\n");
+    }
+
+    #[test]
+    fn errors_at_different_places_differ() {
+        let error = |line| Diagnostic::error("division by zero").with_span(Some(Span::new(0, 1, line, 1)));
+        assert_eq!(error(1), error(1));
+        assert_ne!(error(1), error(2));
+    }
+}
@@ -0,0 +1,105 @@
+use crate::lang_parser::LangParser;
+use ast::error::{RuntimeError, RuntimeErrorKind};
+
+// evaluates `input`, which has to fail
+pub(crate) fn eval_err(input: &str) -> RuntimeError {
+    let mut parser = LangParser::new(input);
+    let mut ast = parser.parse().expect("unexpected failure");
+
+    match ast.eval() {
+        Ok(_) => panic!("expected evaluation of {:?} to fail", input),
+        Err(e) => e,
+    }
+}
+
+#[test]
+fn undefined_variable() {
+    let e = eval_err("let x = y + 1;");
+    assert_eq!(e.kind, RuntimeErrorKind::UndefinedVariable("y".to_string()));
+}
+
+#[test]
+fn division_by_zero() {
+    let e = eval_err("let x = 10;
+    let y = x / 0;");
+    assert_eq!(e.kind, RuntimeErrorKind::DivisionByZero);
+}
+
+#[test]
+fn index_out_of_bounds() {
+    let e = eval_err("let x = [1, 2, 3];
+    let y = x[3];");
+    assert_eq!(e.kind, RuntimeErrorKind::IndexOutOfBounds { index: 3, len: 3 });
+}
+
+#[test]
+fn missing_key() {
+    let e = eval_err("let m = <>;
+    m.push(1, 2);
+    let y = m.get(5);");
+    assert_eq!(e.kind, RuntimeErrorKind::MissingKey("5".to_string()));
+}
+
+#[test]
+fn arity_mismatch() {
+    let e = eval_err("fn add(x, y) {
+        return x + y;
+    }
+    let z = add(1);");
+    assert_eq!(e.kind, RuntimeErrorKind::ArityMismatch { name: "add".to_string(), expected: 2, found: 1 });
+}
+
+#[test]
+fn type_mismatch() {
+    let e = eval_err("let x = true;
+    let y = x + 1;");
+    assert!(matches!(e.kind, RuntimeErrorKind::TypeMismatch { .. }), "{:?}", e);
+}
+
+#[test]
+fn break_stops_only_the_loop() {
+    let input = "let x = 0;
+    for i in 0..10 {
+        if (i == 3) {
+            break;
+        }
+        x = i;
+    }";
+    let mut parser = LangParser::new(input);
+    let mut ast = parser.parse().expect("unexpected failure");
+
+    match ast.eval() {
+        Ok(_) => (),
+        Err(e) => panic!("{:?}", e),
+    }
+    assert_eq!(ast.declarations.get("x").unwrap().val(), ast::value::Value::Int(2));
+}
+
+#[test]
+fn loop_control_outside_a_loop() {
+    let e = eval_err("let x = 1;\nbreak;");
+    assert_eq!(e.kind, RuntimeErrorKind::BreakOutsideLoop);
+
+    let e = eval_err("fn f() {\n    continue;\n}\nf();");
+    assert_eq!(e.kind, RuntimeErrorKind::ContinueOutsideLoop);
+}
+
+#[test]
+fn errors_point_at_the_failing_expression() {
+    let e = eval_err("let x = 1;
+let y = x + z;");
+    assert_eq!(e.kind, RuntimeErrorKind::UndefinedVariable("z".to_string()));
+    let span = e.span.expect("expected a span");
+    assert_eq!((span.line, span.column), (2, 13));
+}
+
+#[test]
+fn errors_inside_functions_point_into_the_body() {
+    let e = eval_err("fn div(a, b) {
+    return a / b;
+}
+let x = div(1, 0);");
+    assert_eq!(e.kind, RuntimeErrorKind::DivisionByZero);
+    let span = e.span.expect("expected a span");
+    assert_eq!((span.line, span.column), (2, 12));
+}
@@ -0,0 +1,285 @@
+use std::collections::{HashMap, HashSet};
+use ast::fir::{FirFunction, FirInstr, FirValue};
+use ast::lower::lower_function;
+use crate::lang_parser::LangParser;
+
+// lowers the first statement of `input`, a function declaration
+pub(crate) fn lower(input: &str) -> Result<FirFunction, String> {
+    let ast = LangParser::new(input).parse().expect("unexpected failure");
+    lower_function(&ast.nodes[0])
+}
+
+// lowers `input` and checks it is a well formed control flow graph
+pub(crate) fn lower_cfg(input: &str) -> FirFunction {
+    let func = lower(input).expect("lowering failed");
+    let mut labels = HashSet::new();
+    for block in &func.blocks {
+        assert!(labels.insert(block.label.as_str()), "`{}` labels two blocks", block.label);
+        let terminators = block.instrs.iter().filter(|instr| instr.is_terminator()).count();
+        assert_eq!(terminators, 1, "`{}` has {} terminators", block.label, terminators);
+        assert!(block.terminator().is_some(), "`{}` doesn't end in its terminator", block.label);
+    }
+    for block in &func.blocks {
+        for successor in block.successors() {
+            assert!(labels.contains(successor), "`{}` jumps to the missing block `{}`", block.label, successor);
+        }
+    }
+    func
+}
+
+// runs `func` on integer arguments, to check a transformation doesn't change what a function computes
+pub(crate) fn run(func: &FirFunction, args: &[i32]) -> Option<FirValue> {
+    let mut locals: HashMap<u32, FirValue> = args.iter().enumerate().map(|(i, arg)| (i as u32, FirValue::ConstInt(*arg))).collect();
+    let read = |locals: &HashMap<u32, FirValue>, value: &FirValue| match value {
+        FirValue::Local(id) => locals.get(id).cloned().unwrap_or_else(|| panic!("`{}` is read before it is written", func.locals[*id as usize])),
+        constant => constant.clone(),
+    };
+    let (mut block, mut from) = (&func.blocks[0], None);
+    for _ in 0..10_000 {
+        // all the phis of a block read their values before any of them is written, a phi of
+        // a variable not written on the way here isn't written either
+        let phis: Vec<(u32, FirValue)> = block.instrs.iter().filter_map(|instr| match instr {
+            FirInstr::Phi(dst, incoming) => {
+                let (_, value) = incoming.iter().find(|(label, _)| Some(label) == from).expect("no value for the block control came from");
+                match value {
+                    FirValue::Local(id) => locals.get(id).map(|value| (*dst, value.clone())),
+                    constant => Some((*dst, constant.clone())),
+                }
+            }
+            _ => None,
+        }).collect();
+        locals.extend(phis);
+
+        let mut next = None;
+        for instr in &block.instrs {
+            let int = |value: &FirValue| match read(&locals, value) {
+                FirValue::ConstInt(n) => n,
+                other => panic!("expected an int, got {:?}", other),
+            };
+            let result = match instr {
+                FirInstr::Add(_, a, b) => FirValue::ConstInt(int(a) + int(b)),
+                FirInstr::Sub(_, a, b) => FirValue::ConstInt(int(a) - int(b)),
+                FirInstr::Mul(_, a, b) => FirValue::ConstInt(int(a) * int(b)),
+                FirInstr::Div(_, a, b) => FirValue::ConstInt(int(a) / int(b)),
+                FirInstr::Mod(_, a, b) => FirValue::ConstInt(int(a) % int(b)),
+                FirInstr::Eq(_, a, b) => FirValue::ConstBool(read(&locals, a) == read(&locals, b)),
+                FirInstr::Lt(_, a, b) => FirValue::ConstBool(int(a) < int(b)),
+                FirInstr::Gt(_, a, b) => FirValue::ConstBool(int(a) > int(b)),
+                FirInstr::Copy(_, value) => read(&locals, value),
+                FirInstr::LoadLocal(_, name) => read(&locals, &FirValue::Local(func.local(name).unwrap())),
+                FirInstr::StoreLocal(name, value) => {
+                    let value = read(&locals, value);
+                    locals.insert(func.local(name).unwrap(), value);
+                    continue;
+                }
+                FirInstr::Return(value) => return value.as_ref().map(|value| read(&locals, value)),
+                FirInstr::Jump(label) => {
+                    next = Some(label);
+                    break;
+                }
+                FirInstr::JumpIf { cond, then_label, else_label } => {
+                    next = Some(if read(&locals, cond) == FirValue::ConstBool(true) { then_label } else { else_label });
+                    break;
+                }
+                FirInstr::Phi(..) | FirInstr::Nop => continue,
+                FirInstr::Call { .. } => panic!("calls can't be run"),
+            };
+            locals.insert(instr.dst().unwrap(), result);
+        }
+        from = Some(&block.label);
+        block = func.block(next.expect("the block has no terminator")).expect("a jump to a missing block");
+    }
+    panic!("`{}` ran too long", func.name)
+}
+
+fn labels(func: &FirFunction) -> Vec<&str> {
+    func.blocks.iter().map(|block| block.label.as_str()).collect()
+}
+
+fn terminator<'a>(func: &'a FirFunction, label: &str) -> &'a FirInstr {
+    func.block(label).and_then(|block| block.terminator()).expect("missing block")
+}
+
+fn jump(label: &str) -> String {
+    format!("{:?}", FirInstr::Jump(label.to_string()))
+}
+
+#[test]
+fn conditionals_branch_to_their_own_blocks() {
+    let func = lower_cfg("fn f(x) {
+        if (x > 1) { x = 2; } else { x = 3; }
+        return x;
+    }");
+    assert_eq!(labels(&func), vec!["entry", "then1", "else2", "join3"]);
+    assert!(matches!(terminator(&func, "entry"), FirInstr::JumpIf { then_label, else_label, .. } if then_label == "then1" && else_label == "else2"));
+    assert_eq!(format!("{:?}", terminator(&func, "then1")), jump("join3"));
+    assert_eq!(format!("{:?}", terminator(&func, "else2")), jump("join3"));
+    assert!(matches!(terminator(&func, "join3"), FirInstr::Return(Some(FirValue::Local(0)))));
+}
+
+#[test]
+fn conditionals_without_else_branch_to_the_join() {
+    let func = lower_cfg("fn f(x) { if (x > 1) { x = 2; } return x; }");
+    assert_eq!(labels(&func), vec!["entry", "then1", "join2"]);
+    assert!(matches!(terminator(&func, "entry"), FirInstr::JumpIf { else_label, .. } if else_label == "join2"));
+}
+
+#[test]
+fn nested_returns_end_their_branch() {
+    let func = lower_cfg("fn sign(x) {
+        if (x < 0) { return 0 - 1; } else { if (x > 0) { return 1; } }
+        return 0;
+    }");
+    assert_eq!(labels(&func), vec!["entry", "then1", "else2", "join3", "then4", "join5"]);
+    assert!(matches!(terminator(&func, "then1"), FirInstr::Return(Some(_))));
+    assert!(matches!(terminator(&func, "then4"), FirInstr::Return(Some(FirValue::ConstInt(1)))));
+    assert_eq!(format!("{:?}", terminator(&func, "join5")), jump("join3"));
+    assert!(matches!(terminator(&func, "join3"), FirInstr::Return(Some(FirValue::ConstInt(0)))));
+}
+
+#[test]
+fn functions_return_when_they_run_off_the_end() {
+    let func = lower_cfg("fn f(x) { x = x + 1; }");
+    assert_eq!(labels(&func), vec!["entry"]);
+    assert!(matches!(terminator(&func, "entry"), FirInstr::Return(None)));
+
+    // and code after a return is kept, in a block nothing jumps to
+    let func = lower_cfg("fn g(x) { return x; x = 2; }");
+    assert_eq!(labels(&func), vec!["entry", "unreachable1"]);
+    assert!(!func.blocks.iter().any(|block| block.successors().contains(&"unreachable1")));
+}
+
+#[test]
+fn while_loops_have_a_header_latch_and_exit() {
+    let func = lower_cfg("fn f(x) {
+        while (x > 0) {
+            if (x == 5) { break; }
+            if (x == 7) { continue; }
+            x = x - 1;
+        }
+        return x;
+    }");
+    assert_eq!(labels(&func), vec!["entry", "header1", "body2", "latch3", "exit4", "then5", "join6", "then7", "join8"]);
+    assert_eq!(format!("{:?}", terminator(&func, "entry")), jump("header1"));
+    assert!(matches!(terminator(&func, "header1"), FirInstr::JumpIf { then_label, else_label, .. } if then_label == "body2" && else_label == "exit4"));
+    assert_eq!(format!("{:?}", terminator(&func, "then5")), jump("exit4"));
+    assert_eq!(format!("{:?}", terminator(&func, "then7")), jump("latch3"));
+    assert_eq!(format!("{:?}", terminator(&func, "join8")), jump("latch3"));
+    assert_eq!(format!("{:?}", terminator(&func, "latch3")), jump("header1"));
+}
+
+#[test]
+fn for_loops_count_through_their_range() {
+    let func = lower_cfg("fn sum(n) {
+        let total = 0;
+        for i in 0..n { total = total + i; }
+        return total;
+    }");
+    assert_eq!(labels(&func), vec!["entry", "header1", "body2", "latch3", "exit4"]);
+    assert!(matches!(terminator(&func, "header1"), FirInstr::JumpIf { then_label, else_label, .. } if then_label == "body2" && else_label == "exit4"));
+    // the loop variable is set from the hidden counter, which the latch increments
+    let body = &func.block("body2").unwrap().instrs;
+    assert!(matches!(&body[0], FirInstr::StoreLocal(name, FirValue::Local(_)) if name == "i"));
+    let latch = &func.block("latch3").unwrap().instrs;
+    assert!(matches!(&latch[0], FirInstr::Add(_, FirValue::Local(_), FirValue::ConstInt(1))));
+    assert_eq!(format!("{:?}", latch.last().unwrap()), jump("header1"));
+}
+
+#[test]
+fn loops_nest() {
+    let func = lower_cfg("fn f(n) {
+        for i in 0..n {
+            loop { break; }
+            if (i == 3) { break; }
+        }
+        return n;
+    }");
+    // the inner break leaves the inner loop, the outer one the for loop
+    assert_eq!(format!("{:?}", terminator(&func, "body5")), jump("exit7"));
+    assert_eq!(format!("{:?}", terminator(&func, "then8")), jump("exit4"));
+    assert_eq!(format!("{:?}", terminator(&func, "latch6")), jump("body5"));
+}
+
+#[test]
+fn short_circuits_skip_the_right_side() {
+    let func = lower_cfg("fn f(a, b) { return a && b; }");
+    assert_eq!(labels(&func), vec!["entry", "rhs1", "join2"]);
+    assert!(matches!(terminator(&func, "entry"), FirInstr::JumpIf { then_label, else_label, .. } if then_label == "rhs1" && else_label == "join2"));
+
+    let func = lower_cfg("fn f(a, b) { return a || b; }");
+    assert!(matches!(terminator(&func, "entry"), FirInstr::JumpIf { then_label, else_label, .. } if then_label == "join2" && else_label == "rhs1"));
+}
+
+#[test]
+fn nested_short_circuits_get_a_result_each() {
+    let func = lower_cfg("fn f(a, b, c) { return (a > 0 && b > 0) && (c > 0 || a > 1); }");
+    let names: HashSet<&String> = func.locals.iter().collect();
+    assert_eq!(names.len(), func.locals.len());
+    for (a, b, c) in [(1, 1, 1), (1, 1, 0), (2, 1, 0), (1, 0, 1), (0, 1, 1)] {
+        let expected = (a > 0 && b > 0) && (c > 0 || a > 1);
+        assert_eq!(run(&func, &[a, b, c]), Some(FirValue::ConstBool(expected)), "f({}, {}, {})", a, b, c);
+    }
+}
+
+#[test]
+fn blocks_scope_their_declarations() {
+    // a `let` in a block declares a new local, which the outer `x` isn't affected by
+    let func = lower_cfg("fn f(x) { if (x > 0) { let x = 5; } return x; }");
+    assert_eq!(func.locals, vec!["x", "tmp.1", "x.2"]);
+    assert_eq!(run(&func, &[3]), Some(FirValue::ConstInt(3)));
+
+    // assigning without `let` writes the outer variable
+    let func = lower_cfg("fn f(x) { if (x > 0) { x = 5; } return x; }");
+    assert_eq!(run(&func, &[3]), Some(FirValue::ConstInt(5)));
+
+    // and so does a `let` of a parameter's name in the body, which shares the parameters' scope
+    let func = lower_cfg("fn f(x) { let x = x + 1; return x; }");
+    assert_eq!(run(&func, &[3]), Some(FirValue::ConstInt(4)));
+}
+
+#[test]
+fn loop_variables_do_not_leak() {
+    let func = lower_cfg("fn f(n) {
+        let i = 100;
+        let last = 0;
+        for i in 0..n { last = i; }
+        return i + last;
+    }");
+    assert!(func.locals.iter().any(|name| name.starts_with("i.") && !name.contains("counter") && !name.contains("end")));
+    assert_eq!(run(&func, &[4]), Some(FirValue::ConstInt(103)));
+}
+
+#[test]
+fn unsupported_code_is_an_error() {
+    assert_eq!(lower("fn f() { break; }").unwrap_err(), "`break` outside of a loop");
+    assert_eq!(lower("fn f() { return y; }").unwrap_err(), "Unknown variable: y");
+    assert!(lower("fn f(a) { return a[0]; }").unwrap_err().starts_with("lowering to FIR is not supported for IndexExpression"));
+}
+
+#[test]
+fn instructions_write_to_their_destination() {
+    let func = lower_cfg("fn f(a, b) { let c = a + b * 2; return c; }");
+    let entry = &func.block("entry").unwrap().instrs;
+    assert_eq!(entry[0], FirInstr::Mul(2, FirValue::Local(1), FirValue::ConstInt(2)));
+    assert_eq!(entry[1], FirInstr::Add(3, FirValue::Local(0), FirValue::Local(2)));
+    assert_eq!(entry[2], FirInstr::StoreLocal("c".to_string(), FirValue::Local(3)));
+    assert_eq!(entry[3], FirInstr::Return(Some(FirValue::Local(4))));
+    assert_eq!(entry[1].dst(), Some(3));
+    assert_eq!(entry[2].dst(), None);
+}
+
+#[test]
+fn every_local_is_listed_once() {
+    let func = lower_cfg("fn f(n) {
+        let total = 0;
+        for i in 0..n { total = total + g(i); }
+        return total;
+    }");
+    assert_eq!(func.locals, vec!["n", "total", "i.counter1", "i.end1", "i", "tmp.5", "tmp.6", "tmp.7", "tmp.8"]);
+    // each temporary is written exactly once
+    let dsts: Vec<u32> = func.blocks.iter().flat_map(|block| &block.instrs).filter_map(FirInstr::dst).collect();
+    assert_eq!(dsts.len(), dsts.iter().collect::<HashSet<_>>().len());
+    for dst in dsts {
+        assert!(func.locals[dst as usize].starts_with("tmp."));
+    }
+}
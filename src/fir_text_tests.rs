@@ -0,0 +1,151 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use ast::fir::{FirFunction, FirInstr, FirModule, FirValue};
+use ast::passes::PassManager;
+use ast::ssa::to_ssa;
+use crate::fir_tests::lower_cfg;
+
+// the FIR files in golden/fir start with a `; passes: a, b` line. Running those passes over the
+// module has to print the module in the `.out.fir` file next to it, which setting UPDATE_GOLDEN
+// writes instead
+#[test]
+fn golden_files() {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("golden/fir");
+    let mut inputs: Vec<PathBuf> = fs::read_dir(&dir).expect("missing golden/fir")
+        .map(|entry| entry.expect("unreadable directory").path())
+        .filter(|path| path.to_string_lossy().ends_with(".fir") && !path.to_string_lossy().ends_with(".out.fir"))
+        .collect();
+    inputs.sort();
+    assert!(!inputs.is_empty());
+
+    let update = std::env::var_os("UPDATE_GOLDEN").is_some();
+    for input in inputs {
+        let text = fs::read_to_string(&input).expect("unreadable golden file");
+        let passes: Vec<&str> = text.lines().next().and_then(|line| line.strip_prefix("; passes:"))
+            .unwrap_or_else(|| panic!("{} doesn't start with `; passes:`", input.display()))
+            .split(',').map(str::trim).collect();
+        let mut module: FirModule = text.parse().unwrap_or_else(|e| panic!("{}: {}", input.display(), e));
+        PassManager::from_names(&passes).expect("unknown pass").run(&mut module);
+
+        let output = input.with_extension("out.fir");
+        let actual = module.to_string();
+        if update {
+            fs::write(&output, &actual).expect("can't write the golden file");
+        } else {
+            let expected = fs::read_to_string(&output).unwrap_or_else(|_| panic!("missing {}, run with UPDATE_GOLDEN=1", output.display()));
+            assert_eq!(actual, expected, "{} changed", output.display());
+        }
+        // the expected output reads back as itself
+        assert_eq!(actual.parse::<FirModule>().expect("output doesn't parse"), module);
+    }
+}
+
+fn round_trip(func: &FirFunction) {
+    let text = func.to_string();
+    let parsed: FirFunction = text.parse().unwrap_or_else(|e| panic!("{}\n{}", e, text));
+    assert_eq!(&parsed, func);
+    assert_eq!(parsed.to_string(), text);
+}
+
+#[test]
+fn lowered_functions_round_trip() {
+    let programs = [
+        "fn sum(n) { let total = 0; for i in 0..n { total = total + i; } return total; }",
+        "fn f(a, b) { if (a > b && b > 0) { return a; } else { while (b < a) { b = b + 1; if (b == 7) { break; } } } return g(a, \"x\", 1.5); }",
+        "fn g() { loop { continue; } }",
+        "fn h(a, b, c) { let x = (a > b && b > c) && (c > 0 || a > 1); if (x) { let x = 1; for a in 0..b { let x = a; } } return x; }",
+    ];
+    for source in programs {
+        let func = lower_cfg(source);
+        round_trip(&func);
+        let mut ssa = func.clone();
+        to_ssa(&mut ssa);
+        round_trip(&ssa);
+    }
+}
+
+#[test]
+fn modules_round_trip() {
+    let mut module = FirModule::new();
+    module.add_function(lower_cfg("fn f(x) { return x * 2; }"));
+    module.add_function(lower_cfg("fn g(x) { return f(x) - 1; }"));
+    let text = module.to_string();
+    assert!(text.contains("}\n\nfn g(x) {\n"));
+    assert_eq!(text.parse::<FirModule>(), Ok(module));
+    assert_eq!("".parse::<FirModule>(), Ok(FirModule::new()));
+}
+
+#[test]
+fn constants_round_trip() {
+    let values = [
+        FirValue::ConstInt(i32::MIN),
+        FirValue::ConstInt(0),
+        FirValue::ConstFloat(1.0),
+        FirValue::ConstFloat(-0.25),
+        FirValue::ConstFloat(1e100),
+        FirValue::ConstFloat(-2.5e-8),
+        FirValue::ConstFloat(f64::INFINITY),
+        FirValue::ConstFloat(f64::NEG_INFINITY),
+        FirValue::ConstBool(false),
+        FirValue::ConstString("tab\t, quote \" ; and ☃\n".to_string()),
+        FirValue::ConstString(String::new()),
+    ];
+    let mut func = FirFunction::new("constants", vec![]);
+    func.add_local("x");
+    for value in values {
+        func.blocks[0].instrs.push(FirInstr::Copy(0, value));
+    }
+    func.blocks[0].instrs.push(FirInstr::Return(None));
+    round_trip(&func);
+
+    let text = func.to_string();
+    assert!(text.contains("%x = copy 1.0\n"));
+    assert!(text.contains("%x = copy -inf\n"));
+    assert!(text.contains(r#"%x = copy "tab\t, quote \" ; and ☃\n""#));
+}
+
+#[test]
+fn hand_written_fir_can_have_comments() {
+    let func: FirFunction = "
+        ; the absolute value
+        fn abs(x) {   ; of an int
+          locals %x, %negative, %result
+        entry:
+          %negative = lt %x, 0
+          br %negative, flip, done
+        flip:
+          %result = sub 0, %x
+          ret %result
+        done:
+          nop
+          ret %x
+        }
+    ".parse().expect("parsing failed");
+    assert_eq!(func.params, ["x"]);
+    assert_eq!(func.locals, ["x", "negative", "result"]);
+    assert_eq!(func.blocks.len(), 3);
+    assert_eq!(func.blocks[1].instrs[0], FirInstr::Sub(2, FirValue::ConstInt(0), FirValue::Local(0)));
+
+    // without locals
+    let func: FirFunction = "fn nothing() {\nentry:\n  ret\n}".parse().expect("parsing failed");
+    assert!(func.locals.is_empty());
+}
+
+#[test]
+fn mistakes_are_reported_with_their_line() {
+    let error = |text: &str| text.parse::<FirModule>().unwrap_err();
+    assert_eq!(error("fn f() {\nentry:\n  ret %x\n}"), "line 3: unknown local %x");
+    assert_eq!(error("fn f() {\n  locals %x\nentry:\n  %x = frob 1, 2\n}"), "line 4: unknown instruction `frob`");
+    assert_eq!(error("fn f() {\n  locals %x\nentry:\n  %x = add 1\n}"), "line 4: expected `,`, found the end of the line");
+    assert_eq!(error("fn f() {\n  locals %x\nentry:\n  %x = copy 2147483648\n}"), "line 4: the int 2147483648 is out of range");
+    assert_eq!(error("fn f() {\nentry:\n  jmp nowhere\n}"), "line 3: a jump to the missing block nowhere");
+    assert_eq!(error("fn f() {\nentry:\n  ret\nentry:\n  ret\n}"), "line 4: the block entry is defined twice");
+    assert_eq!(error("fn f() {\n  locals %x, %x\n}"), "line 2: the local %x is declared twice");
+    assert_eq!(error("fn f() {\n  ret\n}"), "line 2: an instruction before the first block label");
+    assert_eq!(error("fn f() {\n}"), "line 2: `f` has no blocks");
+    assert_eq!(error("fn f() {\nentry:\n  ret\n"), "line 3: `fn f` is missing its closing `}`");
+    assert_eq!(error("fn f() {\nentry:\n  ret \"open\n}"), "line 3: unterminated string");
+    assert_eq!(error("func f() {"), "line 1: expected `fn`, found `func`");
+    assert_eq!(error("fn f() {\nentry:\n  ret 1 2\n}"), "line 3: unexpected `2` at the end of the line");
+    assert_eq!("".parse::<FirFunction>().unwrap_err(), "expected one function, found 0");
+}
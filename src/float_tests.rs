@@ -0,0 +1,45 @@
+use crate::scope_tests::eval;
+use ast::value::Value;
+
+#[test]
+fn float_literals() {
+    assert_eq!(eval("let x = 2.5;", "x"), Value::Float(2.5));
+    assert_eq!(eval("let x = -0.25;", "x"), Value::Float(-0.25));
+    assert_eq!(Value::Float(2.0).to_string(), "2.0");
+}
+
+#[test]
+fn mixed_arithmetic() {
+    assert_eq!(eval("let x = 1 + 2.5;", "x"), Value::Float(3.5));
+    assert_eq!(eval("let x = 3.0 * 2;", "x"), Value::Float(6.0));
+    assert_eq!(eval("let x = 7 / 2.0;", "x"), Value::Float(3.5));
+    assert_eq!(eval("let x = 7.5 % 2;", "x"), Value::Float(1.5));
+    // two ints stay integral
+    assert_eq!(eval("let x = 7 / 2;", "x"), Value::Int(3));
+}
+
+#[test]
+fn float_comparisons() {
+    assert_eq!(eval("let x = 1.5 < 2;", "x"), Value::Bool(true));
+    assert_eq!(eval("let x = 2 > 2.5;", "x"), Value::Bool(false));
+    assert_eq!(eval("let x = 2.0 == 2.0;", "x"), Value::Bool(true));
+}
+
+#[test]
+fn division_by_zero_follows_ieee() {
+    assert_eq!(eval("let x = 1.0 / 0;", "x"), Value::Float(f64::INFINITY));
+}
+
+#[test]
+fn conversions() {
+    assert_eq!(eval("let x = sqrt(16);", "x"), Value::Float(4.0));
+    assert_eq!(eval("let x = int(3.9);", "x"), Value::Int(3));
+    let input = "let sum = 0;
+    let n = 0;
+    for i in 1..5 {
+        sum = sum + i;
+        n = n + 1;
+    }
+    let avg = float(sum) / n;";
+    assert_eq!(eval(input, "avg"), Value::Float(2.5));
+}
@@ -0,0 +1,306 @@
+//! The `.foxc` format, compiled Fox bytecode that can be stored and run later without the source.
+//!
+//! A file starts with the magic bytes `FOXC` and a little endian `u16` version, followed by the
+//! script's chunk. A chunk is laid out as:
+//!
+//! - the constant pool: a `u32` count, then each constant as a tag byte and its payload
+//! - the code: a `u32` length and the bytes
+//! - the line table: a `u32` count of runs, each a span as `u32` start, end, line and column, then a `u32` length
+//!
+//! Functions are constants too, so their chunks are nested inside the pool of the chunk defining them.
+//! Every number is little endian, and strings are a `u32` byte length followed by UTF-8.
+
+use std::sync::Arc;
+use ast::span::Span;
+use ast::value::Function;
+use crate::bytecode::{Chunk, LineRun, Value};
+use crate::verifier;
+
+pub const MAGIC: &[u8; 4] = b"FOXC";
+/// Bumped whenever the layout or the instruction set changes, older files are rejected.
+pub const VERSION: u16 = 2;
+
+const TAG_INT: u8 = 0;
+const TAG_BIG_INT: u8 = 1;
+const TAG_FLOAT: u8 = 2;
+const TAG_STR: u8 = 3;
+const TAG_BOOL: u8 = 4;
+const TAG_BIN: u8 = 5;
+const TAG_FUNCTION: u8 = 6;
+
+/// Encodes a compiled chunk, with the functions defined in it.
+///
+/// Fails for constants that only exist while a program runs, like closures and heap objects,
+/// which the compiler never puts in a chunk.
+pub fn write(chunk: &Chunk) -> Result<Vec<u8>, String> {
+    let mut out = Vec::new();
+    out.extend_from_slice(MAGIC);
+    out.extend_from_slice(&VERSION.to_le_bytes());
+    write_chunk(chunk, &mut out)?;
+    Ok(out)
+}
+
+/// Decodes a chunk written by `write` and verifies it, so it is safe to pass to `VM::interpret`.
+pub fn read(bytes: &[u8]) -> Result<Chunk, String> {
+    let mut reader = Reader { bytes, position: 0 };
+    if reader.take(MAGIC.len()).ok() != Some(MAGIC.as_slice()) {
+        return Err("not a .foxc file, the magic bytes are missing".to_string());
+    }
+    let version = u16::from_le_bytes(reader.array()?);
+    if version != VERSION {
+        return Err(format!("unsupported .foxc version {}, expected {}", version, VERSION));
+    }
+
+    let chunk = reader.chunk()?;
+    if reader.position != bytes.len() {
+        return Err(format!("unexpected data after the chunk at byte {}", reader.position));
+    }
+    verifier::verify(&chunk)?;
+    Ok(chunk)
+}
+
+fn write_chunk(chunk: &Chunk, out: &mut Vec<u8>) -> Result<(), String> {
+    write_len(chunk.constants.len(), out)?;
+    for constant in &chunk.constants {
+        write_constant(constant, out)?;
+    }
+
+    write_len(chunk.code.len(), out)?;
+    out.extend_from_slice(&chunk.code);
+
+    write_len(chunk.lines.len(), out)?;
+    for run in &chunk.lines {
+        for field in [run.span.start, run.span.end, run.span.line, run.span.column, run.length] {
+            write_len(field, out)?;
+        }
+    }
+    Ok(())
+}
+
+fn write_constant(constant: &Value, out: &mut Vec<u8>) -> Result<(), String> {
+    match constant {
+        Value::Int(i) => {
+            out.push(TAG_INT);
+            out.extend_from_slice(&i.to_le_bytes());
+        }
+        // big integers are stored in decimal, like they are written in the source
+        Value::BigInt(big) => {
+            out.push(TAG_BIG_INT);
+            write_str(&big.to_string(), out)?;
+        }
+        Value::Float(f) => {
+            out.push(TAG_FLOAT);
+            out.extend_from_slice(&f.to_bits().to_le_bytes());
+        }
+        Value::Str(s) => {
+            out.push(TAG_STR);
+            write_str(s, out)?;
+        }
+        Value::Bool(b) => out.extend_from_slice(&[TAG_BOOL, *b as u8]),
+        Value::Bin(b) => {
+            out.push(TAG_BIN);
+            out.extend_from_slice(&b.to_le_bytes());
+        }
+        Value::Function(function) => {
+            out.push(TAG_FUNCTION);
+            write_str(&function.name, out)?;
+            write_len(function.arity, out)?;
+            write_len(function.upvalue_count, out)?;
+            write_chunk(&function.chunk, out)?;
+        }
+        other => return Err(format!("a {} can't be stored as a constant", other.type_name())),
+    }
+    Ok(())
+}
+
+fn write_str(s: &str, out: &mut Vec<u8>) -> Result<(), String> {
+    write_len(s.len(), out)?;
+    out.extend_from_slice(s.as_bytes());
+    Ok(())
+}
+
+fn write_len(len: usize, out: &mut Vec<u8>) -> Result<(), String> {
+    let len = u32::try_from(len).map_err(|_| format!("{} is too large for a .foxc file", len))?;
+    out.extend_from_slice(&len.to_le_bytes());
+    Ok(())
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, count: usize) -> Result<&'a [u8], String> {
+        let end = self.position.checked_add(count).filter(|end| *end <= self.bytes.len());
+        let Some(end) = end else {
+            return Err(format!("unexpected end of file at byte {}", self.bytes.len()));
+        };
+        let bytes = &self.bytes[self.position..end];
+        self.position = end;
+        Ok(bytes)
+    }
+
+    fn array<const N: usize>(&mut self) -> Result<[u8; N], String> {
+        Ok(self.take(N)?.try_into().expect("took N bytes"))
+    }
+
+    fn byte(&mut self) -> Result<u8, String> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn len(&mut self) -> Result<usize, String> {
+        Ok(u32::from_le_bytes(self.array()?) as usize)
+    }
+
+    fn str(&mut self) -> Result<String, String> {
+        let len = self.len()?;
+        let start = self.position;
+        let bytes = self.take(len)?;
+        String::from_utf8(bytes.to_vec()).map_err(|_| format!("invalid UTF-8 in the string at byte {}", start))
+    }
+
+    fn chunk(&mut self) -> Result<Chunk, String> {
+        let mut chunk = Chunk::new();
+        // a count can't be trusted to allocate up front, every constant takes at least a byte anyway
+        for _ in 0..self.len()? {
+            let constant = self.constant()?;
+            chunk.constants.push(constant);
+        }
+
+        let len = self.len()?;
+        chunk.code = self.take(len)?.to_vec();
+
+        for _ in 0..self.len()? {
+            let span = Span::new(self.len()?, self.len()?, self.len()?, self.len()?);
+            chunk.lines.push(LineRun { span, length: self.len()? });
+        }
+        Ok(chunk)
+    }
+
+    fn constant(&mut self) -> Result<Value, String> {
+        let start = self.position;
+        let constant = match self.byte()? {
+            TAG_INT => Value::Int(i32::from_le_bytes(self.array()?)),
+            TAG_BIG_INT => {
+                let digits = self.str()?;
+                Value::BigInt(digits.parse().map_err(|_| format!("invalid big integer `{}` at byte {}", digits, start))?)
+            }
+            TAG_FLOAT => Value::Float(f64::from_bits(u64::from_le_bytes(self.array()?))),
+            TAG_STR => Value::Str(self.str()?),
+            TAG_BOOL => match self.byte()? {
+                0 => Value::Bool(false),
+                1 => Value::Bool(true),
+                other => return Err(format!("invalid bool {} at byte {}", other, start + 1)),
+            },
+            TAG_BIN => Value::Bin(u32::from_le_bytes(self.array()?)),
+            TAG_FUNCTION => {
+                let name = self.str()?;
+                let (arity, upvalue_count) = (self.len()?, self.len()?);
+                let chunk = self.chunk()?;
+                Value::Function(Arc::new(Function { name, arity, upvalue_count, chunk }))
+            }
+            tag => return Err(format!("unknown constant tag {} at byte {}", tag, start)),
+        };
+        Ok(constant)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bytecode::OpCode;
+    use crate::compiler::Compiler;
+    use crate::lang_parser::LangParser;
+    use crate::vm::VM;
+
+    fn compile(input: &str) -> Chunk {
+        let ast = LangParser::new(input).parse().expect("unexpected failure");
+        Compiler::compile(&ast).expect("Compilation failed")
+    }
+
+    fn run(chunk: &Chunk) -> Value {
+        VM::new().interpret(chunk).expect("VM execution failed")
+    }
+
+    #[test]
+    fn compiled_programs_round_trip() {
+        let chunk = compile("fn make(step) {
+            let count = 0;
+            return fn() { count = count + step; return count; };
+        }
+        let next = make(2);
+        next();
+        let big = 3000000000;
+        let mask = bin(3) | bin(4);
+        let result = next() + int(big / 1000000000) + int(float(1.5) * 2.0) + int(mask);
+        result");
+        let bytes = write(&chunk).expect("write failed");
+        assert_eq!(bytes[..6], [b'F', b'O', b'X', b'C', 2, 0]);
+
+        let read_back = read(&bytes).expect("read failed");
+        assert_eq!(read_back.code, chunk.code);
+        assert_eq!(read_back.constants.len(), chunk.constants.len());
+        assert_eq!(run(&read_back), run(&chunk));
+        // and reading is the inverse of writing
+        assert_eq!(write(&read_back).expect("write failed"), bytes);
+    }
+
+    #[test]
+    fn constants_keep_their_types() {
+        let mut chunk = Chunk::new();
+        let constants = [Value::Int(-7), Value::Float(-0.0), Value::Str("ünï".to_string()), Value::Bool(true), Value::Bin(5), Value::BigInt("-12345678901234567890".parse().unwrap())];
+        for constant in constants.iter().cloned() {
+            chunk.constants.push(constant);
+        }
+        chunk.set_span(Span::new(10, 14, 3, 5));
+        chunk.write_constant_op(OpCode::Constant, 1);
+        chunk.write(OpCode::Return as u8);
+
+        let read_back = read(&write(&chunk).unwrap()).expect("read failed");
+        assert_eq!(read_back.constants, constants);
+        // equal as values, but still a float and a big integer
+        assert!(matches!(read_back.constants[1], Value::Float(f) if f.is_sign_negative()));
+        assert!(matches!(read_back.constants[5], Value::BigInt(_)));
+        assert_eq!(read_back.lines, vec![LineRun { span: Span::new(10, 14, 3, 5), length: 3 }]);
+    }
+
+    #[test]
+    fn invalid_files_are_rejected() {
+        let bytes = write(&compile("let x = 1; x")).unwrap();
+        assert_eq!(read(b"FOX").unwrap_err(), "not a .foxc file, the magic bytes are missing");
+        assert_eq!(read(b"CXOF\x01\x00").unwrap_err(), "not a .foxc file, the magic bytes are missing");
+
+        let mut newer = bytes.clone();
+        newer[4] = 3;
+        assert_eq!(read(&newer).unwrap_err(), "unsupported .foxc version 3, expected 2");
+
+        let truncated = &bytes[..bytes.len() - 3];
+        assert_eq!(read(truncated).unwrap_err(), format!("unexpected end of file at byte {}", truncated.len()));
+
+        let mut trailing = bytes.clone();
+        trailing.push(0);
+        assert_eq!(read(&trailing).unwrap_err(), format!("unexpected data after the chunk at byte {}", bytes.len()));
+
+        // the first constant is the int 1, its tag follows the header and the count
+        let mut unknown_tag = bytes.clone();
+        unknown_tag[10] = 42;
+        assert_eq!(read(&unknown_tag).unwrap_err(), "unknown constant tag 42 at byte 10");
+    }
+
+    #[test]
+    fn chunks_are_verified_when_read() {
+        let mut chunk = compile("let x = 1; x");
+        chunk.code.insert(0, OpCode::Add as u8);
+        chunk.lines.clear();
+        let bytes = write(&chunk).unwrap();
+        assert!(read(&bytes).unwrap_err().contains("stack underflow"));
+    }
+
+    #[test]
+    fn runtime_values_cannot_be_written() {
+        let mut chunk = Chunk::new();
+        chunk.constants.push(Value::Null);
+        assert_eq!(write(&chunk).unwrap_err(), "a null can't be stored as a constant");
+    }
+}
@@ -4,6 +4,9 @@ use crate::lang_parser::LangParser;
 #[cfg(test)]
 mod tests {
     use std::collections::HashMap;
+    use ast::ast::DEFAULT_MAX_CALL_DEPTH;
+    use ast::error::RuntimeErrorKind;
+    use ast::value::Value;
     use super::*;
     #[test]
     fn simple_function() {
@@ -299,4 +302,122 @@ mod tests {
         }
         println!("decls: {:?}", ast.declarations);
     }
+
+    #[test]
+    fn recursive_factorial() {
+        let input = "fn fact(n) {
+            if (n < 2) {
+                return 1;
+            }
+            return n * fact(n - 1);
+        }
+        let x = fact(10);";
+        let mut ast = LangParser::new(input);
+        let mut ast = ast.parse().expect("unexpected failure");
+        match ast.eval() {
+            Ok(_) => (),
+            Err(e) => panic!("{:?}", e),
+        }
+        assert_eq!(ast.declarations.get("x").unwrap().val(), Value::Int(3628800));
+    }
+
+    #[test]
+    fn recursive_fibonacci() {
+        let input = "fn fib(n) {
+            if (n < 2) {
+                return n;
+            }
+            return fib(n - 1) + fib(n - 2);
+        }
+        let x = fib(15);";
+        let mut ast = LangParser::new(input);
+        let mut ast = ast.parse().expect("unexpected failure");
+        match ast.eval() {
+            Ok(_) => (),
+            Err(e) => panic!("{:?}", e),
+        }
+        assert_eq!(ast.declarations.get("x").unwrap().val(), Value::Int(610));
+    }
+
+    #[test]
+    fn nested_call_arguments() {
+        let input = "fn add(x, y) {
+            return x + y;
+        }
+        let x = add(add(1, 2), add(3, add(4, 5)));";
+        let mut ast = LangParser::new(input);
+        let mut ast = ast.parse().expect("unexpected failure");
+        match ast.eval() {
+            Ok(_) => (),
+            Err(e) => panic!("{:?}", e),
+        }
+        assert_eq!(ast.declarations.get("x").unwrap().val(), Value::Int(15));
+    }
+
+    #[test]
+    fn return_from_inside_loop() {
+        let input = "fn find(n) {
+            for i in 0..100 {
+                if (i == n) {
+                    return i * 2;
+                }
+            }
+            return 0;
+        }
+        let x = find(7);";
+        let mut ast = LangParser::new(input);
+        let mut ast = ast.parse().expect("unexpected failure");
+        match ast.eval() {
+            Ok(_) => (),
+            Err(e) => panic!("{:?}", e),
+        }
+        assert_eq!(ast.declarations.get("x").unwrap().val(), Value::Int(14));
+    }
+
+    #[test]
+    fn call_depth_limit() {
+        let input = "fn down(n) {
+            return down(n + 1);
+        }
+        let x = down(0);";
+        let mut ast = LangParser::new(input);
+        let mut ast = ast.parse().expect("unexpected failure");
+        match ast.eval() {
+            Ok(_) => panic!("expected the call depth limit to be hit"),
+            Err(e) => assert_eq!(e.kind, RuntimeErrorKind::StackOverflow { limit: DEFAULT_MAX_CALL_DEPTH }),
+        }
+
+        // the limit is configurable
+        ast.max_call_depth = 50;
+        match ast.eval() {
+            Ok(_) => panic!("expected the call depth limit to be hit"),
+            Err(e) => assert_eq!(e.kind, RuntimeErrorKind::StackOverflow { limit: 50 }),
+        }
+
+        // but a stack too big to set aside is an error rather than a crash
+        ast.max_call_depth = usize::MAX;
+        match ast.eval() {
+            Ok(_) => panic!("expected the call depth to be rejected"),
+            Err(e) => assert_eq!(e.kind, RuntimeErrorKind::StackUnavailable { depth: usize::MAX }),
+        }
+    }
+
+    #[test]
+    fn deep_recursion_within_limit() {
+        let input = "fn down(n) {
+            if (n == 0) {
+                return 0;
+            }
+            return down(n - 1);
+        }
+        let x = down(1500);";
+        let mut ast = LangParser::new(input);
+        let mut ast = ast.parse().expect("unexpected failure");
+        ast.max_call_depth = 2000;
+        match ast.eval() {
+            Ok(_) => (),
+            Err(e) => panic!("{:?}", e),
+        }
+        assert_eq!(ast.declarations.get("x").unwrap().val(), Value::Int(0));
+    }
 }
@@ -0,0 +1,336 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::mem::size_of;
+use std::sync::Arc;
+use std::time::Duration;
+use crate::bytecode::Value;
+use ast::value::{Closure, ObjectRef, Upvalue};
+
+// by default, the first collection happens once this much is allocated
+const DEFAULT_THRESHOLD: usize = 1024 * 1024;
+// after a collection, the next one waits until the live size has grown by this factor
+const GROWTH_FACTOR: usize = 2;
+
+/// A value the VM allocates on its heap. Variables hold `Value::Object` handles to it; the
+/// compiler emits `Copy` wherever one is stored, so assigning an array or map copies it like in the interpreter.
+/// Strings stay inline as `Value::Str`: they can't be changed in place, so nothing is gained by sharing them.
+#[derive(Debug)]
+pub enum Object {
+    Array(Vec<Value>),
+    Map(BTreeMap<Value, Value>),
+}
+
+impl Object {
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Object::Array(_) => "array",
+            Object::Map(_) => "map",
+        }
+    }
+
+    // an estimate of the memory the object holds on to
+    fn size(&self) -> usize {
+        let contents = match self {
+            Object::Array(elements) => elements.capacity() * size_of::<Value>(),
+            Object::Map(values) => values.len() * 2 * size_of::<Value>(),
+        };
+        size_of::<Object>() + contents
+    }
+
+    // the values inside that can keep other objects alive
+    fn references(&self) -> Vec<Value> {
+        let values: Box<dyn Iterator<Item = &Value>> = match self {
+            Object::Array(elements) => Box::new(elements.iter()),
+            Object::Map(values) => Box::new(values.iter().flat_map(|(key, value)| [key, value])),
+        };
+        values.filter(|value| matches!(value, Value::Object(_) | Value::Closure(_))).cloned().collect()
+    }
+}
+
+/// What the collector has done so far, see `VM::gc_stats`.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct GcStats {
+    /// The estimated size of the objects on the heap right now.
+    pub bytes_allocated: usize,
+    /// The estimated size of every object allocated since the VM was created.
+    pub total_allocated: usize,
+    /// The number of objects on the heap right now.
+    pub objects: usize,
+    pub collections: usize,
+    pub objects_freed: usize,
+    /// The time spent collecting, over all collections.
+    pub total_pause: Duration,
+    pub last_pause: Duration,
+}
+
+struct Slot {
+    object: Object,
+    marked: bool,
+}
+
+/// The objects allocated by a VM, addressed by the index in their handle.
+///
+/// Memory is reclaimed by a mark and sweep collector: the VM marks everything reachable from its
+/// roots with `mark_value` and `mark_closure`, then `trace` follows references from the marked objects
+/// and `sweep` frees the rest. Freed slots are reused by later allocations.
+pub struct Heap {
+    slots: Vec<Option<Slot>>,
+    free: Vec<usize>,
+    // marked objects whose references haven't been followed yet
+    gray: Vec<ObjectRef>,
+    // closures already traced in this collection, closures can capture themselves
+    traced_closures: HashSet<*const Closure>,
+    // collections never happen below this size
+    threshold: usize,
+    next_gc: usize,
+    stats: GcStats,
+}
+
+impl Default for Heap {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Heap {
+    pub fn new() -> Self {
+        Heap {
+            slots: Vec::new(),
+            free: Vec::new(),
+            gray: Vec::new(),
+            traced_closures: HashSet::new(),
+            threshold: DEFAULT_THRESHOLD,
+            next_gc: DEFAULT_THRESHOLD,
+            stats: GcStats::default(),
+        }
+    }
+
+    /// Moves an object onto the heap and returns the value referring to it. This never collects,
+    /// the VM decides when to with `should_collect`.
+    pub fn allocate(&mut self, object: Object) -> Value {
+        let size = object.size();
+        self.stats.bytes_allocated += size;
+        self.stats.total_allocated += size;
+        self.stats.objects += 1;
+
+        let slot = Some(Slot { object, marked: false });
+        let index = match self.free.pop() {
+            Some(index) => {
+                self.slots[index] = slot;
+                index
+            }
+            None => {
+                self.slots.push(slot);
+                self.slots.len() - 1
+            }
+        };
+        Value::Object(ObjectRef(index))
+    }
+
+    /// # Panics
+    /// Panics if the object was collected, which means it wasn't reachable from a root.
+    pub fn get(&self, object: ObjectRef) -> &Object {
+        match &self.slots[object.0] {
+            Some(slot) => &slot.object,
+            None => panic!("object {} was used after it was collected", object.0),
+        }
+    }
+
+    pub fn get_mut(&mut self, object: ObjectRef) -> &mut Object {
+        match &mut self.slots[object.0] {
+            Some(slot) => &mut slot.object,
+            None => panic!("object {} was used after it was collected", object.0),
+        }
+    }
+
+    /// The number of objects on the heap.
+    pub fn len(&self) -> usize {
+        self.stats.objects
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn stats(&self) -> GcStats {
+        self.stats
+    }
+
+    /// Whether enough has been allocated since the last collection to run another one.
+    pub fn should_collect(&self) -> bool {
+        self.stats.bytes_allocated >= self.next_gc
+    }
+
+    /// Sets how much can be allocated before the first collection. Later collections wait until
+    /// the heap has doubled since the last one, but never start below this size.
+    pub fn set_threshold(&mut self, bytes: usize) {
+        self.threshold = bytes;
+        self.next_gc = bytes;
+    }
+
+    /// Marks a root, or anything reachable from one.
+    pub fn mark_value(&mut self, value: &Value) {
+        match value {
+            Value::Object(object) => {
+                let slot = self.slots[object.0].as_mut().expect("a root refers to a collected object");
+                if !slot.marked {
+                    slot.marked = true;
+                    self.gray.push(*object);
+                }
+            }
+            Value::Closure(closure) => self.mark_closure(closure),
+            _ => (),
+        }
+    }
+
+    /// Marks what a closure captured. Open upvalues point at the stack, which is a root already.
+    pub fn mark_closure(&mut self, closure: &Arc<Closure>) {
+        if !self.traced_closures.insert(Arc::as_ptr(closure)) {
+            return;
+        }
+        for upvalue in &closure.upvalues {
+            let closed = match &*upvalue.lock().expect("poisoned upvalue") {
+                Upvalue::Closed(value) => value.clone(),
+                Upvalue::Open(_) => continue,
+            };
+            self.mark_value(&closed);
+        }
+    }
+
+    /// Marks the contents of an object, so whatever it refers to survives even when it isn't
+    /// reachable itself, like an object that is about to be allocated.
+    pub fn mark_contents(&mut self, object: &Object) {
+        for value in object.references() {
+            self.mark_value(&value);
+        }
+    }
+
+    /// Follows the references of every marked object, marking what they refer to.
+    pub fn trace(&mut self) {
+        while let Some(object) = self.gray.pop() {
+            for value in self.get(object).references() {
+                self.mark_value(&value);
+            }
+        }
+    }
+
+    /// Frees every object that wasn't marked and clears the marks for the next collection.
+    pub fn sweep(&mut self, pause: Duration) {
+        let mut live_bytes = 0;
+        for (index, entry) in self.slots.iter_mut().enumerate() {
+            match entry {
+                Some(slot) if slot.marked => {
+                    slot.marked = false;
+                    live_bytes += slot.object.size();
+                }
+                Some(_) => {
+                    *entry = None;
+                    self.free.push(index);
+                    self.stats.objects -= 1;
+                    self.stats.objects_freed += 1;
+                }
+                None => (),
+            }
+        }
+        self.traced_closures.clear();
+
+        self.stats.bytes_allocated = live_bytes;
+        self.stats.collections += 1;
+        self.stats.last_pause = pause;
+        self.stats.total_pause += pause;
+        self.next_gc = (live_bytes * GROWTH_FACTOR).max(self.threshold);
+    }
+
+    /// Copies an array or map along with everything inside it, other values are returned as they are.
+    /// Map keys are kept, since they are only compared. Like `allocate`, this never collects.
+    pub fn copy(&mut self, value: &Value) -> Value {
+        self.copy_object(value, &mut HashMap::new())
+    }
+
+    // `copies` maps the objects copied so far to their copies, so shared and cyclic contents keep their shape
+    // closures are ordered by identity, so the interior mutability of their upvalues can't reorder a map
+    #[allow(clippy::mutable_key_type)]
+    fn copy_object(&mut self, value: &Value, copies: &mut HashMap<ObjectRef, Value>) -> Value {
+        let Value::Object(object) = value else {
+            return value.clone();
+        };
+        if let Some(copy) = copies.get(object) {
+            return copy.clone();
+        }
+        // allocated empty first, so a reference back to the object finds its copy
+        let empty = match self.get(*object) {
+            Object::Array(_) => Object::Array(Vec::new()),
+            Object::Map(_) => Object::Map(BTreeMap::new()),
+        };
+        let copy = self.allocate(empty);
+        copies.insert(*object, copy.clone());
+
+        let contents = match self.get(*object) {
+            Object::Array(elements) => {
+                let elements = elements.clone();
+                Object::Array(elements.iter().map(|element| self.copy_object(element, copies)).collect())
+            }
+            Object::Map(values) => {
+                let values = values.clone();
+                Object::Map(values.into_iter().map(|(key, value)| (key, self.copy_object(&value, copies))).collect())
+            }
+        };
+        let Value::Object(copy_ref) = copy else {
+            unreachable!("allocating returns an object");
+        };
+        self.set_contents(copy_ref, contents);
+        copy
+    }
+
+    // swaps in the contents of an object, keeping the size estimate up to date
+    fn set_contents(&mut self, object: ObjectRef, contents: Object) {
+        let size = contents.size();
+        let old = std::mem::replace(self.get_mut(object), contents).size();
+        self.stats.bytes_allocated = self.stats.bytes_allocated + size - old;
+        self.stats.total_allocated += size.saturating_sub(old);
+    }
+
+    /// Like `Value::type_name`, but tells arrays and maps apart.
+    pub fn type_name(&self, value: &Value) -> &'static str {
+        match value {
+            Value::Object(object) => self.get(*object).type_name(),
+            _ => value.type_name(),
+        }
+    }
+
+    /// Formats a value the way `print` shows it, following handles into the heap. An array or map
+    /// that contains itself is shown as `[...]` or `{...}` where it repeats.
+    pub fn display(&self, value: &Value) -> String {
+        self.display_within(value, &mut Vec::new())
+    }
+
+    // `within` holds the objects being displayed around this value
+    fn display_within(&self, value: &Value, within: &mut Vec<ObjectRef>) -> String {
+        let Value::Object(object) = value else {
+            return value.to_string();
+        };
+        let contents = self.get(*object);
+        if within.contains(object) {
+            return match contents {
+                Object::Array(_) => "[...]".to_string(),
+                Object::Map(_) => "{...}".to_string(),
+            };
+        }
+
+        within.push(*object);
+        let shown = match contents {
+            Object::Array(elements) => {
+                let elements: Vec<String> = elements.iter().map(|element| self.display_within(element, within)).collect();
+                format!("[{}]", elements.join(", "))
+            }
+            Object::Map(values) => {
+                let values: Vec<String> = values
+                    .iter()
+                    .map(|(key, value)| format!("{}: {}", self.display_within(key, within), self.display_within(value, within)))
+                    .collect();
+                format!("{{{}}}", values.join(", "))
+            }
+        };
+        within.pop();
+        shown
+    }
+}
@@ -5,6 +5,7 @@ use ast::node::Node;
 mod tests {
     use super::*;
     use crate::lang_parser::LangParser;
+    use ast::span::Span;
 
     #[test]
     fn test_var() {
@@ -13,11 +14,13 @@ mod tests {
             left: Box::new(Node::Ident {
                 name: "x".to_string(),
                 kind: "var".to_string(),
+                span: Span::default(),
             }),
             right: Box::from(Node::Atomic {
                 value: ast::value::Value::Int(10),
             }),
             kind: "Nat".to_string(),
+            span: Span::default(),
         });
         ast.eval().expect("unexpected failure");
     }
@@ -31,6 +34,7 @@ mod tests {
                 value: ast::value::Value::Str("hello, world".to_string()),
             }],
             returns: vec![],
+            span: Span::default(),
         });
         ast.eval().expect("unexpected failure");
     }
@@ -1,4 +1,5 @@
-use crate::lexer::{Token, TokenKind};
+use crate::lexer::{SyntaxError, Token, TokenKind};
+use ast::span::Span;
 use std::iter::Peekable;
 use std::str::Chars;
 
@@ -6,6 +7,12 @@ pub struct LangLexer<'a> {
     char: Option<char>,
     tokens: Vec<Token>,
     iterator: Peekable<Chars<'a>>,
+    // byte offset, line and column of the current char
+    offset: usize,
+    line: usize,
+    column: usize,
+    // where the token being lexed started
+    start: Span,
 }
 
 impl<'a> LangLexer<'a> {
@@ -16,27 +23,46 @@ impl<'a> LangLexer<'a> {
             tokens: Vec::new(),
             char: iterator.next(),
             iterator,
+            offset: 0,
+            line: 1,
+            column: 1,
+            start: Span::default(),
         }
     }
 
-    pub fn tokenize(&mut self) -> Result<(), String> {
-        'main: while self.char.is_some() {
+    fn mark(&mut self) {
+        self.start = Span::new(self.offset, self.offset, self.line, self.column);
+    }
+
+    // the span from the start of the current token up to and including the current char
+    fn span(&self) -> Span {
+        Span {
+            end: self.offset + self.char.map_or(0, char::len_utf8),
+            ..self.start
+        }
+    }
+
+    pub fn tokenize(&mut self) -> Result<(), SyntaxError> {
+        while self.char.is_some() {
+            self.mark();
             match self.current_char() {
                 ' ' => {}
                 '/' => {
                     if self.peek() == '/' {
-                        while self.char != Some('\n') {
+                        while self.char.is_some() && self.char != Some('\n') {
                             self.next_char();
                         }
                         
                         self.tokens.push(Token {
                             value: self.current_char().to_string(),
                             kind: TokenKind::Comment,
+                            span: self.span(),
                         });
                     } else {
                         self.tokens.push(Token {
                             value: self.current_char().to_string(),
                             kind: TokenKind::Divide,
+                            span: self.span(),
                         });
                     }
                 }
@@ -44,60 +70,72 @@ impl<'a> LangLexer<'a> {
                     self.tokens.push(Token {
                         value: self.current_char().to_string(),
                         kind: TokenKind::LeftParenthesis,
+                        span: self.span(),
                     });
                 }
                 ')' => {
                     self.tokens.push(Token {
                         value: self.current_char().to_string(),
                         kind: TokenKind::RightParenthesis,
+                        span: self.span(),
                     });
                 }
                 '[' => {
                     self.tokens.push(Token {
                         value: self.current_char().to_string(),
                         kind: TokenKind::LBracket,
+                        span: self.span(),
                     });
                 }
                 ']' => {
                     self.tokens.push(Token {
                         value: self.current_char().to_string(),
                         kind: TokenKind::RBracket,
+                        span: self.span(),
                     });
                 }
                 '{' => {
                     self.tokens.push(Token {
                         value: self.current_char().to_string(),
                         kind: TokenKind::LCurlyBracket,
+                        span: self.span(),
                     });
                 }
                 '}' => {
                     self.tokens.push(Token {
                         value: self.current_char().to_string(),
                         kind: TokenKind::RCurlyBracket,
+                        span: self.span(),
                     });
                 }
                 ',' => {
                     self.tokens.push(Token {
                         value: self.current_char().to_string(),
                         kind: TokenKind::Comma,
+                        span: self.span(),
                     });
                 }
                 '\"' => {
                     let mut string = String::new();
                     self.next_char();
                     while self.char != Some('\"') {
+                        if self.char.is_none() {
+                            return Err(SyntaxError::new("unterminated string literal", Span { end: self.start.start + 1, ..self.start }));
+                        }
                         string.push(self.current_char());
                         self.next_char();
                     }
                     self.tokens.push(Token {
                         value: string,
                         kind: TokenKind::String,
+                        span: self.span(),
                     });
                 }
                 ';' => {
                     self.tokens.push(Token {
                         value: self.current_char().to_string(),
                         kind: TokenKind::Semicolon,
+                        span: self.span(),
                     });
                 }
                 '=' => {
@@ -107,11 +145,13 @@ impl<'a> LangLexer<'a> {
                         self.tokens.push(Token {
                             value: "==".to_string(),
                             kind: TokenKind::IsEqual,
+                            span: self.span(),
                         });
                     } else {
                         self.tokens.push(Token {
                             value: self.current_char().to_string(),
                             kind: TokenKind::Equality,
+                            span: self.span(),
                         });
                     }
                 }
@@ -121,11 +161,13 @@ impl<'a> LangLexer<'a> {
                         self.tokens.push(Token {
                             value: "||".to_string(),
                             kind: TokenKind::Or,
+                            span: self.span(),
                         });
                     } else {
                         self.tokens.push(Token {
                             value: self.current_char().to_string(),
                             kind: TokenKind::BitwiseOr,
+                            span: self.span(),
                         });
                     }
                 }
@@ -133,36 +175,43 @@ impl<'a> LangLexer<'a> {
                     self.tokens.push(Token {
                         value: self.current_char().to_string(),
                         kind: TokenKind::BitwiseXor,
+                        span: self.span(),
                     });
                 }
                 '~' => {
                     self.tokens.push(Token {
                         value: self.current_char().to_string(),
                         kind: TokenKind::Negation,
+                        span: self.span(),
                     });
                 }
                 ':' => {
                     self.tokens.push(Token {
                         value: self.current_char().to_string(),
                         kind: TokenKind::Colon,
+                        span: self.span(),
                     });
                 }
                 '+' => self.tokens.push(Token {
                     value: self.current_char().to_string(),
                     kind: TokenKind::Add,
+                    span: self.span(),
                 }),
                 '-' => self.tokens.push(Token {
                     value: self.current_char().to_string(),
                     kind: TokenKind::Subtract,
+                    span: self.span(),
                 }),
                 '*' => self.tokens.push(Token {
                     value: self.current_char().to_string(),
                     kind: TokenKind::Multiply,
+                    span: self.span(),
                 }),
                 '%' => {
                     self.tokens.push(Token {
                         value: self.current_char().to_string(),
                         kind: TokenKind::Modulo,
+                        span: self.span(),
                     });
                 }
                 // for MetaMath specific cases
@@ -170,30 +219,35 @@ impl<'a> LangLexer<'a> {
                     self.tokens.push(Token {
                         value: self.current_char().to_string(),
                         kind: TokenKind::Turnstile,
+                        span: self.span(),
                     });
                 }
                 '→' => {
                     self.tokens.push(Token {
                         value: self.current_char().to_string(),
                         kind: TokenKind::Implies,
+                        span: self.span(),
                     });
                 }
                 '¬' => {
                     self.tokens.push(Token {
                         value: self.current_char().to_string(),
                         kind: TokenKind::Negation,
+                        span: self.span(),
                     });
                 }
                 '∀' => {
                     self.tokens.push(Token {
                         value: self.current_char().to_string(),
                         kind: TokenKind::ForAll,
+                        span: self.span(),
                     });
                 }
                 '∃' => {
                     self.tokens.push(Token {
                         value: self.current_char().to_string(),
                         kind: TokenKind::Exists,
+                        span: self.span(),
                     });
                 }
                 '𝜑' => {
@@ -201,6 +255,7 @@ impl<'a> LangLexer<'a> {
                         value: self.current_char().to_string(),
                         // kind: TokenKind::Phi,
                         kind: TokenKind::WFF,
+                        span: self.span(),
                     });
                 }
                 '𝜓' => {
@@ -208,6 +263,7 @@ impl<'a> LangLexer<'a> {
                         value: self.current_char().to_string(),
                         // kind: TokenKind::Psi,
                         kind: TokenKind::WFF,
+                        span: self.span(),
                     });
                 }
                 '𝜒' => {
@@ -215,6 +271,7 @@ impl<'a> LangLexer<'a> {
                         value: self.current_char().to_string(),
                         // kind: TokenKind::Chi,
                         kind: TokenKind::WFF,
+                        span: self.span(),
                     });
                 }
                 '<' => {
@@ -223,11 +280,13 @@ impl<'a> LangLexer<'a> {
                         self.tokens.push(Token {
                             value: "<<".to_string(),
                             kind: TokenKind::ShiftLeft,
+                            span: self.span(),
                         });
                     } else { 
                         self.tokens.push(Token { 
                             value: self.current_char().to_string(), 
-                            kind: TokenKind::LessThan, 
+                            kind: TokenKind::LessThan,
+                            span: self.span(),
                         });
                     }
                 }
@@ -237,11 +296,13 @@ impl<'a> LangLexer<'a> {
                         self.tokens.push(Token {
                             value: ">>".to_string(),
                             kind: TokenKind::ShiftRight,
+                            span: self.span(),
                         });
                     } else {
                         self.tokens.push(Token {
                             value: self.current_char().to_string(),
                             kind: TokenKind::GreaterThan,
+                            span: self.span(),
                         });
                     }
                 }
@@ -251,11 +312,13 @@ impl<'a> LangLexer<'a> {
                         self.tokens.push(Token {
                             value: "&&".to_string(),
                             kind: TokenKind::And,
+                            span: self.span(),
                         });
                     } else {
                         self.tokens.push(Token {
                             value: self.current_char().to_string(),
                             kind: TokenKind::BitwiseAnd,
+                            span: self.span(),
                         });
                     }
                 }
@@ -263,54 +326,63 @@ impl<'a> LangLexer<'a> {
                     self.tokens.push(Token {
                         value: self.current_char().to_string(),
                         kind: TokenKind::HypothesisEnd,
+                        span: self.span(),
                     });
                 }
                 'A' | 'B' | 'C' => {
                     self.tokens.push(Token {
                         value: self.current_char().to_string(),
                         kind: TokenKind::Identifier,
+                        span: self.span(),
                     });
                 }
                 '𝑡' | '𝑢' | '𝑣' | '𝑥' | '𝑦' | '𝑧' | '𝑤' => {
                     self.tokens.push(Token {
                         value: self.current_char().to_string(),
                         kind: TokenKind::SetVar,
+                        span: self.span(),
                     });
                 }
                 '∈' => {
                     self.tokens.push(Token {
                         value: self.current_char().to_string(),
                         kind: TokenKind::ElementOf,
+                        span: self.span(),
                     });
                 }
                 '↔' => {
                     self.tokens.push(Token {
                         value: self.current_char().to_string(),
                         kind: TokenKind::Biconditional,
+                        span: self.span(),
                     });
                 }
                 '∧' => {
                     self.tokens.push(Token {
                         value: self.current_char().to_string(),
                         kind: TokenKind::Conjunction,
+                        span: self.span(),
                     });
                 }
                 '∨' => {
                     self.tokens.push(Token {
                         value: self.current_char().to_string(),
                         kind: TokenKind::Disjunction,
+                        span: self.span(),
                     });
                 }
                 '⊆' => {
                     self.tokens.push(Token {
                         value: self.current_char().to_string(),
                         kind: TokenKind::Subset,
+                        span: self.span(),
                     });
                 }
                 '≈' => {
                     self.tokens.push(Token {
                         value: self.current_char().to_string(),
                         kind: TokenKind::Equinumerosity,
+                        span: self.span(),
                     });
                 }
 
@@ -323,63 +395,6 @@ impl<'a> LangLexer<'a> {
                         if self.peek().is_alphanumeric() || self.peek() == '_' {
                             self.next_char();
                             word.push(self.current_char());
-                        }  else if self.peek() == '[' {
-                            self.next_char();
-                            
-                            self.tokens.push(Token {
-                                value: word,
-                                kind: TokenKind::Word,
-                            });
-                            
-                            self.tokens.push(Token {
-                                value: "[".to_string(),
-                                kind: TokenKind::LBracket,
-                            });
-                            self.next_char(); // consuming the opening bracket
-                            
-                            let mut index = self.current_char().to_string();
-                            // if the char is a number, parse numbers
-                            // else parse as a word
-                            if self.current_char().is_numeric() {
-                                loop {
-                                    if self.peek().is_numeric() {
-                                        self.next_char();
-                                        index.push(self.current_char());
-                                    } else {
-                                        break;
-                                    }
-                                }
-                                self.tokens.push(Token {
-                                    value: index,
-                                    kind: TokenKind::Number,
-                                });
-                            } else {
-                                let mut index_word = String::new();
-                                loop {
-                                    if self.peek().is_alphabetic() {
-                                        self.next_char();
-                                        index_word.push(self.current_char());
-                                    } else {
-                                        break;
-                                    }
-                                }
-                                index.push_str(&index_word);
-                                self.tokens.push(Token {
-                                    value: index,
-                                    kind: TokenKind::Word,
-                                });
-                            }
-                            
-       
-                            self.next_char(); // consuming the index
-                            
-                            self.tokens.push(Token {
-                                value: "]".to_string(),
-                                kind: TokenKind::RBracket,
-                            });
-                            
-                            self.next_char(); // consuming the closing bracket
-                            continue 'main;
                         } //else if self.peek() == '.' {
                             // self.next_char();
                             // self.tokens.push(Token {
@@ -391,7 +406,7 @@ impl<'a> LangLexer<'a> {
                             //     kind: TokenKind::Period,
                             // });
                             // // self.next_char();
-                            // continue 'main;
+                            // continue;
                     //    } 
                         else {
                             break;
@@ -401,6 +416,7 @@ impl<'a> LangLexer<'a> {
                     self.tokens.push(Token {
                         value: word,
                         kind: TokenKind::Word,
+                        span: self.span(),
                     });
                 }
 
@@ -418,9 +434,22 @@ impl<'a> LangLexer<'a> {
                         }
                     }
 
+                    // a fraction needs a digit after the point, so `0..8` stays a range
+                    let mut kind = TokenKind::Number;
+                    if self.peek() == '.' && self.peek_second().is_ascii_digit() {
+                        kind = TokenKind::Float;
+                        self.next_char();
+                        number.push(self.current_char());
+                        while self.peek().is_ascii_digit() {
+                            self.next_char();
+                            number.push(self.current_char());
+                        }
+                    }
+
                     self.tokens.push(Token {
                         value: number,
-                        kind: TokenKind::Number,
+                        kind,
+                        span: self.span(),
                     });
                 }
                 '.' => {
@@ -429,11 +458,13 @@ impl<'a> LangLexer<'a> {
                         self.tokens.push(Token {
                             value: self.current_char().to_string(),
                             kind: TokenKind::Range,
+                            span: self.span(),
                         });    
                     } else { 
                         self.tokens.push(Token {
                             value: ".".to_string(),
                             kind: TokenKind::Period,
+                            span: self.span(),
                         })
                     }
                     
@@ -441,7 +472,7 @@ impl<'a> LangLexer<'a> {
                 }
                 '\n' => {}
                 _ => {
-                    return Err(format!("Unknown character: {}", self.current_char()));
+                    return Err(SyntaxError::new(format!("unknown character `{}`", self.current_char()), self.span()));
                 }
             }
             self.next_char();
@@ -455,6 +486,15 @@ impl<'a> LangLexer<'a> {
     }
 
     pub fn next_char(&mut self) {
+        if let Some(c) = self.char {
+            self.offset += c.len_utf8();
+            if c == '\n' {
+                self.line += 1;
+                self.column = 1;
+            } else {
+                self.column += 1;
+            }
+        }
         self.char = self.iterator.next();
     }
 
@@ -462,6 +502,11 @@ impl<'a> LangLexer<'a> {
         self.iterator.peek().unwrap_or(&'\0').clone()
     }
 
+    // the char after the peeked one
+    fn peek_second(&self) -> char {
+        self.iterator.clone().nth(1).unwrap_or('\0')
+    }
+
     pub fn tokens(&self) -> Vec<Token> {
         self.tokens.clone()
     }
@@ -508,6 +553,21 @@ mod tests {
         println!("{:?}", tokens);
     }
 
+    #[test]
+    fn float_literals() {
+        let mut lexer = LangLexer::new("let x = 3.25;");
+        lexer.tokenize().expect("unexpected failure");
+        let tokens = lexer.tokens();
+        let floats: Vec<&Token> = tokens.iter().filter(|t| t.kind == TokenKind::Float).collect();
+        assert_eq!(floats.len(), 1);
+        assert_eq!(floats[0].value, "3.25");
+
+        // a range is two integers, not a float
+        let mut lexer = LangLexer::new("0..8");
+        lexer.tokenize().expect("unexpected failure");
+        assert!(lexer.tokens().iter().all(|t| t.kind != TokenKind::Float));
+    }
+
     // MM focused tests
     #[test]
     fn test_ax1() {
@@ -655,4 +715,26 @@ mod tests {
             assert_eq!(lexer.tokens[i].kind, *expected_kind);
         }
     }
+
+    #[test]
+    fn token_spans() {
+        let input = "let x = 10;\nprint(\"héllo\");";
+        let mut lexer = LangLexer::new(input);
+        lexer.tokenize().expect("TODO: panic message");
+        let tokens = lexer.tokens();
+
+        // `10` on the first line
+        assert_eq!(tokens[3].value, "10");
+        assert_eq!((tokens[3].span.line, tokens[3].span.column), (1, 9));
+        assert_eq!(&input[tokens[3].span.start..tokens[3].span.end], "10");
+
+        // `print` starts the second line
+        assert_eq!(tokens[5].value, "print");
+        assert_eq!((tokens[5].span.line, tokens[5].span.column), (2, 1));
+
+        // the string token covers its quotes, and offsets are in bytes
+        assert_eq!(tokens[7].kind, TokenKind::String);
+        assert_eq!(&input[tokens[7].span.start..tokens[7].span.end], "\"héllo\"");
+        assert_eq!(tokens[8].span.column, 14);
+    }
 }
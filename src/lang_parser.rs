@@ -1,10 +1,11 @@
 // use crate::internal_types::{fetch_binary, fetch_integer, fetch_string};
 use crate::lang_lexer::LangLexer;
 use crate::lexer::TokenKind::{And, Comma};
-use crate::lexer::{Token, TokenKind};
+use crate::lexer::{SyntaxError, Token, TokenKind};
 
 use ast::ast::Ast;
 use ast::node::{Node, OperatorKind};
+use ast::span::Span;
 
 
 pub struct LangParser<'a> {
@@ -12,16 +13,16 @@ pub struct LangParser<'a> {
     tokens: Vec<Token>,
     position: usize,
     pub(crate) ast: Ast,
+    // a lexing failure is reported by `parse` rather than panicking in `new`
+    lex_error: Option<SyntaxError>,
+    errors: Vec<SyntaxError>,
 }
 
 impl<'a> LangParser<'a> {
     pub fn new(input: &'a str) -> Self {
         let mut lexer = LangLexer::new(input);
 
-        match lexer.tokenize() {
-            Ok(_) => (),
-            Err(e) => panic!("{:?}", e),
-        }
+        let lex_error = lexer.tokenize().err();
 
         let tokens = lexer.tokens();
         
@@ -32,181 +33,165 @@ impl<'a> LangParser<'a> {
             tokens,
             position: 0,
             ast,
+            lex_error,
+            errors: Vec::new(),
         }
     }
 
-    pub fn parse(&mut self) -> Result<Ast, String> {
-        let mut globals = Vec::new();
-        let mut ast = Ast::new();
+    /// Parses the whole input and returns the first syntax error, see `parse_recovering` to get all of them.
+    pub fn parse(&mut self) -> Result<Ast, SyntaxError> {
+        let (ast, mut errors) = self.parse_recovering();
+        if errors.is_empty() {
+            Ok(ast)
+        } else {
+            Err(errors.remove(0))
+        }
+    }
+
+    /// Parses the whole input without stopping at the first error. After a failed statement the
+    /// parser skips ahead to the next `;` or `}` and carries on, so the result is the AST of every
+    /// statement that did parse along with every syntax error in the input, in source order.
+    pub fn parse_recovering(&mut self) -> (Ast, Vec<SyntaxError>) {
+        self.position = 0;
+        self.ast = Ast::new();
+        self.errors.clear();
 
         while self.position < self.tokens.len() {
-            match self.current_token()?.kind {
-                TokenKind::Word => {
-                    let t = self.current_token()?;
-                    
-                    match t.value.as_str() {
-                        "print" => {
-                            self.consume(TokenKind::Word)?;
-                            let node = self.parse_print()?;
-                            ast.add_node(node);
-                            continue;
-                        }
-                        "let" => {
-                            self.advance();
-                            let ident = self.parse_let()?;
-                            
-                            globals.push(ident.val());
-                            ast.add_node(ident);
-                            continue;
-                        }
-                        "type" => {
-                            panic!();
-                            self.consume(TokenKind::Word)?;
-                            let type_name = self.current_token()?;
-                            self.consume(TokenKind::Word)?;
-
-                            self.consume(TokenKind::Semicolon)?;
-
-                            ast.add_node(ast::node::Node::Type {
-                                name: type_name.value,
-                            });
-                            continue;
-                        }
-                        "if" => {
-                            self.consume(TokenKind::Word)?;
-                            
-                            let node = self.parse_if()?;
-                            
-                            ast.add_node(node);
-                            continue;
-                        }
-                        "for" => {
-                            self.consume(TokenKind::Word)?;
-                            
-                            let node = self.parse_for_loop()?;
-                            
-                            ast.add_node(node);
-                            continue;
-                        }
-                        "fn" => {
-                            let func = self.parse_function()?;
-                            ast.add_node(func);
-                            continue;
-                        }
-                        "bin" => {
-                            // parsing binary number type
-                            self.consume(TokenKind::Word)?;
-                            self.consume(TokenKind::LeftParenthesis)?;
-                            let value = self.parse_node()?;
-                            let bin = ast::internal_types::fetch_binary(value)?;
-
-                            self.consume(TokenKind::RightParenthesis)?;
-
-                            ast.add_node(Node::Atomic { value: ast::value::Value::Bin(bin) });
-                            continue;
-                        }
-                        _ => {
-                            let ident = self.current_token()?;
-                            self.consume(TokenKind::Word)?;
-
-                            match self.current_token()?.kind {
-                                TokenKind::Equality => {
-                                    self.consume(TokenKind::Equality)?;
-                                    let value = self.parse_node()?;
-
-                                    ast.add_node(Node::AssignStmt {
-                                        left: Box::from(Node::Ident {
-                                            name: ident.value,
-                                            kind: "var".to_string(),
-                                        }),
-                                        right: Box::from(value),
-                                        kind: "Nat".to_string(),
-                                    });
-                                    self.consume(TokenKind::Semicolon)?;
-                                    continue;
-                                }
-                                TokenKind::LeftParenthesis => {
-                                    // parsing a function call
-                                    self.consume(TokenKind::LeftParenthesis)?;
-                                    let mut arguments = Vec::new();
-                                    while self.current_token()?.kind != TokenKind::RightParenthesis {
-                                        let arg = self.parse_node()?;
-                                        arguments.push(arg);
-                                        if self.current_token()?.kind == TokenKind::RightParenthesis {
-                                            break;
-                                        }
-                                        self.consume(Comma)?;
-                                    }
-                                    self.consume(TokenKind::RightParenthesis)?;
-                                    self.consume(TokenKind::Semicolon)?;
-                                    ast.add_node(Node::Call {
-                                        name: ident.value,
-                                        arguments,
-                                        returns: vec![],
-                                    })
-                                }
-                                TokenKind::LBracket => {
-                                    self.consume(TokenKind::LBracket)?;
-                                    let index = self.parse_node()?;
-
-                                    self.consume(TokenKind::RBracket)?;
-                                    self.consume(TokenKind::Equality)?;
-                                    let value = self.parse_node()?;
-
-                                    let index_update = Node::IndexExpression {
-                                        left: Box::from(Node::Ident { name: ident.value, kind: "var".to_string() }),
-                                        index: Box::from(index),
-                                    };
-
-                                    let n = Node::AssignStmt {
-                                        left: Box::from(index_update),
-                                        right: Box::from(value),
-                                        kind: "".to_string(),
-                                    };
-                                    self.consume(TokenKind::Semicolon)?;
-                                    ast.add_node(n);
-                                    continue;
-                                }
-                                TokenKind::Period => {
-                                    // this is a method call
-                                    let res = self.parse_method_call(ident)?;
-                                    self.consume(TokenKind::Semicolon)?;
-                                    ast.add_node(res);
-                                    continue;
-                                }
-                                _ => {
-                                }
-                            }
-                        }
+            if let Err(message) = self.parse_statement() {
+                self.report(message);
+                self.synchronize();
+                // a `}` left over from the statement that failed
+                if self.current_kind() == TokenKind::RCurlyBracket {
+                    self.advance();
+                }
+            }
+        }
+
+        let mut errors = std::mem::take(&mut self.errors);
+        if let Some(lex_error) = self.lex_error.clone() {
+            errors.push(lex_error);
+        }
+        errors.sort_by_key(|e| e.span.start);
+
+        (std::mem::take(&mut self.ast), errors)
+    }
+
+    // parses one top level statement into `self.ast`
+    fn parse_statement(&mut self) -> Result<(), String> {
+        match self.current_token()?.kind {
+            TokenKind::Word => {
+                let t = self.current_token()?;
+                
+                match t.value.as_str() {
+                    "type" => {
+                        self.consume(TokenKind::Word)?;
+                        let type_name = self.current_token()?;
+                        self.consume(TokenKind::Word)?;
+
+                        self.consume(TokenKind::Semicolon)?;
+
+                        self.ast.add_node(ast::node::Node::Type {
+                            name: type_name.value,
+                        });
+                        return Ok(());
+                    }
+                    "fn" => {
+                        let func = self.parse_function()?;
+                        self.ast.add_node(func);
+                        return Ok(());
+                    }
+                    _ => {
+                        let node = self.parse_word()?;
+                        self.ast.add_node(node);
+                        return Ok(());
                     }
                 }
-                TokenKind::Comment => {
-                    // skip comments
+            }
+            TokenKind::Comment => {
+                // skip comments
+                self.advance();
+                return Ok(());
+            }
+            TokenKind::BitwiseAnd => {
+                self.consume(TokenKind::BitwiseAnd)?;
+                if self.current_token()?.kind != TokenKind::BitwiseAnd {
+                    return Err("Expected another hypothesis conjunction".to_string());
+                }
+            }
+            _ => {
+                let token = self.current_token()?;
+                return Err(format!("unexpected token {:?} `{}`", token.kind, token.value));
+            }
+        }
+        self.advance();
+        Ok(())
+    }
+
+    // records an error at the token the parser got stuck on
+    fn report(&mut self, message: String) {
+        // the tokens stop where the lexer gave up, running out of them is a knock-on error
+        if self.lex_error.is_some() && self.position >= self.tokens.len() {
+            return;
+        }
+        let span = self.current_token().map(|token| token.span).unwrap_or_default();
+        self.errors.push(SyntaxError::new(message, span));
+    }
+
+    /// Panic-mode recovery: skips tokens up to and including the next `;`, or up to the `}` that
+    /// closes the enclosing block. Blocks opened while skipping are skipped as a whole.
+    fn synchronize(&mut self) {
+        let start = self.position;
+        let mut depth = 0;
+        while self.position < self.tokens.len() {
+            match self.current_kind() {
+                TokenKind::Semicolon if depth == 0 => {
                     self.advance();
-                    continue;
+                    return;
                 }
-                TokenKind::BitwiseAnd => {
-                    self.consume(TokenKind::BitwiseAnd)?;
-                    if self.current_token()?.kind != TokenKind::BitwiseAnd {
-                        return Err("Expected another hypothesis conjunction".to_string());
+                TokenKind::LCurlyBracket => depth += 1,
+                TokenKind::RCurlyBracket if depth == 0 => {
+                    // leave it for the block we are in, unless it is the token that failed
+                    if self.position == start {
+                        self.advance();
                     }
+                    return;
                 }
-                _ => {
-                    println!("peek: {:?}", self.peek_token()?);
-                    return Err(format!("unexpected token: {:?} while parsing", self.current_token()?));
+                TokenKind::RCurlyBracket => {
+                    depth -= 1;
+                    if depth == 0 {
+                        self.advance();
+                        return;
+                    }
                 }
+                _ => {}
             }
             self.advance();
         }
+    }
 
-        // dbg!(globals);
+    // parses statements up to the closing `}`, recovering from errors inside the block
+    fn parse_block(&mut self) -> Result<Vec<Node>, String> {
+        let mut nodes = Vec::new();
+        loop {
+            match self.current_kind() {
+                TokenKind::RCurlyBracket | TokenKind::EOF => break,
+                _ => match self.parse_node() {
+                    Ok(node) => nodes.push(node),
+                    Err(message) => {
+                        self.report(message);
+                        self.synchronize();
+                    }
+                },
+            }
+        }
 
-        Ok(ast)
+        self.consume(TokenKind::RCurlyBracket)?;
+        Ok(nodes)
     }
 
-    pub fn parse_input(&mut self, input: &str) -> Result<(), String> {
+    pub fn parse_input(&mut self, input: &str) -> Result<(), SyntaxError> {
         let mut parser = LangParser::new(input);
-        let ast = parser.parse().expect("unexpected failure");
+        let ast = parser.parse()?;
 
         for node in ast.nodes {
             self.ast.add_node(node);
@@ -215,21 +200,12 @@ impl<'a> LangParser<'a> {
         Ok(())
     }
     
+    // parses a statement, or an expression used as one
     fn parse_node(&mut self) -> Result<Node, String> {
         match self.current_token()?.kind {
-            TokenKind::Number => { self.parse_number() }
-            TokenKind::Word => { self.parse_word() }
-            TokenKind::LeftParenthesis => { self.parse_left_parenthesis() }
-            TokenKind::Identifier => { self.parse_identifier() }
-            TokenKind::LBracket => { self.parse_left_bracket() }
-            TokenKind::String => { self.parse_string() }
-            TokenKind::Negation => { self.parse_negation() }
-            TokenKind::LessThan => { self.parse_less_than() }
-            _ => {
-                println!("current: {:?}", self.current_token()?);
-                println!("peek: {:?}", self.peek_token()?);
-                Err(format!("unexpected token: {:?}", self.current_token()?))
-            }
+            TokenKind::Word => self.parse_word(),
+            TokenKind::EOF => Err("unexpected end of input".to_string()),
+            _ => self.parse_expression_statement(),
         }
     }
 
@@ -240,162 +216,250 @@ impl<'a> LangParser<'a> {
     }
 
     fn parse_word(&mut self) -> Result<Node, String> {
-        let name = self.current_token()?;
-        self.advance();
+        let start = self.current_token()?.span;
 
-        match name.value.as_str() {
-            "print" => return self.parse_print(),
+        match self.current_token()?.value.as_str() {
+            "print" => {
+                self.advance();
+                self.parse_print()
+            }
             "let" => {
-                let ident = self.parse_let()?;
-
-                return Ok(ident);
+                self.advance();
+                self.parse_let()
+            }
+            "if" => {
+                self.advance();
+                self.parse_if()
+            }
+            "for" => {
+                self.advance();
+                self.parse_for_loop()
+            }
+            "while" => {
+                self.advance();
+                self.parse_while()
+            }
+            "loop" => {
+                self.advance();
+                self.consume(TokenKind::LCurlyBracket)?;
+                let body = self.parse_body()?;
+                Ok(Node::Loop { body, span: self.span_from(start) })
             }
-            "if" => return self.parse_if(),
             "break" => {
+                self.advance();
                 self.consume(TokenKind::Semicolon)?;
-                return Ok(Node::Break{});
+                Ok(Node::Break{ span: self.span_from(start) })
             }
-            "return" => {
-                let value = self.parse_node()?;
+            "continue" => {
+                self.advance();
                 self.consume(TokenKind::Semicolon)?;
-                return Ok(Node::Return { value: Box::from(value) });
-            }
-            "true" => {
-                return Ok(Node::Atomic { value: ast::value::Value::Bool(true) });
+                Ok(Node::Continue { span: self.span_from(start) })
             }
-            "false" => {
-                return Ok(Node::Atomic { value: ast::value::Value::Bool(false) });
+            // a named function; `fn(...)` without a name is a lambda, an expression
+            "fn" if self.peek_token().is_ok_and(|token| token.kind == TokenKind::Word) => self.parse_function(),
+            "return" => {
+                self.advance();
+                let value = self.parse_expression()?;
+                self.consume(TokenKind::Semicolon)?;
+                Ok(Node::Return { value: Box::from(value), span: self.span_from(start) })
             }
-            "bin" => {
-                self.consume(TokenKind::LeftParenthesis)?;
-                let value = self.parse_node()?;
-
-                self.consume(TokenKind::RightParenthesis)?;
+            _ => self.parse_expression_statement(),
+        }
+    }
 
-                if self.current_token()?.kind.is_binary_operator()  {
-                    let op = self.current_token()?;
-                    let op_kind = token_kind_to_operator_kind(op.kind)?;
-                    self.advance();
-                    let right = self.parse_node()?;
+    // `target = value;`, or an expression evaluated for its side effects such as `list.push(1);`
+    fn parse_expression_statement(&mut self) -> Result<Node, String> {
+        let start = self.current_token()?.span;
+        let expression = self.parse_expression()?;
 
-                    return Ok(Node::BinaryExpression {
-                        left: Box::from(value),
-                        operator: op_kind,
-                        right: Box::from(right),
-                    });
-                }
-                let integer = ast::internal_types::fetch_integer(value)?;
-                return Ok(Node::Atomic { value: ast::value::Value::Bin(integer as u32) });
+        if self.current_kind() != TokenKind::Equality {
+            if self.current_kind() == TokenKind::Semicolon {
+                self.advance();
             }
-            _ => {}
+            return Ok(expression);
         }
 
-        if self.current_token()?.kind == TokenKind::Period {
-            let res = self.parse_method_call(name)?;
-            if self.current_token()?.kind == TokenKind::Semicolon {
-                self.advance();
+        let kind = match &expression {
+            Node::Ident { .. } => "Nat",
+            Node::IndexExpression { .. } => "",
+            _ => return Err("invalid left-hand side of an assignment".to_string()),
+        };
+        self.consume(TokenKind::Equality)?;
+        let value = self.parse_expression()?;
+        self.consume(TokenKind::Semicolon)?;
+
+        Ok(Node::AssignStmt {
+            left: Box::from(expression),
+            right: Box::from(value),
+            kind: kind.to_string(),
+            span: self.span_from(start),
+        })
+    }
+
+    /// Parses an expression by precedence climbing over `binding_power`, so `a + b * c` groups
+    /// as `a + (b * c)` and `a - b - c` as `(a - b) - c`.
+    pub(crate) fn parse_expression(&mut self) -> Result<Node, String> {
+        self.parse_binary(0)
+    }
+
+    // parses operands joined by operators that bind at least as tight as `min_precedence`
+    fn parse_binary(&mut self, min_precedence: u8) -> Result<Node, String> {
+        let start = self.current_token()?.span;
+        let mut left = self.parse_unary()?;
+
+        while let Some((operator, precedence, associativity)) = self.current_binary_operator() {
+            if precedence < min_precedence {
+                break;
             }
-            return Ok(res);
-        }
+            self.advance();
 
-        // parse potential function
-        if self.current_token()?.kind == TokenKind::LeftParenthesis {
-            self.consume(TokenKind::LeftParenthesis)?;
-            let mut arguments = Vec::new();
-            while self.current_token()?.kind != TokenKind::RightParenthesis {
-                let arg = self.parse_node()?;
-                arguments.push(arg);
-                if self.current_token()?.kind == TokenKind::RightParenthesis {
-                    break;
+            let next = match associativity {
+                Associativity::Right => precedence,
+                Associativity::Left | Associativity::Neither => precedence + 1,
+            };
+            let right = self.parse_binary(next)?;
+
+            if associativity == Associativity::Neither {
+                if let Some((other, p, _)) = self.current_binary_operator() {
+                    if p == precedence {
+                        return Err(format!("`{}` cannot be chained with `{}`, split it up with `&&`", operator, other));
+                    }
                 }
-                self.consume(Comma)?;
             }
-            self.consume(TokenKind::RightParenthesis)?;
-            return Ok(Node::Call {
-                name: name.value,
-                arguments,
-                returns: vec![],
-            });
+
+            left = Node::BinaryExpression {
+                left: Box::from(left),
+                operator,
+                right: Box::from(right),
+                span: self.span_from(start),
+            };
         }
 
-        match self.current_token()?.kind {
-            TokenKind::Equality => {
-                self.consume(TokenKind::Equality)?;
-                let value = self.parse_node()?;
-                self.consume(TokenKind::Semicolon)?;
-                Ok(Node::AssignStmt {
-                    left: Box::from(Node::Ident { name: name.value, kind: "var".to_string() }),
-                    kind: "Nat".to_string(),
-                    right: Box::from(value),
-                })
+        Ok(left)
+    }
+
+    fn current_binary_operator(&self) -> Option<(OperatorKind, u8, Associativity)> {
+        let operator = token_kind_to_operator_kind(self.current_kind()).ok()?;
+        let (precedence, associativity) = binding_power(&operator)?;
+        Some((operator, precedence, associativity))
+    }
+
+    // prefix `-` and `~` bind tighter than any binary operator
+    fn parse_unary(&mut self) -> Result<Node, String> {
+        let start = self.current_token()?.span;
+        let operator = match self.current_kind() {
+            TokenKind::Subtract => OperatorKind::Subtract,
+            TokenKind::Negation => OperatorKind::Negation,
+            _ => return self.parse_primary(),
+        };
+        self.advance();
+
+        let right = self.parse_unary()?;
+        Ok(Node::UnaryExpression { operator, right: Box::from(right), span: self.span_from(start) })
+    }
+
+    fn parse_primary(&mut self) -> Result<Node, String> {
+        let token = self.current_token()?;
+        match token.kind {
+            TokenKind::Number | TokenKind::Float => self.parse_number(),
+            TokenKind::String => self.parse_string(),
+            TokenKind::Word => self.parse_name(),
+            TokenKind::Identifier => self.parse_identifier(),
+            TokenKind::LeftParenthesis => self.parse_left_parenthesis(),
+            TokenKind::LBracket => self.parse_left_bracket(),
+            TokenKind::LessThan => self.parse_less_than(),
+            TokenKind::EOF => Err("unexpected end of input".to_string()),
+            _ => Err(format!("unexpected token {:?} `{}`", token.kind, token.value)),
+        }
+    }
+
+    // a word in expression position: a literal, a variable, a call or a method call, followed by
+    // any number of `[index]`
+    fn parse_name(&mut self) -> Result<Node, String> {
+        let name = self.current_token()?;
+        let start = name.span;
+        self.advance();
+
+        let mut node = match name.value.as_str() {
+            "true" => Node::Atomic { value: ast::value::Value::Bool(true) },
+            // `fn(x) { ... }`, an anonymous function
+            "fn" => {
+                let arguments = self.parse_parameters()?;
+                self.consume(TokenKind::LCurlyBracket)?;
+                let body = self.parse_body()?;
+                Node::Lambda { arguments, body, span: self.span_from(start) }
             }
-            TokenKind::Add | TokenKind::Subtract | TokenKind::Multiply | TokenKind::Divide => {
-                let operator = self.current_token()?;
-                let operator_kind = token_kind_to_operator_kind(operator.kind)?;
-                self.advance();
-                let right = self.parse_node()?;
-                
-                Ok(Node::BinaryExpression {
-                    left: Box::from(Node::AssignStmt {
-                        left: Box::from(Node::Ident { name: name.value, kind: "var".to_string() }),
-                        right: Box::from(Node::Atomic {
-                            value: ast::value::Value::Int(0),
-                        }),
-                        kind: "Nat".to_string(),
-                    }),
-                    operator: operator_kind,
-                    right: Box::from(right),
-                })
+            "false" => Node::Atomic { value: ast::value::Value::Bool(false) },
+            "bin" => {
+                let mut arguments = self.parse_arguments()?;
+                match arguments.as_slice() {
+                    // literals are converted right away, anything else by the `bin` builtin
+                    [Node::Atomic { value: ast::value::Value::Int(i) }] => {
+                        Node::Atomic { value: ast::value::Value::Bin(*i as u32) }
+                    }
+                    [_] => Node::Call {
+                        name: name.value,
+                        arguments: vec![arguments.remove(0)],
+                        returns: vec![],
+                        span: self.span_from(start),
+                    },
+                    _ => return Err(format!("`bin` takes 1 argument but {} were given", arguments.len())),
+                }
             }
-            TokenKind::LBracket => {
-                self.consume(TokenKind::LBracket)?;
-                let index = self.parse_node()?;
-
-                self.consume(TokenKind::RBracket)?;
-
-                // TODO: Check for nested arrays
-                if self.current_token()?.kind == TokenKind::LBracket {
-                    self.consume(TokenKind::LBracket)?;
-                    let index2 = self.parse_node()?;
-                    self.consume(TokenKind::RBracket)?;
-
-                    if self.current_token()?.kind == TokenKind::Equality {
-                        self.consume(TokenKind::Equality)?;
-                        let _right = self.parse_node()?;
-                        panic!("not yet implemented");
+            _ => match self.current_kind() {
+                TokenKind::LeftParenthesis => {
+                    let arguments = self.parse_arguments()?;
+                    Node::Call {
+                        name: name.value,
+                        arguments,
+                        returns: vec![],
+                        span: self.span_from(start),
                     }
-
-                    return Ok(Node::IndexExpression {
-                        left: Box::from(Node::IndexExpression {
-                            left: Box::from(Node::Ident {name: name.value, kind: "var".to_string()}),
-                            index: Box::from(index),
-                        }),
-                        index: Box::from(index2),
-                    });
                 }
+                TokenKind::Period => self.parse_method_call(name)?,
+                _ => Node::Ident { name: name.value, kind: "var".to_string(), span: name.span },
+            },
+        };
 
-                Ok(Node::IndexExpression {
-                    left: Box::from(Node::AssignStmt {
-                        left: Box::from(Node::Ident { name: name.value, kind: "var".to_string() }),
-                        right: Box::from(Node::Atomic {
-                            value: ast::value::Value::Int(0),
-                        }),
-                        kind: "Nat".to_string(),
-                    }),
-                    index: Box::from(index),
-                })
-            }
-            TokenKind::Period => {
-                Ok(Node::Ident { name: name.value, kind: "var".to_string() })
-            }
-            _ => {
-                Ok(Node::Ident { name: name.value, kind: "var".to_string() })
+        while self.current_kind() == TokenKind::LBracket {
+            self.consume(TokenKind::LBracket)?;
+            let index = self.parse_expression()?;
+            self.consume(TokenKind::RBracket)?;
+
+            node = Node::IndexExpression {
+                left: Box::from(node),
+                index: Box::from(index),
+                span: self.span_from(start),
+            };
+        }
+
+        Ok(node)
+    }
+
+    // `(a, b, ...)`
+    fn parse_arguments(&mut self) -> Result<Vec<Node>, String> {
+        self.consume(TokenKind::LeftParenthesis)?;
+        let mut arguments = Vec::new();
+        while self.current_kind() != TokenKind::RightParenthesis {
+            arguments.push(self.parse_expression()?);
+            if self.current_kind() == TokenKind::RightParenthesis {
+                break;
             }
+            self.consume(Comma)?;
         }
+        self.consume(TokenKind::RightParenthesis)?;
+        Ok(arguments)
     }
 
     fn parse_left_parenthesis(&mut self) -> Result<Node, String> {
-        // at the moment, the language expects this to be an expression. This might need to be rethought as the language grows
+        if !self.is_mm_expression() {
+            self.consume(TokenKind::LeftParenthesis)?;
+            let expression = self.parse_expression()?;
+            self.consume(TokenKind::RightParenthesis)?;
+            return Ok(expression);
+        }
+
+        // metamath formulas are kept as text for the metamath parser
         let mut expression: String = "(".to_string();
         self.consume(TokenKind::LeftParenthesis)?;
         while self.current_token()?.kind != TokenKind::Semicolon {
@@ -406,15 +470,43 @@ impl<'a> LangParser<'a> {
         Ok(Node::MMExpression { expression })
     }
 
+    // whether the parenthesised group starting at the current token holds metamath symbols
+    fn is_mm_expression(&self) -> bool {
+        let mut depth = 0;
+        for token in &self.tokens[self.position..] {
+            match token.kind {
+                TokenKind::LeftParenthesis => depth += 1,
+                TokenKind::RightParenthesis => {
+                    depth -= 1;
+                    if depth == 0 {
+                        return false;
+                    }
+                }
+                TokenKind::Implies
+                | TokenKind::Biconditional
+                | TokenKind::Conjunction
+                | TokenKind::Disjunction
+                | TokenKind::ForAll
+                | TokenKind::Exists
+                | TokenKind::ElementOf
+                | TokenKind::Turnstile
+                | TokenKind::Identifier => return true,
+                _ => {}
+            }
+        }
+        false
+    }
+
     fn parse_identifier(&mut self) -> Result<Node, String> {
         let name = self.current_token()?;
         self.advance();
         Ok(Node::AssignStmt {
-            left: Box::from(Node::Ident { name: name.value, kind: "var".to_string() }),
+            left: Box::from(Node::Ident { name: name.value, kind: "var".to_string(), span: name.span }),
             right: Box::from(Node::Atomic {
                 value: ast::value::Value::Int(0),
             }),
             kind: "Nat".to_string(),
+            span: name.span,
         })
     }
 
@@ -424,7 +516,7 @@ impl<'a> LangParser<'a> {
         let mut nodes = Vec::new();
 
         while self.current_token()?.kind != TokenKind::RBracket {
-            nodes.push(self.parse_node()?);
+            nodes.push(self.parse_expression()?);
             if self.current_token()?.kind == TokenKind::RBracket {
                 break;
             }
@@ -441,12 +533,6 @@ impl<'a> LangParser<'a> {
         Ok(Node::Atomic { value: val })
     }
 
-    fn parse_negation(&mut self) -> Result<Node, String> {
-        self.consume(TokenKind::Negation)?;
-        let node = self.parse_node()?;
-        Ok(Node::UnaryExpression { operator: OperatorKind::Negation, right: Box::from(node) })
-    }
-
     fn parse_less_than(&mut self) -> Result<Node, String> {
         // parsing a new Hashmap
         self.consume(TokenKind::LessThan)?;
@@ -456,9 +542,9 @@ impl<'a> LangParser<'a> {
     }
 
     fn parse_if(&mut self) -> Result<Node, String> {
-        
-        let condition = self.parse_condition_header()?;
-        println!("condition: {:?}", condition);
+        // the `if` keyword has already been consumed
+        let start = self.previous_span();
+        let condition = self.parse_expression()?;
         self.consume(TokenKind::LCurlyBracket)?;
 
         // parse consequence
@@ -469,10 +555,25 @@ impl<'a> LangParser<'a> {
                 condition: Box::from(condition),
                 consequence,
                 alternative: vec![],
+                span: self.span_from(start),
             });
         }
         
         self.consume(TokenKind::Word)?;
+
+        // `else if` chains nest as the alternative of the previous branch
+        if self.current_token()?.value == "if" {
+            self.advance();
+            let chained = self.parse_if()?;
+
+            return Ok(Node::Conditional {
+                condition: Box::from(condition),
+                consequence,
+                alternative: vec![chained],
+                span: self.span_from(start),
+            });
+        }
+
         self.consume(TokenKind::LCurlyBracket)?;
         
         let alternative = self.parse_consequence()?;
@@ -481,289 +582,96 @@ impl<'a> LangParser<'a> {
             condition: Box::from(condition),
             consequence,
             alternative,
+            span: self.span_from(start),
+        })
+    }
+
+    fn parse_while(&mut self) -> Result<Node, String> {
+        // the `while` keyword has already been consumed
+        let start = self.previous_span();
+        let condition = self.parse_expression()?;
+        self.consume(TokenKind::LCurlyBracket)?;
+        let body = self.parse_body()?;
+
+        Ok(Node::WhileLoop {
+            condition: Box::from(condition),
+            body,
+            span: self.span_from(start),
         })
     }
 
     fn parse_let(&mut self) -> Result<Node, String> {
+        // the `let` keyword has already been consumed
+        let start = self.previous_span();
         let name = self.current_token()?;
         
         self.consume(TokenKind::Word)?;
-
         self.consume(TokenKind::Equality)?;
 
-        let left = self.parse_node()?;
-        
-        // TODO: Will need a more robust way to handle expressions in the future
-        if self.current_token()?.kind == TokenKind::Add
-            || self.current_token()?.kind == TokenKind::Modulo
-            || self.current_token()?.kind == TokenKind::Subtract
-            || self.current_token()?.kind == TokenKind::Multiply
-            || self.current_token()?.kind == TokenKind::Divide
-            || self.current_token()?.kind == TokenKind::BitwiseAnd
-            || self.current_token()?.kind == TokenKind::BitwiseOr
-            || self.current_token()?.kind == TokenKind::BitwiseXor
-            || self.current_token()?.kind == TokenKind::ShiftLeft
-            || self.current_token()?.kind == TokenKind::ShiftRight
-        {
-            let op = self.current_token()?;
-            let op_kind = token_kind_to_operator_kind(op.kind)?;
-            self.advance();
-
-            let right = self.parse_node()?;
-
-            let n = Node::BinaryExpression {
-                left: Box::from(left),
-                operator: op_kind,
-                right: Box::from(right),
-            };
+        let value = self.parse_expression()?;
+        self.consume(TokenKind::Semicolon)?;
 
-            let ident = Node::AssignStmt {
-                left: Box::from(Node::Ident { name: name.value, kind: "var".to_string() }),
-                right: Box::from(n),
-                kind: "str".to_string(),
-            };
-            
-            
-            self.consume(TokenKind::Semicolon)?;
-            return Ok(ident)
-        }
-        
-        let ident = Node::AssignStmt {
-            left: Box::from(Node::Ident { name: name.value, kind: "var".to_string() }),
-            right: Box::from(left),
-            kind: "str".to_string(),
-        };
-        
-        // TODO: this is so ugly. Will need to find a way to unify semicolon consumption
-        if self.current_token()?.kind == TokenKind::Semicolon {
-            self.consume(TokenKind::Semicolon)?;    
-        }
-        
-        
-        Ok(ident)
+        Ok(Node::AssignStmt {
+            left: Box::from(Node::Ident { name: name.value, kind: "var".to_string(), span: name.span }),
+            right: Box::from(value),
+            kind: "let".to_string(),
+            span: self.span_from(start),
+        })
     }
     
     fn parse_method_call(&mut self, target: Token) -> Result<Node, String> {
         self.consume(TokenKind::Period)?;
         let function_name = self.current_token()?;
         self.consume(TokenKind::Word)?;
-        self.consume(TokenKind::LeftParenthesis)?;
-        let mut arguments = Vec::new();
-        while self.current_token()?.kind != TokenKind::RightParenthesis {
-            let arg = self.parse_node()?;
-            arguments.push(arg);
-            if self.current_token()?.kind == TokenKind::RightParenthesis {
-                break;
-            }
-            self.consume(Comma)?;
-        }
-        self.consume(TokenKind::RightParenthesis)?;
+        let arguments = self.parse_arguments()?;
 
-        let right = Node::MethodCall {
+        Ok(Node::MethodCall {
             name: function_name.value,
             target: target.value,
             arguments,
             returns: vec![],
-        };
-
-        Ok(right)
+            span: self.span_from(target.span),
+        })
     }
     
     fn parse_for_loop(&mut self) -> Result<Node, String> {
+        // the `for` keyword has already been consumed
+        let for_start = self.previous_span();
         let variable = self.current_token()?;
         self.consume(TokenKind::Word)?;
 
         self.consume(TokenKind::Word)?; // consume "in"
-        
-        let start = self.current_token()?;
-        
-        self.consume(TokenKind::Number)?;
-        self.consume(TokenKind::Range)?;
-        let end = self.current_token()?;
-        match end.kind {
-            TokenKind::Number => {
-                // self.consume(TokenKind::Number)?;
-                self.advance();
-                self.consume(TokenKind::LCurlyBracket)?;
-
 
-                let mut bracket_count = 1;
-
-                let mut nodes = Vec::new();
-                //while self.current_token()?.kind != TokenKind::RBracket {
-                while bracket_count > 0 {
-
-                    let node = self.parse_node()?;
-                    nodes.push(node);
-                    if self.current_token()?.kind == TokenKind::LCurlyBracket {
-                        bracket_count += 1;
-                    } else if self.current_token()?.kind == TokenKind::RCurlyBracket {
-                        bracket_count -= 1;
-                    }
-                }
-                self.consume(TokenKind::RCurlyBracket)?;
-
-                Ok(Node::ForLoop {
-                    variable: variable.value,
-                    range: (
-                        Box::from(Node::Atomic { value: ast::value::Value::Int(start.value.parse::<i32>().unwrap()) }),
-                        Box::from(Node::Atomic { value: ast::value::Value::Int(end.value.parse::<i32>().unwrap()) }),
-                    ),
-                    body: nodes,
-                })
-            }
-            TokenKind::Word => {
-                self.consume(TokenKind::Word)?;
-                self.consume(TokenKind::LCurlyBracket)?;
-
-                let mut bracket_count = 1;
-
-                let mut nodes = Vec::new();
-                //while self.current_token()?.kind != TokenKind::RBracket {
-                while bracket_count > 0 {
+        let start = self.parse_range_bound()?;
+        self.consume(TokenKind::Range)?;
+        let end = self.parse_range_bound()?;
+        self.consume(TokenKind::LCurlyBracket)?;
+        let body = self.parse_body()?;
 
-                    let node = self.parse_node()?;
-                    nodes.push(node);
-                    if self.current_token()?.kind == TokenKind::LCurlyBracket {
-                        bracket_count += 1;
-                    } else if self.current_token()?.kind == TokenKind::RCurlyBracket {
-                        bracket_count -= 1;
-                    }
-                }
-                self.consume(TokenKind::RCurlyBracket)?;
-
-                Ok(Node::ForLoop {
-                    variable: variable.value,
-                    range: (
-                        Box::from(Node::Atomic { value: ast::value::Value::Int(start.value.parse::<i32>().unwrap()) }),
-                        Box::from(Node::Ident { name: end.value, kind: "var".to_string() }),
-                    ),
-                    body: nodes,
-                })
-            }
-            _ => {
-                Err(format!("expected number, got {:?}", end))
-            }
-        }
-        
+        Ok(Node::ForLoop {
+            variable: variable.value,
+            range: (Box::from(start), Box::from(end)),
+            body,
+            span: self.span_from(for_start),
+        })
     }
-    
-    fn parse_condition_header(&mut self) -> Result<Node, String> {
-        // conditions must be wrapped in parentheses
-        self.consume(TokenKind::LeftParenthesis)?;
-        match self.current_token()?.kind {
-            TokenKind::Word => {
-                let n = self.parse_node()?;
-                
-                if self.current_token()?.kind == TokenKind::RightParenthesis {
-                    self.advance();
-                    return Ok(n);
-                }
-                
-                let operator = self.current_token()?;
-                let operator_kind = token_kind_to_operator_kind(operator.kind)?;
-                self.advance();
-                
-                let right = self.parse_node()?;
-                
-                // todo: check if the current token is a binary operator
-                if self.current_token()?.kind.is_binary_operator() {
-                    let op = self.current_token()?;
-                    let op_kind = token_kind_to_operator_kind(op.kind)?;
-                    self.advance();
-                    let right_left = self.parse_node()?;
 
-                    if self.current_token()?.kind != TokenKind::RightParenthesis {
-                        let op_right = self.current_token()?;
-                        let op_right_kind = token_kind_to_operator_kind(op_right.kind)?;
-                        self.advance();
-                        // more expressions to parse
-                        let right_right = self.parse_node()?;
-                        self.consume(TokenKind::RightParenthesis)?;
-                        return Ok(Node::BinaryExpression {
-                            left: Box::from(Node::BinaryExpression {
-                                left: Box::from(n),
-                                operator: operator_kind,
-                                right: Box::from(right),
-                            }),
-                            operator: op_kind,
-                            right: Box::from(Node::BinaryExpression {
-                                left: Box::from(right_left),
-                                operator: op_right_kind,
-                                right: Box::from(right_right),
-                            }),
-                        });
-                    }
-                    
-                    return Ok(Node::BinaryExpression {
-                        left: Box::from(Node::BinaryExpression {
-                            left: Box::from(n),
-                            operator: operator_kind,
-                            right: Box::from(right),
-                        }),
-                        operator: op_kind,
-                        right: Box::from(right_left),
-                    });
-                }
-                self.consume(TokenKind::RightParenthesis)?;
-                
-                Ok(Node::BinaryExpression {
-                    left: Box::from(n),
-                    operator: operator_kind,
-                    right: Box::from(right),
-                })
-            }
-            TokenKind::Number => {
-                let left = self.current_token()?;
-                self.advance();
-                let operator = self.current_token()?;
-                let operator_kind = token_kind_to_operator_kind(operator.kind)?;
-                self.advance();
-                let right = self.parse_condition()?;
-                
-                let node = Node::BinaryExpression {
-                    left: Box::from(Node::Atomic {
-                        value: ast::value::Value::Int(left.value.parse::<i32>().unwrap()),
-                    }),
-                    operator: operator_kind,
-                    right: Box::from(right),
-                };
-                Ok(node)
-            }
-            _ => {
-                Err(format!("invalid conditional: {:?}", self.current_token()?))
-            }
+    // a bound of a `for` range, which has to be an int when the loop runs
+    fn parse_range_bound(&mut self) -> Result<Node, String> {
+        let bound = self.parse_expression()?;
+        match &bound {
+            Node::Atomic { value: ast::value::Value::Int(_) } => Ok(bound),
+            Node::Atomic { value } => Err(format!("a `for` range bound has to be an int, found `{}`", value)),
+            _ => Ok(bound),
         }
     }
     
-    // parse_expression will parse any potential expressions
-    // example x will return a unary expression node
-    // example x + 1 will return a BinaryExpression node
-    // example: x == 1 will return a BinaryExpression node
-    fn parse_expression(&mut self) -> Result<Node, String> {
-        match self.current_token()?.kind {
-            _ => {
-                Err("not yet implemented".to_string())
-            }
-        }
-    }
-
+    
     fn parse_function(&mut self) -> Result<Node, String> {
+        let start = self.current_token()?.span;
         self.consume(TokenKind::Word)?; // consume "fn"
         let name = self.parse_function_name()?;
-
-        self.consume(TokenKind::LeftParenthesis)?;
-        let mut arguments = Vec::new();
-
-        while self.current_token()?.kind != TokenKind::RightParenthesis {
-            let arg = self.parse_function_input()?;
-
-            if self.current_token()?.kind != TokenKind::RightParenthesis {
-                self.consume(Comma)?;
-            }
-            arguments.push(arg);
-        }
-        self.consume(TokenKind::RightParenthesis)?;
+        let arguments = self.parse_parameters()?;
 
         // TODO: ignoring return types for now :(
 
@@ -776,9 +684,27 @@ impl<'a> LangParser<'a> {
             arguments,
             returns: vec![],
             body,
+            span: self.span_from(start),
         })
     }
 
+    // `(x, y)` in a function declaration or a lambda
+    fn parse_parameters(&mut self) -> Result<Vec<Node>, String> {
+        self.consume(TokenKind::LeftParenthesis)?;
+        let mut arguments = Vec::new();
+
+        while self.current_token()?.kind != TokenKind::RightParenthesis {
+            let arg = self.parse_function_input()?;
+
+            if self.current_token()?.kind != TokenKind::RightParenthesis {
+                self.consume(Comma)?;
+            }
+            arguments.push(arg);
+        }
+        self.consume(TokenKind::RightParenthesis)?;
+        Ok(arguments)
+    }
+
     fn parse_function_name(&mut self) -> Result<Node, String> {
         let name = self.current_token()?;
         self.consume(TokenKind::Word)?;
@@ -786,6 +712,7 @@ impl<'a> LangParser<'a> {
         Ok(Node::Ident {
             name: name.value,
             kind: "fn".to_string(),
+            span: name.span,
         })
     }
 
@@ -798,115 +725,35 @@ impl<'a> LangParser<'a> {
         Ok(Node::Ident {
             name: ident.value,
             kind: "var".to_string(),
-        })
-    }
-
-    fn parse_condition(&mut self) -> Result<Node, String> {
-        // conditions must be wrapped in parentheses
-        let left = self.current_token()?;
-        if left.value == "true" {
-            self.advance();
-            return Ok(Node::Atomic {
-                value: ast::value::Value::Bool(true),
-            });
-        } else if left.value == "false" {
-            self.advance();
-            return Ok(Node::Atomic {
-                value: ast::value::Value::Bool(false),
-            });
-        }
-        
-        if self.peek_token()?.kind == TokenKind::RightParenthesis {
-            let val = self.current_token()?.value;
-            self.advance();
-            
-            if self.current_token()?.kind != TokenKind::Number {
-                self.consume(TokenKind::RightParenthesis)?;
-                return Ok(Node::Atomic {
-                    value: ast::value::Value::Int(val.parse().unwrap()),
-                });
-            }
-
-            self.advance();
-            self.consume(TokenKind::RightParenthesis)?;
-            
-            return Ok(Node::AssignStmt {
-                left: Box::from(Node::Ident { name: left.value, kind: "var".to_string() }),
-                right: Box::from(Node::Atomic {
-                    value: ast::value::Value::Int(0),
-                }),
-                kind: "Nat".to_string(),
-            });
-        }
-        
-        self.advance();
-        let operator = self.current_token()?;
-        let operator_kind = token_kind_to_operator_kind(operator.kind)?;
-
-        self.advance();
-        println!("operator: {:?}", operator_kind);
-        
-        let right = self.parse_node()?;
-        // TODO: add support for the right side being a variable
-        let node = Node::BinaryExpression {
-            left: Box::from(Node::AssignStmt {
-                left: Box::from(Node::Ident { name: left.value, kind: "var".to_string() }),
-                right: Box::from(Node::Atomic {
-                    value: ast::value::Value::Int(0),
-                }),
-                kind: "Nat".to_string(),
-            }),
-            operator: operator_kind,
-            right: Box::from(right),
-        };
-        
-        if self.current_token()?.kind != And && self.current_token()?.kind != TokenKind::Or {
-            return Ok(node);
-        }
-        
-        let op = self.current_token()?;
-        let op_kind = token_kind_to_operator_kind(op.kind)?;
-        self.advance();
-
-        let right2 = self.parse_condition()?;
-
-        Ok(Node::BinaryExpression {
-            left: Box::from(node),
-            operator: op_kind,
-            right: Box::from(right2),
+            span: ident.span,
         })
     }
 
     fn parse_consequence(&mut self) -> Result<Vec<Node>, String> {
-        let mut nodes = Vec::new();
-        while self.current_token()?.kind != TokenKind::RCurlyBracket {
-            nodes.push(self.parse_node()?);
-        }
-        
-        self.consume(TokenKind::RCurlyBracket)?;
-        Ok(nodes)
+        self.parse_block()
     }
     
     fn parse_print(&mut self) -> Result<Node, String> {
+        // the `print` keyword has already been consumed
+        let start = self.previous_span();
         self.consume(TokenKind::LeftParenthesis)?;
-
-        let input = self.parse_node()?;
-        
-        self.advance();
-
+        let input = self.parse_expression()?;
+        self.consume(TokenKind::RightParenthesis)?;
         self.consume(TokenKind::Semicolon)?;
 
-        let node = Node::Call {
+        Ok(Node::Call {
             name: "print".to_string(),
             arguments: vec![input],
             returns: vec![],
-        };
-        Ok(node)
+            span: self.span_from(start),
+        })
     }
     
     fn parse_len(&mut self) -> Result<Node, String> {
+        // the `len` keyword has already been consumed
+        let start = self.previous_span();
         self.consume(TokenKind::LeftParenthesis)?;
-        let input = self.parse_node()?;
+        let input = self.parse_expression()?;
         self.consume(TokenKind::RightParenthesis)?;
         self.consume(TokenKind::Semicolon)?;
         
@@ -914,34 +761,24 @@ impl<'a> LangParser<'a> {
             name: "len".to_string(),
             arguments: vec![input],
             returns: vec![],
+            span: self.span_from(start),
         };
         Ok(node)
     }
 
     fn parse_body(&mut self) -> Result<Vec<Node>, String> {
-        let mut nodes = Vec::new();
-        while self.current_token()?.kind != TokenKind::RCurlyBracket {
-            nodes.push(self.parse_node()?);
-        }
-        
-        self.consume(TokenKind::RCurlyBracket)?;
-        Ok(nodes)
+        self.parse_block()
     }
 
     fn consume(&mut self, kind: TokenKind) -> Result<(), String> {
-        // debug
-        if self.tokens[self.position].kind == kind {
+        let current = self.current_token()?;
+        if current.kind == kind {
             self.advance();
             Ok(())
+        } else if current.kind == TokenKind::EOF {
+            Err(format!("Expected {:?} but reached the end of the input", kind))
         } else {
-            println!("position: {:?}", self.position);
-            println!("Val: {:?}", self.tokens[self.position].value);
-            println!("Kind: {:?}", self.tokens[self.position].kind);
-            println!("{:?}", self.tokens);
-            Err(format!(
-                "Expected {:?} but found {:?}",
-                kind, self.tokens[self.position].kind
-            ))
+            Err(format!("Expected {:?} but found {:?} `{}`", kind, current.kind, current.value))
         }
     }
 
@@ -949,17 +786,38 @@ impl<'a> LangParser<'a> {
         self.position += 1;
     }
 
+    // span of the last consumed token
+    fn previous_span(&self) -> Span {
+        self.position
+            .checked_sub(1)
+            .and_then(|i| self.tokens.get(i))
+            .map(|token| token.span)
+            .unwrap_or_default()
+    }
+
+    // everything from `start` up to and including the last consumed token
+    fn span_from(&self, start: Span) -> Span {
+        start.to(self.previous_span())
+    }
+
     fn current_token(&self) -> Result<Token, String> {
         if self.position < self.tokens.len() {
             Ok(self.tokens[self.position].clone())
         } else {
+            // point at the end of the input
+            let end = self.tokens.last().map(|token| token.span).unwrap_or_default();
             Ok(Token {
                 kind: TokenKind::EOF,
                 value: "".to_string(),
+                span: Span { start: end.end, ..end },
             })
         }
     }
 
+    fn current_kind(&self) -> TokenKind {
+        self.tokens.get(self.position).map(|token| token.kind.clone()).unwrap_or(TokenKind::EOF)
+    }
+
     fn peek_token(&self) -> Result<Token, String> {
         if self.position + 1 < self.tokens.len() {
             Ok(self.tokens[self.position + 1].clone())
@@ -969,6 +827,42 @@ impl<'a> LangParser<'a> {
     }
 }
 
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Associativity {
+    Left,
+    Right,
+    // `a < b < c` is rejected rather than silently grouped
+    Neither,
+}
+
+/// Precedence of the binary operators, higher binds tighter. Comparisons bind looser than the
+/// bitwise operators so `x & mask == 0` reads as `(x & mask) == 0`. The operators that aren't
+/// binary operators in expressions, such as assignment and the quantifiers, have none.
+fn binding_power(operator: &OperatorKind) -> Option<(u8, Associativity)> {
+    let power = match operator {
+        OperatorKind::Biconditional => (1, Associativity::Left),
+        OperatorKind::Implies => (2, Associativity::Right),
+        OperatorKind::Or | OperatorKind::Disjunction => (3, Associativity::Left),
+        OperatorKind::And | OperatorKind::Conjunction => (4, Associativity::Left),
+        OperatorKind::IsEqual
+        | OperatorKind::LessThan
+        | OperatorKind::GreaterThan
+        | OperatorKind::ElementOf => (5, Associativity::Neither),
+        OperatorKind::BitwiseOr => (6, Associativity::Left),
+        OperatorKind::BitwiseXor => (7, Associativity::Left),
+        OperatorKind::BitwiseAnd => (8, Associativity::Left),
+        OperatorKind::ShiftLeft | OperatorKind::ShiftRight => (9, Associativity::Left),
+        OperatorKind::Add | OperatorKind::Subtract => (10, Associativity::Left),
+        OperatorKind::Multiply | OperatorKind::Divide | OperatorKind::Modulo => (11, Associativity::Left),
+        OperatorKind::Equality
+        | OperatorKind::ForAll
+        | OperatorKind::Exists
+        | OperatorKind::Identifier
+        | OperatorKind::Negation => return None,
+    };
+    Some(power)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -1077,8 +971,117 @@ mod tests {
 
     #[test]
     fn error_handling() {
-        // add checks that the parser returns an error when it should
-        // for example, having an expression not terminate with a semicolon
+        let input = "let x = 1 + 2\nlet y = 2;";
+        let mut parser = LangParser::new(input);
+        let e = parser.parse().expect_err("expected a missing semicolon to fail");
+        assert_eq!(e.message, "Expected Semicolon but found Word `let`");
+        assert_eq!((e.span.line, e.span.column), (2, 1));
+    }
+
+    #[test]
+    fn recovers_after_a_bad_statement() {
+        let input = "let a = 1;\n\
+        let b = );\n\
+        let c = 3;\n\
+        let d = ];\n\
+        print(c);";
+        let mut parser = LangParser::new(input);
+        let (ast, errors) = parser.parse_recovering();
+
+        let lines: Vec<usize> = errors.iter().map(|e| e.span.line).collect();
+        assert_eq!(lines, vec![2, 4]);
+        // `a`, `c` and the print survive
+        assert_eq!(ast.nodes.len(), 3);
+    }
+
+    #[test]
+    fn recovers_around_type_declarations() {
+        let input = "type Point;\n\
+        let a = );\n\
+        type;\n\
+        let b = 2;";
+        let mut parser = LangParser::new(input);
+        let (ast, errors) = parser.parse_recovering();
+
+        let lines: Vec<usize> = errors.iter().map(|e| e.span.line).collect();
+        assert_eq!(lines, vec![2, 3]);
+        assert_eq!(ast.nodes.len(), 2);
+        assert_eq!(ast.nodes[0], Node::Type { name: "Point".to_string() });
+    }
+
+    #[test]
+    fn for_loops_take_expressions_as_bounds() {
+        let input = "let n = 2;\nfor i in n - 1..n * 3 { }";
+        let ast = LangParser::new(input).parse().expect("unexpected failure");
+        match &ast.nodes[1] {
+            Node::ForLoop { range, body, .. } => {
+                assert!(matches!(*range.0, Node::BinaryExpression { .. }));
+                assert!(matches!(*range.1, Node::BinaryExpression { .. }));
+                assert!(body.is_empty());
+            }
+            node => panic!("expected a for loop, got {:?}", node),
+        }
+
+        let e = LangParser::new("for i in 0..3000000000 { }").parse().expect_err("expected a syntax error");
+        assert_eq!(e.message, "a `for` range bound has to be an int, found `3000000000`");
+    }
+
+    #[test]
+    fn recovers_inside_for_loops() {
+        let input = "for i in 0..3 {\n\
+            let x = );\n\
+            print(i);\n\
+        }\n\
+        let y = 1;";
+        let mut parser = LangParser::new(input);
+        let (ast, errors) = parser.parse_recovering();
+
+        let lines: Vec<usize> = errors.iter().map(|e| e.span.line).collect();
+        assert_eq!(lines, vec![2]);
+        assert_eq!(ast.nodes.len(), 2);
+        match &ast.nodes[0] {
+            Node::ForLoop { body, .. } => assert_eq!(body.len(), 1),
+            node => panic!("expected a for loop, got {:?}", node),
+        }
+    }
+
+    #[test]
+    fn recovers_inside_blocks() {
+        let input = "fn f(n) {\n\
+            let x = );\n\
+            return n;\n\
+        }\n\
+        if (1 < 2) {\n\
+            let y = ];\n\
+        }\n\
+        let z = f(3);";
+        let mut parser = LangParser::new(input);
+        let (ast, errors) = parser.parse_recovering();
+
+        let lines: Vec<usize> = errors.iter().map(|e| e.span.line).collect();
+        assert_eq!(lines, vec![2, 6]);
+        assert_eq!(ast.nodes.len(), 3);
+        match &ast.nodes[0] {
+            Node::FunctionDecl { body, .. } => assert_eq!(body.len(), 1),
+            node => panic!("expected a function, got {:?}", node),
+        }
+    }
+
+    #[test]
+    fn a_valid_program_has_no_errors() {
+        let input = "let x = 1; let y = x + 2;";
+        let mut parser = LangParser::new(input);
+        let (ast, errors) = parser.parse_recovering();
+        assert!(errors.is_empty(), "{:?}", errors);
+        assert_eq!(ast.nodes.len(), 2);
+    }
+
+    #[test]
+    fn nodes_compare_without_their_spans() {
+        let first = LangParser::new("let x = 1 + 2;").parse().expect("unexpected failure");
+        let second = LangParser::new("\n\n  let x =  1 +   2;").parse().expect("unexpected failure");
+        assert_ne!(first.nodes[0].span(), second.nodes[0].span());
+        assert_eq!(first.nodes, second.nodes);
     }
 }
 
@@ -1110,7 +1113,7 @@ pub fn token_kind_to_operator_kind(kind: TokenKind) -> Result<OperatorKind, Stri
         TokenKind::Identifier => Ok(OperatorKind::Identifier),
         TokenKind::Negation => Ok(OperatorKind::Negation),
         TokenKind::Print => Ok(OperatorKind::Identifier),
-        _ => panic!("unexpected token kind {:?}", kind),
+        _ => Err(format!("expected an operator but found {:?}", kind)),
     }
 }
 
@@ -1,17 +1,41 @@
+use ast::span::Span;
 use crate::parser::Lexer;
 
 #[derive(Debug, Clone)]
 pub struct Token {
     pub value: String,
     pub kind: TokenKind,
+    pub span: Span,
 }
 
+/// An error found while lexing or parsing, and where in the source it was found.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SyntaxError {
+    pub message: String,
+    pub span: Span,
+}
+
+impl SyntaxError {
+    pub fn new(message: impl Into<String>, span: Span) -> Self {
+        Self { message: message.into(), span }
+    }
+}
+
+impl std::fmt::Display for SyntaxError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{} at {}:{}", self.message, self.span.line, self.span.column)
+    }
+}
+
+impl std::error::Error for SyntaxError {}
+
 #[derive(Debug, Clone, PartialEq, Hash)]
 #[derive(Eq)]
 #[derive(PartialOrd)]
 #[derive(Ord)]
 pub enum TokenKind {
     Number,
+    Float,
     Operator,
     BinaryOperator,
     UnaryOperator,
@@ -90,6 +114,7 @@ impl std::fmt::Display for TokenKind {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
             TokenKind::Number => write!(f, "Number"),
+            TokenKind::Float => write!(f, "Float"),
             TokenKind::Operator => write!(f, "Operator"),
             TokenKind::BinaryOperator => write!(f, "BinaryOperator"),
             TokenKind::UnaryOperator => write!(f, "UnaryOperator"),
@@ -196,6 +221,12 @@ pub struct DefaultLexer {
     input: String,
     pub(crate) position: usize,
     char: char,
+    // byte offset, line and column of the current char
+    offset: usize,
+    line: usize,
+    column: usize,
+    // where the token being lexed started
+    start: Span,
 }
 
 impl DefaultLexer {
@@ -206,6 +237,22 @@ impl DefaultLexer {
             input,
             position: 0,
             char: first_char,
+            offset: 0,
+            line: 1,
+            column: 1,
+            start: Span::default(),
+        }
+    }
+
+    fn mark(&mut self) {
+        self.start = Span::new(self.offset, self.offset, self.line, self.column);
+    }
+
+    // the span from the start of the current token up to and including the current char
+    fn span(&self) -> Span {
+        Span {
+            end: self.offset + self.char.len_utf8(),
+            ..self.start
         }
     }
 }
@@ -213,6 +260,7 @@ impl DefaultLexer {
 impl Lexer for DefaultLexer {
     fn tokenize(&mut self) {
         while self.position < self.input.len() {
+            self.mark();
             match self.char {
                 ' ' => {
                     // TODO: Add this as a flag to include or exclude whitespace
@@ -225,24 +273,28 @@ impl Lexer for DefaultLexer {
                     self.tokens.push(Token {
                         value: self.char.to_string(),
                         kind: TokenKind::LeftParenthesis,
+                        span: self.span(),
                     });
                 }
                 ')' => {
                     self.tokens.push(Token {
                         value: self.char.to_string(),
                         kind: TokenKind::RightParenthesis,
+                        span: self.span(),
                     });
                 }
                 '[' => {
                     self.tokens.push(Token {
                         value: self.char.to_string(),
                         kind: TokenKind::LBracket,
+                        span: self.span(),
                     });
                 }
                 ']' => {
                     self.tokens.push(Token {
                         value: self.char.to_string(),
                         kind: TokenKind::RBracket,
+                        span: self.span(),
                     });
                 }
                 '|' => {
@@ -250,12 +302,14 @@ impl Lexer for DefaultLexer {
                         self.tokens.push(Token {
                             value: "|-".to_string(),
                             kind: TokenKind::Turnstile,
+                            span: self.span(),
                         });
                         self.next_char();
                     } else if self.peek() == '|' {
                         self.tokens.push(Token {
                             value: "||".to_string(),
                             kind: TokenKind::Or,
+                            span: self.span(),
                         });
                         self.next_char();
                     }
@@ -263,6 +317,7 @@ impl Lexer for DefaultLexer {
                         self.tokens.push(Token {
                             value: self.char.to_string(),
                             kind: TokenKind::BitwiseOr,
+                            span: self.span(),
                         });
                     }
                 }
@@ -271,12 +326,14 @@ impl Lexer for DefaultLexer {
                         self.tokens.push(Token {
                             value: "->".to_string(),
                             kind: TokenKind::Implies,
+                            span: self.span(),
                         });
                         self.next_char();
                     } else {
                         self.tokens.push(Token {
                             value: self.char.to_string(),
                             kind: TokenKind::Operator,
+                            span: self.span(),
                         });
                     }
                 }
@@ -284,12 +341,14 @@ impl Lexer for DefaultLexer {
                     self.tokens.push(Token {
                         value: self.char.to_string(),
                         kind: TokenKind::Negation,
+                        span: self.span(),
                     });
                 }
                 '→' => {
                     self.tokens.push(Token {
                         value: self.char.to_string(),
                         kind: TokenKind::Implies,
+                        span: self.span(),
                     });
                 }
                 '&' => {
@@ -297,12 +356,14 @@ impl Lexer for DefaultLexer {
                         self.tokens.push(Token {
                             value: "&&".to_string(),
                             kind: TokenKind::Conjunction,
+                            span: self.span(),
                         });
                         self.next_char();
                     } else {
                         self.tokens.push(Token {
                             value: self.char.to_string(),
                             kind: TokenKind::BitwiseAnd,
+                            span: self.span(),
                         });
                     }
                 }
@@ -310,18 +371,21 @@ impl Lexer for DefaultLexer {
                     self.tokens.push(Token {
                         value: self.char.to_string(),
                         kind: TokenKind::HypothesisEnd,
+                        span: self.span(),
                     });
                 }
                 '∀' => {
                     self.tokens.push(Token {
                         value: self.char.to_string(),
                         kind: TokenKind::ForAll,
+                        span: self.span(),
                     });
                 }
                 '𝜑' | '𝜓' | '𝜒' => {
                    self.tokens.push(Token {
                        value: self.char.to_string(),
                        kind: TokenKind::WFF,
+                       span: self.span(),
                    }); 
                 }
                 // test if alphabetic
@@ -336,11 +400,13 @@ impl Lexer for DefaultLexer {
                         self.tokens.push(Token {
                             value: identifier,
                             kind: TokenKind::Identifier,
+                            span: self.span(),
                         });
                     } else {
                         self.tokens.push(Token {
                             value: self.char.to_string(),
                             kind: TokenKind::Identifier,
+                            span: self.span(),
                         });
                     }
                 }
@@ -348,12 +414,14 @@ impl Lexer for DefaultLexer {
                     self.tokens.push(Token {
                         value: self.char.to_string(),
                         kind: TokenKind::End,
+                        span: self.span(),
                     });
                 }
                 _ => {
                     self.tokens.push(Token {
                         value: self.char.to_string(),
                         kind: TokenKind::Number,
+                        span: self.span(),
                     });
                 }
             }
@@ -366,6 +434,13 @@ impl Lexer for DefaultLexer {
     }
 
     fn next_char(&mut self) {
+        self.offset += self.char.len_utf8();
+        if self.char == '\n' {
+            self.line += 1;
+            self.column = 1;
+        } else {
+            self.column += 1;
+        }
         self.position += 1;
         self.char = self.input.chars().nth(self.position).unwrap_or(' ');
     }
@@ -7,10 +7,36 @@ mod lang_parser;
 mod lang_lexer;
 pub mod lang_ast;
 mod conditional_tests;
+#[cfg(test)]
+mod scope_tests;
+#[cfg(test)]
+mod error_tests;
+#[cfg(test)]
+mod precedence_tests;
+#[cfg(test)]
+mod float_tests;
+#[cfg(test)]
+mod bigint_tests;
+#[cfg(test)]
+mod closure_tests;
+#[cfg(test)]
+mod fir_tests;
+#[cfg(test)]
+mod ssa_tests;
+#[cfg(test)]
+mod passes_tests;
+#[cfg(test)]
+mod fir_text_tests;
 mod compile;
+#[cfg(test)]
 mod pe;
 mod arrays;
 pub mod functions;
 pub mod bytecode;
 pub mod compiler;
-pub mod vm;
\ No newline at end of file
+pub mod vm;
+pub mod heap;
+pub mod foxc;
+pub mod verifier;
+pub mod tracer;
+pub mod diagnostics;
\ No newline at end of file
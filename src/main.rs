@@ -8,34 +8,226 @@ pub mod lang_parser;
 pub mod lang_ast;
 
 pub mod cut;
+mod diagnostics;
 
-// import ast module
+pub mod bytecode;
+pub mod compiler;
+pub mod vm;
+pub mod heap;
+pub mod foxc;
+pub mod verifier;
+pub mod tracer;
 
+use ast::ast::Ast;
+use bytecode::Chunk;
+use compiler::Compiler;
+use diagnostics::Diagnostic;
+use lang_parser::LangParser;
+use tracer::Tracer;
+use vm::{VmError, VM};
+
+// parses and evaluates `source` on top of the existing declarations, printing any
+// error as a diagnostic instead of aborting
+fn run(ast: &mut Ast, file_name: &str, source: &str) -> bool {
+    let (program, errors) = LangParser::new(source).parse_recovering();
+    if !errors.is_empty() {
+        report_syntax_errors(file_name, source, &errors);
+        return false;
+    }
+
+    ast.nodes = program.nodes;
+    match ast.eval() {
+        Ok(_) => true,
+        Err(e) => {
+            eprint!("{}", Diagnostic::from(&e).render(file_name, source));
+            false
+        }
+    }
+}
+
+// compiles `source` to bytecode and runs it on the VM instead of the tree-walking interpreter
+fn run_vm(file_name: &str, source: &str, tracer: Option<Box<dyn Tracer>>) -> bool {
+    let (program, errors) = LangParser::new(source).parse_recovering();
+    if !errors.is_empty() {
+        report_syntax_errors(file_name, source, &errors);
+        return false;
+    }
+
+    let chunk = match Compiler::compile(&program) {
+        Ok(chunk) => chunk,
+        Err(e) => {
+            eprint!("{}", Diagnostic::from(&e).render(file_name, source));
+            return false;
+        }
+    };
+    match interpret_traced(&chunk, tracer) {
+        Ok(()) => true,
+        Err(e) => {
+            eprint!("{}", Diagnostic::from(&e).render(file_name, source));
+            false
+        }
+    }
+}
+
+// compiles `source` and writes the bytecode next to it, as `main.foxc` for `main.fox`
+fn compile(file_name: &str, source: &str) -> bool {
+    let (program, errors) = LangParser::new(source).parse_recovering();
+    if !errors.is_empty() {
+        report_syntax_errors(file_name, source, &errors);
+        return false;
+    }
+
+    let output = std::path::Path::new(file_name).with_extension("foxc");
+    let chunk = match Compiler::compile(&program) {
+        Ok(chunk) => chunk,
+        Err(e) => {
+            eprint!("{}", Diagnostic::from(&e).render(file_name, source));
+            return false;
+        }
+    };
+    let result = foxc::write(&chunk)
+        .and_then(|bytes| std::fs::write(&output, bytes).map_err(|e| format!("could not write {}: {}", output.display(), e)));
+    match result {
+        Ok(()) => true,
+        Err(e) => {
+            eprint!("{}", Diagnostic::error(e).render(file_name, source));
+            false
+        }
+    }
+}
+
+// runs bytecode written by `compile`, which is verified before the VM gets it
+fn run_foxc(file_name: &str, tracer: Option<Box<dyn Tracer>>) -> bool {
+    // there is no source to show, so errors only point at the line and column
+    let chunk = std::fs::read(file_name)
+        .map_err(|e| format!("could not read {}: {}", file_name, e))
+        .and_then(|bytes| foxc::read(&bytes));
+    let chunk = match chunk {
+        Ok(chunk) => chunk,
+        Err(e) => {
+            eprint!("{}", Diagnostic::error(e).render(file_name, ""));
+            return false;
+        }
+    };
+    match interpret_traced(&chunk, tracer) {
+        Ok(()) => true,
+        Err(e) => {
+            eprint!("{}", Diagnostic::from(&e).render(file_name, ""));
+            false
+        }
+    }
+}
+
+// runs a chunk on a new VM, printing what the tracer found once it is done, even after an error
+fn interpret_traced(chunk: &Chunk, tracer: Option<Box<dyn Tracer>>) -> Result<(), VmError> {
+    let mut vm = VM::new();
+    if let Some(tracer) = tracer {
+        vm.set_tracer(tracer);
+    }
+    let result = vm.interpret(chunk);
+    if let Some(summary) = vm.take_tracer().summary() {
+        eprintln!("{}", summary);
+    }
+    result.map(|_| ())
+}
+
+// parses `source` without running it and reports every syntax error in it
+fn check(file_name: &str, source: &str) -> bool {
+    let (_, errors) = LangParser::new(source).parse_recovering();
+    if errors.is_empty() {
+        println!("{}: no errors found", file_name);
+        return true;
+    }
+    report_syntax_errors(file_name, source, &errors);
+    false
+}
+
+fn report_syntax_errors(file_name: &str, source: &str, errors: &[lexer::SyntaxError]) {
+    for e in errors {
+        eprintln!("{}", Diagnostic::from(e).render(file_name, source));
+    }
+    let plural = if errors.len() == 1 { "" } else { "s" };
+    eprintln!("error: could not parse {} due to {} previous error{}", file_name, errors.len(), plural);
+}
+
+fn read_file(filename: &str) -> Option<String> {
+    match std::fs::read_to_string(filename) {
+        Ok(contents) => Some(contents),
+        Err(e) => {
+            eprintln!("error: could not read {}: {}", filename, e);
+            None
+        }
+    }
+}
 
 fn main() {
-    println!("Welcome to the FoxLang REPL");
-    println!("Type 'help' for a list of commands");
-    println!();
-    let mut ast = ast::ast::Ast::new();
-    let mut fox_parser = lang_parser::LangParser::new("");
-    // TODO: construct a parser instead of an AST 
+    let mut ast = Ast::new();
     
     let args: Vec<String> = env::args().collect();
     
+    if args.len() > 2 && args[1] == "check" {
+        // only report syntax errors, e.g. `foxlang check main.fox`
+        let filename = &args[2];
+        let ok = read_file(filename).is_some_and(|contents| check(filename, &contents));
+        std::process::exit(if ok { 0 } else { 1 });
+    }
+
+    if args.len() > 2 && args[1] == "compile" {
+        // write the bytecode of a file to run later, e.g. `foxlang compile main.fox` creates main.foxc
+        let filename = &args[2];
+        let ok = read_file(filename).is_some_and(|contents| compile(filename, &contents));
+        std::process::exit(if ok { 0 } else { 1 });
+    }
+
+    if args.len() > 2 && args[1] == "--vm" {
+        // run a file on the bytecode VM, e.g. `foxlang --vm main.fox` or `foxlang --vm main.foxc`,
+        // optionally traced with `--trace=stack`, `--trace=opcodes` or `--trace=functions` before the file
+        let mut filename = &args[2];
+        let mut tracer = None;
+        if let Some(name) = filename.strip_prefix("--trace=") {
+            tracer = tracer::from_name(name);
+            if tracer.is_none() {
+                eprintln!("error: unknown tracer `{}`, expected stack, opcodes or functions", name);
+                std::process::exit(1);
+            }
+            let Some(file) = args.get(3) else {
+                eprintln!("error: no file to run");
+                std::process::exit(1);
+            };
+            filename = file;
+        }
+        if filename.ends_with(".foxc") {
+            std::process::exit(if run_foxc(filename, tracer) { 0 } else { 1 });
+        }
+        let ok = read_file(filename).is_some_and(|contents| run_vm(filename, &contents, tracer));
+        std::process::exit(if ok { 0 } else { 1 });
+    }
+
     if args.len() > 1 {
         // need to parse a file
         let filename = &args[1];
-        let contents = std::fs::read_to_string(filename).expect("could not read file");
-        fox_parser.parse_input(&contents).expect("unexpected failure");
-        ast.eval().expect("unexpected failure");
-        return;
+        let ok = read_file(filename).is_some_and(|contents| run(&mut ast, filename, &contents));
+        std::process::exit(if ok { 0 } else { 1 });
     }
-    
+
+    println!("Welcome to the FoxLang REPL");
+    println!("Type 'help' for a list of commands");
+    println!();
     loop {
         let mut input = String::new();
-        std::io::stdin().read_line(&mut input).unwrap();
-        let mut parts = input.trim().split_whitespace();
-        let command = parts.next().unwrap();
+        match std::io::stdin().read_line(&mut input) {
+            // end of input
+            Ok(0) => break,
+            Ok(_) => (),
+            Err(e) => {
+                eprintln!("error: could not read input: {}", e);
+                break;
+            }
+        }
+        let mut parts = input.split_whitespace();
+        let Some(command) = parts.next() else {
+            continue;
+        };
         
         match command {
             "solve" => {
@@ -58,6 +250,7 @@ fn main() {
                 println!("reset - reset the current scope");
                 println!("ls - list the files in the current directory");
                 println!("eval - evaluates a provided file");
+                println!("check - reports every syntax error in a provided file without running it");
             }
             "exit" => {
                 println!("Exiting the Fox REPL");
@@ -69,23 +262,36 @@ fn main() {
             "ls" => {
                 // just the "ls" command
                 println!();
-                let files = std::fs::read_dir(".").expect("could not read directory");
-                for file in files {
-                    let file = file.expect("could not read file");
-                    println!("{}", file.file_name().into_string().unwrap());
+                match std::fs::read_dir(".") {
+                    Ok(files) => {
+                        for file in files.flatten() {
+                            println!("{}", file.file_name().to_string_lossy());
+                        }
+                    }
+                    Err(e) => eprintln!("error: could not read directory: {}", e),
                 }
             }
             "eval" => {
-                let filename = parts.next().expect("expected filename");
-                let contents = std::fs::read_to_string(filename).expect("could not read file");
-                fox_parser.parse_input(&contents).expect("unexpected failure");
-                ast.eval().expect("unexpected failure");
+                let Some(filename) = parts.next() else {
+                    eprintln!("error: expected a file name, e.g. `eval main.fox`");
+                    continue;
+                };
+                if let Some(contents) = read_file(filename) {
+                    run(&mut ast, filename, &contents);
+                }
+            }
+            "check" => {
+                let Some(filename) = parts.next() else {
+                    eprintln!("error: expected a file name, e.g. `check main.fox`");
+                    continue;
+                };
+                if let Some(contents) = read_file(filename) {
+                    check(filename, &contents);
+                }
             }
             _ => {
                 // call the lang parser
-                fox_parser.parse_input(input.trim()).expect("unexpected failure");
-                
-                ast.eval().expect("unexpected failure");
+                run(&mut ast, "<repl>", input.trim());
             }
         }
     }
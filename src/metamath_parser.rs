@@ -29,7 +29,7 @@ mod tests {
             panic!("Expected Node::Identifier");
         }
 
-        if let Node::BinaryExpression { left, operator: _, right } = right {
+        if let Node::BinaryExpression { left, operator: _, right, .. } = right {
             if let Node::Identifier { value } = *left {
                 assert_eq!(value, "𝜓");
             } else {
@@ -1,6 +1,7 @@
 use std::cmp::PartialEq;
 use crate::lexer::{Token, TokenKind};
 use crate::lexer::DefaultLexer;
+use ast::span::Span;
 
 use ast::node::Node;
 
@@ -103,6 +104,7 @@ impl Parser {
                             left: Box::new(left),
                             operator: operator_kind,
                             right: Box::new(right),
+                            span: Span::default(),
                         });
                     }
                     return Ok(Node::Identifier {
@@ -175,12 +177,21 @@ impl Parser {
             return Token {
                 value: "".to_string(),
                 kind: TokenKind::End,
+                span: Span::default(),
             };
         }
 
         self.tokens[self.position+1].clone()
     }
 
+    /// The span of the token the parser stopped at, for pointing diagnostics at a failed parse.
+    pub fn error_span(&self) -> Option<Span> {
+        self.tokens
+            .get(self.position)
+            .or_else(|| self.tokens.last())
+            .map(|token| token.span)
+    }
+
     fn current(&self) -> Result<Token, ParseError> {
         if self.position < self.tokens.len() {
             Ok(self.tokens[self.position].clone())
@@ -221,6 +232,7 @@ impl Parser {
                         left: Box::new(left),
                         operator: operator_kind,
                         right: Box::new(right),
+                        span: Span::default(),
                     })
                 } else if self.peek().kind == RightParenthesis {
                     self.consume(RightParenthesis)?;
@@ -254,6 +266,7 @@ impl Parser {
                             left: Box::new(ident),
                             operator: operator_kind,
                             right: Box::new(right),
+                            span: Span::default(),
                         });
                     }
 
@@ -262,6 +275,7 @@ impl Parser {
                         left: Box::new(ident),
                         operator: operator_kind,
                         right: Box::new(right),
+                        span: Span::default(),
                     });
                 }
 
@@ -279,6 +293,7 @@ impl Parser {
                         left: Box::new(node),
                         operator: operator_kind,
                         right: Box::new(right),
+                        span: Span::default(),
                     });
                 }
                 Ok(node)
@@ -300,6 +315,7 @@ impl Parser {
                         left: Box::new(first),
                         operator: operator_kind,
                         right: Box::new(second),
+                        span: Span::default(),
                     });
                 }
 
@@ -309,6 +325,7 @@ impl Parser {
                     left: Box::new(first),
                     operator: operator_kind,
                     right: Box::new(second),
+                    span: Span::default(),
                 })
             }
             TokenKind::Exists => {
@@ -327,6 +344,7 @@ impl Parser {
                         left: Box::new(first),
                         operator: operator_kind,
                         right: Box::new(second),
+                        span: Span::default(),
                     });
                 }
 
@@ -342,6 +360,7 @@ impl Parser {
                         left: Box::new(first.clone()),
                         operator: operator_kind,
                         right: Box::new(second),
+                        span: Span::default(),
                     };
 
                     let parent_operator = self.get_operator()?;
@@ -353,6 +372,7 @@ impl Parser {
                         left: Box::new(parent_left),
                         operator: parent_operator_kind,
                         right: Box::new(parent_right),
+                        span: Span::default(),
                     });
                 }
 
@@ -360,6 +380,7 @@ impl Parser {
                     left: Box::new(first),
                     operator: operator_kind,
                     right: Box::new(second),
+                    span: Span::default(),
                 })
             }
             SetVar => {
@@ -386,6 +407,7 @@ impl Parser {
                     left: Box::new(left.clone()),
                     operator: operator_kind,
                     right: Box::new(right),
+                    span: Span::default(),
                 };
 
                 if self.position >= self.tokens.len() {
@@ -406,6 +428,7 @@ impl Parser {
                     left: Box::new(parent_left),
                     operator: parent_op_kind,
                     right: Box::new(parent_right),
+                    span: Span::default(),
                 })
             }
             TokenKind::WFF => {
@@ -432,6 +455,7 @@ impl Parser {
                             left: Box::new(ident),
                             operator: operator_kind,
                             right: Box::new(right),
+                            span: Span::default(),
                         });
                     }
 
@@ -440,6 +464,7 @@ impl Parser {
                         left: Box::new(ident),
                         operator: operator_kind,
                         right: Box::new(right),
+                        span: Span::default(),
                     });
                 }
 
@@ -473,6 +498,7 @@ impl Parser {
             left: Box::new(left),
             operator: operator_kind,
             right: Box::new(right),
+            span: Span::default(),
         })
     }
 
@@ -491,6 +517,7 @@ impl Parser {
             left: Box::new(left),
             operator: operator_kind,
             right: Box::new(right),
+            span: Span::default(),
         })
     }
 
@@ -587,6 +614,7 @@ impl Parser {
                     left: Box::new(left),
                     operator: operator_kind,
                     right: Box::new(right),
+                    span: Span::default(),
                 });
             }
 
@@ -596,6 +624,7 @@ impl Parser {
                 left: Box::new(left),
                 operator: operator_kind,
                 right: Box::new(right),
+                span: Span::default(),
             });
         }
 
@@ -633,6 +662,7 @@ impl Parser {
         Ok(Node::UnaryExpression {
             operator: operator_kind,
             right: Box::new(right),
+            span: Span::default(),
         })
     }
 
@@ -664,6 +694,7 @@ impl Parser {
                 left: Box::new(left_node),
                 operator: operator_kind,
                 right: Box::new(self.parse_expression()?),
+                span: Span::default(),
             });
         }
         
@@ -676,6 +707,7 @@ impl Parser {
                 left: Box::new(left_node),
                 operator: operator_kind,
                 right: Box::new(self.parse_unary_expression()?),
+                span: Span::default(),
             });    
         }
         
@@ -685,6 +717,7 @@ impl Parser {
             left: Box::new(left_node),
             operator: operator_kind,
             right: Box::new(right_node),
+            span: Span::default(),
         };
 
         Ok(binary_expression)
@@ -738,10 +771,10 @@ mod tests {
 
         let x = res.unwrap();
 
-        if let Node::BinaryExpression {left, operator, right} = x {
+        if let Node::BinaryExpression {left, operator, right, ..} = x {
             assert_eq!(operator, OperatorKind::Implies);
 
-            if let Node::BinaryExpression {left: left_left, operator: left_operator, right: left_right} = *left {
+            if let Node::BinaryExpression {left: left_left, operator: left_operator, right: left_right, ..} = *left {
                 assert_eq!(left_operator, OperatorKind::Implies);
 
                 if let Node::Identifier {value} = *left_left {
@@ -759,7 +792,7 @@ mod tests {
                 panic!("Expected a binary expression");
             }
 
-            if let Node::BinaryExpression {left: right_left, operator: right_operator, right: right_right} = *right {
+            if let Node::BinaryExpression {left: right_left, operator: right_operator, right: right_right, ..} = *right {
                 assert_eq!(right_operator, OperatorKind::Implies);
 
                 if let Node::Identifier {value} = *right_left {
@@ -790,10 +823,10 @@ mod tests {
         let x = res.unwrap();
         println!("{:?}", x);
 
-        if let Node::BinaryExpression {left, operator, right} = x {
+        if let Node::BinaryExpression {left, operator, right, ..} = x {
             assert_eq!(operator, OperatorKind::Implies);
 
-            if let Node::BinaryExpression {left: left_left, operator: left_operator, right: left_right} = *left {
+            if let Node::BinaryExpression {left: left_left, operator: left_operator, right: left_right, ..} = *left {
                 assert_eq!(left_operator, OperatorKind::Implies);
 
                 if let Node::Identifier {value} = *left_left {
@@ -828,7 +861,7 @@ mod tests {
 
         let x = res.unwrap();
 
-        if let Node::BinaryExpression {left, operator, right} = x {
+        if let Node::BinaryExpression {left, operator, right, ..} = x {
             assert_eq!(operator, OperatorKind::Implies);
 
             if let Node::Identifier {value} = *left {
@@ -837,7 +870,7 @@ mod tests {
                 panic!("Expected an identifier");
             }
 
-            if let Node::BinaryExpression {left: right_left, operator: right_operator, right: right_right} = *right {
+            if let Node::BinaryExpression {left: right_left, operator: right_operator, right: right_right, ..} = *right {
                 assert_eq!(right_operator, OperatorKind::Implies);
 
                 if let Node::Identifier {value} = *right_left {
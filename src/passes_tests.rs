@@ -0,0 +1,362 @@
+use ast::fir::{FirFunction, FirInstr, FirModule, FirValue};
+use ast::passes::{PassManager, PassStats};
+use ast::ssa::{out_of_ssa, to_ssa};
+use crate::fir_tests::{lower_cfg, run};
+
+// runs `passes` over the function in `source`, returning its text before and after, and the statistics
+fn optimize(source: &str, mut passes: PassManager) -> (String, String, Vec<PassStats>) {
+    let mut module = FirModule::new();
+    module.add_function(lower_cfg(source));
+    let before = module.to_string();
+    let stats = passes.run(&mut module);
+    (before, module.to_string(), stats)
+}
+
+fn only(pass: &str) -> PassManager {
+    PassManager::from_names(&[pass]).expect("unknown pass")
+}
+
+fn changes(stats: &[PassStats]) -> Vec<(&str, usize)> {
+    stats.iter().map(|stats| (stats.name, stats.changes)).collect()
+}
+
+#[test]
+fn constant_folding_computes_constant_operations() {
+    let (before, after, stats) = optimize("fn f(a) {
+        let x = 7 / 0;
+        let y = 2147483647 + 1;
+        let z = 10 % 3;
+        let b = 1 == 1;
+        let c = true == false;
+        return a - 1;
+    }", only("constant-folding"));
+    assert_eq!(before, "\
+fn f(a) {
+  locals %a, %tmp.1, %x, %tmp.3, %y, %tmp.5, %z, %tmp.7, %b, %tmp.9, %c, %tmp.11
+entry:
+  %tmp.1 = div 7, 0
+  store %x, %tmp.1
+  %tmp.3 = add 2147483647, 1
+  store %y, %tmp.3
+  %tmp.5 = mod 10, 3
+  store %z, %tmp.5
+  %tmp.7 = eq 1, 1
+  store %b, %tmp.7
+  %tmp.9 = eq true, false
+  store %c, %tmp.9
+  %tmp.11 = sub %a, 1
+  ret %tmp.11
+}
+");
+    // dividing by zero and overflowing are left to happen when the program runs
+    assert_eq!(after, "\
+fn f(a) {
+  locals %a, %tmp.1, %x, %tmp.3, %y, %tmp.5, %z, %tmp.7, %b, %tmp.9, %c, %tmp.11
+entry:
+  %tmp.1 = div 7, 0
+  store %x, %tmp.1
+  %tmp.3 = add 2147483647, 1
+  store %y, %tmp.3
+  %tmp.5 = copy 1
+  store %z, %tmp.5
+  %tmp.7 = copy true
+  store %b, %tmp.7
+  %tmp.9 = copy false
+  store %c, %tmp.9
+  %tmp.11 = sub %a, 1
+  ret %tmp.11
+}
+");
+    assert_eq!(changes(&stats), [("constant-folding", 3)]);
+}
+
+#[test]
+fn copy_propagation_reads_through_copies_that_never_change() {
+    let (_, after, stats) = optimize("fn f(a) {
+        let b = a;
+        let c = b;
+        let d = c + a;
+        a = 2;
+        return d;
+    }", only("copy-propagation"));
+    // `a` is written twice, so `b` still has to be read instead
+    assert_eq!(after, "\
+fn f(a) {
+  locals %a, %b, %c, %tmp.3, %d
+entry:
+  store %b, %a
+  store %c, %b
+  %tmp.3 = add %b, %a
+  store %d, %tmp.3
+  store %a, 2
+  ret %tmp.3
+}
+");
+    assert_eq!(changes(&stats), [("copy-propagation", 2)]);
+}
+
+#[test]
+fn copy_propagation_reads_through_phis_of_a_single_value() {
+    // `%r` takes `%a` from both blocks, `%s` doesn't
+    let mut func = FirFunction::new("f", vec!["a".to_string(), "b".to_string()]);
+    let [a, b, c, r, s, t] = ["a", "b", "c", "r", "s", "t"].map(|name| func.add_local(name));
+    let then1 = func.add_block("then1");
+    let join2 = func.add_block("join2");
+    func.blocks[0].instrs = vec![
+        FirInstr::Gt(c, FirValue::Local(a), FirValue::Local(b)),
+        FirInstr::JumpIf { cond: FirValue::Local(c), then_label: "then1".to_string(), else_label: "join2".to_string() },
+    ];
+    func.blocks[then1].instrs = vec![FirInstr::Jump("join2".to_string())];
+    func.blocks[join2].instrs = vec![
+        FirInstr::Phi(r, vec![("entry".to_string(), FirValue::Local(a)), ("then1".to_string(), FirValue::Local(a))]),
+        FirInstr::Phi(s, vec![("entry".to_string(), FirValue::Local(a)), ("then1".to_string(), FirValue::Local(b))]),
+        FirInstr::Add(t, FirValue::Local(r), FirValue::Local(s)),
+        FirInstr::Return(Some(FirValue::Local(t))),
+    ];
+    let mut module = FirModule::new();
+    module.add_function(func);
+    let stats = only("copy-propagation").run(&mut module);
+    assert_eq!(module.functions[0].blocks[join2].instrs[2], FirInstr::Add(t, FirValue::Local(a), FirValue::Local(s)));
+    assert_eq!(changes(&stats), [("copy-propagation", 1)]);
+}
+
+#[test]
+fn dead_store_elimination_removes_writes_nothing_reads() {
+    let (_, after, _) = optimize("fn f(a) {
+        let x = a * 2;
+        let y = x + 1;
+        g(y);
+        x = 3;
+        let unused = a + 5;
+        return a;
+    }", only("dead-store-elimination"));
+    // the call stays for what it does, and so do the values it reads
+    assert_eq!(after, "\
+fn f(a) {
+  locals %a, %tmp.1, %x, %tmp.3, %y, %tmp.5, %tmp.6, %unused
+entry:
+  %tmp.1 = mul %a, 2
+  store %x, %tmp.1
+  %tmp.3 = add %x, 1
+  store %y, %tmp.3
+  %tmp.5 = call g(%y)
+  ret %a
+}
+");
+}
+
+#[test]
+fn dead_store_elimination_keeps_divisions_that_can_fail() {
+    let (_, after, _) = optimize("fn f(a, b) {
+        let q = a / b;
+        let r = a % 0;
+        let half = a / 2;
+        return a;
+    }", only("dead-store-elimination"));
+    // dividing by zero is an error when it runs, even when the result goes unused
+    assert_eq!(after, "\
+fn f(a, b) {
+  locals %a, %b, %tmp.2, %q, %tmp.4, %r, %tmp.6, %half
+entry:
+  %tmp.2 = div %a, %b
+  %tmp.4 = mod %a, 0
+  ret %a
+}
+");
+}
+
+#[test]
+fn dead_store_elimination_keeps_stores_read_around_a_loop() {
+    let (before, after, stats) = optimize("fn f(n) {
+        let i = 0;
+        let last = 0;
+        while (i < n) { last = i; i = i + 1; }
+        return i;
+    }", only("dead-store-elimination"));
+    assert_eq!(after, before.replace("  store %last, 0\n", "").replace("  store %last, %i\n", ""));
+    assert_eq!(changes(&stats), [("dead-store-elimination", 2)]);
+}
+
+#[test]
+fn unreachable_blocks_are_removed() {
+    let (_, after, stats) = optimize("fn f(a) {
+        if (a > 1) { return 1; } else { return 2; }
+        return 3;
+    }", only("unreachable-blocks"));
+    assert_eq!(after, "\
+fn f(a) {
+  locals %a, %tmp.1
+entry:
+  %tmp.1 = gt %a, 1
+  br %tmp.1, then1, else2
+then1:
+  ret 1
+else2:
+  ret 2
+}
+");
+    assert_eq!(changes(&stats), [("unreachable-blocks", 1)]);
+}
+
+#[test]
+fn branch_simplification_follows_constant_conditions() {
+    let (before, after, _) = optimize("fn f(a) {
+        while (true) {
+            a = a + 1;
+            if (false) { break; }
+        }
+        return a;
+    }", only("branch-simplification"));
+    assert_eq!(before, "\
+fn f(a) {
+  locals %a, %tmp.1
+entry:
+  jmp header1
+header1:
+  br true, body2, exit4
+body2:
+  %tmp.1 = add %a, 1
+  store %a, %tmp.1
+  br false, then5, join6
+latch3:
+  jmp header1
+exit4:
+  ret %a
+then5:
+  jmp exit4
+join6:
+  jmp latch3
+}
+");
+    // the blocks left behind can't be reached anymore, the loop is endless
+    assert_eq!(after, "\
+fn f(a) {
+  locals %a, %tmp.1
+entry:
+  jmp body2
+body2:
+  %tmp.1 = add %a, 1
+  store %a, %tmp.1
+  jmp body2
+then5:
+  ret %a
+join6:
+  jmp body2
+}
+");
+}
+
+#[test]
+fn branch_simplification_merges_straight_line_blocks() {
+    let (_, after, _) = optimize("fn f(a) {
+        if (a > 1) { a = 1; } else { a = 2; }
+        a = a * 3;
+        return a;
+    }", PassManager::from_names(&["constant-folding", "branch-simplification"]).unwrap());
+    // nothing is known about the condition, so the branches stay
+    assert!(after.contains("br %tmp.1, then1, else2"));
+
+    let (_, after, _) = optimize("fn f(a) {
+        if (1 > 2) { a = 1; } else { a = 2; }
+        a = a * 3;
+        return a;
+    }", PassManager::standard());
+    assert_eq!(after, "\
+fn f(a) {
+  locals %a, %tmp.1, %tmp.2
+entry:
+  store %a, 2
+  %tmp.2 = mul %a, 3
+  store %a, %tmp.2
+  ret %a
+}
+");
+}
+
+#[test]
+fn the_standard_passes_work_together() {
+    let (before, after, stats) = optimize("fn f() {
+        let x = 2 * 3;
+        if (x > 5) { return x; }
+        return 0;
+    }", PassManager::standard());
+    assert_eq!(before, "\
+fn f() {
+  locals %tmp.0, %x, %tmp.2
+entry:
+  %tmp.0 = mul 2, 3
+  store %x, %tmp.0
+  %tmp.2 = gt %x, 5
+  br %tmp.2, then1, join2
+then1:
+  ret %x
+join2:
+  ret 0
+}
+");
+    assert_eq!(after, "\
+fn f() {
+  locals %tmp.0, %x, %tmp.2
+entry:
+  ret 6
+}
+");
+    assert_eq!(changes(&stats), [
+        ("constant-folding", 2),
+        ("copy-propagation", 4),
+        ("branch-simplification", 2),
+        ("unreachable-blocks", 1),
+        ("dead-store-elimination", 3),
+    ]);
+    // the last round found nothing left to do
+    assert!(stats.iter().all(|stats| stats.runs == 3));
+}
+
+#[test]
+fn optimizing_keeps_the_results() {
+    let programs = [
+        "fn sum(n) { let total = 0; for i in 0..n { total = total + i * 2; } return total; }",
+        "fn swap(n) { let a = 1; let b = 2; let i = 0; while (i < n) { let t = a; a = b; b = t; i = i + 1; } return a * 10 + b; }",
+        "fn sign(x) { let s = 0; if (x < 0) { s = 0 - 1; } else { if (x > 0) { s = 1; } } return s; }",
+        "fn f(x) { let k = 4 * 5; loop { if (x > k) { break; } x = x + k / 2; } return x - 100 % 7; }",
+    ];
+    for source in programs {
+        let original = lower_cfg(source);
+        let mut optimized = FirModule::new();
+        optimized.add_function(original.clone());
+        PassManager::standard().run(&mut optimized);
+
+        // and in SSA form, out of which the copies are cleaned up again
+        let mut ssa = FirModule::new();
+        let mut func = original.clone();
+        to_ssa(&mut func);
+        ssa.add_function(func);
+        PassManager::standard().run(&mut ssa);
+        out_of_ssa(&mut ssa.functions[0]);
+        PassManager::standard().run(&mut ssa);
+
+        for arg in [-5, 0, 1, 3, 40] {
+            let expected = run(&original, &[arg]);
+            assert_eq!(run(&optimized.functions[0], &[arg]), expected, "{} with {}", original.name, arg);
+            assert_eq!(run(&ssa.functions[0], &[arg]), expected, "{} in SSA form with {}", original.name, arg);
+        }
+    }
+}
+
+#[test]
+fn passes_are_configured_by_name() {
+    assert_eq!(PassManager::from_names(&["constant-folding", "inlining"]).err().unwrap(), "unknown pass `inlining`");
+
+    let mut passes = PassManager::from_names(&["copy-propagation", "constant-folding"]).unwrap();
+    passes.set_max_rounds(1);
+    let (_, after, stats) = optimize("fn f() { let x = 1 + 2; return x * 2; }", passes);
+    // a single round, where folding comes too late for its result to be propagated
+    assert!(after.contains("%tmp.0 = copy 3"));
+    assert!(after.contains("mul %tmp.0, 2"));
+    assert_eq!(stats.iter().map(|stats| (stats.name, stats.runs)).collect::<Vec<_>>(), [("copy-propagation", 1), ("constant-folding", 1)]);
+    assert!(stats[1].to_string().starts_with("constant-folding              1 changes in 1 runs"));
+
+    let mut module = FirModule::new();
+    assert!(PassManager::new().run(&mut module).is_empty());
+    assert_eq!(run(&lower_cfg("fn f() { return 1; }"), &[]), Some(FirValue::ConstInt(1)));
+}
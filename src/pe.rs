@@ -1,9 +1,32 @@
 // these are the tests that attempt to solve problems posed in "Project Euler"
 
+use crate::compiler::Compiler;
 use crate::lang_parser::LangParser;
+use crate::vm::VM;
+use ast::value::Value;
+
+// runs a solution on the tree-walking interpreter and on the VM, returning `sum` from each
+fn solve(input: &str) -> (Value, Value) {
+    let mut ast = LangParser::new(input);
+    let mut ast = ast.parse().expect("unexpected failure");
+
+    match ast.eval() {
+        Ok(_) => (),
+        Err(e) => panic!("{:?}", e),
+    }
+    let interpreted = ast.declarations.get("sum").unwrap().val();
+
+    // the script's last expression is what the VM hands back
+    let ast = LangParser::new(&format!("{}\nsum", input)).parse().expect("unexpected failure");
+    let chunk = Compiler::compile(&ast).expect("compilation failed");
+    let compiled = VM::new().interpret(&chunk).expect("vm failure");
+
+    (interpreted, compiled)
+}
 
 #[test]
 fn problem1() {
+    const ANSWER: i32 = 233168;
     let input = "let sum = 0;
     for i in 0..1000 {
         let x = i % 3;
@@ -14,19 +37,14 @@ fn problem1() {
         }
     }";
 
-    let mut ast = LangParser::new(input);
-    let mut ast = ast.parse().expect("unexpected failure");
-
-    match ast.eval() {
-        Ok(_) => (),
-        Err(e) => panic!("{:?}", e),
-    }
-
-    println!("{:?}", ast.declarations);
+    let (interpreted, compiled) = solve(input);
+    assert_eq!(interpreted, Value::Int(ANSWER));
+    assert_eq!(compiled, Value::Int(ANSWER));
 }
 
 #[test]
 fn problem2() {
+    const ANSWER: i32 = 4613732;
     let input = "let sum = 0;
     let a = 0;
     let b = 1;
@@ -45,13 +63,7 @@ fn problem2() {
         }
     }";
 
-    let mut ast = LangParser::new(input);
-    let mut ast = ast.parse().expect("unexpected failure");
-
-    match ast.eval() {
-        Ok(_) => (),
-        Err(e) => panic!("{:?}", e),
-    }
-
-    println!("{:?}", ast.declarations);
+    let (interpreted, compiled) = solve(input);
+    assert_eq!(interpreted, Value::Int(ANSWER));
+    assert_eq!(compiled, Value::Int(ANSWER));
 }
\ No newline at end of file
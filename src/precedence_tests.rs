@@ -0,0 +1,115 @@
+use crate::lang_parser::LangParser;
+use crate::scope_tests::eval;
+use ast::node::Node;
+use ast::value::Value;
+
+// renders the tree shape of an expression, e.g. `a + b * c` as `(+ a (* b c))`
+fn sexpr(node: &Node) -> String {
+    match node {
+        Node::BinaryExpression { left, operator, right, .. } => {
+            format!("({} {} {})", operator, sexpr(left), sexpr(right))
+        }
+        Node::UnaryExpression { operator, right, .. } => format!("({} {})", operator, sexpr(right)),
+        Node::Ident { name, .. } => name.clone(),
+        Node::Atomic { value } => value.to_string(),
+        Node::Call { name, arguments, .. } => {
+            let arguments: Vec<String> = arguments.iter().map(sexpr).collect();
+            format!("({} {})", name, arguments.join(" "))
+        }
+        Node::IndexExpression { left, index, .. } => format!("([] {} {})", sexpr(left), sexpr(index)),
+        other => panic!("no s-expression for {:?}", other),
+    }
+}
+
+fn parse(input: &str) -> String {
+    let mut parser = LangParser::new(input);
+    let node = parser.parse_expression().expect("unexpected failure");
+    sexpr(&node)
+}
+
+fn parse_err(input: &str) -> String {
+    let mut parser = LangParser::new(input);
+    match parser.parse_expression() {
+        Ok(node) => panic!("expected {:?} to fail, got {}", input, sexpr(&node)),
+        Err(e) => e,
+    }
+}
+
+#[test]
+fn arithmetic() {
+    assert_eq!(parse("a + b * c"), "(+ a (* b c))");
+    assert_eq!(parse("a * b + c"), "(+ (* a b) c)");
+    assert_eq!(parse("a - b - c"), "(- (- a b) c)");
+    assert_eq!(parse("a / b % c * d"), "(* (% (/ a b) c) d)");
+    assert_eq!(parse("(a + b) * c"), "(* (+ a b) c)");
+}
+
+#[test]
+fn bitwise() {
+    assert_eq!(parse("a | b ^ c & d"), "(| a (^ b (& c d)))");
+    assert_eq!(parse("a & b | c"), "(| (& a b) c)");
+    assert_eq!(parse("a << 1 + b"), "(<< a (+ 1 b))");
+    assert_eq!(parse("a >> 1 & b"), "(& (>> a 1) b)");
+}
+
+#[test]
+fn comparisons_and_logic() {
+    assert_eq!(parse("a + 1 == b * 2"), "(== (+ a 1) (* b 2))");
+    assert_eq!(parse("x & mask == 0"), "(== (& x mask) 0)");
+    assert_eq!(parse("a < b && b < c || d"), "(|| (&& (< a b) (< b c)) d)");
+    assert_eq!(parse("a || b && c"), "(|| a (&& b c))");
+    assert_eq!(parse("a || b || c"), "(|| (|| a b) c)");
+}
+
+#[test]
+fn formulas() {
+    assert_eq!(parse("a → b → c"), "(→ a (→ b c))");
+    assert_eq!(parse("a ∧ b → c ∨ d"), "(→ (∧ a b) (∨ c d))");
+    assert_eq!(parse("a → b ↔ c"), "(↔ (→ a b) c)");
+}
+
+#[test]
+fn unary() {
+    assert_eq!(parse("-a * b"), "(* (- a) b)");
+    assert_eq!(parse("a - -b"), "(- a (- b))");
+    assert_eq!(parse("~a & b"), "(& (¬ a) b)");
+    assert_eq!(parse("-(a + b)"), "(- (+ a b))");
+}
+
+#[test]
+fn postfix_operands() {
+    assert_eq!(parse("f(a + 1, b) * xs[i + 1]"), "(* (f (+ a 1) b) ([] xs (+ i 1)))");
+    assert_eq!(parse("m[0][1] + 1"), "(+ ([] ([] m 0) 1) 1)");
+}
+
+#[test]
+fn comparisons_do_not_chain() {
+    let e = parse_err("a < b < c");
+    assert!(e.contains("cannot be chained"), "{}", e);
+}
+
+#[test]
+fn missing_operand() {
+    assert_eq!(parse_err("a +"), "unexpected end of input");
+    assert_eq!(parse_err("(a + b"), "Expected RightParenthesis but reached the end of the input");
+}
+
+#[test]
+fn evaluates_with_precedence() {
+    assert_eq!(eval("let x = 1 + 2 * 3;", "x"), Value::Int(7));
+    assert_eq!(eval("let x = 10 - 4 - 3;", "x"), Value::Int(3));
+    assert_eq!(eval("let x = -2 * 3 + 10 % 4;", "x"), Value::Int(-4));
+    assert_eq!(eval("let x = (1 + 2) * 3;", "x"), Value::Int(9));
+    assert_eq!(eval("let x = 1 + 2 * 3 == 7 && 2 > 1;", "x"), Value::Bool(true));
+    assert_eq!(eval("let x = 2 < 1 || 1 < 2;", "x"), Value::Bool(true));
+}
+
+#[test]
+fn conditions_use_the_same_precedence() {
+    let input = "let x = 0;
+    let a = 3;
+    if (a * 2 + 1 == 7 && a > 2) {
+        x = 1;
+    }";
+    assert_eq!(eval(input, "x"), Value::Int(1));
+}
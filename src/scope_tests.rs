@@ -0,0 +1,73 @@
+use crate::lang_parser::LangParser;
+use ast::ast::Ast;
+use ast::value::Value;
+
+// evaluates `input`, returning the program with its declarations
+pub(crate) fn eval_ast(input: &str) -> Ast {
+    let mut ast = LangParser::new(input);
+    let mut ast = ast.parse().expect("unexpected failure");
+    match ast.eval() {
+        Ok(_) => (),
+        Err(e) => panic!("{:?}", e),
+    }
+    ast
+}
+
+// evaluates `input` and returns the value of its variable `name`
+pub(crate) fn eval(input: &str, name: &str) -> Value {
+    eval_ast(input).declarations.get(name).unwrap().val()
+}
+
+#[test]
+fn parameters_do_not_overwrite_globals() {
+    let input = "let x = 1;
+    fn id(x) {
+        return x;
+    }
+    let y = id(5);";
+    assert_eq!(eval(input, "x"), Value::Int(1));
+    assert_eq!(eval(input, "y"), Value::Int(5));
+}
+
+#[test]
+fn nested_calls_keep_their_own_frames() {
+    let input = "fn inner(a) {
+        let t = a + 1;
+        return t;
+    }
+    fn outer(a) {
+        let t = inner(a);
+        return a;
+    }
+    let r = outer(3);";
+    let ast = eval_ast(input);
+
+    assert_eq!(ast.declarations.get("r").unwrap().val(), Value::Int(3));
+    assert!(!ast.declarations.contains_key("t"));
+    assert!(!ast.declarations.contains_key("a"));
+}
+
+#[test]
+fn let_in_block_shadows_outer_variable() {
+    let input = "let x = 1;
+    if (true) {
+        let x = 2;
+        print(x);
+    }";
+    assert_eq!(eval(input, "x"), Value::Int(1));
+}
+
+#[test]
+fn loop_variable_does_not_leak() {
+    let input = "let i = 10;
+    let sum = 0;
+    for i in 0..4 {
+        let t = i;
+        sum = sum + t;
+    }";
+    let ast = eval_ast(input);
+
+    assert_eq!(ast.declarations.get("i").unwrap().val(), Value::Int(10));
+    assert_eq!(ast.declarations.get("sum").unwrap().val(), Value::Int(6));
+    assert!(!ast.declarations.contains_key("t"));
+}
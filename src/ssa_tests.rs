@@ -0,0 +1,195 @@
+use std::collections::HashSet;
+use ast::cfg::{Cfg, Dominators};
+use ast::fir::{FirFunction, FirInstr, FirValue};
+use ast::ssa::{out_of_ssa, to_ssa};
+use crate::fir_tests::{lower_cfg, run};
+
+const SUM: &str = "fn sum(n) {
+    let total = 0;
+    for i in 0..n { total = total + i; }
+    return total;
+}";
+
+const SWAP: &str = "fn swap(n) {
+    let a = 1;
+    let b = 2;
+    let i = 0;
+    while (i < n) {
+        let t = a;
+        a = b;
+        b = t;
+        i = i + 1;
+    }
+    return a * 10 + b;
+}";
+
+const SIGN: &str = "fn sign(x) {
+    let s = 0;
+    if (x < 0) { s = 0 - 1; } else { if (x > 0) { s = 1; } }
+    return s;
+}";
+
+fn index(func: &FirFunction, label: &str) -> usize {
+    func.blocks.iter().position(|block| block.label == label).expect("missing block")
+}
+
+fn label(func: &FirFunction, b: usize) -> &str {
+    &func.blocks[b].label
+}
+
+fn phis(func: &FirFunction, label: &str) -> Vec<(String, Vec<(String, FirValue)>)> {
+    let block = func.block(label).expect("missing block");
+    block.instrs.iter().filter_map(|instr| match instr {
+        FirInstr::Phi(dst, incoming) => Some((func.locals[*dst as usize].clone(), incoming.clone())),
+        _ => None,
+    }).collect()
+}
+
+// checks `func` is in SSA form: no stores, every local written once and phis only at the start of blocks
+fn assert_ssa(func: &FirFunction) {
+    let mut written = HashSet::new();
+    for block in &func.blocks {
+        let mut in_phis = true;
+        for instr in &block.instrs {
+            assert!(!matches!(instr, FirInstr::StoreLocal(..)), "`{}` still stores a variable", block.label);
+            in_phis &= matches!(instr, FirInstr::Phi(..));
+            assert!(in_phis || !matches!(instr, FirInstr::Phi(..)), "a phi of `{}` follows other instructions", block.label);
+            if let Some(dst) = instr.dst() {
+                assert!(written.insert(dst), "`{}` is written twice", func.locals[dst as usize]);
+            }
+        }
+    }
+}
+
+#[test]
+fn dominators_follow_the_branches() {
+    let func = lower_cfg(SIGN);
+    let doms = Dominators::new(&Cfg::new(&func));
+    let (entry, then1, else2, join3, then4, join5) = (0, index(&func, "then1"), index(&func, "else2"), index(&func, "join3"), index(&func, "then4"), index(&func, "join5"));
+    assert_eq!(doms.idom(entry), None);
+    assert_eq!(doms.idom(then1), Some(entry));
+    assert_eq!(doms.idom(join3), Some(entry));
+    assert_eq!(doms.idom(then4), Some(else2));
+    assert_eq!(doms.idom(join5), Some(else2));
+    assert!(doms.dominates(else2, then4));
+    assert!(!doms.dominates(then1, join3));
+
+    assert_eq!(doms.frontier(then1), [join3]);
+    assert_eq!(doms.frontier(then4), [join5]);
+    assert_eq!(doms.frontier(join5), [join3]);
+    assert_eq!(doms.frontier(else2), [join3]);
+    assert!(doms.frontier(entry).is_empty());
+}
+
+#[test]
+fn loop_bodies_have_the_header_in_their_frontier() {
+    let func = lower_cfg(SUM);
+    let doms = Dominators::new(&Cfg::new(&func));
+    let (header, body, latch, exit) = (index(&func, "header1"), index(&func, "body2"), index(&func, "latch3"), index(&func, "exit4"));
+    assert_eq!(doms.idom(body), Some(header));
+    assert_eq!(doms.idom(exit), Some(header));
+    assert_eq!(doms.children(header), [body, exit]);
+    assert_eq!(doms.frontier(latch), [header]);
+    assert_eq!(doms.frontier(body), [header]);
+    // the header is in its own frontier, being reachable from itself through the back edge
+    assert_eq!(doms.frontier(header), [header]);
+    assert_eq!(label(&func, doms.idom(latch).unwrap()), "body2");
+}
+
+#[test]
+fn phis_merge_the_stores_of_each_branch() {
+    let mut func = lower_cfg(SIGN);
+    to_ssa(&mut func);
+    assert_ssa(&func);
+
+    let inner = phis(&func, "join5");
+    assert_eq!(inner.len(), 1);
+    let (name, incoming) = &inner[0];
+    assert!(name.starts_with("s.v"));
+    let from: Vec<&str> = incoming.iter().map(|(label, _)| label.as_str()).collect();
+    assert_eq!(from, ["else2", "then4"]);
+    // coming straight from else2 `s` still has the value stored in the entry
+    let entry_store = func.blocks[0].instrs.iter().find_map(|instr| match instr {
+        FirInstr::Copy(dst, FirValue::ConstInt(0)) => Some(*dst),
+        _ => None,
+    });
+    assert_eq!(incoming[0].1, FirValue::Local(entry_store.unwrap()));
+
+    let outer = phis(&func, "join3");
+    assert_eq!(outer.len(), 1);
+    let Some(FirInstr::Return(Some(FirValue::Local(returned)))) = func.block("join3").unwrap().terminator() else { panic!("expected a return") };
+    assert_eq!(func.locals[*returned as usize], outer[0].0);
+}
+
+#[test]
+fn loop_headers_get_phis_for_the_variables_the_loop_stores() {
+    let mut func = lower_cfg(SUM);
+    to_ssa(&mut func);
+    assert_ssa(&func);
+
+    let header: Vec<String> = phis(&func, "header1").into_iter().map(|(name, _)| name).collect();
+    assert!(header.iter().any(|name| name.starts_with("total.v")));
+    assert!(header.iter().any(|name| name.starts_with("i.counter1.v")));
+    for (_, incoming) in phis(&func, "header1") {
+        let from: Vec<&str> = incoming.iter().map(|(label, _)| label.as_str()).collect();
+        assert_eq!(from, ["entry", "latch3"]);
+    }
+    assert!(phis(&func, "body2").is_empty());
+}
+
+#[test]
+fn unreachable_blocks_are_dropped() {
+    let mut func = lower_cfg("fn f(x) { return x; x = 2; }");
+    to_ssa(&mut func);
+    assert_eq!(func.blocks.len(), 1);
+}
+
+#[test]
+fn going_into_and_out_of_ssa_keeps_the_results() {
+    for (source, args) in [(SUM, [0, 1, 5]), (SWAP, [0, 1, 4]), (SIGN, [-3, 0, 8])] {
+        let original = lower_cfg(source);
+        let mut ssa = original.clone();
+        to_ssa(&mut ssa);
+        let mut out = ssa.clone();
+        out_of_ssa(&mut out);
+        assert!(out.blocks.iter().flat_map(|block| &block.instrs).all(|instr| !matches!(instr, FirInstr::Phi(..))));
+
+        for arg in args {
+            let expected = run(&original, &[arg]);
+            assert_eq!(run(&ssa, &[arg]), expected, "{} in SSA form with {}", original.name, arg);
+            assert_eq!(run(&out, &[arg]), expected, "{} out of SSA form with {}", original.name, arg);
+        }
+    }
+    assert_eq!(run(&lower_cfg(SWAP), &[3]), Some(FirValue::ConstInt(21)));
+}
+
+#[test]
+fn phis_reading_each_other_are_copied_in_parallel() {
+    let mut func = lower_cfg(SWAP);
+    to_ssa(&mut func);
+    // fold the copies of the body into the phis, so `a` and `b` swap through the phis themselves
+    let copies: Vec<(u32, FirValue)> = func.blocks.iter().flat_map(|block| &block.instrs).filter_map(|instr| match instr {
+        FirInstr::Copy(dst, value) => Some((*dst, value.clone())),
+        _ => None,
+    }).collect();
+    for _ in 0..copies.len() {
+        for block in &mut func.blocks {
+            for instr in &mut block.instrs {
+                for operand in instr.operands_mut() {
+                    if let Some((_, value)) = copies.iter().find(|(dst, _)| *operand == FirValue::Local(*dst)) {
+                        *operand = value.clone();
+                    }
+                }
+            }
+        }
+    }
+    let header = phis(&func, "header1");
+    let a = header.iter().find(|(name, _)| name.starts_with("a.v")).unwrap();
+    let b = header.iter().find(|(name, _)| name.starts_with("b.v")).unwrap();
+    assert_eq!(a.1[1].1, FirValue::Local(func.local(&b.0).unwrap()));
+    assert_eq!(b.1[1].1, FirValue::Local(func.local(&a.0).unwrap()));
+
+    out_of_ssa(&mut func);
+    assert_eq!(run(&func, &[3]), Some(FirValue::ConstInt(21)));
+    assert_eq!(run(&func, &[4]), Some(FirValue::ConstInt(12)));
+}
@@ -0,0 +1,203 @@
+//! Hooks for watching the VM run, see `VM::set_tracer`.
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+use ast::value::Function;
+use crate::bytecode::{OpCode, Value};
+use crate::compiler::debug;
+
+/// Observes every instruction the VM runs. The VM calls it before running each instruction, so an
+/// implementation sees the stack the instruction works on.
+pub trait Tracer {
+    /// `offset` is where the instruction starts in the code of `function`.
+    fn instruction(&mut self, function: &Function, offset: usize, stack: &[Value]);
+
+    /// What the tracer found, for printing once the program has finished.
+    fn summary(&self) -> Option<String> {
+        None
+    }
+}
+
+/// Shares a tracer with the code setting it up, which can look at what it collected once the VM is done.
+impl<T: Tracer> Tracer for Rc<RefCell<T>> {
+    fn instruction(&mut self, function: &Function, offset: usize, stack: &[Value]) {
+        self.borrow_mut().instruction(function, offset, stack);
+    }
+
+    fn summary(&self) -> Option<String> {
+        self.borrow().summary()
+    }
+}
+
+/// The default, which does nothing.
+#[derive(Debug, Default)]
+pub struct NoTracer;
+
+impl Tracer for NoTracer {
+    fn instruction(&mut self, _function: &Function, _offset: usize, _stack: &[Value]) {}
+}
+
+/// Prints the stack, then the instruction about to run.
+#[derive(Debug, Default)]
+pub struct StackTracer;
+
+impl Tracer for StackTracer {
+    fn instruction(&mut self, function: &Function, offset: usize, stack: &[Value]) {
+        print!("          ");
+        for value in stack {
+            print!("[ {} ]", value);
+        }
+        println!();
+        debug::disassemble_instruction(&function.chunk, offset);
+    }
+}
+
+/// Counts how often each instruction runs.
+#[derive(Debug, Default)]
+pub struct OpcodeHistogram {
+    counts: HashMap<OpCode, usize>,
+}
+
+impl OpcodeHistogram {
+    pub fn count(&self, opcode: OpCode) -> usize {
+        self.counts.get(&opcode).copied().unwrap_or(0)
+    }
+
+    /// Every instruction that ran, the most frequent first.
+    pub fn counts(&self) -> Vec<(OpCode, usize)> {
+        let mut counts: Vec<(OpCode, usize)> = self.counts.iter().map(|(opcode, count)| (*opcode, *count)).collect();
+        counts.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| (a.0 as u8).cmp(&(b.0 as u8))));
+        counts
+    }
+}
+
+impl Tracer for OpcodeHistogram {
+    fn instruction(&mut self, function: &Function, offset: usize, _stack: &[Value]) {
+        if let Some(opcode) = OpCode::from_byte(function.chunk.code[offset]) {
+            *self.counts.entry(opcode).or_insert(0) += 1;
+        }
+    }
+
+    fn summary(&self) -> Option<String> {
+        let lines: Vec<String> = self.counts().iter().map(|(opcode, count)| format!("{:>10} {:?}", count, opcode)).collect();
+        Some(lines.join("\n"))
+    }
+}
+
+/// Counts how many instructions each function runs, by name. The top level is `script`,
+/// and every lambda shares the name `lambda`.
+#[derive(Debug, Default)]
+pub struct FunctionProfile {
+    counts: HashMap<String, usize>,
+}
+
+impl FunctionProfile {
+    pub fn count(&self, function: &str) -> usize {
+        self.counts.get(function).copied().unwrap_or(0)
+    }
+
+    /// Every function that ran, the busiest first.
+    pub fn counts(&self) -> Vec<(&str, usize)> {
+        let mut counts: Vec<(&str, usize)> = self.counts.iter().map(|(name, count)| (name.as_str(), *count)).collect();
+        counts.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(b.0)));
+        counts
+    }
+}
+
+impl Tracer for FunctionProfile {
+    fn instruction(&mut self, function: &Function, _offset: usize, _stack: &[Value]) {
+        // looked up by reference first, so the name is only copied the first time
+        match self.counts.get_mut(function.name.as_str()) {
+            Some(count) => *count += 1,
+            None => {
+                self.counts.insert(function.name.clone(), 1);
+            }
+        }
+    }
+
+    fn summary(&self) -> Option<String> {
+        let lines: Vec<String> = self.counts().iter().map(|(name, count)| format!("{:>10} {}", count, name)).collect();
+        Some(lines.join("\n"))
+    }
+}
+
+/// The tracer a command line option names: `stack`, `opcodes` or `functions`.
+pub fn from_name(name: &str) -> Option<Box<dyn Tracer>> {
+    match name {
+        "stack" => Some(Box::new(StackTracer)),
+        "opcodes" => Some(Box::new(OpcodeHistogram::default())),
+        "functions" => Some(Box::new(FunctionProfile::default())),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compiler::Compiler;
+    use crate::lang_parser::LangParser;
+    use crate::vm::VM;
+
+    // runs `input` with `tracer` attached, handing it back afterwards
+    fn trace<T: Tracer + 'static>(input: &str, tracer: T) -> Rc<RefCell<T>> {
+        let ast = LangParser::new(input).parse().expect("unexpected failure");
+        let chunk = Compiler::compile(&ast).expect("Compilation failed");
+        let tracer = Rc::new(RefCell::new(tracer));
+        let mut vm = VM::new();
+        vm.set_tracer(Box::new(tracer.clone()));
+        vm.interpret(&chunk).expect("VM execution failed");
+        tracer
+    }
+
+    #[test]
+    fn opcode_histogram_counts_each_instruction() {
+        let histogram = trace("let x = 0; while (x < 3) { x = x + 1; } x", OpcodeHistogram::default());
+        let histogram = histogram.borrow();
+        // the condition runs once more than the body
+        assert_eq!(histogram.count(OpCode::OpLess), 4);
+        assert_eq!(histogram.count(OpCode::Add), 3);
+        assert_eq!(histogram.count(OpCode::Loop), 3);
+        assert_eq!(histogram.count(OpCode::Return), 1);
+        assert_eq!(histogram.count(OpCode::Call), 0);
+        // ties are listed in opcode order
+        assert_eq!(histogram.counts()[..2], [(OpCode::Constant, 8), (OpCode::GetGlobal, 8)]);
+        assert!(histogram.summary().unwrap().starts_with("         8 Constant\n         8 GetGlobal\n"));
+    }
+
+    #[test]
+    fn function_profile_counts_instructions_per_function() {
+        let profile = trace("fn double(x) { return x * 2; }
+        let a = double(1);
+        let b = double(a);", FunctionProfile::default());
+        let profile = profile.borrow();
+        // GetLocal, Constant, Multiply and Return, twice
+        assert_eq!(profile.count("double"), 8);
+        assert!(profile.count("script") > 0);
+        assert_eq!(profile.count("lambda"), 0);
+        let names: Vec<&str> = profile.counts().iter().map(|(name, _)| *name).collect();
+        assert_eq!(names, vec!["script", "double"]);
+    }
+
+    #[test]
+    fn the_vm_hands_its_tracer_back() {
+        let ast = LangParser::new("let x = 1 + 2; x").parse().expect("unexpected failure");
+        let chunk = Compiler::compile(&ast).expect("Compilation failed");
+        let mut vm = VM::new();
+        vm.set_tracer(Box::new(OpcodeHistogram::default()));
+        vm.interpret(&chunk).expect("VM execution failed");
+
+        let summary = vm.take_tracer().summary().expect("histograms have a summary");
+        assert!(summary.contains(" Add"));
+        // and goes back to not tracing
+        assert_eq!(vm.take_tracer().summary(), None);
+    }
+
+    #[test]
+    fn tracers_are_found_by_name() {
+        assert!(from_name("stack").is_some());
+        assert!(from_name("opcodes").is_some());
+        assert!(from_name("functions").is_some());
+        assert!(from_name("everything").is_none());
+    }
+}
@@ -0,0 +1,378 @@
+//! Checks bytecode that didn't come straight from the compiler, like a `.foxc` file, before the VM
+//! runs it. The VM trusts its input: a bad operand would index out of bounds or break the stack.
+
+use crate::bytecode::{Builtin, Chunk, OpCode, Value};
+
+/// Checks that a chunk, and every function defined in it, can be run by the VM:
+///
+/// - every instruction is known and its operands fit in the code
+/// - constant, local and upvalue operands refer to something that exists
+/// - jumps land on the start of an instruction
+/// - the line table, if there is one, covers exactly the code
+/// - the stack height at each instruction is the same on every path reaching it,
+///   nothing pops more than is there and execution can't run past the end
+pub fn verify(chunk: &Chunk) -> Result<(), String> {
+    // the script's frame starts out empty
+    verify_function("script", chunk, 0, 0)
+}
+
+fn verify_function(name: &str, chunk: &Chunk, initial_height: usize, upvalue_count: usize) -> Result<(), String> {
+    let verifier = Verifier { name, chunk, upvalue_count };
+    let instructions = verifier.decode()?;
+    verifier.check_stack(&instructions, initial_height)?;
+
+    for constant in &chunk.constants {
+        if let Value::Function(function) = constant {
+            // slot 0 holds the function being called, the arguments follow it
+            verify_function(&function.name, &function.chunk, 1 + function.arity, function.upvalue_count)?;
+        }
+    }
+    Ok(())
+}
+
+// what the stack check needs to know about an instruction
+struct Instruction {
+    offset: usize,
+    length: usize,
+    // how many values it needs, and how many are left in their place
+    pops: usize,
+    pushes: usize,
+    // the stack slots it reads, writes or captures, which have to exist
+    slots: Vec<usize>,
+    jump: Option<usize>,
+    // whether the next instruction can run after it
+    falls_through: bool,
+}
+
+struct Verifier<'a> {
+    name: &'a str,
+    chunk: &'a Chunk,
+    upvalue_count: usize,
+}
+
+impl Verifier<'_> {
+    fn error(&self, offset: usize, message: String) -> String {
+        format!("invalid bytecode in `{}` at {}: {}", self.name, offset, message)
+    }
+
+    // decodes the code front to back, checking everything that doesn't depend on the stack
+    fn decode(&self) -> Result<Vec<Instruction>, String> {
+        let code = &self.chunk.code;
+        if code.is_empty() {
+            return Err(self.error(0, "the code is empty".to_string()));
+        }
+
+        let covered: usize = self.chunk.lines.iter().map(|run| run.length).sum();
+        if !self.chunk.lines.is_empty() && covered != code.len() {
+            return Err(self.error(0, format!("the line table covers {} bytes of code, not {}", covered, code.len())));
+        }
+
+        let mut instructions = Vec::new();
+        let mut offset = 0;
+        while offset < code.len() {
+            let instruction = self.decode_instruction(offset)?;
+            offset += instruction.length;
+            instructions.push(instruction);
+        }
+
+        // jumps can only be checked once every instruction start is known
+        for instruction in &instructions {
+            if let Some(target) = instruction.jump {
+                if instructions.binary_search_by_key(&target, |i| i.offset).is_err() {
+                    return Err(self.error(instruction.offset, format!("jump target {} is not the start of an instruction", target)));
+                }
+            }
+        }
+        Ok(instructions)
+    }
+
+    fn decode_instruction(&self, offset: usize) -> Result<Instruction, String> {
+        let code = &self.chunk.code;
+        let Some(opcode) = OpCode::from_byte(code[offset]) else {
+            return Err(self.error(offset, format!("unknown instruction {}", code[offset])));
+        };
+        // the operand bytes of the instruction, checked to be there before they are read
+        let operands = |count: usize| -> Result<&[u8], String> {
+            code.get(offset + 1..offset + 1 + count)
+                .ok_or_else(|| self.error(offset, format!("{:?} is missing its operands", opcode)))
+        };
+
+        let mut slots = Vec::new();
+        let mut jump = None;
+        // (length, pops, pushes)
+        let (length, pops, pushes) = match opcode {
+            OpCode::Add | OpCode::Subtract | OpCode::Multiply | OpCode::Divide | OpCode::OpModulo | OpCode::Power
+            | OpCode::OpEqual | OpCode::OpGreater | OpCode::OpLess
+            | OpCode::BitwiseAnd | OpCode::BitwiseOr | OpCode::BitwiseXor | OpCode::ShiftLeft | OpCode::ShiftRight
+            | OpCode::GetIndex => (1, 2, 1),
+            OpCode::Negate | OpCode::OpNot | OpCode::Len | OpCode::Copy => (1, 1, 1),
+            OpCode::OpTrue | OpCode::OpFalse | OpCode::Nil | OpCode::Map => (1, 0, 1),
+            OpCode::Pop | OpCode::Print | OpCode::Return => (1, 1, 0),
+            OpCode::SetIndex => (1, 3, 1),
+
+            OpCode::Constant | OpCode::ConstantLong => {
+                self.constant_operand(opcode, offset)?;
+                (1 + operand_width(opcode), 0, 1)
+            }
+            OpCode::DefineGlobal | OpCode::DefineGlobalLong | OpCode::GetGlobal | OpCode::GetGlobalLong
+            | OpCode::SetGlobal | OpCode::SetGlobalLong => {
+                let index = self.constant_operand(opcode, offset)?;
+                self.expect_name(offset, index)?;
+                let length = 1 + operand_width(opcode);
+                match opcode {
+                    OpCode::DefineGlobal | OpCode::DefineGlobalLong => (length, 1, 0),
+                    OpCode::GetGlobal | OpCode::GetGlobalLong => (length, 0, 1),
+                    _ => (length, 1, 1),
+                }
+            }
+            OpCode::Invoke | OpCode::InvokeLong => {
+                let index = self.constant_operand(opcode, offset)?;
+                self.expect_name(offset, index)?;
+                let width = operand_width(opcode);
+                let arg_count = operands(width + 1)?[width] as usize;
+                // the receiver and the arguments make way for the result
+                (2 + width, arg_count + 1, 1)
+            }
+            OpCode::Closure | OpCode::ClosureLong => {
+                let index = self.constant_operand(opcode, offset)?;
+                let Value::Function(function) = &self.chunk.constants[index] else {
+                    return Err(self.error(offset, format!("constant {} is not a function", index)));
+                };
+                let width = operand_width(opcode);
+                let captures = &operands(width + 2 * function.upvalue_count)?[width..];
+                for pair in captures.chunks(2) {
+                    match pair {
+                        [1, slot] => slots.push(*slot as usize),
+                        [0, upvalue] => self.expect_upvalue(offset, *upvalue)?,
+                        _ => return Err(self.error(offset, format!("invalid capture kind {}", pair[0]))),
+                    }
+                }
+                (1 + width + captures.len(), 0, 1)
+            }
+
+            OpCode::GetLocal | OpCode::SetLocal | OpCode::CloseUpvalues => {
+                slots.push(operands(1)?[0] as usize);
+                match opcode {
+                    OpCode::GetLocal => (2, 0, 1),
+                    OpCode::SetLocal => (2, 1, 1),
+                    _ => (2, 0, 0),
+                }
+            }
+            OpCode::GetUpvalue | OpCode::SetUpvalue => {
+                self.expect_upvalue(offset, operands(1)?[0])?;
+                match opcode {
+                    OpCode::GetUpvalue => (2, 0, 1),
+                    _ => (2, 1, 1),
+                }
+            }
+
+            // the callee and the arguments make way for the result
+            OpCode::Call => (2, operands(1)?[0] as usize + 1, 1),
+            OpCode::Array => (2, operands(1)?[0] as usize, 1),
+            OpCode::Builtin => {
+                let operands = operands(2)?;
+                let Some(builtin) = Builtin::from_byte(operands[0]) else {
+                    return Err(self.error(offset, format!("unknown builtin {}", operands[0])));
+                };
+                let arg_count = operands[1] as usize;
+                if arg_count != builtin.arity() {
+                    return Err(self.error(offset, format!("`{}` takes {} arguments, not {}", builtin.name(), builtin.arity(), arg_count)));
+                }
+                (3, arg_count, 1)
+            }
+
+            OpCode::Jump | OpCode::JumpIfFalse | OpCode::Loop => {
+                operands(3)?;
+                let distance = self.chunk.read_u24(offset + 1);
+                // distances count from the end of the instruction
+                let end = offset + 4;
+                let target = match opcode {
+                    OpCode::Loop => end.checked_sub(distance),
+                    _ => Some(end + distance),
+                };
+                let Some(target) = target else {
+                    return Err(self.error(offset, format!("loop jumps {} bytes back, before the start of the code", distance)));
+                };
+                jump = Some(target);
+                if opcode == OpCode::JumpIfFalse { (4, 1, 0) } else { (4, 0, 0) }
+            }
+        };
+
+        let falls_through = !matches!(opcode, OpCode::Return | OpCode::Jump | OpCode::Loop);
+        Ok(Instruction { offset, length, pops, pushes, slots, jump, falls_through })
+    }
+
+    fn constant_operand(&self, opcode: OpCode, offset: usize) -> Result<usize, String> {
+        if offset + operand_width(opcode) >= self.chunk.code.len() {
+            return Err(self.error(offset, format!("{:?} is missing its operands", opcode)));
+        }
+        let (index, _) = self.chunk.read_constant_index(opcode, offset);
+        if index >= self.chunk.constants.len() {
+            return Err(self.error(offset, format!("constant {} is out of bounds, there are {}", index, self.chunk.constants.len())));
+        }
+        Ok(index)
+    }
+
+    fn expect_name(&self, offset: usize, index: usize) -> Result<(), String> {
+        match &self.chunk.constants[index] {
+            Value::Str(_) => Ok(()),
+            other => Err(self.error(offset, format!("constant {} is a {}, not a name", index, other.type_name()))),
+        }
+    }
+
+    fn expect_upvalue(&self, offset: usize, index: u8) -> Result<(), String> {
+        if index as usize >= self.upvalue_count {
+            return Err(self.error(offset, format!("upvalue {} is out of bounds, there are {}", index, self.upvalue_count)));
+        }
+        Ok(())
+    }
+
+    // follows every path through the code, tracking how many values are on the stack
+    fn check_stack(&self, instructions: &[Instruction], initial_height: usize) -> Result<(), String> {
+        let mut heights: Vec<Option<usize>> = vec![None; instructions.len()];
+        let mut pending = vec![(0, initial_height)];
+        let index_of = |offset: usize| instructions.binary_search_by_key(&offset, |i| i.offset).expect("checked by decode");
+
+        while let Some((index, height)) = pending.pop() {
+            let instruction = &instructions[index];
+            match heights[index] {
+                Some(known) if known == height => continue,
+                Some(known) => {
+                    return Err(self.error(instruction.offset, format!("inconsistent stack height, {} on one path and {} on another", known, height)));
+                }
+                None => heights[index] = Some(height),
+            }
+
+            if height < instruction.pops {
+                return Err(self.error(instruction.offset, format!("stack underflow, needs {} values but has {}", instruction.pops, height)));
+            }
+            if let Some(slot) = instruction.slots.iter().find(|slot| **slot >= height) {
+                return Err(self.error(instruction.offset, format!("slot {} is out of bounds, the stack has {} values", slot, height)));
+            }
+
+            let after = height - instruction.pops + instruction.pushes;
+            if let Some(target) = instruction.jump {
+                pending.push((index_of(target), after));
+            }
+            if instruction.falls_through {
+                if index + 1 == instructions.len() {
+                    return Err(self.error(instruction.offset, "execution runs past the end of the code".to_string()));
+                }
+                pending.push((index + 1, after));
+            }
+        }
+        Ok(())
+    }
+}
+
+fn operand_width(opcode: OpCode) -> usize {
+    if opcode.is_long() { 3 } else { 1 }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compiler::Compiler;
+    use crate::lang_parser::LangParser;
+
+    fn compile(input: &str) -> Chunk {
+        let ast = LangParser::new(input).parse().expect("unexpected failure");
+        Compiler::compile(&ast).expect("Compilation failed")
+    }
+
+    fn chunk_with(code: &[u8], constants: &[Value]) -> Chunk {
+        let mut chunk = Chunk::new();
+        chunk.code = code.to_vec();
+        chunk.constants = constants.to_vec();
+        chunk
+    }
+
+    #[test]
+    fn compiled_programs_verify() {
+        let programs = [
+            "let x = 1; x",
+            "let total = 0; for i in 0..10 { if (i == 3) { continue; } if (i == 8) { break; } total = total + i; } total",
+            "fn fib(n) { if (n < 2) { return n; } return fib(n - 1) + fib(n - 2); } fib(10)",
+            "fn make() { let xs = [1, 2]; let count = 0; return fn(x) { count = count + len(xs); return count; }; } let f = make(); f(1)",
+            "let m = <>; m.push(1, [2]); let ys = map([1, 2], fn(x) { return x > 1 && x < 3 || x == 0; }); ys",
+            "let x = 0; while (x < 3) { let y = x; x = y + 1; } x",
+        ];
+        for program in programs {
+            verify(&compile(program)).unwrap_or_else(|e| panic!("{} failed to verify: {}", program, e));
+        }
+        let mut input: String = (0..300).map(|i| format!("let x{} = {};", i, i)).collect();
+        input.push_str("x299");
+        verify(&compile(&input)).expect("long constant operands should verify");
+    }
+
+    #[test]
+    fn operands_are_bounds_checked() {
+        let return_ = OpCode::Return as u8;
+        let chunk = chunk_with(&[OpCode::Constant as u8, 1, return_], &[Value::Int(1)]);
+        assert_eq!(verify(&chunk).unwrap_err(), "invalid bytecode in `script` at 0: constant 1 is out of bounds, there are 1");
+
+        let chunk = chunk_with(&[OpCode::Constant as u8], &[]);
+        assert_eq!(verify(&chunk).unwrap_err(), "invalid bytecode in `script` at 0: Constant is missing its operands");
+
+        let chunk = chunk_with(&[OpCode::GetGlobal as u8, 0, return_], &[Value::Int(1)]);
+        assert_eq!(verify(&chunk).unwrap_err(), "invalid bytecode in `script` at 0: constant 0 is a int, not a name");
+
+        let chunk = chunk_with(&[OpCode::GetUpvalue as u8, 0, return_], &[]);
+        assert_eq!(verify(&chunk).unwrap_err(), "invalid bytecode in `script` at 0: upvalue 0 is out of bounds, there are 0");
+
+        let chunk = chunk_with(&[OpCode::Nil as u8, OpCode::Builtin as u8, 4, 1, return_], &[]);
+        assert_eq!(verify(&chunk).unwrap_err(), "invalid bytecode in `script` at 1: `map` takes 2 arguments, not 1");
+
+        let chunk = chunk_with(&[200], &[]);
+        assert_eq!(verify(&chunk).unwrap_err(), "invalid bytecode in `script` at 0: unknown instruction 200");
+    }
+
+    #[test]
+    fn line_tables_must_cover_the_code() {
+        let mut chunk = compile("let x = 1;\nx");
+        chunk.lines.last_mut().unwrap().length += 1;
+        let code_len = chunk.code.len();
+        assert_eq!(verify(&chunk).unwrap_err(), format!("invalid bytecode in `script` at 0: the line table covers {} bytes of code, not {}", code_len + 1, code_len));
+    }
+
+    #[test]
+    fn jumps_must_land_on_instructions() {
+        let jump = OpCode::Jump as u8;
+        let chunk = chunk_with(&[jump, 0, 0, 1, OpCode::Constant as u8, 0, OpCode::Return as u8], &[Value::Int(1)]);
+        assert_eq!(verify(&chunk).unwrap_err(), "invalid bytecode in `script` at 0: jump target 5 is not the start of an instruction");
+
+        let chunk = chunk_with(&[OpCode::Nil as u8, OpCode::Loop as u8, 0, 0, 9, OpCode::Return as u8], &[]);
+        assert_eq!(verify(&chunk).unwrap_err(), "invalid bytecode in `script` at 1: loop jumps 9 bytes back, before the start of the code");
+    }
+
+    #[test]
+    fn stack_heights_must_be_consistent() {
+        let (nil, pop, return_) = (OpCode::Nil as u8, OpCode::Pop as u8, OpCode::Return as u8);
+        let chunk = chunk_with(&[pop, nil, return_], &[]);
+        assert_eq!(verify(&chunk).unwrap_err(), "invalid bytecode in `script` at 0: stack underflow, needs 1 values but has 0");
+
+        // the false branch pushes one value more than the true one before they meet
+        let chunk = chunk_with(&[OpCode::OpTrue as u8, OpCode::JumpIfFalse as u8, 0, 0, 1, nil, nil, return_], &[]);
+        assert_eq!(verify(&chunk).unwrap_err(), "invalid bytecode in `script` at 6: inconsistent stack height, 1 on one path and 0 on another");
+
+        let chunk = chunk_with(&[nil, OpCode::GetLocal as u8, 1, return_], &[]);
+        assert_eq!(verify(&chunk).unwrap_err(), "invalid bytecode in `script` at 1: slot 1 is out of bounds, the stack has 1 values");
+
+        let chunk = chunk_with(&[nil, pop], &[]);
+        assert_eq!(verify(&chunk).unwrap_err(), "invalid bytecode in `script` at 1: execution runs past the end of the code");
+    }
+
+    #[test]
+    fn functions_are_verified_with_their_own_frame() {
+        // `fn f(a) { return a; }` reads its argument from slot 1, past the function in slot 0
+        let mut chunk = compile("fn f(a) { return a; } f(1)");
+        verify(&chunk).expect("should verify");
+
+        let Value::Function(function) = &chunk.constants[0] else { panic!("expected a function") };
+        let mut function = ast::value::Function { name: function.name.clone(), arity: 0, upvalue_count: 0, chunk: function.chunk.clone() };
+        function.chunk.code = vec![OpCode::GetLocal as u8, 1, OpCode::Return as u8];
+        function.chunk.lines.clear();
+        chunk.constants[0] = Value::Function(std::sync::Arc::new(function));
+        assert_eq!(verify(&chunk).unwrap_err(), "invalid bytecode in `f` at 0: slot 1 is out of bounds, the stack has 1 values");
+    }
+}
@@ -1,7 +1,14 @@
-use std::cmp::PartialEq;
 use std::collections::HashMap;
-use crate::compiler::debug;
-use crate::bytecode::{Chunk, OpCode, Value};
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+use crate::bytecode::{Builtin, Chunk, OpCode, Value};
+use crate::heap::{GcStats, Heap, Object};
+use crate::tracer::{NoTracer, Tracer};
+use ast::ast::DEFAULT_MAX_CALL_DEPTH;
+use ast::error::RuntimeErrorKind;
+use ast::span::Span;
+use ast::value::{Arithmetic, Bitwise, Closure, Function, Upvalue};
 
 // A helper macro to handle binary operations.
 // It pops two numbers, performs an operation, and pushes the result.
@@ -28,10 +35,109 @@ macro_rules! binary_op {
 }
 
 
+/// How many calls a `VmError` shows in its trace, after folding calls repeated back to back.
+pub const MAX_TRACE_LINES: usize = 16;
+
+/// A runtime error, with the calls that were active when it happened.
+#[derive(Debug, Clone, PartialEq)]
+pub struct VmError {
+    pub message: String,
+    /// The innermost call first, ending with the script.
+    pub trace: Vec<TraceEntry>,
+}
+
+/// A call in a `VmError`'s stack trace.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TraceEntry {
+    pub function: String,
+    /// Where in the source the call was running, if the chunk records it.
+    pub span: Option<Span>,
+}
+
+impl TraceEntry {
+    pub fn line(&self) -> Option<usize> {
+        self.span.map(|span| span.line)
+    }
+}
+
+impl VmError {
+    /// The span of the instruction that failed.
+    pub fn span(&self) -> Option<Span> {
+        self.trace.first().and_then(|entry| entry.span)
+    }
+
+    /// The line of the instruction that failed.
+    pub fn line(&self) -> Option<usize> {
+        self.span().map(|span| span.line)
+    }
+
+    /// The trace as lines to print, innermost call first. A call repeated back to back, like in
+    /// runaway recursion, is shown once with a count, and at most `MAX_TRACE_LINES` calls are shown.
+    pub fn trace_lines(&self) -> Vec<String> {
+        let mut lines = Vec::new();
+        let mut entries = self.trace.iter().peekable();
+        let mut shown = 0;
+        while let Some(entry) = entries.next() {
+            if shown == MAX_TRACE_LINES {
+                let rest = 1 + entries.count();
+                lines.push(format!("... and {} more call{}", rest, if rest == 1 { "" } else { "s" }));
+                break;
+            }
+            match entry.line() {
+                Some(line) => lines.push(format!("[line {}] in {}", line, entry.function)),
+                None => lines.push(format!("[line ?] in {}", entry.function)),
+            }
+            let mut repeats = 0;
+            while entries.next_if_eq(&entry).is_some() {
+                repeats += 1;
+            }
+            if repeats > 0 {
+                lines.push(format!("... repeated {} more time{}", repeats, if repeats == 1 { "" } else { "s" }));
+            }
+            shown += 1;
+        }
+        lines
+    }
+}
+
+impl fmt::Display for VmError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.message)?;
+        for line in self.trace_lines() {
+            write!(f, "\n{}", line)?;
+        }
+        Ok(())
+    }
+}
+
 pub struct VM {
     // The VM has its own stack. `Vec` is perfect for this.
     stack: Vec<Value>,
     globals: HashMap<String, Value>,
+    // the active calls, innermost last; the script itself runs in the first frame
+    frames: Vec<CallFrame>,
+    // upvalues still pointing at stack slots, shared by every closure capturing the same slot
+    open_upvalues: Vec<Arc<Mutex<Upvalue>>>,
+    // the arrays and maps created while running
+    heap: Heap,
+    // where the instruction running in the innermost frame starts, for stack traces
+    instruction: usize,
+    tracer: Box<dyn Tracer>,
+    /// How many calls can be active at once before running fails with a stack overflow.
+    pub max_call_depth: usize,
+    /// Collects garbage before every allocation instead of waiting for the threshold, so an
+    /// object that isn't reachable from a root is freed as early as possible. For testing the VM.
+    pub stress_gc: bool,
+}
+
+/// An active call. Its locals live on the VM stack, starting at `base`, and
+/// `GetLocal`/`SetLocal` slots are counted from there. For a function, slot 0
+/// holds the closure itself and its arguments follow.
+struct CallFrame {
+    closure: Arc<Closure>,
+    // where to resume once the call this frame is making returns
+    ip: usize,
+    base: usize,
 }
 
 impl Default for VM {
@@ -40,44 +146,342 @@ impl Default for VM {
     }
 }
 
-impl PartialEq for OpCode {
-    fn eq(&self, other: &Self) -> bool {
-        matches!((self, other), (OpCode::Return, OpCode::Return) | (OpCode::Constant, OpCode::Constant) | (OpCode::Nil, OpCode::Nil) | (OpCode::OpTrue, OpCode::OpTrue) | (OpCode::OpFalse, OpCode::OpFalse) | (OpCode::Pop, OpCode::Pop) | (OpCode::Add, OpCode::Add) | (OpCode::Subtract, OpCode::Subtract) | (OpCode::Multiply, OpCode::Multiply) | (OpCode::Divide, OpCode::Divide) | (OpCode::Jump, OpCode::Jump) | (OpCode::JumpIfFalse, OpCode::JumpIfFalse))
-    }
-}
-
 impl VM {
     pub fn new() -> Self {
         VM {
             stack: Vec::new(),
             globals: HashMap::new(),
+            frames: Vec::new(),
+            open_upvalues: Vec::new(),
+            heap: Heap::new(),
+            instruction: 0,
+            tracer: Box::new(NoTracer),
+            max_call_depth: DEFAULT_MAX_CALL_DEPTH,
+            stress_gc: false,
         }
     }
     
-    pub fn interpret(&mut self, chunk: &Chunk) -> Result<Value, String> {
-        // instruction pointer
-        let mut ip = 0;
+    fn frame(&self) -> &CallFrame {
+        self.frames.last().expect("no active call frame")
+    }
+
+    pub fn interpret(&mut self, chunk: &Chunk) -> Result<Value, VmError> {
+        let script = Function { name: "script".to_string(), arity: 0, upvalue_count: 0, chunk: chunk.clone() };
+        let script = Closure { function: Arc::new(script), upvalues: Vec::new() };
+        let base = self.stack.len();
+        self.frames.push(CallFrame { closure: Arc::new(script), ip: 0, base });
+        let result = self.run(0).map_err(|message| VmError { message, trace: self.stack_trace() });
+        // unwind every frame, also after an error left calls and values behind
+        self.frames.clear();
+        self.open_upvalues.clear();
+        self.stack.truncate(base);
+        result
+    }
+
+    // the active calls and the lines they are at, innermost first
+    fn stack_trace(&self) -> Vec<TraceEntry> {
+        let innermost = self.frames.len() - 1;
+        self.frames
+            .iter()
+            .enumerate()
+            .rev()
+            .map(|(index, frame)| {
+                // the other frames are waiting for a call, and resume after the instruction making it
+                let offset = if index == innermost { self.instruction } else { frame.ip - 1 };
+                let function = &frame.closure.function;
+                TraceEntry { function: function.name.clone(), span: function.chunk.span_at(offset) }
+            })
+            .collect()
+    }
+
+    /// Has `tracer` watch every instruction from now on, in place of the current one.
+    pub fn set_tracer(&mut self, tracer: Box<dyn Tracer>) {
+        self.tracer = tracer;
+    }
+
+    /// Takes the current tracer back, for instance to print its summary, and stops tracing.
+    pub fn take_tracer(&mut self) -> Box<dyn Tracer> {
+        std::mem::replace(&mut self.tracer, Box::new(NoTracer))
+    }
+
+    /// What the garbage collector has done so far.
+    pub fn gc_stats(&self) -> GcStats {
+        self.heap.stats()
+    }
+
+    /// Sets how many bytes of objects can be allocated before the first garbage collection.
+    pub fn set_gc_threshold(&mut self, bytes: usize) {
+        self.heap.set_threshold(bytes);
+    }
+
+    /// Frees every object that can't be reached from the stack, the call frames or the globals.
+    pub fn collect_garbage(&mut self) {
+        self.collect(None);
+    }
+
+    // `pending` is an object about to be allocated, which keeps what it refers to alive
+    fn collect(&mut self, pending: Option<&Object>) {
+        let start = Instant::now();
+
+        for value in self.stack.iter().chain(self.globals.values()) {
+            self.heap.mark_value(value);
+        }
+        for frame in &self.frames {
+            self.heap.mark_closure(&frame.closure);
+        }
+        for upvalue in &self.open_upvalues {
+            if let Upvalue::Open(slot) = *upvalue.lock().expect("poisoned upvalue") {
+                self.heap.mark_value(&self.stack[slot]);
+            }
+        }
+        if let Some(object) = pending {
+            self.heap.mark_contents(object);
+        }
+
+        self.heap.trace();
+        self.heap.sweep(start.elapsed());
+    }
+
+    // every object the VM creates goes through here, so the collector can run first
+    fn allocate(&mut self, object: Object) -> Value {
+        if self.stress_gc || self.heap.should_collect() {
+            self.collect(Some(&object));
+        }
+        self.heap.allocate(object)
+    }
+
+    // the error for operands that don't fit the operator an instruction computes, worded like the interpreter's
+    fn operands_mismatch(&self, opcode: OpCode, a: &Value, b: &Value) -> String {
+        let expected = format!("operands for {:?}", opcode.operator().expect("an operator instruction"));
+        type_mismatch(&expected, &format!("{} and {}", self.heap.type_name(a), self.heap.type_name(b)))
+    }
+
+    fn operand_mismatch(&self, opcode: OpCode, value: &Value) -> String {
+        let expected = format!("operand for {:?}", opcode.operator().expect("an operator instruction"));
+        type_mismatch(&expected, self.heap.type_name(value))
+    }
+
+    /// Formats a value the way `print` shows it, including the arrays and maps it refers to.
+    pub fn display(&self, value: &Value) -> String {
+        self.heap.display(value)
+    }
+
+    // starts a call to the value sitting below `arg_count` arguments on the stack, by pushing its frame
+    fn call(&mut self, arg_count: usize) -> Result<(), String> {
+        let callee = self.stack[self.stack.len() - 1 - arg_count].clone();
+        let Value::Closure(callee) = callee else {
+            return Err(type_mismatch("function", self.heap.type_name(&callee)));
+        };
+        if callee.function.arity != arg_count {
+            let error = RuntimeErrorKind::ArityMismatch {
+                name: callee.function.name.clone(),
+                expected: callee.function.arity,
+                found: arg_count,
+            };
+            return Err(error.to_string());
+        }
+        if self.frames.len() >= self.max_call_depth {
+            return Err(RuntimeErrorKind::StackOverflow { limit: self.max_call_depth }.to_string());
+        }
+
+        let base = self.stack.len() - 1 - arg_count;
+        self.frames.push(CallFrame { closure: callee, ip: 0, base });
+        Ok(())
+    }
+
+    // calls a function from native code, like the callback of `map`, and runs it to completion
+    fn call_value(&mut self, callee: Value, arguments: Vec<Value>) -> Result<Value, String> {
+        let depth = self.frames.len();
+        let arg_count = arguments.len();
+        self.stack.push(callee);
+        self.stack.extend(arguments);
+        self.call(arg_count)?;
+        // the caller's instruction is still running once the callee returns
+        let instruction = self.instruction;
+        let result = self.run(depth);
+        if result.is_ok() {
+            self.instruction = instruction;
+        }
+        result
+    }
+
+    // an element of an array argument, None past the end; read one at a time, since callbacks can change the array
+    fn array_element(&self, value: &Value, index: usize) -> Result<Option<Value>, String> {
+        match value {
+            Value::Object(object) => match self.heap.get(*object) {
+                Object::Array(elements) => Ok(elements.get(index).cloned()),
+                other => Err(type_mismatch("array", other.type_name())),
+            },
+            other => Err(type_mismatch("array", other.type_name())),
+        }
+    }
+
+    // the arguments stay on the stack while the builtin runs, so a collection sees them as roots
+    fn call_builtin(&mut self, builtin: Builtin, arg_count: usize) -> Result<Value, String> {
+        let arguments = self.stack[self.stack.len() - arg_count..].to_vec();
+        if arguments.len() != builtin.arity() {
+            let error = RuntimeErrorKind::ArityMismatch {
+                name: builtin.name().to_string(),
+                expected: builtin.arity(),
+                found: arguments.len(),
+            };
+            return Err(error.to_string());
+        }
+
+        let value = &arguments[0];
+        match builtin {
+            Builtin::Bin => value.to_bin().map_err(|e| e.to_string()),
+            Builtin::Int => value.to_int().map_err(|e| e.to_string()),
+            Builtin::Float | Builtin::Sqrt => {
+                let Some(f) = value.as_f64() else {
+                    return Err(type_mismatch("number", self.heap.type_name(value)));
+                };
+                Ok(Value::Float(if builtin == Builtin::Sqrt { f.sqrt() } else { f }))
+            }
+            Builtin::Map | Builtin::Filter => {
+                let function = &arguments[1];
+                // checked before allocating, so a bad argument doesn't leave the results behind
+                self.array_element(value, 0)?;
+                let results = self.allocate(Object::Array(Vec::new()));
+                let Value::Object(results_ref) = results else {
+                    unreachable!("allocating returns an object");
+                };
+                self.stack.push(results.clone());
+
+                let mut index = 0;
+                while let Some(element) = self.array_element(value, index)? {
+                    let argument = self.heap.copy(&element);
+                    let result = self.call_value(function.clone(), vec![argument])?;
+                    let kept = match (builtin, result) {
+                        (Builtin::Map, result) => result,
+                        (_, Value::Bool(true)) => element,
+                        (_, Value::Bool(false)) => {
+                            index += 1;
+                            continue;
+                        }
+                        (_, other) => return Err(type_mismatch("bool", self.heap.type_name(&other))),
+                    };
+                    if let Object::Array(elements) = self.heap.get_mut(results_ref) {
+                        elements.push(kept);
+                    }
+                    index += 1;
+                }
+
+                self.stack.pop();
+                Ok(results)
+            }
+        }
+    }
+
+    // calls a method of an object, with the receiver and the arguments already popped
+    // closures are ordered by identity, so the interior mutability of their upvalues can't reorder a map
+    #[allow(clippy::mutable_key_type)]
+    fn invoke(&mut self, receiver: Value, name: &str, arguments: Vec<Value>) -> Result<Value, String> {
+        let expected = match name {
+            "push" => 2,
+            "get" => 1,
+            _ => return Err(RuntimeErrorKind::UnknownMethod(name.to_string()).to_string()),
+        };
+        let values = match &receiver {
+            Value::Object(object) => match self.heap.get_mut(*object) {
+                Object::Map(values) => values,
+                other => return Err(type_mismatch("map", other.type_name())),
+            },
+            other => return Err(type_mismatch("map", other.type_name())),
+        };
+        if arguments.len() != expected {
+            let error = RuntimeErrorKind::ArityMismatch { name: name.to_string(), expected, found: arguments.len() };
+            return Err(error.to_string());
+        }
+
+        let mut arguments = arguments.into_iter();
+        let key = arguments.next().expect("checked the arity");
+        if name == "push" {
+            values.insert(key, arguments.next().expect("checked the arity"));
+            return Ok(Value::Null);
+        }
+        match values.get(&key) {
+            Some(value) => Ok(value.clone()),
+            None => Err(RuntimeErrorKind::MissingKey(key.to_string()).to_string()),
+        }
+    }
+
+    // the open upvalue for a stack slot, so closures capturing the same variable share it
+    fn capture_upvalue(&mut self, slot: usize) -> Arc<Mutex<Upvalue>> {
+        let existing = self.open_upvalues.iter().find(|upvalue| {
+            matches!(*upvalue.lock().expect("poisoned upvalue"), Upvalue::Open(open) if open == slot)
+        });
+        if let Some(upvalue) = existing {
+            return upvalue.clone();
+        }
+        let upvalue = Arc::new(Mutex::new(Upvalue::Open(slot)));
+        self.open_upvalues.push(upvalue.clone());
+        upvalue
+    }
+
+    // moves the values of the slots from `from` upwards into the upvalues pointing at them
+    fn close_upvalues(&mut self, from: usize) {
+        let stack = &self.stack;
+        self.open_upvalues.retain(|upvalue| {
+            let mut upvalue = upvalue.lock().expect("poisoned upvalue");
+            match *upvalue {
+                Upvalue::Open(slot) if slot >= from => {
+                    *upvalue = Upvalue::Closed(stack[slot].clone());
+                    false
+                }
+                _ => true,
+            }
+        });
+    }
+
+    fn read_upvalue(&self, upvalue: &Mutex<Upvalue>) -> Value {
+        match &*upvalue.lock().expect("poisoned upvalue") {
+            Upvalue::Open(slot) => self.stack[*slot].clone(),
+            Upvalue::Closed(value) => value.clone(),
+        }
+    }
+
+    fn write_upvalue(&mut self, upvalue: &Mutex<Upvalue>, value: Value) {
+        match &mut *upvalue.lock().expect("poisoned upvalue") {
+            Upvalue::Open(slot) => self.stack[*slot] = value,
+            Upvalue::Closed(closed) => *closed = value,
+        }
+    }
+
+    // runs until the frame count drops back to `depth`, returning the value the last frame returned
+    fn run(&mut self, depth: usize) -> Result<Value, String> {
+        // the running frame is cached in locals, and written back to `frames` on a call
+        let frame = self.frame();
+        let (mut closure, mut ip, mut base) = (frame.closure.clone(), frame.ip, frame.base);
         
         loop {
-            // Print the current state of the stack before each instruction.
-            print!("          ");
-            for value in &self.stack {
-                print!("[ {} ]", value);
-            }
-            println!();
-            
-            debug::disassemble_instruction(chunk, ip);
-            
+            let chunk = &closure.function.chunk;
+
+            self.tracer.instruction(&closure.function, ip, &self.stack);
+            self.instruction = ip;
             let instruction = chunk.code[ip];
             let opcode: OpCode = unsafe { std::mem::transmute(instruction) };
             match opcode {
                 OpCode::Return => {
-                    return Ok(self.stack.pop().unwrap_or(Value::Null));
+                    let result = self.stack.pop().unwrap_or(Value::Null);
+                    let finished = self.frames.pop().expect("no active call frame");
+                    // the callee's locals are about to go, closures that captured them keep a copy
+                    self.close_upvalues(finished.base);
+                    // drop the callee, its arguments and its locals
+                    self.stack.truncate(finished.base);
+                    if self.frames.len() == depth {
+                        return Ok(result);
+                    }
+
+                    // and resume the caller
+                    self.stack.push(result);
+                    let caller = self.frame();
+                    (closure, ip, base) = (caller.closure.clone(), caller.ip, caller.base);
                 }
-                OpCode::Constant => {
-                    let const_index = chunk.code[ip + 1] as usize;
+                OpCode::Constant | OpCode::ConstantLong => {
+                    let (const_index, width) = chunk.read_constant_index(opcode, ip);
                     self.stack.push(chunk.constants[const_index].clone());
-                    ip += 2;
+                    ip += 1 + width;
                 }
                 OpCode::Nil => {
                     self.stack.push(Value::Null);
@@ -95,66 +499,109 @@ impl VM {
                     self.stack.pop();
                     ip += 1;
                 }
-                OpCode::OpEqual | OpCode::OpGreater | OpCode::OpLess | OpCode::Add | OpCode::Subtract | OpCode::Multiply | OpCode::Divide => {
-                    let b = self.stack.pop().unwrap();
-                    let a = self.stack.pop().unwrap();
- 
-                    if let (Value::Int(a_val), Value::Int(b_val)) = (a.clone(), b.clone()) {
-                        let result = match opcode {
-                            OpCode::OpEqual => Value::Bool(a_val == b_val),
-                            OpCode::OpGreater => Value::Bool(a_val > b_val),
-                            OpCode::OpLess => Value::Bool(a_val < b_val),
-                            OpCode::Add => Value::Int(a_val + b_val),
-                            OpCode::Subtract => Value::Int(a_val - b_val),
-                            OpCode::Multiply => Value::Int(a_val * b_val),
-                            OpCode::Divide => Value::Int(a_val / b_val),
-                            _ => unreachable!(),
-                        };
-                        self.stack.push(result);
-                    } else if opcode == OpCode::OpEqual {
-                        self.stack.push(Value::Bool(a == b));
-                    } else {
-                        return Err("Mismatched types for operation".to_string());
+                OpCode::OpEqual | OpCode::OpGreater | OpCode::OpLess | OpCode::Add | OpCode::Subtract | OpCode::Multiply | OpCode::Divide | OpCode::OpModulo => {
+                    let b = self.stack.pop().expect("Stack underflow");
+                    let a = self.stack.pop().expect("Stack underflow");
+                    let numbers = a.as_f64().is_some() && b.as_f64().is_some();
+
+                    let result = match opcode {
+                        OpCode::OpEqual if numbers => {
+                            Value::Bool(Value::compare_numbers(&a, &b).is_some_and(|o| o.is_eq()))
+                        }
+                        OpCode::OpEqual => Value::Bool(a == b),
+                        OpCode::OpGreater | OpCode::OpLess => {
+                            if !numbers {
+                                return Err(self.operands_mismatch(opcode, &a, &b));
+                            }
+                            // a NaN is neither smaller nor greater than anything
+                            let ordering = Value::compare_numbers(&a, &b);
+                            match opcode {
+                                OpCode::OpGreater => Value::Bool(ordering.is_some_and(|o| o.is_gt())),
+                                _ => Value::Bool(ordering.is_some_and(|o| o.is_lt())),
+                            }
+                        }
+                        _ => {
+                            let op = match opcode {
+                                OpCode::Add => Arithmetic::Add,
+                                OpCode::Subtract => Arithmetic::Subtract,
+                                OpCode::Multiply => Arithmetic::Multiply,
+                                OpCode::Divide => Arithmetic::Divide,
+                                _ => Arithmetic::Modulo,
+                            };
+                            if Value::is_integer_division_by_zero(op, &a, &b) {
+                                return Err(RuntimeErrorKind::DivisionByZero.to_string());
+                            }
+                            match Value::arithmetic(op, &a, &b) {
+                                Some(value) => value,
+                                None => return Err(self.operands_mismatch(opcode, &a, &b)),
+                            }
+                        }
+                    };
+                    self.stack.push(result);
+                    ip += 1;
+                }
+                OpCode::Power => {
+                    let exponent = self.stack.pop().expect("Stack underflow");
+                    let base = self.stack.pop().expect("Stack underflow");
+                    match base.pow(&exponent) {
+                        Some(value) => self.stack.push(value),
+                        None => {
+                            let found = format!("{} and {}", self.heap.type_name(&base), self.heap.type_name(&exponent));
+                            return Err(type_mismatch("numbers for pow", &found));
+                        }
                     }
                     ip += 1;
                 }
 
+                OpCode::GetLocal => {
+                    let slot = base + chunk.code[ip + 1] as usize;
+                    self.stack.push(self.stack[slot].clone());
+                    ip += 2;
+                }
+                OpCode::SetLocal => {
+                    let slot = base + chunk.code[ip + 1] as usize;
+                    self.stack[slot] = self.stack.last().expect("Stack underflow").clone();
+                    ip += 2;
+                }
                 OpCode::Jump => {
-                    let offset = ((chunk.code[ip + 1] as u16) << 8) | chunk.code[ip + 2] as u16;
-                    ip += 3 + offset as usize;
+                    let offset = chunk.read_u24(ip + 1);
+                    ip += 4 + offset;
+                }
+                OpCode::Loop => {
+                    let offset = chunk.read_u24(ip + 1);
+                    ip = ip + 4 - offset;
                 }
                 OpCode::JumpIfFalse => {
-                    let offset = ((chunk.code[ip + 1] as u16) << 8) | chunk.code[ip + 2] as u16;
+                    let offset = chunk.read_u24(ip + 1);
                     let condition = self.stack.pop().expect("Stack underflow");
 
                     if value_is_falsey(&condition) {
-                        ip += 3 + offset as usize;
+                        ip += 4 + offset;
                     } else {
-                        ip += 3;
+                        ip += 4;
                     }
                 }
                 OpCode::Negate   => {
                     let value = self.stack.pop().expect("Stack underflow");
-                    match value {
-                        Value::Int(val) => self.stack.push(Value::Int(-val)),
-                        _ => return Err("Operand must be a number.".to_string()),
+                    match value.negate() {
+                        Some(value) => self.stack.push(value),
+                        None => return Err(self.operand_mismatch(opcode, &value)),
                     }
                     ip += 1;
                 },
-                OpCode::DefineGlobal => {
-                    let name_index = chunk.code[ip + 1] as usize;
+                OpCode::DefineGlobal | OpCode::DefineGlobalLong => {
+                    let (name_index, width) = chunk.read_constant_index(opcode, ip);
                     let name = chunk.constants[name_index].clone();
                     if let Value::Str(name_str) = name {
                         let value = self.stack.pop().expect("Stack underflow");
-                        self.globals.insert(name_str.clone(), value);
-                        println!("Defined global variable: {}", name_str);
+                        self.globals.insert(name_str, value);
                     } else {
                         return Err("Global variable name must be a string.".to_string());
                     }
-                    ip += 2;
+                    ip += 1 + width;
                 },
-                OpCode::GetGlobal => {
-                    let name_index = chunk.code[ip + 1] as usize;
+                OpCode::GetGlobal | OpCode::GetGlobalLong => {
+                    let (name_index, width) = chunk.read_constant_index(opcode, ip);
                     let name = chunk.constants[name_index].clone();
                     if let Value::Str(name_str) = name {
                         match self.globals.get(&name_str) {
@@ -162,66 +609,203 @@ impl VM {
                                 self.stack.push(value.clone());
                             }
                             None => {
-                                return Err(format!("Undefined variable '{}'.", name_str));
+                                return Err(RuntimeErrorKind::UndefinedVariable(name_str).to_string());
                             }
                         }
                     } else {
                         return Err("Global variable name must be a string.".to_string());
                     }
-                    ip += 2;
+                    ip += 1 + width;
                 },
-                OpCode::SetGlobal => {
-                    let name_index = chunk.code[ip + 1] as usize;
+                OpCode::SetGlobal | OpCode::SetGlobalLong => {
+                    let (name_index, width) = chunk.read_constant_index(opcode, ip);
                     let name = chunk.constants[name_index].clone();
                     if let Value::Str(name_str) = name {
                         let value = self.stack.last().expect("Stack underflow").clone();
                         if self.globals.contains_key(&name_str) {
                             self.globals.insert(name_str.clone(), value);
                         } else {
-                            return Err(format!("Undefined variable '{}'.", name_str));
+                            return Err(RuntimeErrorKind::UndefinedVariable(name_str).to_string());
                         }
                     } else {
                         return Err("Global variable name must be a string.".to_string());
                     }
-                    ip += 2;
+                    ip += 1 + width;
                 },
                 OpCode::OpNot => {
                     let value = self.stack.pop().expect("Stack underflow");
                     match value {
                         Value::Bool(b) => self.stack.push(Value::Bool(!b)),
-                        _ => return Err("Operand must be a boolean.".to_string()),
-                    }
-                },
-                OpCode::OpModulo => {
-                    let b = self.stack.pop().expect("Stack underflow");
-                    let a = self.stack.pop().expect("Stack underflow");
-                    match (a, b) {
-                        (Value::Int(a_val), Value::Int(b_val)) => {
-                            if b_val == 0 {
-                                return Err("Division by zero.".to_string());
-                            }
-                            self.stack.push(Value::Int(a_val % b_val));
-                        },
-                        _ => return Err("Operands must be numbers.".to_string()),
+                        // `~` flips the bits of ints and bins, like in the interpreter
+                        Value::Int(i) => self.stack.push(Value::Int(!i)),
+                        Value::Bin(b) => self.stack.push(Value::Bin(!b)),
+                        other => return Err(self.operand_mismatch(opcode, &other)),
                     }
+                    ip += 1;
                 },
                 OpCode::Call => {
-                    // Needs to have logic to handle not built-in functions.
-                    let func_name_index = chunk.code[ip + 1] as usize;
-                    let func_name = chunk.constants[func_name_index].clone();
-                    if let Value::Str(name) = func_name {
-                        match name.as_str() {
-                            "print" => {
-                                let value = self.stack.pop().expect("Stack underflow");
-                                println!("{}", value);
-                            }
-                            _ => return Err(format!("Unknown function '{}'.", name)),
-                        }
-                    } else {
-                        return Err("Function name must be a string.".to_string());
+                    let arg_count = chunk.code[ip + 1] as usize;
+                    // the caller resumes after the call instruction
+                    self.frames.last_mut().expect("no active call frame").ip = ip + 2;
+                    self.call(arg_count)?;
+                    let callee = self.frame();
+                    (closure, ip, base) = (callee.closure.clone(), callee.ip, callee.base);
+                }
+                OpCode::Closure | OpCode::ClosureLong => {
+                    let (constant_index, width) = chunk.read_constant_index(opcode, ip);
+                    let Value::Function(function) = &chunk.constants[constant_index] else {
+                        return Err("Closure constant must be a function.".to_string());
+                    };
+                    let function = function.clone();
+                    ip += 1 + width;
+
+                    let mut upvalues = Vec::with_capacity(function.upvalue_count);
+                    for _ in 0..function.upvalue_count {
+                        let (is_local, index) = (chunk.code[ip] == 1, chunk.code[ip + 1] as usize);
+                        let upvalue = if is_local {
+                            self.capture_upvalue(base + index)
+                        } else {
+                            closure.upvalues[index].clone()
+                        };
+                        upvalues.push(upvalue);
+                        ip += 2;
                     }
+                    self.stack.push(Value::Closure(Arc::new(Closure { function, upvalues })));
+                }
+                OpCode::GetUpvalue => {
+                    let index = chunk.code[ip + 1] as usize;
+                    let value = self.read_upvalue(&closure.upvalues[index]);
+                    self.stack.push(value);
+                    ip += 2;
+                }
+                OpCode::SetUpvalue => {
+                    let index = chunk.code[ip + 1] as usize;
+                    let value = self.stack.last().expect("Stack underflow").clone();
+                    self.write_upvalue(&closure.upvalues[index], value);
+                    ip += 2;
+                }
+                OpCode::CloseUpvalues => {
+                    let slot = base + chunk.code[ip + 1] as usize;
+                    self.close_upvalues(slot);
                     ip += 2;
                 }
+                OpCode::Array => {
+                    let count = chunk.code[ip + 1] as usize;
+                    let elements = self.stack.split_off(self.stack.len() - count);
+                    let array = self.allocate(Object::Array(elements));
+                    self.stack.push(array);
+                    ip += 2;
+                }
+                OpCode::Map => {
+                    let map = self.allocate(Object::Map(Default::default()));
+                    self.stack.push(map);
+                    ip += 1;
+                }
+                OpCode::GetIndex => {
+                    let index = self.stack.pop().expect("Stack underflow");
+                    let container = self.stack.pop().expect("Stack underflow");
+                    let index = extract_index(&index)?;
+                    let element = match &container {
+                        Value::Object(object) => match self.heap.get(*object) {
+                            Object::Array(elements) => {
+                                elements.get(index).cloned().ok_or_else(|| index_out_of_bounds(index, elements.len()))?
+                            }
+                            other => return Err(type_mismatch("array or string", other.type_name())),
+                        },
+                        // strings are indexed by character
+                        Value::Str(s) => match s.chars().nth(index) {
+                            Some(c) => Value::Str(c.to_string()),
+                            None => return Err(index_out_of_bounds(index, s.chars().count())),
+                        },
+                        other => return Err(type_mismatch("array or string", other.type_name())),
+                    };
+                    self.stack.push(element);
+                    ip += 1;
+                }
+                OpCode::SetIndex => {
+                    let value = self.stack.pop().expect("Stack underflow");
+                    let index = self.stack.pop().expect("Stack underflow");
+                    let container = self.stack.pop().expect("Stack underflow");
+                    let index = extract_index(&index)?;
+                    let elements = match &container {
+                        Value::Object(object) => match self.heap.get_mut(*object) {
+                            Object::Array(elements) => elements,
+                            other => return Err(type_mismatch("array", other.type_name())),
+                        },
+                        other => return Err(type_mismatch("array", other.type_name())),
+                    };
+                    let len = elements.len();
+                    let slot = elements.get_mut(index).ok_or_else(|| index_out_of_bounds(index, len))?;
+                    *slot = value.clone();
+                    self.stack.push(value);
+                    ip += 1;
+                }
+                OpCode::Len => {
+                    let value = self.stack.pop().expect("Stack underflow");
+                    let len = match &value {
+                        Value::Object(object) => match self.heap.get(*object) {
+                            Object::Array(elements) => elements.len(),
+                            Object::Map(values) => values.len(),
+                        },
+                        Value::Str(s) => s.chars().count(),
+                        other => return Err(type_mismatch("array, map or string", other.type_name())),
+                    };
+                    self.stack.push(Value::Int(len as i32));
+                    ip += 1;
+                }
+                OpCode::Copy => {
+                    let value = self.stack.pop().expect("Stack underflow");
+                    // the copies are rooted once they are back on the stack, so nothing can be collected in between
+                    let copy = self.heap.copy(&value);
+                    self.stack.push(copy);
+                    ip += 1;
+                }
+                OpCode::Invoke | OpCode::InvokeLong => {
+                    let (name_index, width) = chunk.read_constant_index(opcode, ip);
+                    let arg_count = chunk.code[ip + 1 + width] as usize;
+                    let Value::Str(name) = &chunk.constants[name_index] else {
+                        return Err("Method name must be a string.".to_string());
+                    };
+                    let arguments = self.stack.split_off(self.stack.len() - arg_count);
+                    let receiver = self.stack.pop().expect("Stack underflow");
+                    let result = self.invoke(receiver, name, arguments)?;
+                    self.stack.push(result);
+                    ip += 2 + width;
+                }
+                OpCode::Builtin => {
+                    let Some(builtin) = Builtin::from_byte(chunk.code[ip + 1]) else {
+                        return Err(format!("Unknown builtin {}.", chunk.code[ip + 1]));
+                    };
+                    let arg_count = chunk.code[ip + 2] as usize;
+                    // callbacks run in nested frames, this one resumes after the instruction
+                    self.frames.last_mut().expect("no active call frame").ip = ip + 3;
+                    let result = self.call_builtin(builtin, arg_count)?;
+                    self.stack.truncate(self.stack.len() - arg_count);
+                    self.stack.push(result);
+                    ip += 3;
+                }
+                OpCode::BitwiseAnd | OpCode::BitwiseOr | OpCode::BitwiseXor | OpCode::ShiftLeft | OpCode::ShiftRight => {
+                    let b = self.stack.pop().expect("Stack underflow");
+                    let a = self.stack.pop().expect("Stack underflow");
+                    let op = match opcode {
+                        OpCode::BitwiseAnd => Bitwise::And,
+                        OpCode::BitwiseOr => Bitwise::Or,
+                        OpCode::BitwiseXor => Bitwise::Xor,
+                        OpCode::ShiftLeft => Bitwise::ShiftLeft,
+                        _ => Bitwise::ShiftRight,
+                    };
+                    match Value::bitwise(op, &a, &b) {
+                        Ok(Some(value)) => self.stack.push(value),
+                        Ok(None) => return Err(self.operands_mismatch(opcode, &a, &b)),
+                        Err(e) => return Err(e.to_string()),
+                    }
+                    ip += 1;
+                }
+                OpCode::Print => {
+                    let value = self.stack.pop().expect("Stack underflow");
+                    println!("{}", self.heap.display(&value));
+                    ip += 1;
+                }
             }
         }
     }
@@ -231,22 +815,67 @@ fn value_is_falsey(value: &Value) -> bool {
     matches!(value, Value::Bool(false))
 }
 
+// the runtime errors shared with the interpreter read the same
+fn type_mismatch(expected: &str, found: &str) -> String {
+    RuntimeErrorKind::TypeMismatch { expected: expected.to_string(), found: found.to_string() }.to_string()
+}
+
+fn index_out_of_bounds(index: usize, len: usize) -> String {
+    RuntimeErrorKind::IndexOutOfBounds { index, len }.to_string()
+}
+
+fn extract_index(index: &Value) -> Result<usize, String> {
+    match index {
+        Value::Int(i) if *i >= 0 => Ok(*i as usize),
+        other => Err(type_mismatch("non-negative index", other.type_name())),
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
     use crate::compiler::Compiler;
-    use std::collections::HashMap;
+    use crate::lang_parser::LangParser;
     use ast::ast::Ast;
     use ast::node::{Node, OperatorKind};
+    use ast::span::Span;
     use ast::value::Value::Int;
 
+    // compiles and runs Fox source, returning the value of its last expression
+    fn run_source(input: &str) -> Value {
+        let ast = LangParser::new(input).parse().expect("unexpected failure");
+        let chunk = Compiler::compile(&ast).expect("Test compilation failed");
+        let mut vm = VM::new();
+        vm.interpret(&chunk).expect("Test VM execution failed")
+    }
+
+    // like `run_source`, but shows the result the way `print` would, so arrays and maps can be checked
+    fn run_source_display(input: &str) -> String {
+        let ast = LangParser::new(input).parse().expect("unexpected failure");
+        let chunk = Compiler::compile(&ast).expect("Test compilation failed");
+        let mut vm = VM::new();
+        let value = vm.interpret(&chunk).expect("Test VM execution failed");
+        vm.display(&value)
+    }
+
+    // runs `input` on the tree-walking interpreter and on the VM, showing the value of `name` from each
+    fn run_both(input: &str, name: &str) -> (String, String) {
+        let mut ast = LangParser::new(input).parse().expect("unexpected failure");
+        ast.eval().expect("Test evaluation failed");
+        let interpreted = ast.declarations.get(name).expect("undefined variable").val().to_string();
+
+        // the script's last expression is what the VM hands back
+        let ast = LangParser::new(&format!("{}\n{}", input, name)).parse().expect("unexpected failure");
+        let chunk = Compiler::compile(&ast).expect("Test compilation failed");
+        let mut vm = VM::new();
+        let value = vm.interpret(&chunk).expect("Test VM execution failed");
+        (interpreted, vm.display(&value))
+    }
+
     /// A helper function to run a test case.
     /// It takes an AST node, compiles it, runs the VM, and returns the result.
     fn run_vm_test(root_node: Node) -> Value {
-        let ast = Ast {
-            nodes: vec![root_node],
-            declarations: HashMap::new(),
-        };
+        let ast = Ast::from_nodes(vec![root_node]);
         let chunk = Compiler::compile(&ast).expect("Test compilation failed");
         let mut vm = VM::new();
         vm.interpret(&chunk).expect("Test VM execution failed")
@@ -258,6 +887,7 @@ mod tests {
             left: Box::new(Node::Atomic { value: Int(5) }),
             operator: OperatorKind::Add,
             right: Box::new(Node::Atomic { value: Int(10) }),
+            span: Span::default(),
         };
         let result = run_vm_test(node);
         assert_eq!(result.to_string(), "15");
@@ -266,6 +896,7 @@ mod tests {
             left: Box::new(Node::Atomic { value: Int(20) }),
             operator: OperatorKind::Subtract,
             right: Box::new(Node::Atomic { value: Int(2) }),
+            span: Span::default(),
         };
         let result = run_vm_test(node);
         assert_eq!(result.to_string(), "18");
@@ -274,6 +905,7 @@ mod tests {
             left: Box::new(Node::Atomic { value: Int(7) }),
             operator: OperatorKind::Multiply,
             right: Box::new(Node::Atomic { value: Int(7) }),
+            span: Span::default(),
         };
         let result = run_vm_test(node);
         assert_eq!(result.to_string(), "49");
@@ -282,6 +914,7 @@ mod tests {
             left: Box::new(Node::Atomic { value: Int(100) }),
             operator: OperatorKind::Divide,
             right: Box::new(Node::Atomic { value: Int(20) }),
+            span: Span::default(),
         };
         let result = run_vm_test(node);
         assert_eq!(result.to_string(), "5");
@@ -294,13 +927,16 @@ mod tests {
                 left: Box::new(Node::Atomic { value: Int(100) }),
                 operator: OperatorKind::Subtract,
                 right: Box::new(Node::Atomic { value: Int(20) }),
+                span: Span::default(),
             }),
             operator: OperatorKind::Divide,
             right: Box::new(Node::BinaryExpression {
                 left: Box::new(Node::Atomic { value: Int(2) }),
                 operator: OperatorKind::Multiply,
                 right: Box::new(Node::Atomic { value: Int(2) }),
+                span: Span::default(),
             }),
+            span: Span::default(),
         };
 
         let result = run_vm_test(node);
@@ -316,7 +952,9 @@ mod tests {
                 left: Box::new(Node::Atomic { value: Int(2) }),
                 operator: OperatorKind::Multiply,
                 right: Box::new(Node::Atomic { value: Int(10) }),
+                span: Span::default(),
             }),
+            span: Span::default(),
         };
         
         let result = run_vm_test(node);
@@ -328,8 +966,7 @@ mod tests {
         // Simulates compiling `-10`
         let mut chunk = Chunk::new();
         let const_idx = chunk.add_constant(Int(10));
-        chunk.write(OpCode::Constant as u8);
-        chunk.write(const_idx);
+        chunk.write_constant_op(OpCode::Constant, const_idx);
         chunk.write(OpCode::Negate as u8);
         chunk.write(OpCode::Return as u8);
 
@@ -338,6 +975,97 @@ mod tests {
         assert_eq!(result.to_string(), "-10");
     }
 
+    #[test]
+    fn functions_assign_to_globals_declared_after_them() {
+        let input = "fn bump() { count = count + 1; return count; }
+        let count = 0;
+        bump();
+        bump();";
+        assert_eq!(run_both(input, "count"), ("2".to_string(), "2".to_string()));
+    }
+
+    #[test]
+    fn arrays_and_maps_are_copied_when_stored() {
+        let input = "let a = [1];
+        let b = a;
+        b[0] = 5;
+        let x = a[0];";
+        assert_eq!(run_both(input, "x"), ("1".to_string(), "1".to_string()));
+
+        // a callee changing an argument leaves the caller's array alone
+        let input = "fn set(xs) { xs[0] = 5; return xs[0]; }
+        let a = [1];
+        let y = set(a);
+        let x = a[0] * 10 + y;";
+        assert_eq!(run_both(input, "x"), ("15".to_string(), "15".to_string()));
+
+        let input = "let m = <>;
+        m.push(1, 2);
+        let n = m;
+        n.push(1, 3);
+        let x = m.get(1);";
+        assert_eq!(run_both(input, "x"), ("2".to_string(), "2".to_string()));
+
+        let input = "let grid = [[1, 2]];
+        let row = grid[0];
+        row[0] = 5;
+        let first = grid[0];
+        let x = first[0];";
+        assert_eq!(run_both(input, "x"), ("1".to_string(), "1".to_string()));
+    }
+
+    #[test]
+    fn arrays_that_contain_themselves() {
+        let mut heap = Heap::new();
+        let array = heap.allocate(Object::Array(vec![Int(1)]));
+        let Value::Object(array_ref) = array else {
+            panic!("expected an object");
+        };
+        if let Object::Array(elements) = heap.get_mut(array_ref) {
+            elements.push(array.clone());
+        }
+        assert_eq!(heap.display(&array), "[1, [...]]");
+
+        // a copy refers to itself rather than to the original
+        let copy = heap.copy(&array);
+        let Value::Object(copy_ref) = copy else {
+            panic!("expected an object");
+        };
+        assert_ne!(copy_ref, array_ref);
+        let Object::Array(elements) = heap.get(copy_ref) else {
+            panic!("expected an array");
+        };
+        assert_eq!(elements[1], copy);
+        assert_eq!(heap.display(&copy), "[1, [...]]");
+    }
+
+    #[test]
+    fn errors_read_like_the_interpreters() {
+        for input in [
+            "let x = 1 + true;",
+            "let x = 1 < \"a\";",
+            "let x = -true;",
+            "let x = bin(1) & true;",
+            "let x = 1 / 0;",
+            "let x = y;",
+            "let x = pow(2, true);",
+            "let f = 5; f(1);",
+            "fn f(a) { return a; } f(1, 2);",
+            "let x = [1]; let y = x[3];",
+        ] {
+            let mut ast = LangParser::new(input).parse().expect("unexpected failure");
+            let interpreted = ast.eval().expect_err("expected the interpreter to fail").kind.to_string();
+            assert_eq!(run_source_err(input), interpreted, "for `{}`", input);
+        }
+    }
+
+    #[test]
+    fn bitwise_not() {
+        assert_eq!(run_source("let x = ~5; x"), Int(-6));
+        assert_eq!(run_source("let x = ~true; x"), Value::Bool(false));
+        assert_eq!(run_source_err("let x = ~\"a\";"), "type mismatch: expected operand for Negation, found string");
+    }
+
     #[test]
     fn test_global_variable_definition_and_use() {
         // Simulates compiling and running:
@@ -348,20 +1076,16 @@ mod tests {
 
         // Statement 1: `let a = 20;`
         let val_idx = chunk.add_constant(Int(20));
-        chunk.write(OpCode::Constant as u8);
-        chunk.write(val_idx);
+        chunk.write_constant_op(OpCode::Constant, val_idx);
 
         let name_idx = chunk.add_constant(Value::Str("a".to_string()));
-        chunk.write(OpCode::DefineGlobal as u8);
-        chunk.write(name_idx);
+        chunk.write_constant_op(OpCode::DefineGlobal, name_idx);
 
         // Statement 2: `a / 4`
-        chunk.write(OpCode::GetGlobal as u8);
-        chunk.write(name_idx);
+        chunk.write_constant_op(OpCode::GetGlobal, name_idx);
 
         let four_idx = chunk.add_constant(Int(4));
-        chunk.write(OpCode::Constant as u8);
-        chunk.write(four_idx);
+        chunk.write_constant_op(OpCode::Constant, four_idx);
 
         chunk.write(OpCode::Divide as u8); // a / 4
         chunk.write(OpCode::Return as u8);
@@ -382,18 +1106,18 @@ mod tests {
         // `let a = 3;`
         let three_idx = chunk.add_constant(Int(3));
         let a_idx = chunk.add_constant(Value::Str("a".to_string()));
-        chunk.write(OpCode::Constant as u8); chunk.write(three_idx);
-        chunk.write(OpCode::DefineGlobal as u8); chunk.write(a_idx);
+        chunk.write_constant_op(OpCode::Constant, three_idx);
+        chunk.write_constant_op(OpCode::DefineGlobal, a_idx);
 
         // `let b = 4;`
         let four_idx = chunk.add_constant(Int(4));
         let b_idx = chunk.add_constant(Value::Str("b".to_string()));
-        chunk.write(OpCode::Constant as u8); chunk.write(four_idx);
-        chunk.write(OpCode::DefineGlobal as u8); chunk.write(b_idx);
+        chunk.write_constant_op(OpCode::Constant, four_idx);
+        chunk.write_constant_op(OpCode::DefineGlobal, b_idx);
 
         // `a * b`
-        chunk.write(OpCode::GetGlobal as u8); chunk.write(a_idx);
-        chunk.write(OpCode::GetGlobal as u8); chunk.write(b_idx);
+        chunk.write_constant_op(OpCode::GetGlobal, a_idx);
+        chunk.write_constant_op(OpCode::GetGlobal, b_idx);
         chunk.write(OpCode::Multiply as u8);
         chunk.write(OpCode::Return as u8);
 
@@ -407,25 +1131,22 @@ mod tests {
         // Simulates running code that uses an undefined variable `x`.
         let mut chunk = Chunk::new();
         let name_idx = chunk.add_constant(Value::Str("x".to_string()));
-        chunk.write(OpCode::GetGlobal as u8);
-        chunk.write(name_idx);
+        chunk.write_constant_op(OpCode::GetGlobal, name_idx);
 
         let mut vm = VM::new();
         let result = vm.interpret(&chunk);
 
         assert!(result.is_err(), "Expected an error, but got Ok");
-        assert_eq!(result.unwrap_err(), "Undefined variable 'x'.");
+        assert_eq!(result.unwrap_err().message, "undefined variable `x`");
     }
     #[test]
     fn test_if_else_statement() {
-        let ast_true = Ast {
-            nodes: vec![Node::Conditional {
+        let ast_true = Ast::from_nodes(vec![Node::Conditional {
                 condition: Box::new(Node::Atomic { value: Value::Bool(true) }),
                 consequence: vec![Node::Atomic { value: Int(10) }],
                 alternative: vec![Node::Atomic { value: Int(20) }],
-            }],
-            declarations: Default::default(),
-        };
+                span: Span::default(),
+            }]);
         let chunk_true = Compiler::compile(&ast_true).expect("Compilation failed for true branch");
         let mut vm_true = VM::new();
         let result_true = vm_true.interpret(&chunk_true).expect("VM execution failed for true branch");
@@ -437,11 +1158,11 @@ mod tests {
         let mut chunk = Chunk::new();
         
         let idx10 = chunk.add_constant(Value::Int(10));
-        chunk.write(OpCode::Constant as u8); chunk.write(idx10);
+        chunk.write_constant_op(OpCode::Constant, idx10);
         chunk.write(OpCode::Pop as u8); // Pop the unused value
 
         let idx20 = chunk.add_constant(Value::Int(20));
-        chunk.write(OpCode::Constant as u8); chunk.write(idx20);
+        chunk.write_constant_op(OpCode::Constant, idx20);
         chunk.write(OpCode::Pop as u8);
 
         chunk.write(OpCode::Nil as u8);
@@ -452,4 +1173,734 @@ mod tests {
 
         assert_eq!(result, Value::Null);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn while_loop() {
+        let result = run_source("let i = 0;
+        let sum = 0;
+        while i < 10 {
+            sum = sum + i;
+            i = i + 1;
+        }
+        sum");
+        assert_eq!(result, Int(45));
+    }
+
+    #[test]
+    fn loop_with_break_and_continue() {
+        let result = run_source("let i = 0;
+        let odd = 0;
+        loop {
+            i = i + 1;
+            if (i > 9) {
+                break;
+            }
+            if (i % 2 == 0) {
+                continue;
+            }
+            odd = odd + i;
+        }
+        odd");
+        assert_eq!(result, Int(25));
+    }
+
+    #[test]
+    fn nested_loops_break_the_innermost() {
+        let result = run_source("let count = 0;
+        let i = 0;
+        while i < 3 {
+            i = i + 1;
+            loop {
+                count = count + 1;
+                break;
+            }
+        }
+        count");
+        assert_eq!(result, Int(3));
+    }
+
+    #[test]
+    fn else_if_chain() {
+        let result = run_source("let x = 5;
+        let size = 0;
+        if (x < 3) {
+            size = 1;
+        } else if (x < 6) {
+            size = 2;
+        } else {
+            size = 3;
+        }
+        size");
+        assert_eq!(result, Int(2));
+    }
+
+    #[test]
+    fn float_arithmetic() {
+        assert_eq!(run_source("let x = 1.5; x * 2"), Value::Float(3.0));
+        assert_eq!(run_source("let x = 1; x + 0.5"), Value::Float(1.5));
+        assert_eq!(run_source("let x = 7.5; x % 2"), Value::Float(1.5));
+        assert_eq!(run_source("let x = 2.5; let y = -x; y"), Value::Float(-2.5));
+        assert_eq!(run_source("let x = 1.5; x < 2"), Value::Bool(true));
+    }
+
+    #[test]
+    fn integers_promote_to_bigints() {
+        assert_eq!(run_source("let x = 2147483647; x + 1").to_string(), "2147483648");
+        assert_eq!(run_source("let x = 2147483647; x + 1 - 1"), Int(i32::MAX));
+        assert_eq!(run_source("let x = pow(2, 64); x").to_string(), "18446744073709551616");
+        assert_eq!(run_source("let x = 10000000000; x > 5"), Value::Bool(true));
+        assert_eq!(run_source("let x = -2147483647 - 1; let y = -x; y").to_string(), "2147483648");
+    }
+
+    #[test]
+    fn digit_sum_of_a_bigint() {
+        let result = run_source("let n = pow(2, 100);
+        let sum = 0;
+        while (n > 0) {
+            sum = sum + n % 10;
+            n = n / 10;
+        }
+        sum");
+        assert_eq!(result, Int(115));
+    }
+
+    #[test]
+    fn block_locals() {
+        let result = run_source("let x = 1;
+        let y = 0;
+        if (x == 1) {
+            let a = 10;
+            let b = a + 5;
+            y = a + b;
+        }
+        y");
+        assert_eq!(result, Int(25));
+    }
+
+    #[test]
+    fn let_in_a_block_shadows() {
+        let result = run_source("let x = 1;
+        let inner = 0;
+        if (x == 1) {
+            let x = 2;
+            x = x + 1;
+            inner = x;
+        }
+        x + inner * 10");
+        assert_eq!(result, Int(31));
+    }
+
+    #[test]
+    fn a_block_keeps_its_value_after_dropping_locals() {
+        let result = run_source("let c = 1;
+        if (c == 1) {
+            let a = 2;
+            let b = 3;
+            a * b
+        } else {
+            0
+        }");
+        assert_eq!(result, Int(6));
+    }
+
+    #[test]
+    fn break_and_continue_drop_loop_locals() {
+        let result = run_source("let i = 0;
+        let total = 0;
+        while (i < 10) {
+            let next = i + 1;
+            i = next;
+            if (next % 2 == 0) {
+                let skipped = next;
+                continue;
+            }
+            if (next > 7) {
+                let last = next;
+                break;
+            }
+            total = total + next;
+        }
+        total");
+        // 1 + 3 + 5 + 7
+        assert_eq!(result, Int(16));
+    }
+
+    #[test]
+    fn the_stack_is_clean_after_a_run() {
+        let ast = LangParser::new("let c = 1;
+        while (c < 5) {
+            let a = c;
+            let b = a * 2;
+            c = c + 1;
+        }").parse().expect("unexpected failure");
+        let chunk = Compiler::compile(&ast).expect("Test compilation failed");
+        let mut vm = VM::new();
+        vm.interpret(&chunk).expect("Test VM execution failed");
+        assert!(vm.stack.is_empty());
+        assert!(vm.frames.is_empty());
+    }
+
+    fn run_source_err(input: &str) -> String {
+        run_source_error(input).message
+    }
+
+    fn run_source_error(input: &str) -> VmError {
+        let ast = LangParser::new(input).parse().expect("unexpected failure");
+        let chunk = Compiler::compile(&ast).expect("Test compilation failed");
+        let mut vm = VM::new();
+        match vm.interpret(&chunk) {
+            Ok(value) => panic!("expected an error, got {}", value),
+            Err(e) => e,
+        }
+    }
+
+    #[test]
+    fn call_function() {
+        let result = run_source("fn add(x, y) {
+            print(\"Adding\");
+            let z = x + y;
+            print(z);
+            return z;
+        }
+        let z = add(5, 10);
+        z");
+        assert_eq!(result, Int(15));
+    }
+
+    #[test]
+    fn a_function_without_return_gives_nil() {
+        assert_eq!(run_source("fn f(x) { x + 1 } f(1)"), Value::Null);
+    }
+
+    #[test]
+    fn recursive_factorial() {
+        let result = run_source("fn fact(n) {
+            if (n < 2) {
+                return 1;
+            }
+            return n * fact(n - 1);
+        }
+        fact(10)");
+        assert_eq!(result, Int(3628800));
+    }
+
+    #[test]
+    fn recursive_fibonacci() {
+        let result = run_source("fn fib(n) {
+            if (n < 2) {
+                return n;
+            }
+            return fib(n - 1) + fib(n - 2);
+        }
+        fib(15)");
+        assert_eq!(result, Int(610));
+    }
+
+    #[test]
+    fn nested_call_arguments() {
+        let result = run_source("fn add(x, y) {
+            return x + y;
+        }
+        add(add(1, 2), add(3, add(4, 5)))");
+        assert_eq!(result, Int(15));
+    }
+
+    #[test]
+    fn return_from_inside_loop() {
+        let result = run_source("fn find(n) {
+            let i = 0;
+            while (i < 100) {
+                let doubled = i * 2;
+                if (i == n) {
+                    return doubled;
+                }
+                i = i + 1;
+            }
+            return 0;
+        }
+        find(7) + find(200)");
+        assert_eq!(result, Int(14));
+    }
+
+    #[test]
+    fn functions_are_values() {
+        let result = run_source("fn twice(f, x) {
+            return f(f(x));
+        }
+        fn inc(x) {
+            return x + 1;
+        }
+        let g = inc;
+        twice(g, 5)");
+        assert_eq!(result, Int(7));
+        assert_eq!(run_source("fn inc(x) { return x + 1; } inc").to_string(), "<fn inc>");
+    }
+
+    #[test]
+    fn functions_update_globals() {
+        let result = run_source("let count = 0;
+        fn bump() {
+            count = count + 1;
+        }
+        bump();
+        bump();
+        count");
+        assert_eq!(result, Int(2));
+    }
+
+    #[test]
+    fn arity_is_checked() {
+        let e = run_source_err("fn add(x, y) { return x + y; } add(1)");
+        assert_eq!(e, "`add` expects 2 arguments but got 1");
+        let e = run_source_err("let x = 1; x(2)");
+        assert_eq!(e, "type mismatch: expected function, found int");
+    }
+
+    #[test]
+    fn call_depth_limit() {
+        let e = run_source_err("fn down(n) { return down(n + 1); } down(0)");
+        assert!(e.starts_with("stack overflow"), "{}", e);
+    }
+
+    const MAKE_COUNTER: &str = "fn make_counter() {
+        let count = 0;
+        fn next() {
+            count = count + 1;
+            return count;
+        }
+        return next;
+    }
+    ";
+
+    #[test]
+    fn closures_keep_captured_locals_alive() {
+        let result = run_source(&format!("{}let c = make_counter(); c(); c(); c()", MAKE_COUNTER));
+        assert_eq!(result, Int(3));
+        let result = run_source(&format!("{}let a = make_counter(); let b = make_counter(); a(); a(); b(); a() * 10 + b()", MAKE_COUNTER));
+        assert_eq!(result, Int(32));
+    }
+
+    #[test]
+    fn closures_share_a_captured_variable() {
+        let result = run_source("fn pair() {
+            let n = 0;
+            fn inc() { n = n + 1; return n; }
+            fn get() { return n; }
+            inc();
+            inc();
+            return get;
+        }
+        let get = pair();
+        get()");
+        assert_eq!(result, Int(2));
+    }
+
+    #[test]
+    fn captured_locals_are_shared_while_open() {
+        // the closure and the function that created it see each other's assignments
+        let result = run_source("fn f() {
+            let x = 1;
+            fn set() { x = 10; }
+            set();
+            return x;
+        }
+        f()");
+        assert_eq!(result, Int(10));
+    }
+
+    #[test]
+    fn each_loop_iteration_captures_its_own_local() {
+        let result = run_source("fn collect() {
+            let first = 0;
+            let second = 0;
+            let i = 0;
+            while (i < 2) {
+                let j = i * 10;
+                fn get() { return j; }
+                if (i == 0) { first = get; } else { second = get; }
+                i = i + 1;
+            }
+            return first() + second() * 100;
+        }
+        collect()");
+        assert_eq!(result, Int(1000));
+    }
+
+    #[test]
+    fn upvalues_are_closed_when_a_block_ends() {
+        let result = run_source("let get = 0;
+        if (true) {
+            let hidden = 42;
+            get = fn() { return hidden; };
+        }
+        let other = 7;
+        get()");
+        assert_eq!(result, Int(42));
+    }
+
+    #[test]
+    fn lambdas_as_callbacks() {
+        let result = run_source("fn apply_twice(f, x) {
+            return f(f(x));
+        }
+        let step = 3;
+        fn adder(n) {
+            return fn(x) { return x + n + step; };
+        }
+        apply_twice(adder(2), 1) + apply_twice(fn(x) { return x * x; }, 3)");
+        assert_eq!(result, Int(92));
+        assert_eq!(run_source("let id = fn(x) { return x; }; id").to_string(), "<fn lambda>");
+    }
+
+    #[test]
+    fn nested_closures_capture_through_each_level() {
+        let result = run_source("fn outer() {
+            let x = 1;
+            fn middle() {
+                fn inner() {
+                    x = x + 1;
+                    return x;
+                }
+                return inner;
+            }
+            return middle();
+        }
+        let f = outer();
+        f();
+        f()");
+        assert_eq!(result, Int(3));
+    }
+
+    #[test]
+    fn local_functions_can_recurse() {
+        let result = run_source("fn outer(n) {
+            fn fact(k) {
+                if (k < 2) { return 1; }
+                return k * fact(k - 1);
+            }
+            return fact(n);
+        }
+        outer(5)");
+        assert_eq!(result, Int(120));
+    }
+
+    #[test]
+    fn for_loop_over_a_range() {
+        let result = run_source("let sum = 0;
+        for i in 0..10 {
+            sum = sum + i;
+        }
+        sum");
+        assert_eq!(result, Int(45));
+        // the end is exclusive, and an empty range never runs the body
+        assert_eq!(run_source("let n = 0; for i in 3..3 { n = n + 1; } n"), Int(0));
+    }
+
+    #[test]
+    fn for_loop_bounds_are_evaluated_once() {
+        let result = run_source("let end = 3;
+        let runs = 0;
+        for i in 0..end {
+            end = end + 1;
+            i = i + 100;
+            runs = runs + 1;
+        }
+        runs");
+        assert_eq!(result, Int(3));
+    }
+
+    #[test]
+    fn for_loop_break_and_continue() {
+        let result = run_source("let sum = 0;
+        for i in 0..100 {
+            let odd = i % 2;
+            if (odd == 1) { continue; }
+            if (i > 10) { break; }
+            sum = sum + i;
+        }
+        sum");
+        assert_eq!(result, Int(30));
+    }
+
+    #[test]
+    fn nested_for_loops() {
+        let result = run_source("let count = 0;
+        for i in 0..4 {
+            for j in 0..i {
+                count = count + 1;
+            }
+        }
+        count");
+        assert_eq!(result, Int(6));
+    }
+
+    #[test]
+    fn for_loop_variable_is_gone_after_the_loop() {
+        let e = run_source_err("for i in 0..3 { i } i");
+        assert_eq!(e, "undefined variable `i`");
+    }
+
+    #[test]
+    fn for_loop_inside_a_function() {
+        let result = run_source("fn sum_to(n) {
+            let total = 0;
+            for i in 0..n {
+                total = total + i + 1;
+            }
+            return total;
+        }
+        sum_to(100)");
+        assert_eq!(result, Int(5050));
+    }
+
+    #[test]
+    fn logical_operators_short_circuit() {
+        assert_eq!(run_source("let x = 1 == 1 || 1 == 2; x"), Value::Bool(true));
+        assert_eq!(run_source("let x = 1 == 2 || 2 == 2; x"), Value::Bool(true));
+        assert_eq!(run_source("let x = 1 == 2 && 2 == 2; x"), Value::Bool(false));
+        assert_eq!(run_source("let x = 1 == 1 && 2 == 2; x"), Value::Bool(true));
+        // the right side would be an error, so it must not run
+        assert_eq!(run_source("let x = 1 == 1 || undefined(); x"), Value::Bool(true));
+        assert_eq!(run_source("let x = 1 == 2 && undefined(); x"), Value::Bool(false));
+    }
+
+    #[test]
+    fn array_literals_and_indexing() {
+        assert_eq!(run_source_display("let x = [1, 2, 3, 4, 5]; x"), "[1, 2, 3, 4, 5]");
+        assert_eq!(run_source("let x = [1, 2, 3, 4, 5]; let y = x[2]; y"), Int(3));
+        assert_eq!(run_source("let x = [[33, 11], [22]]; x[0][1]"), Int(11));
+        assert_eq!(run_source_display("let x = []; x"), "[]");
+    }
+
+    #[test]
+    fn update_array() {
+        assert_eq!(run_source_display("let x = [1, 2, 3, 4, 5]; x[2] = 10; x"), "[1, 2, 10, 4, 5]");
+        let result = run_source_display("let x = [1, 2, 3, 4, 5];
+        let y = 2;
+        x[y] = x[y] * 10;
+        x");
+        assert_eq!(result, "[1, 2, 30, 4, 5]");
+    }
+
+    #[test]
+    fn index_errors() {
+        assert_eq!(run_source_err("let x = [1, 2]; x[2]"), "index 2 out of bounds for length 2");
+        assert_eq!(run_source_err("let x = [1, 2]; x[5] = 1;"), "index 5 out of bounds for length 2");
+        assert_eq!(run_source_err("let x = 5; x[0]"), "type mismatch: expected array or string, found int");
+    }
+
+    #[test]
+    fn strings_and_lengths() {
+        assert_eq!(run_source("let x = \"hello\"; x[1]"), Value::Str("e".to_string()));
+        assert_eq!(run_source("let x = \"hello\"; len(x)"), Int(5));
+        assert_eq!(run_source("let x = [1, 2, 3, 4, 5]; len(x)"), Int(5));
+        let result = run_source("let x = \"hello world this is a long string\";
+        let l = len(x);
+        let spaces = 0;
+        for i in 0..l {
+            let space = \" \";
+            if (x[i] == space) {
+                spaces = spaces + 1;
+            }
+        }
+        spaces");
+        assert_eq!(result, Int(6));
+    }
+
+    #[test]
+    fn hashmaps() {
+        let result = run_source("let x = <>;
+        x.push(1, 2);
+        let y = x.get(1);
+        y");
+        assert_eq!(result, Int(2));
+        assert_eq!(run_source_display("let x = <>; x.push(\"b\", 2); x.push(\"a\", 1); x"), "{a: 1, b: 2}");
+        assert_eq!(run_source("let x = <>; x.push(1, 2); x.push(1, 3); len(x)"), Int(1));
+        assert_eq!(run_source_err("let x = <>; x.get(1)"), "missing key 1");
+        assert_eq!(run_source_err("let x = <>; x.pop()"), "unknown method `pop`");
+    }
+
+    #[test]
+    fn hashmap_with_masks() {
+        let result = run_source_display("let masks = <>;
+        let input = bin(110);
+        for i in 0..8 {
+            let mask = bin(i) << 5;
+            let res = input & mask;
+            masks.push(i, res);
+        }
+        masks");
+        assert_eq!(result, "{0: 0, 1: 100000, 2: 1000000, 3: 1100000, 4: 0, 5: 100000, 6: 1000000, 7: 1100000}");
+    }
+
+    #[test]
+    fn map_and_filter_take_callbacks() {
+        let result = run_source_display("let factor = 3;
+        let xs = map([1, 2, 3], fn(x) { return x * factor; });
+        xs");
+        assert_eq!(result, "[3, 6, 9]");
+        let result = run_source_display("fn even(x) { return x % 2 == 0; }
+        let ys = filter([1, 2, 3, 4, 5, 6], even);
+        ys");
+        assert_eq!(result, "[2, 4, 6]");
+        // a callback can itself call a builtin that calls back
+        let result = run_source_display("let xs = map([[1, 2], [3]], fn(row) { return map(row, fn(x) { return x + 1; }); }); xs");
+        assert_eq!(result, "[[2, 3], [4]]");
+    }
+
+    #[test]
+    fn builtin_conversions() {
+        assert_eq!(run_source("let x = int(2.7); x"), Int(2));
+        assert_eq!(run_source("let x = sqrt(16); x"), Value::Float(4.0));
+        assert_eq!(run_source("let x = bin(5) | bin(2); x"), Value::Bin(7));
+        assert_eq!(run_source_err("let x = bin(1) << 40; x"), "integer overflow: shift by 40 bits");
+    }
+
+    #[test]
+    fn runtime_errors_report_the_line_and_the_calls() {
+        let e = run_source_error("fn add(a, b) {
+            return a + b;
+        }
+        let x = 1;
+        add(x, true)");
+        assert_eq!(e.message, "type mismatch: expected operands for Add, found int and bool");
+        assert_eq!(e.line(), Some(2));
+        let trace: Vec<(&str, Option<usize>)> = e.trace.iter().map(|entry| (entry.function.as_str(), entry.line())).collect();
+        assert_eq!(trace, vec![("add", Some(2)), ("script", Some(5))]);
+        assert_eq!(e.to_string(), "type mismatch: expected operands for Add, found int and bool\n[line 2] in add\n[line 5] in script");
+
+        // calls failing before the callee runs are reported at the call
+        let e = run_source_error("fn f(a) { return a; }
+        let y = f(1, 2);");
+        assert_eq!(e.to_string(), "`f` expects 1 arguments but got 2\n[line 2] in script");
+    }
+
+    #[test]
+    fn repeated_calls_are_folded_in_traces() {
+        let e = run_source_error("fn down(n) {
+            return down(n + 1);
+        }
+        down(0);");
+        assert_eq!(e.trace.len(), DEFAULT_MAX_CALL_DEPTH);
+        let expected = format!(
+            "stack overflow: exceeded the maximum call depth of {}\n[line 2] in down\n... repeated {} more times\n[line 4] in script",
+            DEFAULT_MAX_CALL_DEPTH,
+            DEFAULT_MAX_CALL_DEPTH - 2
+        );
+        assert_eq!(e.to_string(), expected);
+
+        // calls that differ are all kept, up to a limit
+        let mut input: String = (0..20).map(|i| format!("fn f{}() {{ return f{}(); }}\n", i, i + 1)).collect();
+        input.push_str("fn f20() { return 1 / 0; }\nf0();");
+        let lines = run_source_error(&input).trace_lines();
+        assert_eq!(lines.len(), MAX_TRACE_LINES + 1);
+        assert_eq!(lines[0], "[line 21] in f20");
+        assert_eq!(lines[MAX_TRACE_LINES], "... and 6 more calls");
+    }
+
+    #[test]
+    fn runtime_errors_in_callbacks_are_traced() {
+        let e = run_source_error("let xs = [1, 2, 0];
+        let ys = map(xs, fn(x) {
+            return 10 / x;
+        });");
+        assert_eq!(e.to_string(), "division by zero\n[line 3] in lambda\n[line 2] in script");
+
+        // the callback returned, the builtin is what fails
+        let e = run_source_error("let xs = filter([1],
+            fn(x) {
+                return x;
+            });");
+        assert_eq!(e.to_string(), "type mismatch: expected bool, found int\n[line 1] in script");
+    }
+
+    #[test]
+    fn programs_with_more_than_256_constants() {
+        let mut input: String = (0..300).map(|i| format!("let x{} = {};", i, i * 2)).collect();
+        input.push_str("let total = x0 + x150 + x299; total");
+        assert_eq!(run_source(&input), Int(898));
+    }
+
+    #[test]
+    fn jumps_over_more_than_64k_of_code() {
+        // each `y = y + 1;` is 8 bytes of code, so the bodies are far larger than a 16 bit jump
+        let body = "y = y + 1;".repeat(10_000);
+        let input = format!("let x = 0; if (x == 0) {{ let y = 0; {} x = y; }} x", body);
+        assert_eq!(run_source(&input), Int(10_000));
+        let input = format!("let x = 0; while (x < 2) {{ let y = 0; {} x = x + 1; }} x", body);
+        assert_eq!(run_source(&input), Int(2));
+    }
+
+    // runs with a collection before every allocation, returning the VM so its heap can be checked
+    fn run_stressed(input: &str) -> (String, VM) {
+        let ast = LangParser::new(input).parse().expect("unexpected failure");
+        let chunk = Compiler::compile(&ast).expect("Test compilation failed");
+        let mut vm = VM::new();
+        vm.stress_gc = true;
+        let value = vm.interpret(&chunk).expect("Test VM execution failed");
+        (vm.display(&value), vm)
+    }
+
+    #[test]
+    fn garbage_is_collected() {
+        let (result, vm) = run_stressed("let total = 0;
+        for i in 0..100 { let xs = [i, [i, i]]; total = total + len(xs); }
+        total");
+        assert_eq!(result, "200");
+        let stats = vm.gc_stats();
+        assert!(stats.collections >= 200);
+        assert!(stats.objects_freed >= 190);
+        assert!(stats.objects <= 4);
+        assert!(stats.total_allocated > stats.bytes_allocated);
+    }
+
+    #[test]
+    fn reachable_objects_survive_collections() {
+        // nested in other objects and held by globals
+        let (result, _) = run_stressed("let m = <>; m.push(\"xs\", [[1], [2, 3]]); let ys = [m, [4]]; ys");
+        assert_eq!(result, "[{xs: [[1], [2, 3]]}, [4]]");
+        // held by a function's locals while it allocates more
+        let (result, _) = run_stressed("fn build(n) { let xs = [n]; let ys = [xs, [n + 1]]; return [ys, xs]; }
+        let result = build(1); result");
+        assert_eq!(result, "[[[1], [2]], [1]]");
+        // captured by a closure after its scope ended
+        let (result, _) = run_stressed("fn make() { let xs = [1, 2]; return fn() { return xs; }; }
+        let get = make(); let junk = [[0], [0]]; let xs = get(); xs");
+        assert_eq!(result, "[1, 2]");
+        // arguments and partial results of callbacks
+        let (result, _) = run_stressed("let xs = map([[1, 2], [3]], fn(row) { return map(row, fn(x) { return [x]; }); }); xs");
+        assert_eq!(result, "[[[1], [2]], [[3]]]");
+        let (result, _) = run_stressed("let xs = filter([[1], [], [2, 3]], fn(x) { return len([x, x]) == len(x) + 2 - len(x); }); xs");
+        assert_eq!(result, "[[1], [], [2, 3]]");
+    }
+
+    #[test]
+    fn collections_wait_for_the_threshold() {
+        let input = "for i in 0..50 { let xs = [i, i, i]; } let x = 1; x";
+        let ast = LangParser::new(input).parse().expect("unexpected failure");
+        let chunk = Compiler::compile(&ast).expect("Test compilation failed");
+
+        let mut vm = VM::new();
+        vm.interpret(&chunk).expect("Test VM execution failed");
+        let stats = vm.gc_stats();
+        assert_eq!(stats.collections, 0);
+        assert_eq!(stats.objects, 50);
+
+        let mut vm = VM::new();
+        vm.set_gc_threshold(1);
+        vm.interpret(&chunk).expect("Test VM execution failed");
+        let stats = vm.gc_stats();
+        assert!(stats.collections > 0);
+        assert!(stats.objects < 50);
+
+        vm.collect_garbage();
+        assert_eq!(vm.gc_stats().objects, 0);
+        assert!(vm.gc_stats().total_pause >= vm.gc_stats().last_pause);
+    }
+
+    #[test]
+    fn break_outside_a_loop_does_not_compile() {
+        let ast = LangParser::new("break;").parse().expect("unexpected failure");
+        assert!(Compiler::compile(&ast).is_err());
+    }
+}